@@ -0,0 +1,148 @@
+// `subpub`: a command-line client for trying out a SubPub server, e.g. checking a
+// mapping from a laptop without writing any code.
+use anyhow::{anyhow, bail, Context, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use subpub_client::Client;
+
+const USAGE: &str = "\
+Usage: subpub [options] <command>
+Commands:
+  discover                  list the servers answering on this network
+  sub <topic> [topic...]    print what's published to the topics until Ctrl+C
+  pub <topic> <payload>     publish once
+  stats                     print the server's counters
+Options:
+  --server <addr:port>      server to use (default: the first one discovered)
+  --auth <user:secret>      log in first, for servers with [auth] enabled
+  --name <name>             name this client in the server's logs (HELLO)
+  --wait <secs>             how long discovery listens for answers (default: 2)
+Options may go before or after the command; use -- before a payload that starts with --.";
+
+struct Options {
+    server: Option<SocketAddr>,
+    auth: Option<(String, String)>,
+    name: Option<String>,
+    wait: Duration,
+    command: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut options = Self { server: None, auth: None, name: None, wait: Duration::from_secs(2), command: Vec::new() };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--server" => options.server = Some(value()?.parse().context("--server must be <address>:<port>")?),
+                "--auth" => {
+                    let (user, secret) = value()?.split_once(':').ok_or_else(|| anyhow!("--auth must be <user>:<secret>"))?;
+                    options.auth = Some((user.to_string(), secret.to_string()));
+                }
+                "--name" => options.name = Some(value()?.clone()),
+                "--wait" => options.wait = Duration::from_secs_f64(value()?.parse().context("--wait must be a number")?),
+                // Everything after a bare "--" is an argument, e.g. a payload starting with "--"
+                "--" => options.command.extend(args.by_ref().cloned()),
+                _ if !arg.starts_with("--") => options.command.push(arg.clone()),
+                other => bail!("Unknown option '{}'", other),
+            }
+        }
+        if options.command.is_empty() {
+            bail!("No command given");
+        }
+        Ok(options)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{:#}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to create Tokio runtime: {}", e);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = runtime.block_on(run(options)) {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(options: Options) -> Result<()> {
+    let command: Vec<&str> = options.command.iter().map(String::as_str).collect();
+    match command.as_slice() {
+        ["discover"] => {
+            let servers = subpub_client::discover_all(options.wait).await?;
+            if servers.is_empty() {
+                bail!("No SubPub server answered within {:.1}s", options.wait.as_secs_f64());
+            }
+            for server in servers {
+                println!("{}", server);
+            }
+        }
+        ["sub", topics @ ..] if !topics.is_empty() => {
+            let client = connect(&options).await?;
+            let mut tasks = Vec::new();
+            for topic in topics {
+                let mut subscription = client.subscribe(topic).await?;
+                eprintln!("Subscribed to '{}' on {}", topic, client.server());
+                tasks.push(tokio::spawn(async move {
+                    while let Some(message) = subscription.recv().await {
+                        println!("{} {}", message.topic, String::from_utf8_lossy(&message.payload));
+                    }
+                    eprintln!("Subscription to '{}' ended (refused by the server?)", subscription.topic());
+                }));
+            }
+            // Runs until every subscription has ended or the user interrupts it.
+            for task in tasks {
+                let _ = task.await;
+            }
+        }
+        ["pub", topic, payload] => {
+            let client = connect(&options).await?;
+            client.publish(topic, payload).await?;
+            // A reply to this PING means the publish has been processed too.
+            client.ping().await?;
+        }
+        ["stats"] => {
+            let client = connect(&options).await?;
+            let stats = client.stats().await?;
+            println!("Server:        {}", client.server());
+            println!("Uptime:        {}s", stats.uptime_secs);
+            println!("Messages:      {}", stats.messages_processed);
+            println!("Channels:      {}", stats.channels);
+            println!("Subscribers:   {}", stats.subscribers);
+            println!("MIDI sent:     {}", stats.midi_messages_sent);
+        }
+        _ => bail!("Unknown command or wrong arguments: {}", options.command.join(" ")),
+    }
+    Ok(())
+}
+
+// Connects to --server, or to the first server discovered, and logs in / says hello.
+async fn connect(options: &Options) -> Result<Client> {
+    let server = match options.server {
+        Some(server) => server,
+        None => subpub_client::discover(options.wait).await.context("Pass --server if discovery is blocked on this network")?,
+    };
+    let client = Client::connect(server).await?;
+    if let Some((user, secret)) = &options.auth {
+        client.login(user, secret).await?;
+    }
+    if let Some(name) = &options.name {
+        client.hello(name).await?;
+    }
+    Ok(client)
+}
@@ -0,0 +1,297 @@
+use anyhow::{anyhow, bail, Context, Result};
+use flate2::read::DeflateDecoder;
+use futures_core::Stream;
+use serde::Deserialize;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::task::{Context as TaskContext, Poll};
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, timeout, Duration, Instant, MissedTickBehavior};
+
+use crate::frame::Frame;
+use crate::protocol;
+
+// How long a request waits for the server's reply
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+// Subscriptions ping this often, so servers with a subscriber TTL keep them
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+// Messages a subscription holds for a slow reader; after that the socket buffer fills
+const SUBSCRIPTION_BUFFER: usize = 1024;
+// PUBID attempts before publish_acked gives up
+const ACK_ATTEMPTS: u32 = 3;
+// Largest datagram the server sends
+const MAX_DATAGRAM: usize = 65507;
+
+// A payload received on a subscription, as published: text, or any bytes if it was
+// published in a binary frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    // The payload, if it's text.
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+}
+
+// The server's STATS reply.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerStats {
+    pub uptime_secs: u64,
+    pub messages_processed: u64,
+    pub channels: usize,
+    pub subscribers: usize,
+    pub midi_messages_sent: u64,
+}
+
+// A connection to one server. Requests with a reply (ping, stats, login, ...) go one at
+// a time over the client's own socket. Subscribers get bare payloads without the topic,
+// so every subscription has a socket (and so a client address) of its own.
+pub struct Client {
+    server: SocketAddr,
+    socket: UdpSocket,
+    // Held for a whole request, so replies can't end up with the wrong caller
+    requests: Mutex<()>,
+    // Repeated on every subscription socket after `login`
+    credentials: StdMutex<Option<(String, String)>>,
+    next_token: AtomicU64,
+}
+
+impl Client {
+    // Opens a socket to `server` and checks that it answers a PING.
+    pub async fn connect(server: SocketAddr) -> Result<Self> {
+        let client = Self {
+            server,
+            socket: connected_socket(server).await?,
+            requests: Mutex::new(()),
+            credentials: StdMutex::new(None),
+            next_token: AtomicU64::new(1),
+        };
+        client.ping().await.with_context(|| format!("No SubPub server answering at {}", server))?;
+        Ok(client)
+    }
+
+    pub fn server(&self) -> SocketAddr {
+        self.server
+    }
+
+    // AUTH, for servers with [auth] enabled. Subscriptions made afterwards log in too.
+    pub async fn login(&self, user: &str, secret: &str) -> Result<()> {
+        let reply = self.request(&protocol::auth(user, secret), &format!("AUTH:{}:", user)).await?;
+        if !reply.ends_with(":ok") {
+            bail!("Login as '{}' was denied", user);
+        }
+        *self.credentials.lock().unwrap() = Some((user.to_string(), secret.to_string()));
+        Ok(())
+    }
+
+    // Names this client in the server's logs and admin API.
+    pub async fn hello(&self, name: &str) -> Result<()> {
+        self.request(&protocol::hello(name), &format!("HELLO:{}:", name)).await.map(|_| ())
+    }
+
+    // Fire and forget, like a plain PUB from any other client.
+    pub async fn publish(&self, topic: &str, payload: &str) -> Result<()> {
+        self.socket.send(protocol::publish(topic, payload).as_bytes()).await?;
+        Ok(())
+    }
+
+    // PUB in a binary frame, for payloads that aren't text or contain anything the text
+    // protocol can't carry. Subscribers get the bytes as they are.
+    pub async fn publish_bytes(&self, topic: &str, payload: &[u8]) -> Result<()> {
+        let frame = Frame { action: "PUB", channel: Some(topic), payload: Some(payload), id: None };
+        self.socket.send(&frame.encode()).await?;
+        Ok(())
+    }
+
+    // PUBID: retried until the server acknowledges it; the server runs it only once per `id`.
+    pub async fn publish_acked(&self, topic: &str, id: &str, payload: &str) -> Result<()> {
+        let message = protocol::publish_with_id(topic, id, payload);
+        let ack = format!("ACK:{}:{}", topic, id);
+        let mut last_error = anyhow!("No ACK");
+        for _ in 0..ACK_ATTEMPTS {
+            match self.request(&message, &ack).await {
+                Ok(_) => return Ok(()),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error.context(format!("Publish {} on '{}' wasn't acknowledged", id, topic)))
+    }
+
+    // Round-trip time to the server.
+    pub async fn ping(&self) -> Result<Duration> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed).to_string();
+        let started = Instant::now();
+        self.request(&protocol::ping(&token), &format!("PONG:{}", token)).await?;
+        Ok(started.elapsed())
+    }
+
+    pub async fn stats(&self) -> Result<ServerStats> {
+        let reply = self.request("STATS", "STATS:{").await?;
+        serde_json::from_str(&reply["STATS:".len()..]).context("Unexpected STATS reply")
+    }
+
+    // Channels with subscribers matching `pattern` ("*", "drums/*", or a name), with
+    // their subscriber counts.
+    pub async fn list(&self, pattern: &str) -> Result<Vec<(String, usize)>> {
+        let _request = self.requests.lock().await;
+        self.socket.send(format!("LIST:{}", pattern).as_bytes()).await?;
+        let mut channels = Vec::new();
+        loop {
+            let reply = await_reply(&self.socket, "LIST").await?;
+            if reply.starts_with("LIST_END:") {
+                return Ok(channels);
+            }
+            if let Some((channel, count)) = reply.strip_prefix("LIST:").and_then(|rest| rest.rsplit_once(':')) {
+                channels.push((channel.to_string(), count.parse().unwrap_or(0)));
+            }
+        }
+    }
+
+    // SUB on a socket of its own. The stream ends if the server refuses the topic.
+    pub async fn subscribe(&self, topic: &str) -> Result<Subscription> {
+        self.subscribe_with_options(topic, "").await
+    }
+
+    // SUB with options, e.g. "max_hz=10,compress=deflate". Compressed payloads are
+    // inflated before they reach the Subscription.
+    pub async fn subscribe_with_options(&self, topic: &str, options: &str) -> Result<Subscription> {
+        let socket = Arc::new(connected_socket(self.server).await?);
+        let credentials = self.credentials.lock().unwrap().clone();
+        if let Some((user, secret)) = credentials {
+            socket.send(protocol::auth(&user, &secret).as_bytes()).await?;
+            let reply = await_reply(&socket, &format!("AUTH:{}:", user)).await?;
+            if !reply.ends_with(":ok") {
+                bail!("Login as '{}' was denied", user);
+            }
+        }
+        let sub = if options.is_empty() { protocol::sub(topic) } else { protocol::sub_with_options(topic, options) };
+        socket.send(sub.as_bytes()).await?;
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_BUFFER);
+        let reader = tokio::spawn(read_subscription(socket.clone(), topic.to_string(), tx));
+        Ok(Subscription { topic: topic.to_string(), socket, messages: rx, reader })
+    }
+
+    // Sends `message` and waits for the reply starting with `reply_prefix`.
+    async fn request(&self, message: &str, reply_prefix: &str) -> Result<String> {
+        let _request = self.requests.lock().await;
+        self.socket.send(message.as_bytes()).await?;
+        await_reply(&self.socket, reply_prefix).await
+    }
+}
+
+async fn connected_socket(server: SocketAddr) -> Result<UdpSocket> {
+    let local: SocketAddr = if server.is_ipv6() { "[::]:0".parse()? } else { "0.0.0.0:0".parse()? };
+    let socket = UdpSocket::bind(local).await.context("Failed to bind a local socket")?;
+    socket.connect(server).await.with_context(|| format!("Failed to reach {}", server))?;
+    Ok(socket)
+}
+
+// The next datagram starting with `prefix`, skipping anything else (RESULT echoes,
+// late replies). An ERROR reply fails the request.
+async fn await_reply(socket: &UdpSocket, prefix: &str) -> Result<String> {
+    let mut buf = vec![0; MAX_DATAGRAM];
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let len = timeout(remaining, socket.recv(&mut buf)).await.map_err(|_| anyhow!("Timed out waiting for {}", prefix))??;
+        let Ok(reply) = std::str::from_utf8(&buf[..len]) else { continue };
+        if reply.starts_with(prefix) {
+            return Ok(reply.to_string());
+        }
+        if reply.starts_with("ERROR:") {
+            bail!("Server replied {}", reply);
+        }
+    }
+}
+
+// Forwards the subscription's payloads and keeps it alive until the Subscription is dropped.
+async fn read_subscription(socket: Arc<UdpSocket>, topic: String, tx: mpsc::Sender<Message>) {
+    let refused = format!("ERROR:{}:", topic);
+    let pong = format!("PONG:{}", topic);
+    let mut buf = vec![0; MAX_DATAGRAM];
+    let mut keepalive = interval(KEEPALIVE_INTERVAL);
+    keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    keepalive.reset();
+    loop {
+        tokio::select! {
+            received = socket.recv(&mut buf) => {
+                let Ok(len) = received else { return };
+                let payload = &buf[..len];
+                if payload == protocol::KEEPALIVE_MESSAGE.as_bytes() || payload == pong.as_bytes() {
+                    continue;
+                }
+                if payload.starts_with(refused.as_bytes()) {
+                    return;
+                }
+                let payload = match payload {
+                    [protocol::COMPRESSED_MAGIC, algorithm, compressed @ ..] => match inflate(*algorithm, compressed) {
+                        Some(payload) => payload,
+                        None => continue,
+                    },
+                    payload => payload.to_vec(),
+                };
+                let message = Message { topic: topic.clone(), payload };
+                if tx.send(message).await.is_err() {
+                    return;
+                }
+            }
+            _ = keepalive.tick() => {
+                let _ = socket.send(protocol::ping(&topic).as_bytes()).await;
+            }
+        }
+    }
+}
+
+// A compressed payload (see protocol::COMPRESSED_MAGIC). None if it can't be read.
+fn inflate(algorithm: u8, compressed: &[u8]) -> Option<Vec<u8>> {
+    if algorithm != protocol::COMPRESSION_DEFLATE {
+        return None;
+    }
+    let mut payload = Vec::with_capacity(compressed.len() * 4);
+    DeflateDecoder::new(compressed).read_to_end(&mut payload).ok()?;
+    Some(payload)
+}
+
+// Payloads of one topic, as a Stream or with `recv`. Dropping it unsubscribes.
+pub struct Subscription {
+    topic: String,
+    socket: Arc<UdpSocket>,
+    messages: mpsc::Receiver<Message>,
+    reader: JoinHandle<()>,
+}
+
+impl Subscription {
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    // The next message, or None once the subscription has ended.
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.messages.recv().await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Message;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Message>> {
+        self.get_mut().messages.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        // Best effort; a server with a subscriber TTL drops it later anyway.
+        let _ = self.socket.try_send(protocol::unsub(&self.topic).as_bytes());
+        self.reader.abort();
+    }
+}
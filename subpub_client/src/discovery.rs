@@ -0,0 +1,43 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::time::{timeout, Duration, Instant};
+
+use crate::protocol::{self, DISCOVERY_BROADCAST_PORT, DISCOVERY_GROUP, DISCOVERY_MESSAGE};
+
+// The first server that answers a discovery ping within `wait`.
+pub async fn discover(wait: Duration) -> Result<SocketAddr> {
+    let servers = discover_all(wait).await?;
+    servers.into_iter().next().ok_or_else(|| anyhow!("No SubPub server answered within {}ms", wait.as_millis()))
+}
+
+// Every server that answers within `wait`, in the order they answered. Pings go to the
+// IPv4 multicast group and to the broadcast address, for networks that block multicast.
+pub async fn discover_all(wait: Duration) -> Result<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await.context("Failed to bind a local socket")?;
+    socket.set_broadcast(true)?;
+    let group: SocketAddr = DISCOVERY_GROUP.parse()?;
+    let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_BROADCAST_PORT));
+    let multicast_sent = socket.send_to(DISCOVERY_MESSAGE.as_bytes(), group).await;
+    let broadcast_sent = socket.send_to(DISCOVERY_MESSAGE.as_bytes(), broadcast).await;
+    if let (Err(e), Err(_)) = (&multicast_sent, &broadcast_sent) {
+        bail!("Failed to send a discovery ping: {}", e);
+    }
+
+    // A server answers both pings; each one is listed once.
+    let mut servers = Vec::new();
+    let mut buf = [0; 1024];
+    let deadline = Instant::now() + wait;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Ok(received) = timeout(remaining, socket.recv_from(&mut buf)).await else {
+            return Ok(servers);
+        };
+        let (len, _) = received?;
+        if let Some(server) = std::str::from_utf8(&buf[..len]).ok().and_then(protocol::parse_discovery_response)
+            && !servers.contains(&server)
+        {
+            servers.push(server);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+// Binary frames, for payloads the text protocol can't carry (raw bytes, anything that
+// isn't UTF-8). A frame is FRAME_MAGIC, FRAME_VERSION, then a MessagePack array
+//
+//   [action, channel, payload, id]
+//
+// with action a str, channel and id a str or nil, and payload a bin, a str or nil.
+// Trailing nils may be left out, e.g. ["STATS"]. The format is chosen per datagram:
+// FRAME_MAGIC can't start a UTF-8 text message, so both coexist on the same port, and
+// the server answers a frame with a frame.
+//
+// Only the part of MessagePack this needs is implemented: arrays, nil, str and bin.
+use anyhow::{anyhow, bail, Result};
+
+// 0xC1 never occurs in UTF-8 (and is unused by MessagePack itself)
+pub const FRAME_MAGIC: u8 = 0xC1;
+pub const FRAME_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Frame<'a> {
+    pub action: &'a str,
+    pub channel: Option<&'a str>,
+    pub payload: Option<&'a [u8]>,
+    pub id: Option<&'a str>,
+}
+
+// Whether `datagram` is a frame rather than a text message.
+pub fn is_frame(datagram: &[u8]) -> bool {
+    datagram.first() == Some(&FRAME_MAGIC)
+}
+
+impl<'a> Frame<'a> {
+    // A text message as a frame: `ACTION:channel:payload`, split at the first two
+    // colons. Used for replies, e.g. "ACK:drums:42" becomes ["ACK", "drums", "42"].
+    pub fn from_text(text: &'a str) -> Self {
+        let mut parts = text.splitn(3, ':');
+        Self {
+            action: parts.next().unwrap_or(""),
+            channel: parts.next(),
+            payload: parts.next().map(str::as_bytes),
+            id: None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(8 + self.action.len() + self.payload.map_or(0, <[u8]>::len));
+        self.encode_into(&mut out);
+        out
+    }
+
+    // Like `encode`, into a buffer that's cleared first.
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.clear();
+        out.push(FRAME_MAGIC);
+        out.push(FRAME_VERSION);
+        let len = if self.id.is_some() { 4 } else if self.payload.is_some() { 3 } else if self.channel.is_some() { 2 } else { 1 };
+        out.push(0x90 | len as u8); // fixarray
+        write_str(out, self.action.as_bytes());
+        let fields = [self.channel.map(str::as_bytes), self.payload, self.id.map(str::as_bytes)];
+        for (index, field) in fields.into_iter().take(len - 1).enumerate() {
+            match field {
+                None => out.push(0xC0),
+                Some(payload) if index == 1 => write_bin(out, payload),
+                Some(text) => write_str(out, text),
+            }
+        }
+    }
+
+    // Borrows the fields out of `datagram`.
+    pub fn decode(datagram: &'a [u8]) -> Result<Self> {
+        let Some((&FRAME_MAGIC, rest)) = datagram.split_first() else {
+            bail!("not a binary frame");
+        };
+        let Some((&version, body)) = rest.split_first() else {
+            bail!("truncated frame");
+        };
+        if version != FRAME_VERSION {
+            bail!("unsupported frame version {}", version);
+        }
+        let mut reader = Reader { data: body };
+        let len = reader.array_len()?;
+        if !(1..=4).contains(&len) {
+            bail!("expected an array of 1 to 4 fields, got {}", len);
+        }
+        let mut frame = Frame { action: reader.str()?.ok_or_else(|| anyhow!("missing action"))?, ..Frame::default() };
+        if len > 1 {
+            frame.channel = reader.str()?;
+        }
+        if len > 2 {
+            frame.payload = reader.bytes()?;
+        }
+        if len > 3 {
+            frame.id = reader.str()?;
+        }
+        if !reader.data.is_empty() {
+            bail!("{} trailing bytes after the frame", reader.data.len());
+        }
+        Ok(frame)
+    }
+}
+
+// `s` is already known to be UTF-8.
+fn write_str(out: &mut Vec<u8>, s: &[u8]) {
+    match s.len() {
+        len @ 0..32 => out.push(0xA0 | len as u8),
+        len @ 32..256 => out.extend_from_slice(&[0xD9, len as u8]),
+        len @ 256..65536 => {
+            out.push(0xDA);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(0xDB);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(s);
+}
+
+fn write_bin(out: &mut Vec<u8>, bytes: &[u8]) {
+    match bytes.len() {
+        len @ 0..256 => out.extend_from_slice(&[0xC4, len as u8]),
+        len @ 256..65536 => {
+            out.push(0xC5);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(0xC6);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            bail!("truncated frame");
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn length(&mut self, size: usize) -> Result<usize> {
+        Ok(self.take(size)?.iter().fold(0, |len, byte| len << 8 | *byte as usize))
+    }
+
+    fn array_len(&mut self) -> Result<usize> {
+        match self.byte()? {
+            marker @ 0x90..=0x9F => Ok((marker & 0x0F) as usize),
+            0xDC => self.length(2),
+            marker => bail!("expected an array, got marker 0x{:02X}", marker),
+        }
+    }
+
+    // A str, bin or nil, as bytes.
+    fn bytes(&mut self) -> Result<Option<&'a [u8]>> {
+        let len = match self.byte()? {
+            0xC0 => return Ok(None),
+            marker @ 0xA0..=0xBF => (marker & 0x1F) as usize,
+            0xC4 | 0xD9 => self.length(1)?,
+            0xC5 | 0xDA => self.length(2)?,
+            0xC6 | 0xDB => self.length(4)?,
+            marker => bail!("expected a str or bin, got marker 0x{:02X}", marker),
+        };
+        self.take(len).map(Some)
+    }
+
+    fn str(&mut self) -> Result<Option<&'a str>> {
+        match self.bytes()? {
+            Some(bytes) => Ok(Some(std::str::from_utf8(bytes).map_err(|_| anyhow!("field isn't valid UTF-8"))?)),
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,24 @@
+// Client library for the SubPub server: the UDP string protocol behind an async API,
+// so Rust installations don't have to reimplement it.
+//
+//   let server = subpub_client::discover(Duration::from_secs(2)).await?;
+//   let client = Client::connect(server).await?;
+//   let mut fader = client.subscribe("mixer/fader1").await?;
+//   client.publish("lights/scene", "intro").await?;
+//   while let Some(message) = fader.next().await { ... } // via StreamExt from futures or tokio-stream
+//
+// Payloads that aren't UTF-8 go out as binary frames (see frame.rs) with `publish_bytes`.
+//
+// Plain transport only; servers with [encryption] or [signing] enabled need those too.
+
+// Declare the protocol module
+pub mod protocol;
+// Declare the frame module
+pub mod frame;
+// Declare the client module
+mod client;
+// Declare the discovery module
+mod discovery;
+
+pub use client::{Client, Message, ServerStats, Subscription};
+pub use discovery::{discover, discover_all};
@@ -0,0 +1,64 @@
+// The wire format shared by the server and its clients. Every message is one UTF-8
+// datagram, `ACTION:<channel>[:<payload>]`; subscribers receive bare payloads.
+use std::net::SocketAddr;
+
+pub const DEFAULT_PORT: u16 = 7878;
+// Multicast group and broadcast port the server answers discovery pings on
+pub const DISCOVERY_GROUP: &str = "239.0.0.100:50100";
+pub const DISCOVERY_GROUP_V6: &str = "[ff02::7375:6270]:50100";
+pub const DISCOVERY_BROADCAST_PORT: u16 = 50101;
+pub const DISCOVERY_MESSAGE: &str = "DISCOVER_SUBPUB_SERVER";
+// Followed by a space and the server's address, e.g. "SUBPUB_SERVER_AT: 192.168.1.20:7878"
+pub const DISCOVERY_RESPONSE_PREFIX: &str = "SUBPUB_SERVER_AT:";
+// Sent to subscribers now and then to keep NAT pinholes open
+pub const KEEPALIVE_MESSAGE: &str = "KEEPALIVE";
+// A compressed payload, for subscriptions with `compress=deflate`: this byte, the
+// algorithm, then the compressed payload. 0xF6 never occurs in UTF-8.
+pub const COMPRESSED_MAGIC: u8 = 0xF6;
+// Raw deflate (RFC 1951), no zlib or gzip header
+pub const COMPRESSION_DEFLATE: u8 = 1;
+
+pub fn sub(topic: &str) -> String {
+    format!("SUB:{}", topic)
+}
+
+// SUB with options, comma separated, e.g. "max_hz=10,compress=deflate".
+pub fn sub_with_options(topic: &str, options: &str) -> String {
+    format!("SUB:{}:{}", topic, options)
+}
+
+pub fn unsub(topic: &str) -> String {
+    format!("UNSUB:{}", topic)
+}
+
+pub fn publish(topic: &str, payload: &str) -> String {
+    format!("PUB:{}:{}", topic, payload)
+}
+
+// Runs at most once per `id` on the server, which replies ACK:<topic>:<id>.
+pub fn publish_with_id(topic: &str, id: &str, payload: &str) -> String {
+    format!("PUBID:{}:{}:{}", topic, id, payload)
+}
+
+// Lets `@<n>` stand for `topic` in later messages from the same client, e.g. PUB:@42:0.5.
+// The server replies ALIAS:<n>:ok.
+pub fn alias(n: u16, topic: &str) -> String {
+    format!("ALIAS:{}:{}", n, topic)
+}
+
+pub fn auth(user: &str, secret: &str) -> String {
+    format!("AUTH:{}:{}", user, secret)
+}
+
+pub fn hello(name: &str) -> String {
+    format!("HELLO:{}", name)
+}
+
+pub fn ping(token: &str) -> String {
+    format!("PING:{}", token)
+}
+
+// The server's address from a discovery response.
+pub fn parse_discovery_response(response: &str) -> Option<SocketAddr> {
+    response.trim().strip_prefix(DISCOVERY_RESPONSE_PREFIX)?.trim().parse().ok()
+}
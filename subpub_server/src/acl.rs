@@ -0,0 +1,43 @@
+use log::info;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::config::{AclConfig, AclRule};
+use crate::server::topic_matches;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Publish,
+    Subscribe,
+}
+
+// Per-client topic permissions. The first rule matching the client's AUTH user or
+// address applies; clients no rule matches get the defaults.
+pub struct Acl {
+    config: AclConfig,
+}
+
+impl Acl {
+    pub fn from_config(config: &AclConfig) -> Option<Arc<Self>> {
+        if !config.enabled {
+            return None;
+        }
+        info!("Topic access control enabled with {} rule(s).", config.rules.len());
+        Some(Arc::new(Self { config: config.clone() }))
+    }
+
+    pub fn allows(&self, user: Option<&str>, addr: SocketAddr, access: Access, topic: &str) -> bool {
+        let ip = addr.ip().to_string();
+        let rule = self.config.rules.iter().find(|rule| {
+            user.is_some_and(|user| rule.users.iter().any(|u| u == user))
+                || rule.clients.iter().any(|pattern| topic_matches(pattern, &ip))
+        });
+        let patterns = match (rule, access) {
+            (Some(AclRule { publish, .. }), Access::Publish) => publish,
+            (Some(AclRule { subscribe, .. }), Access::Subscribe) => subscribe,
+            (None, Access::Publish) => &self.config.default_publish,
+            (None, Access::Subscribe) => &self.config.default_subscribe,
+        };
+        patterns.iter().any(|pattern| topic_matches(pattern, topic))
+    }
+}
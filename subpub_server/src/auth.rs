@@ -0,0 +1,212 @@
+use anyhow::{anyhow, bail, Context, Result};
+use dashmap::DashMap;
+use log::{info, warn};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+
+use crate::config::{AuthBackendKind, AuthConfig};
+use crate::paths;
+
+// Checks client credentials sent with `AUTH:<user>:<secret>`.
+// Verification may block (file or network access), so callers run it off the server loop.
+pub trait AuthBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn verify(&self, user: &str, secret: &str) -> Result<bool>;
+}
+
+pub fn backend_from_config(config: &AuthConfig) -> Result<Arc<dyn AuthBackend>> {
+    let backend: Arc<dyn AuthBackend> = match config.backend {
+        AuthBackendKind::StaticTokens => {
+            if config.tokens.is_empty() {
+                warn!("Auth uses static tokens but none are configured. Every client will be refused.");
+            }
+            Arc::new(StaticTokens { tokens: config.tokens.iter().cloned().collect() })
+        }
+        AuthBackendKind::Htpasswd => Arc::new(HtpasswdFile { path: paths::data_path(&config.htpasswd_file) }),
+        AuthBackendKind::HttpHook => Arc::new(HttpHook::new(&config.hook_url, Duration::from_millis(config.hook_timeout_ms))?),
+    };
+    info!("Client authentication enabled using the '{}' backend.", backend.name());
+    Ok(backend)
+}
+
+// Any of a fixed list of shared tokens. The user name is only used for logging.
+struct StaticTokens {
+    tokens: HashSet<String>,
+}
+
+impl AuthBackend for StaticTokens {
+    fn name(&self) -> &'static str {
+        "static_tokens"
+    }
+
+    fn verify(&self, _user: &str, secret: &str) -> Result<bool> {
+        // Every token is compared, so the time taken doesn't tell which one came close.
+        Ok(self.tokens.iter().fold(false, |found, token| found | secrets_match(secret, token)))
+    }
+}
+
+// `user:password` lines as written by `htpasswd -s` ({SHA}) or plain text.
+// The file is read on every check, so users can be added without a restart.
+struct HtpasswdFile {
+    path: PathBuf,
+}
+
+impl AuthBackend for HtpasswdFile {
+    fn name(&self) -> &'static str {
+        "htpasswd"
+    }
+
+    fn verify(&self, user: &str, secret: &str) -> Result<bool> {
+        let contents = fs::read_to_string(&self.path)
+            .with_context(|| format!("Failed to read htpasswd file {:?}", self.path))?;
+        let Some(stored) = contents
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .find_map(|line| line.split_once(':').filter(|(name, _)| *name == user).map(|(_, hash)| hash.trim()))
+        else {
+            return Ok(false);
+        };
+
+        if let Some(sha_b64) = stored.strip_prefix("{SHA}") {
+            return Ok(secrets_match(&base64_encode(&Sha1::digest(secret.as_bytes())), sha_b64));
+        }
+        if stored.starts_with("$apr1$") || stored.starts_with("$2y$") || stored.starts_with("$2a$") || stored.starts_with("$2b$") {
+            bail!("Unsupported htpasswd hash for user '{}'. Use `htpasswd -s` (SHA1) entries.", user);
+        }
+        Ok(secrets_match(secret, stored))
+    }
+}
+
+// Asks an external HTTP endpoint. The hook receives
+// `POST <path>` with `{"user": "...", "secret": "..."}` and allows the client on any 2xx.
+struct HttpHook {
+    host: String,
+    port: u16,
+    path: String,
+    timeout: Duration,
+}
+
+impl HttpHook {
+    fn new(url: &str, timeout: Duration) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("Auth hook URL '{}' must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().with_context(|| format!("Invalid port in auth hook URL '{}'", url))?),
+            None => (authority, 80),
+        };
+        Ok(Self { host: host.to_string(), port, path: path.to_string(), timeout })
+    }
+}
+
+impl AuthBackend for HttpHook {
+    fn name(&self) -> &'static str {
+        "http_hook"
+    }
+
+    fn verify(&self, user: &str, secret: &str) -> Result<bool> {
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow!("Could not resolve auth hook host '{}'", self.host))?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)
+            .with_context(|| format!("Failed to connect to auth hook at {}", addr))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let body = serde_json::json!({ "user": user, "secret": secret }).to_string();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        // Only the status line matters. It may arrive in pieces, so read up to its line end
+        // (capped, in case the hook never sends one).
+        let mut status_line = String::new();
+        BufReader::new(stream.take(1024)).read_line(&mut status_line)?;
+        let status: u16 = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| anyhow!("Malformed response from auth hook: {:?}", status_line))?;
+        Ok((200..300).contains(&status))
+    }
+}
+
+// Compares a secret sent by a client with the expected one in constant time, so the
+// time a comparison takes doesn't reveal how much of a guess was right.
+pub fn secrets_match(given: &str, expected: &str) -> bool {
+    given.as_bytes().ct_eq(expected.as_bytes()).into()
+}
+
+// Slows down guessing: an address that failed AUTH `max_failures` times in a row is
+// denied without a check until `lockout_secs` have passed since its last failure.
+pub struct FailedLogins {
+    max_failures: u32,
+    lockout: Duration,
+    // Failures in a row and when the last one happened, per source address
+    failures: DashMap<IpAddr, (u32, Instant)>,
+}
+
+impl FailedLogins {
+    pub fn new(config: &AuthConfig) -> Arc<Self> {
+        Arc::new(Self {
+            max_failures: config.max_failures,
+            lockout: Duration::from_secs(config.lockout_secs),
+            failures: DashMap::new(),
+        })
+    }
+
+    pub fn is_locked_out(&self, ip: IpAddr) -> bool {
+        self.max_failures > 0
+            && self
+                .failures
+                .get(&ip)
+                .is_some_and(|entry| entry.0 >= self.max_failures && entry.1.elapsed() < self.lockout)
+    }
+
+    pub fn record(&self, ip: IpAddr, accepted: bool) {
+        if accepted {
+            self.failures.remove(&ip);
+            return;
+        }
+        // Addresses that have been quiet for a lockout period start over.
+        self.failures.retain(|_, (_, last)| last.elapsed() < self.lockout);
+        let mut entry = self.failures.entry(ip).or_insert((0, Instant::now()));
+        let failures = entry.0.saturating_add(1);
+        *entry = (failures, Instant::now());
+        if self.max_failures > 0 && failures == self.max_failures {
+            warn!("{} failed to authenticate {} times in a row. Refusing its AUTH for {}s.", ip, failures, self.lockout.as_secs());
+        }
+    }
+}
+
+// Standard base64 with padding, as used by htpasswd {SHA} entries.
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 { ALPHABET[(n >> 6) as usize & 63] as char } else { '=' });
+        out.push(if chunk.len() > 2 { ALPHABET[n as usize & 63] as char } else { '=' });
+    }
+    out
+}
@@ -0,0 +1,146 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::midi_handler::{MappingEntry, MidiAction};
+use crate::server::topic_matches;
+
+const DEFAULT_RELEASE_AFTER_MS: u64 = 60_000;
+
+// Topics matching `pattern` get their own channel (or note range) from the pool on
+// first publish, so participants don't have to be declared up front.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AutoChannelConfig {
+    pub pattern: String,
+    pub channels: Vec<u8>,
+    // Split every channel into note ranges of this size, starting at `note_start`.
+    // Without it each topic gets a whole channel.
+    pub note_range_size: Option<u8>,
+    #[serde(default)]
+    pub note_start: u8,
+    // Allocations are released after this much inactivity (default 60s).
+    pub release_after_ms: Option<u64>,
+    // Template actions. Their channel and notes are moved into the allocated slot.
+    pub actions: Vec<MidiAction>,
+    pub zone: Option<String>,
+}
+
+// A channel plus a note range handed out to one topic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AllocatedSlot {
+    pub channel: u8,
+    pub note_start: u8,
+    pub note_count: u8,
+}
+
+impl AllocatedSlot {
+    // Folds any note into the slot's range.
+    pub fn map_note(&self, note: u8) -> u8 {
+        self.note_start + note % self.note_count
+    }
+}
+
+impl AutoChannelConfig {
+    fn slots(&self) -> Vec<AllocatedSlot> {
+        let note_start = self.note_start.min(127);
+        let available = 128 - note_start as u16;
+        let size = self.note_range_size.map_or(available, |s| (s as u16).clamp(1, available));
+        let ranges = available / size;
+        self.channels
+            .iter()
+            .flat_map(|&channel| {
+                (0..ranges).map(move |i| AllocatedSlot {
+                    channel: channel & 0x0F,
+                    note_start: (note_start as u16 + i * size) as u8,
+                    note_count: size as u8,
+                })
+            })
+            .collect()
+    }
+}
+
+struct Allocation {
+    pool_index: usize,
+    slot: AllocatedSlot,
+    last_used: Instant,
+}
+
+pub struct AutoChannelAllocator {
+    pools: Vec<AutoChannelConfig>,
+    allocations: HashMap<String, Allocation>,
+}
+
+impl AutoChannelAllocator {
+    pub fn new(pools: Vec<AutoChannelConfig>) -> Self {
+        Self { pools, allocations: HashMap::new() }
+    }
+
+    // Returns a mapping for a topic matching one of the pools, allocating a slot if needed.
+    pub fn mapping_for(&mut self, topic: &str) -> Option<MappingEntry> {
+        let pool_index = self.pools.iter().position(|pool| topic_matches(&pool.pattern, topic))?;
+        self.release_idle();
+
+        let slot = match self.allocations.get_mut(topic) {
+            Some(allocation) => {
+                allocation.last_used = Instant::now();
+                allocation.slot
+            }
+            None => {
+                let slot = self.free_slot(pool_index)?;
+                info!(
+                    "Allocated channel {} (notes {}-{}) to '{}'",
+                    slot.channel,
+                    slot.note_start,
+                    slot.note_start as u16 + slot.note_count as u16 - 1,
+                    topic
+                );
+                self.allocations.insert(topic.to_string(), Allocation { pool_index, slot, last_used: Instant::now() });
+                slot
+            }
+        };
+
+        let pool = &self.pools[pool_index];
+        Some(MappingEntry {
+            sub_topic: topic.to_string(),
+            priority: 0,
+            actions: pool.actions.clone(),
+            scale: None,
+            schedule: None,
+            zone: pool.zone.clone(),
+            echo: false,
+            humanize: None,
+            transpose: 0,
+            rotate: None,
+            on_payload_error: None,
+            payload_schema: None,
+            slot: Some(slot),
+        })
+    }
+
+    fn free_slot(&self, pool_index: usize) -> Option<AllocatedSlot> {
+        let pool = &self.pools[pool_index];
+        let free = pool.slots().into_iter().find(|slot| {
+            !self.allocations.values().any(|a| a.pool_index == pool_index && a.slot == *slot)
+        });
+        if free.is_none() {
+            warn!("Auto channel pool '{}' is exhausted. Ignoring new topic.", pool.pattern);
+        }
+        free
+    }
+
+    fn release_idle(&mut self) {
+        let pools = &self.pools;
+        self.allocations.retain(|topic, allocation| {
+            let release_after = Duration::from_millis(
+                pools[allocation.pool_index].release_after_ms.unwrap_or(DEFAULT_RELEASE_AFTER_MS),
+            );
+            let keep = allocation.last_used.elapsed() < release_after;
+            if !keep {
+                info!("Released channel {} from inactive topic '{}'", allocation.slot.channel, topic);
+            }
+            keep
+        });
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::net::UdpSocket;
+use tokio::time::{interval, sleep, timeout, Duration, Instant, MissedTickBehavior};
+
+const USAGE: &str = "\
+Usage: subpub_server --bench [options]
+Floods a running server with publishes and reports throughput, drops and latency.
+  --server <addr:port>   server to test (default: this machine's LAN address, port 7878)
+  --topics <a,b,...>     topics to publish to, round robin (default: bench/load)
+  --rate <n>             publishes per second, all senders together (default: 1000)
+  --duration <secs>      how long to publish (default: 10)
+  --payload-bytes <n>    payload size (default: 32; the timing header can make it longer)
+  --senders <n>          sockets to publish from, e.g. to spread over receive_loops (default: 1)
+  --auth <user:secret>   log in first, for servers with [auth] enabled
+Encrypted ([encryption]) and signed-only ([signing]) servers aren't supported.";
+
+// How long the receiver keeps listening after the last publish
+const DRAIN_TIME: Duration = Duration::from_secs(1);
+// Pause after SUB and AUTH so the server has them before the flood starts
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+const STATS_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct BenchOptions {
+    server: SocketAddr,
+    topics: Vec<String>,
+    rate: u64,
+    duration: Duration,
+    payload_bytes: usize,
+    senders: usize,
+    auth: Option<String>,
+}
+
+impl BenchOptions {
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut options = Self {
+            server: default_server(),
+            topics: vec!["bench/load".to_string()],
+            rate: 1000,
+            duration: Duration::from_secs(10),
+            payload_bytes: 32,
+            senders: 1,
+            auth: None,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--server" => options.server = value()?.parse().context("--server must be <address>:<port>")?,
+                "--topics" => options.topics = value()?.split(',').filter(|t| !t.is_empty()).map(str::to_string).collect(),
+                "--rate" => options.rate = value()?.parse().context("--rate must be a number")?,
+                "--duration" => options.duration = Duration::from_secs_f64(value()?.parse().context("--duration must be a number")?),
+                "--payload-bytes" => options.payload_bytes = value()?.parse().context("--payload-bytes must be a number")?,
+                "--senders" => options.senders = value()?.parse().context("--senders must be a number")?,
+                "--auth" => options.auth = Some(value()?.clone()),
+                other => bail!("Unknown option '{}'", other),
+            }
+        }
+        if options.topics.is_empty() || options.rate == 0 || options.senders == 0 {
+            bail!("--topics, --rate and --senders can't be empty or zero");
+        }
+        Ok(options)
+    }
+}
+
+// The address the server binds to by default (see `bind_main_socket`).
+fn default_server() -> SocketAddr {
+    let ip = local_ip_address::local_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    SocketAddr::new(ip, 7878)
+}
+
+// `--bench [options]`: load test against a running server. Returns the exit code.
+pub fn run_cli(args: &[String]) -> i32 {
+    if args.iter().any(|arg| arg == "--help") {
+        println!("{}", USAGE);
+        return 0;
+    }
+    let options = match BenchOptions::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{:#}\n\n{}", e, USAGE);
+            return 2;
+        }
+    };
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Failed to create Tokio runtime: {}", e);
+            return 2;
+        }
+    };
+    match runtime.block_on(run(options)) {
+        Ok(report) => {
+            println!("{}", report);
+            0
+        }
+        Err(e) => {
+            eprintln!("Benchmark failed: {:#}", e);
+            1
+        }
+    }
+}
+
+// The fields of the STATS reply the report needs.
+#[derive(Deserialize)]
+struct ServerCounters {
+    messages_processed: u64,
+    midi_messages_sent: u64,
+}
+
+async fn bind_for(server: SocketAddr) -> Result<UdpSocket> {
+    let local: SocketAddr = if server.is_ipv6() { "[::]:0".parse()? } else { "0.0.0.0:0".parse()? };
+    let socket = UdpSocket::bind(local).await.context("Failed to bind a local socket")?;
+    socket.connect(server).await.with_context(|| format!("Failed to reach {}", server))?;
+    Ok(socket)
+}
+
+async fn login(socket: &UdpSocket, auth: &Option<String>) -> Result<()> {
+    if let Some(credentials) = auth {
+        socket.send(format!("AUTH:{}", credentials).as_bytes()).await?;
+    }
+    Ok(())
+}
+
+async fn server_counters(socket: &UdpSocket) -> Result<ServerCounters> {
+    socket.send(b"STATS").await?;
+    let mut buf = vec![0; 65536];
+    let deadline = Instant::now() + STATS_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let len = timeout(remaining, socket.recv(&mut buf))
+            .await
+            .map_err(|_| anyhow!("No STATS reply from the server. Is it running, and reachable at this address?"))??;
+        // Anything else arriving on this socket (e.g. a late AUTH reply) is skipped.
+        if let Some(json) = std::str::from_utf8(&buf[..len]).ok().and_then(|reply| reply.strip_prefix("STATS:")) {
+            return serde_json::from_str(json).context("Unexpected STATS reply");
+        }
+    }
+}
+
+// Payloads start with "<sender> <seq> <micros since start>" so the receiver can tell
+// its own messages apart and time them; the rest is padding up to --payload-bytes.
+fn payload(sender: usize, seq: u64, micros: u64, size: usize) -> String {
+    let mut payload = format!("{} {} {} ", sender, seq, micros);
+    while payload.len() < size {
+        payload.push('x');
+    }
+    payload
+}
+
+async fn run_sender(index: usize, options: Arc<BenchOptions>, started: Instant, send_errors: Arc<AtomicU64>) -> Result<u64> {
+    let socket = bind_for(options.server).await?;
+    login(&socket, &options.auth).await?;
+    sleep(SETTLE_TIME).await;
+    let rate = options.rate as f64 / options.senders as f64;
+    let mut ticker = interval(Duration::from_millis(1));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let begin = Instant::now();
+    let mut sent = 0u64;
+    while begin.elapsed() < options.duration {
+        ticker.tick().await;
+        // Catch up to where the rate says we should be, so slow ticks don't lower it.
+        let due = (begin.elapsed().as_secs_f64() * rate) as u64;
+        while sent < due {
+            let topic = &options.topics[sent as usize % options.topics.len()];
+            let micros = started.elapsed().as_micros() as u64;
+            let message = format!("PUB:{}:{}", topic, payload(index, sent, micros, options.payload_bytes));
+            if socket.send(message.as_bytes()).await.is_err() {
+                send_errors.fetch_add(1, Ordering::Relaxed);
+            }
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+struct Received {
+    count: u64,
+    latencies_us: Vec<u64>,
+}
+
+// Subscribes to every topic and counts what comes back until the senders are done
+// and DRAIN_TIME has passed.
+async fn run_receiver(socket: &UdpSocket, options: &BenchOptions, started: Instant) -> Result<Received> {
+    let mut received = Received { count: 0, latencies_us: Vec::new() };
+    let mut buf = vec![0; 65536];
+    let end = started + SETTLE_TIME + options.duration + DRAIN_TIME;
+    loop {
+        let remaining = end.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(received);
+        }
+        let Ok(result) = timeout(remaining, socket.recv(&mut buf)).await else {
+            return Ok(received);
+        };
+        let len = result?;
+        let Ok(text) = std::str::from_utf8(&buf[..len]) else { continue };
+        let mut fields = text.split(' ');
+        let (Some(_sender), Some(_seq), Some(micros)) = (fields.next(), fields.next(), fields.next()) else { continue };
+        let Ok(sent_at) = micros.parse::<u64>() else { continue };
+        received.count += 1;
+        received.latencies_us.push((started.elapsed().as_micros() as u64).saturating_sub(sent_at));
+    }
+}
+
+async fn run(options: BenchOptions) -> Result<String> {
+    let options = Arc::new(options);
+    let receiver = bind_for(options.server).await?;
+    login(&receiver, &options.auth).await?;
+    sleep(SETTLE_TIME).await;
+    let before = server_counters(&receiver).await?;
+    for topic in &options.topics {
+        receiver.send(format!("SUB:{}", topic).as_bytes()).await?;
+    }
+    println!(
+        "Publishing {}/s to {} topic(s) on {} for {:.1}s from {} sender(s), {}-byte payloads...",
+        options.rate,
+        options.topics.len(),
+        options.server,
+        options.duration.as_secs_f64(),
+        options.senders,
+        options.payload_bytes
+    );
+
+    let started = Instant::now();
+    let send_errors = Arc::new(AtomicU64::new(0));
+    let senders: Vec<_> = (0..options.senders)
+        .map(|index| tokio::spawn(run_sender(index, options.clone(), started, send_errors.clone())))
+        .collect();
+    let mut received = run_receiver(&receiver, &options, started).await?;
+    let mut sent = 0;
+    for sender in senders {
+        sent += sender.await.context("Sender task failed")??;
+    }
+
+    for topic in &options.topics {
+        receiver.send(format!("UNSUB:{}", topic).as_bytes()).await?;
+    }
+    let after = server_counters(&receiver).await?;
+
+    // What else the server processed in between: SUB, UNSUB, the senders' AUTH and the
+    // second STATS (the first one was counted before it replied).
+    let overhead = options.topics.len() as u64 * 2 + 1 + if options.auth.is_some() { options.senders as u64 } else { 0 };
+    let processed = after.messages_processed.saturating_sub(before.messages_processed).saturating_sub(overhead);
+    let seconds = options.duration.as_secs_f64();
+    let percent = |part: u64| if sent == 0 { 0.0 } else { part as f64 * 100.0 / sent as f64 };
+    received.latencies_us.sort_unstable();
+    let latency = |quantile: f64| {
+        let index = ((received.latencies_us.len() as f64 - 1.0) * quantile).round() as usize;
+        received.latencies_us.get(index).map(|us| format!("{:.2}ms", *us as f64 / 1000.0)).unwrap_or_else(|| "-".to_string())
+    };
+
+    let mut report = String::new();
+    report.push_str(&format!("Sent:        {} publishes ({:.0}/s), {} send errors\n", sent, sent as f64 / seconds, send_errors.load(Ordering::Relaxed)));
+    report.push_str(&format!(
+        "Processed:   {} by the server, {} dropped before processing ({:.2}%)\n",
+        processed,
+        sent.saturating_sub(processed),
+        percent(sent.saturating_sub(processed))
+    ));
+    report.push_str(&format!(
+        "Delivered:   {} back to the subscriber ({:.0}/s), {} lost ({:.2}%)\n",
+        received.count,
+        received.count as f64 / seconds,
+        sent.saturating_sub(received.count),
+        percent(sent.saturating_sub(received.count))
+    ));
+    report.push_str(&format!("Latency:     p50 {}, p99 {}, max {}\n", latency(0.5), latency(0.99), latency(1.0)));
+    report.push_str(&format!("MIDI:        {} messages sent by the server", after.midi_messages_sent.saturating_sub(before.midi_messages_sent)));
+    Ok(report)
+}
@@ -0,0 +1,96 @@
+use dashmap::DashMap;
+use log::info;
+use std::time::Instant;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::config::ChannelExpiryConfig;
+use crate::server::{topic_matches, ServerContext};
+
+// Forgets channels nobody uses any more, so a long-running installation doesn't keep
+// the history and fanout stats of every topic it has ever seen. A channel is idle once
+// it had no publish and no subscriber for its TTL; then its history (the payloads HIST
+// replays) and its per-channel stats are dropped. A publish brings it back as new.
+pub struct ChannelExpiry {
+    default_ttl: Option<Duration>,
+    // (pattern, TTL) from [[channel_expiry.overrides]], first match wins. None = never.
+    overrides: Vec<(String, Option<Duration>)>,
+    check_interval: Duration,
+    // When each channel was last published to, or last seen with a subscriber
+    last_active: DashMap<String, Instant>,
+}
+
+fn ttl(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+impl ChannelExpiry {
+    pub fn new(config: &ChannelExpiryConfig) -> Self {
+        Self {
+            default_ttl: ttl(config.idle_ttl_secs),
+            overrides: config.overrides.iter().map(|o| (o.pattern.clone(), ttl(o.idle_ttl_secs))).collect(),
+            check_interval: Duration::from_secs(config.check_interval_secs.max(1)),
+            last_active: DashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.default_ttl.is_some() || self.overrides.iter().any(|(_, ttl)| ttl.is_some())
+    }
+
+    pub fn ttl_for(&self, channel: &str) -> Option<Duration> {
+        match self.overrides.iter().find(|(pattern, _)| topic_matches(pattern, channel)) {
+            Some((_, ttl)) => *ttl,
+            None => self.default_ttl,
+        }
+    }
+
+    // Runs for every publish; the channel name is only copied the first time.
+    pub fn touch(&self, channel: &str) {
+        if !self.enabled() {
+            return;
+        }
+        match self.last_active.get_mut(channel) {
+            Some(mut last_active) => *last_active = Instant::now(),
+            None => {
+                self.last_active.insert(channel.to_string(), Instant::now());
+            }
+        }
+    }
+
+    // Channels whose TTL ran out, removed from the tracking. Channels that still have
+    // subscribers count as active.
+    fn take_expired(&self, ctx: &ServerContext) -> Vec<(String, Duration)> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.last_active.retain(|channel, last_active| {
+            if ctx.subscribers.get(channel).is_some_and(|set| !set.is_empty()) {
+                *last_active = now;
+                return true;
+            }
+            match self.ttl_for(channel) {
+                Some(ttl) if now.duration_since(*last_active) >= ttl => {
+                    expired.push((channel.clone(), now.duration_since(*last_active)));
+                    false
+                }
+                _ => true,
+            }
+        });
+        expired
+    }
+}
+
+// Periodically drops the state of channels that have been idle for their TTL.
+pub async fn run_channel_expiry(ctx: ServerContext) {
+    let expiry = ctx.channel_expiry.clone();
+    info!("Expiring idle channels every {}s", expiry.check_interval.as_secs());
+    let mut ticker = interval(expiry.check_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        for (channel, idle) in expiry.take_expired(&ctx) {
+            ctx.history.forget(&channel);
+            ctx.stats.forget_channel(&channel);
+            info!(topic = channel.as_str(); "Channel '{}' expired after {}s without publishes or subscribers.", channel, idle.as_secs());
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr;
+
+use crate::server::ServerContext;
+
+// Counters for one client address.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientCounters {
+    pub published: u64,
+    // Publishes dropped by the per-client rate limit
+    pub rate_limited: u64,
+    // Messages dropped as duplicates by their sequence number
+    pub duplicates: u64,
+}
+
+// Per-client counters, to find the device that misbehaves. Kept after a client expires,
+// so a device that dropped off can still be looked at.
+#[derive(Default)]
+pub struct ClientStats {
+    counters: DashMap<SocketAddr, ClientCounters>,
+}
+
+impl ClientStats {
+    pub fn record_published(&self, client: SocketAddr) {
+        self.counters.entry(client).or_default().published += 1;
+    }
+
+    pub fn record_rate_limited(&self, client: SocketAddr) {
+        self.counters.entry(client).or_default().rate_limited += 1;
+    }
+
+    pub fn record_duplicate(&self, client: SocketAddr) {
+        self.counters.entry(client).or_default().duplicates += 1;
+    }
+
+    // Counters per client, sorted by address.
+    pub fn snapshot(&self) -> Vec<(SocketAddr, ClientCounters)> {
+        let mut snapshot: Vec<(SocketAddr, ClientCounters)> =
+            self.counters.iter().map(|entry| (*entry.key(), *entry.value())).collect();
+        snapshot.sort_by_key(|(addr, _)| *addr);
+        snapshot
+    }
+}
+
+// One client as shown by `STATS:clients` and `GET /admin/clients`.
+#[derive(Serialize)]
+pub struct ClientReport {
+    pub addr: String,
+    pub name: Option<String>,
+    pub user: Option<String>,
+    pub published: u64,
+    pub subscriptions: usize,
+    // None for clients that have expired or disconnected
+    pub last_seen_secs_ago: Option<f64>,
+    pub rate_limited: u64,
+    pub duplicates: u64,
+}
+
+// Every client that is connected or has counters, sorted by address.
+pub fn client_reports(ctx: &ServerContext) -> Vec<ClientReport> {
+    let counters: HashMap<SocketAddr, ClientCounters> = ctx.stats.clients().snapshot().into_iter().collect();
+    let mut subscriptions: HashMap<SocketAddr, usize> = HashMap::new();
+    for entry in ctx.subscribers.iter() {
+        for addr in entry.value() {
+            *subscriptions.entry(*addr).or_default() += 1;
+        }
+    }
+    let addrs: BTreeSet<SocketAddr> =
+        counters.keys().chain(subscriptions.keys()).copied().chain(ctx.clients.addresses()).collect();
+    addrs
+        .into_iter()
+        .map(|addr| {
+            let counters = counters.get(&addr).copied().unwrap_or_default();
+            ClientReport {
+                addr: addr.to_string(),
+                name: ctx.clients.name(&addr),
+                user: ctx.clients.user(&addr),
+                published: counters.published,
+                subscriptions: subscriptions.get(&addr).copied().unwrap_or(0),
+                last_seen_secs_ago: ctx.clients.last_seen(&addr).map(|seen| seen.elapsed().as_secs_f64()),
+                rate_limited: counters.rate_limited,
+                duplicates: counters.duplicates,
+            }
+        })
+        .collect()
+}
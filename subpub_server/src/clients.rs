@@ -0,0 +1,135 @@
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Most topic aliases one client can register, so a client can't grow the table forever
+const MAX_ALIASES_PER_CLIENT: usize = 1024;
+
+// Tracks when each client address was last heard from.
+// Any datagram from a client counts as a sign of life.
+#[derive(Default)]
+pub struct ClientRegistry {
+    last_seen: DashMap<SocketAddr, Instant>,
+    // User name each authenticated client logged in as
+    authenticated: DashMap<SocketAddr, String>,
+    // Names clients gave themselves with HELLO
+    names: DashMap<SocketAddr, String>,
+    // Last wills from HELLO, as (topic, payload)
+    wills: DashMap<SocketAddr, (String, String)>,
+    // Topic aliases from ALIAS, by number
+    aliases: DashMap<SocketAddr, HashMap<u16, Arc<str>>>,
+}
+
+impl ClientRegistry {
+    pub fn touch(&self, addr: SocketAddr) {
+        self.last_seen.insert(addr, Instant::now());
+    }
+
+    pub fn remove(&self, addr: &SocketAddr) {
+        self.last_seen.remove(addr);
+        self.authenticated.remove(addr);
+        self.names.remove(addr);
+        self.wills.remove(addr);
+        self.aliases.remove(addr);
+    }
+
+    pub fn set_name(&self, addr: SocketAddr, name: &str) {
+        self.names.insert(addr, name.to_string());
+    }
+
+    pub fn name(&self, addr: &SocketAddr) -> Option<String> {
+        self.names.get(addr).map(|name| name.value().clone())
+    }
+
+    // Replaces the client's will; None clears it.
+    pub fn set_will(&self, addr: SocketAddr, will: Option<(&str, &str)>) {
+        match will {
+            Some((topic, payload)) => {
+                self.wills.insert(addr, (topic.to_string(), payload.to_string()));
+            }
+            None => {
+                self.wills.remove(&addr);
+            }
+        }
+    }
+
+    // Registers or replaces alias `n`. False if the client already has the most aliases allowed.
+    pub fn set_alias(&self, addr: SocketAddr, n: u16, topic: &str) -> bool {
+        let mut aliases = self.aliases.entry(addr).or_default();
+        if aliases.len() >= MAX_ALIASES_PER_CLIENT && !aliases.contains_key(&n) {
+            return false;
+        }
+        aliases.insert(n, Arc::from(topic));
+        true
+    }
+
+    pub fn alias(&self, addr: &SocketAddr, n: u16) -> Option<Arc<str>> {
+        self.aliases.get(addr)?.get(&n).cloned()
+    }
+
+    pub fn take_will(&self, addr: &SocketAddr) -> Option<(String, String)> {
+        self.wills.remove(addr).map(|(_, will)| will)
+    }
+
+    // How a client shows up in log lines: "stage-left-ipad (192.168.0.12:50123)" once it
+    // said HELLO, the bare address before.
+    pub fn label(&self, addr: SocketAddr) -> String {
+        match self.names.get(&addr) {
+            Some(name) => format!("{} ({})", name.value(), addr),
+            None => addr.to_string(),
+        }
+    }
+
+    // Same as `label`, but only looked up when a log line actually gets written, which
+    // saves an allocation per datagram in the processing loop.
+    pub fn lazy_label(&self, addr: SocketAddr) -> ClientLabel<'_> {
+        ClientLabel { registry: self, addr }
+    }
+
+    pub fn set_authenticated(&self, addr: SocketAddr, user: &str) {
+        self.authenticated.insert(addr, user.to_string());
+    }
+
+    pub fn is_authenticated(&self, addr: &SocketAddr) -> bool {
+        self.authenticated.contains_key(addr)
+    }
+
+    pub fn user(&self, addr: &SocketAddr) -> Option<String> {
+        self.authenticated.get(addr).map(|user| user.value().clone())
+    }
+
+    pub fn last_seen(&self, addr: &SocketAddr) -> Option<Instant> {
+        self.last_seen.get(addr).map(|seen| *seen.value())
+    }
+
+    // Every client heard from that hasn't expired or disconnected
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.last_seen.iter().map(|entry| *entry.key()).collect()
+    }
+
+    // Clients that haven't been heard from within `ttl`.
+    pub fn expired(&self, ttl: Duration) -> Vec<SocketAddr> {
+        self.last_seen
+            .iter()
+            .filter(|entry| entry.value().elapsed() > ttl)
+            .map(|entry| *entry.key())
+            .collect()
+    }
+}
+
+pub struct ClientLabel<'a> {
+    registry: &'a ClientRegistry,
+    addr: SocketAddr,
+}
+
+impl fmt::Display for ClientLabel<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.registry.names.get(&self.addr) {
+            Some(name) => write!(f, "{} ({})", name.value(), self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use dashmap::DashSet;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use log::warn;
+use std::borrow::Cow;
+use std::io::Write;
+use std::net::SocketAddr;
+use subpub_client::protocol::{COMPRESSED_MAGIC, COMPRESSION_DEFLATE};
+
+use crate::config::CompressionConfig;
+
+// Compresses large payloads for subscribers that asked for it with
+// `SUB:<channel>:compress=deflate`, so big JSON scene descriptions stay under a safe
+// datagram size. A compressed datagram is COMPRESSED_MAGIC, the algorithm byte, then
+// the raw deflate stream; other subscribers keep getting the payload as is.
+pub struct PayloadCompressor {
+    enabled: bool,
+    threshold_bytes: usize,
+    level: Compression,
+    // (channel, subscriber) pairs that asked for compression
+    requested: DashSet<(String, SocketAddr)>,
+}
+
+impl PayloadCompressor {
+    pub fn new(config: &CompressionConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            threshold_bytes: config.threshold_bytes,
+            level: Compression::new(config.level.min(9)),
+            requested: DashSet::new(),
+        }
+    }
+
+    // Every SUB replaces the previous options for that channel, like max_hz.
+    pub fn set_requested(&self, channel: &str, addr: SocketAddr, requested: bool) {
+        if requested {
+            self.requested.insert((channel.to_string(), addr));
+        } else {
+            self.requested.remove(&(channel.to_string(), addr));
+        }
+    }
+
+    pub fn requested(&self, channel: &str, addr: SocketAddr) -> bool {
+        !self.requested.is_empty() && self.requested.contains(&(channel.to_string(), addr))
+    }
+
+    pub fn remove_client(&self, addr: &SocketAddr) {
+        self.requested.retain(|(_, requested_addr)| requested_addr != addr);
+    }
+
+    // The compressed datagram for `payload`, if it's over the threshold and compressing
+    // actually makes it smaller.
+    pub fn compress(&self, payload: &[u8]) -> Option<Vec<u8>> {
+        if !self.enabled || payload.len() < self.threshold_bytes {
+            return None;
+        }
+        let mut encoder = DeflateEncoder::new(vec![COMPRESSED_MAGIC, COMPRESSION_DEFLATE], self.level);
+        let compressed = match encoder.write_all(payload).and_then(|_| encoder.finish()) {
+            Ok(compressed) => compressed,
+            Err(e) => {
+                warn!("Failed to compress a {}-byte payload: {}", payload.len(), e);
+                return None;
+            }
+        };
+        (compressed.len() < payload.len()).then_some(compressed)
+    }
+
+    // What to send one subscriber, e.g. for a throttled (max_hz) delivery.
+    pub fn datagram_for<'a>(&self, channel: &str, addr: SocketAddr, payload: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.requested(channel, addr).then(|| self.compress(payload)).flatten() {
+            Some(compressed) => Cow::Owned(compressed),
+            None => Cow::Borrowed(payload),
+        }
+    }
+}
+
+// Parses SUB options like `compress=deflate`. Unknown options are ignored.
+pub fn compression_from_sub_options(options: &str) -> bool {
+    options
+        .split(',')
+        .filter_map(|option| option.split_once('='))
+        .any(|(name, value)| name.trim().eq_ignore_ascii_case("compress") && value.trim().eq_ignore_ascii_case("deflate"))
+}
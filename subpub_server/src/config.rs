@@ -0,0 +1,1119 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::normalizer::NormalizerConfig;
+use crate::paths;
+use crate::safe_mode::{SafeMode, SafeModeCause};
+
+// Lives in the config directory, see paths.rs
+pub const CONFIG_FILE_NAME: &str = "subpub_server.toml";
+
+// Settings for the "Show Mode" lockdown.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ShowModeConfig {
+    // Start the application with Show Mode already engaged.
+    #[serde(default)]
+    pub enabled: bool,
+    // Passphrase that allows a locked action to go through anyway.
+    // If not set, locked actions can only happen after Show Mode is turned off in the tray.
+    #[serde(default)]
+    pub override_passphrase: Option<String>,
+}
+
+// Overrides for where files are kept (see paths.rs). Empty means the platform default.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PathsConfig {
+    // Log, event log, subscriptions, message IDs and recordings; relative to the config directory
+    pub data_dir: String,
+    // Relative to the data directory
+    pub log_file: String,
+}
+
+// Retry-with-backoff settings for startup dependencies (MIDI service, network bind).
+// The delay doubles after every failed attempt, capped at `max_delay_ms`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct StartupRetryConfig {
+    pub max_attempts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for StartupRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 10,
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+        }
+    }
+}
+
+impl StartupRetryConfig {
+    // Delay to wait after the given failed attempt (1-based).
+    pub fn delay_after_attempt(&self, attempt: u32) -> Duration {
+        backoff_delay(self.initial_delay_ms, self.max_delay_ms, attempt)
+    }
+}
+
+// `initial_ms` doubled for every attempt after the first (1-based), capped at `max_ms`.
+fn backoff_delay(initial_ms: u64, max_ms: u64, attempt: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    Duration::from_millis(initial_ms.saturating_mul(factor).min(max_ms))
+}
+
+// Restarts of the processing loop when it fails while the server is running (see watchdog.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub auto_restart: bool,
+    // Restarts in a row before the server is stopped with an error
+    pub max_restarts: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self { auto_restart: true, max_restarts: 5, initial_delay_ms: 500, max_delay_ms: 10_000 }
+    }
+}
+
+impl WatchdogConfig {
+    // Delay before the given restart (1-based).
+    pub fn delay_before_restart(&self, restart: u32) -> Duration {
+        backoff_delay(self.initial_delay_ms, self.max_delay_ms, restart)
+    }
+}
+
+// What happens to MIDI work from publishes when the MIDI thread falls behind.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiOverflow {
+    // Make room by dropping the oldest queued action (the default)
+    #[default]
+    DropOldest,
+    // Keep the queue and drop the new action
+    DropNewest,
+    // Replace a queued action for the same CC or topic, else drop the oldest
+    Coalesce,
+}
+
+// Queue between the server and the MIDI thread (see midi_actor.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MidiQueueConfig {
+    // Queued publishes and LFO values before `overflow` kicks in
+    pub capacity: usize,
+    pub overflow: MidiOverflow,
+}
+
+impl Default for MidiQueueConfig {
+    fn default() -> Self {
+        Self { capacity: 1024, overflow: MidiOverflow::DropOldest }
+    }
+}
+
+// Small HTTP listener for Prometheus metrics (`/metrics`) and the admin API.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct HttpApiConfig {
+    pub enabled: bool,
+    pub bind_address: String,
+    // Required as an `X-Admin-Token` header on every endpoint but /metrics and webhooks.
+    // Empty = no check, and file uploads are refused.
+    pub admin_token: String,
+    pub webhooks: WebhookConfig,
+}
+
+impl Default for HttpApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_address: "127.0.0.1:9898".to_string(),
+            admin_token: String::new(),
+            webhooks: WebhookConfig::default(),
+        }
+    }
+}
+
+// `POST /webhook/{channel}` for cloud services, usually reached through a reverse proxy.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    // Required as `?token=` or an `X-Webhook-Token` header. Empty = no token needed.
+    pub token: String,
+    // Channels webhooks may publish to: exact names, or prefixes like "webhooks/*"
+    pub channels: Vec<String>,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self { token: String::new(), channels: vec!["*".to_string()] }
+    }
+}
+
+// Server -> subscriber keepalives and subscriber liveness.
+// Clients stay alive by sending anything (e.g. re-sending SUB) within the TTL.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    // 0 = automatic: a third of the TTL if one is set, otherwise no keepalives.
+    pub interval_ms: u64,
+    // 0 = subscribers never expire.
+    pub subscriber_ttl_ms: u64,
+}
+
+impl KeepaliveConfig {
+    pub fn subscriber_ttl(&self) -> Option<Duration> {
+        (self.subscriber_ttl_ms > 0).then(|| Duration::from_millis(self.subscriber_ttl_ms))
+    }
+
+    // Keepalives are sent well within the TTL so both directions stay alive.
+    pub fn effective_interval(&self) -> Option<Duration> {
+        if self.interval_ms > 0 {
+            Some(Duration::from_millis(self.interval_ms))
+        } else {
+            self.subscriber_ttl().map(|ttl| (ttl / 3).max(Duration::from_millis(100)))
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackendKind {
+    #[default]
+    StaticTokens,
+    Htpasswd,
+    HttpHook,
+}
+
+// Client authentication. Clients send `AUTH:<user>:<secret>` before SUB/PUB.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub backend: AuthBackendKind,
+    // static_tokens: accepted secrets
+    pub tokens: Vec<String>,
+    // htpasswd: path to a `user:{SHA}...` file
+    pub htpasswd_file: String,
+    // http_hook: endpoint that answers 2xx for valid credentials
+    pub hook_url: String,
+    pub hook_timeout_ms: u64,
+    // Failed AUTHs in a row before an address is locked out. 0 = no limit.
+    pub max_failures: u32,
+    pub lockout_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: AuthBackendKind::default(),
+            tokens: Vec::new(),
+            htpasswd_file: "subpub_users.htpasswd".to_string(),
+            hook_url: String::new(),
+            hook_timeout_ms: 2000,
+            max_failures: 5,
+            lockout_secs: 60,
+        }
+    }
+}
+
+// Per-client token bucket for publishes.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    // Sustained publishes per second per client. 0 = no limit.
+    pub publishes_per_sec: f64,
+    // Publishes allowed in a burst on top of that
+    pub burst: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self { publishes_per_sec: 0.0, burst: 20 }
+    }
+}
+
+// Limits for SCHEDULE, publishes held back to run later (see delayed_publish.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct DelayedPublishConfig {
+    // Scheduled publishes waiting at once, over all clients
+    pub max_pending: usize,
+    // How far ahead a publish can be scheduled
+    pub max_delay_secs: u64,
+}
+
+impl Default for DelayedPublishConfig {
+    fn default() -> Self {
+        Self { max_pending: 1000, max_delay_secs: 86400 }
+    }
+}
+
+// Duplicate suppression for clients that send sequence numbers.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SequenceConfig {
+    // How many of a client's latest sequence numbers are remembered
+    pub window: u64,
+}
+
+impl Default for SequenceConfig {
+    fn default() -> Self {
+        Self { window: 256 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum IpMode {
+    // The LAN IPv4 address (the default)
+    #[default]
+    Ipv4,
+    // All IPv6 addresses only
+    Ipv6,
+    // All IPv6 and IPv4 addresses on one socket
+    Dual,
+}
+
+// Which IP versions the main socket and discovery use.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub ip_mode: IpMode,
+}
+
+// Multicast discovery (`DISCOVER_SUBPUB_SERVER` pings).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    pub enabled: bool,
+    // IPv4 group and port, e.g. "239.0.0.100:50100"
+    pub group: String,
+    // IPv6 group and port, used when `[network] ip_mode` includes IPv6
+    pub group_v6: String,
+    // Also answer pings sent to the broadcast address, for networks that block multicast
+    pub broadcast: bool,
+    pub broadcast_port: u16,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            group: "239.0.0.100:50100".to_string(),
+            group_v6: "[ff02::7375:6270]:50100".to_string(),
+            broadcast: true,
+            broadcast_port: 50101,
+        }
+    }
+}
+
+// mDNS/DNS-SD advertising as `_subpub._udp.local`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MdnsConfig {
+    pub enabled: bool,
+    // Name shown in service browsers
+    pub instance_name: String,
+}
+
+impl Default for MdnsConfig {
+    fn default() -> Self {
+        Self { enabled: true, instance_name: "SubPub Server".to_string() }
+    }
+}
+
+// Receive buffer and reassembly of fragmented messages (see fragments.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct DatagramConfig {
+    // Receive buffer size in bytes; longer datagrams are truncated
+    pub buffer_size: usize,
+    // How long the fragments of one message may take to arrive
+    pub fragment_timeout_ms: u64,
+    // Largest message accepted after reassembly
+    pub max_message_bytes: usize,
+    // Sockets sharing the main port (SO_REUSEPORT), each with its own processing loop
+    pub receive_loops: usize,
+    // Accept protobuf Envelopes (proto/subpub.proto) next to text and binary frames
+    pub protobuf: bool,
+}
+
+// Upper bound for receive_loops; more loops than cores only adds contention.
+const MAX_RECEIVE_LOOPS: usize = 64;
+
+impl Default for DatagramConfig {
+    fn default() -> Self {
+        Self { buffer_size: 65507, fragment_timeout_ms: 2000, max_message_bytes: 1_048_576, receive_loops: 1, protobuf: false }
+    }
+}
+
+impl DatagramConfig {
+    // receive_loops, limited to 1..=MAX_RECEIVE_LOOPS
+    pub fn effective_receive_loops(&self) -> usize {
+        self.receive_loops.clamp(1, MAX_RECEIVE_LOOPS)
+    }
+}
+
+// Deflate for large payloads to subscribers that ask for it (see compression.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    // Payloads shorter than this are sent as they are
+    pub threshold_bytes: usize,
+    // 0 (fastest) to 9 (smallest)
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self { enabled: false, threshold_bytes: 1024, level: 6 }
+    }
+}
+
+// Encrypted transport for the main socket and discovery (XChaCha20-Poly1305).
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    // 32-byte key as 64 hex characters, shared with every client
+    pub key: String,
+}
+
+// Source IP filtering. Entries are addresses or CIDR ranges ("192.168.0.0/24").
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct IpFilterConfig {
+    // Only these may talk to the server. Empty = everyone not blocked.
+    pub allow: Vec<String>,
+    pub block: Vec<String>,
+}
+
+// One access rule. A client matches by AUTH user name or by source IP ("192.168.0.*").
+// Topic lists take exact names, prefixes like "guest/*", or "*".
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AclRule {
+    pub users: Vec<String>,
+    pub clients: Vec<String>,
+    pub publish: Vec<String>,
+    pub subscribe: Vec<String>,
+}
+
+// Which topics each client may publish to and subscribe to.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct AclConfig {
+    pub enabled: bool,
+    // Checked in order, the first matching rule applies
+    pub rules: Vec<AclRule>,
+    // For clients no rule matches
+    pub default_publish: Vec<String>,
+    pub default_subscribe: Vec<String>,
+}
+
+impl Default for AclConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rules: Vec::new(),
+            default_publish: vec!["*".to_string()],
+            default_subscribe: vec!["*".to_string()],
+        }
+    }
+}
+
+// HMAC-signed datagrams with a pre-shared key (`SIG:<ts>:<hmac>:<message>`).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SigningConfig {
+    pub enabled: bool,
+    pub key: String,
+    // How far a message's timestamp may be from the server clock
+    pub max_skew_secs: u64,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        Self { enabled: false, key: String::new(), max_skew_secs: 30 }
+    }
+}
+
+// Bridges channels to local programs through stdout/stdin or named pipes, as NDJSON lines.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct PipeBridgeConfig {
+    pub enabled: bool,
+    // "stdio" for stdout, or the path of a named pipe (mkfifo)
+    pub output: String,
+    // Channels to write: exact names, prefixes like "sensors/*", or "*" for all
+    pub channels: Vec<String>,
+    // Optional: "stdio" for stdin, or a named pipe to read publishes from
+    pub input: Option<String>,
+}
+
+impl Default for PipeBridgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            output: "stdio".to_string(),
+            channels: vec!["*".to_string()],
+            input: None,
+        }
+    }
+}
+
+// A link to another SubPub server, see federation.rs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct FederationConfig {
+    pub enabled: bool,
+    // host:port of the other server. Empty = the first other server found by discovery.
+    pub remote: String,
+    pub discovery_wait_ms: u64,
+    // Topics subscribed to there and published here
+    pub import: Vec<String>,
+    // Topics published here that are also published there (exact, or prefixes like "cues/*")
+    pub export: Vec<String>,
+    // Shown in the other server's client list
+    pub name: String,
+    // For a remote server with [auth] enabled
+    pub auth_user: String,
+    pub auth_secret: String,
+    pub reconnect_secs: u64,
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            remote: String::new(),
+            discovery_wait_ms: 2000,
+            import: Vec::new(),
+            export: Vec::new(),
+            name: "federation".to_string(),
+            auth_user: String::new(),
+            auth_secret: String::new(),
+            reconnect_secs: 5,
+        }
+    }
+}
+
+// An outgoing connection to a WebSocket relay that topics are bridged over, see websocket_bridge.rs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebSocketBridgeConfig {
+    pub enabled: bool,
+    // ws:// or wss://
+    pub url: String,
+    // Publishes here on these topics are sent to the relay (exact, or prefixes like "cues/*")
+    pub outgoing: Vec<String>,
+    // Messages from the relay on these topics are published here
+    pub incoming: Vec<String>,
+    // Reconnect delay, doubling after every failed attempt up to the max
+    pub reconnect_min_ms: u64,
+    pub reconnect_max_ms: u64,
+}
+
+impl Default for WebSocketBridgeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            reconnect_min_ms: 1000,
+            reconnect_max_ms: 60000,
+        }
+    }
+}
+
+// Two servers with the same config and mappings as a failover pair, see failover.rs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct FailoverConfig {
+    pub enabled: bool,
+    pub role: FailoverRole,
+    // host:port of the primary, for the standby. Empty = the first other server found by discovery.
+    pub primary: String,
+    pub discovery_wait_ms: u64,
+    // How often the primary publishes its heartbeat
+    pub heartbeat_ms: u64,
+    // How long the standby waits without a heartbeat before it takes over
+    pub timeout_ms: u64,
+    // For a primary with [auth] enabled
+    pub auth_user: String,
+    pub auth_secret: String,
+}
+
+impl Default for FailoverConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            role: FailoverRole::Primary,
+            primary: String::new(),
+            discovery_wait_ms: 2000,
+            heartbeat_ms: 500,
+            timeout_ms: 2000,
+            auth_user: String::new(),
+            auth_secret: String::new(),
+        }
+    }
+}
+
+impl FailoverConfig {
+    // A standby keeps the MIDI port, discovery and mDNS to itself until it takes over.
+    pub fn is_standby(&self) -> bool {
+        self.enabled && self.role == FailoverRole::Standby
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailoverRole {
+    Primary,
+    Standby,
+}
+
+// Publishes at set times or intervals, see scheduler.rs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    // For `cron` jobs: "local", "UTC" or a fixed offset like "+01:00"
+    pub timezone: String,
+    pub jobs: Vec<ScheduledPublish>,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self { timezone: "local".to_string(), jobs: Vec::new() }
+    }
+}
+
+// Publishes `payload` on `topic` whenever `cron` matches, or every `every_secs`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduledPublish {
+    // "minute hour day-of-month month day-of-week", e.g. "30 18 * * mon-fri"
+    pub cron: Option<String>,
+    pub every_secs: Option<u64>,
+    pub topic: String,
+    #[serde(default)]
+    pub payload: String,
+}
+
+// A global keyboard shortcut on the machine running the tray app, publishing `payload` on `topic`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct HotkeyConfig {
+    // e.g. "ctrl+shift+1" or "alt+F5"
+    pub keys: String,
+    pub topic: String,
+    #[serde(default)]
+    pub payload: String,
+}
+
+// subpub:// links that publish on this machine, see url_scheme.rs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct UrlHandlerConfig {
+    pub enabled: bool,
+    // Make this app the handler for subpub:// links at startup (Windows, Linux)
+    pub register: bool,
+    // Channels links may publish to: exact names, or prefixes like "cues/*"
+    pub channels: Vec<String>,
+}
+
+impl Default for UrlHandlerConfig {
+    fn default() -> Self {
+        Self { enabled: false, register: true, channels: vec!["*".to_string()] }
+    }
+}
+
+// A normalizer chain run on a channel's payloads before they go out to subscribers.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TransformConfig {
+    // Exact name, or a prefix like "sensors/*"
+    pub channel: String,
+    pub chain: Vec<NormalizerConfig>,
+}
+
+// Every MIDI message sent, republished on topics under `prefix` (see midi_mirror.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MidiMirrorConfig {
+    pub enabled: bool,
+    pub prefix: String,
+}
+
+impl Default for MidiMirrorConfig {
+    fn default() -> Self {
+        Self { enabled: false, prefix: "_midi/out".to_string() }
+    }
+}
+
+// Serial ports (e.g. Arduino-style microcontrollers on USB) whose lines are published, see serial_input.rs.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SerialInputConfig {
+    pub enabled: bool,
+    pub ports: Vec<SerialPortConfig>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SerialPortConfig {
+    // "/dev/ttyACM0", "/dev/cu.usbmodem1101", "COM3"
+    pub path: String,
+    #[serde(default = "default_serial_baud")]
+    pub baud: u32,
+    #[serde(default)]
+    pub framing: SerialFraming,
+    // Topic for `line` framing, and for `json` lines without a "channel". Empty = lines
+    // carry their own topic.
+    #[serde(default)]
+    pub topic: String,
+}
+
+fn default_serial_baud() -> u32 {
+    115200
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SerialFraming {
+    // Each line is a payload, or `<topic>:<payload>` without a configured topic
+    #[default]
+    Line,
+    // Each line is {"channel": ..., "payload": ...}, like pipe bridge input
+    Json,
+}
+
+// DMX levels sent as sACN (E1.31) multicast, set by publishes on the channels' topics (see sacn.rs).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SacnConfig {
+    pub enabled: bool,
+    // Shown by receivers and consoles
+    pub source_name: String,
+    // Changed universes go out at most this often
+    pub frame_ms: u64,
+    // Unchanged universes are sent again this often, so receivers don't time out
+    pub keepalive_ms: u64,
+    // Universe the sync packets go to after each frame. 0 = no sync packets.
+    pub sync_universe: u16,
+    pub universes: Vec<SacnUniverse>,
+    pub channels: Vec<SacnChannel>,
+}
+
+impl Default for SacnConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            source_name: "SubPub".to_string(),
+            frame_ms: 25,
+            keepalive_ms: 1000,
+            sync_universe: 0,
+            universes: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SacnUniverse {
+    // 1-63999
+    pub universe: u16,
+    // 0-200, receivers take the highest priority source of a universe
+    #[serde(default = "default_sacn_priority")]
+    pub priority: u8,
+}
+
+fn default_sacn_priority() -> u8 {
+    100
+}
+
+// A publish on `topic` sets the DMX slot `slot` (1-512) of `universe`. A number sets that
+// slot, an array of numbers sets it and the ones after it (e.g. [255, 128, 0] for RGB).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SacnChannel {
+    pub topic: String,
+    pub universe: u16,
+    pub slot: u16,
+}
+
+// A virtual MIDI input (e.g. fed by a DAW) whose messages are published back onto the bus.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MidiInputConfig {
+    pub enabled: bool,
+    // Name of the virtual port. On Windows, an existing port containing this name.
+    pub port_name: String,
+    pub routes: Vec<MidiInputRoute>,
+}
+
+impl Default for MidiInputConfig {
+    fn default() -> Self {
+        Self { enabled: false, port_name: "Zerver In".to_string(), routes: Vec::new() }
+    }
+}
+
+// Incoming messages matching `message` (and `channel`/`number` if set) are published on `topic`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MidiInputRoute {
+    pub message: MidiInputMessage,
+    // 0-15, any channel if missing
+    pub channel: Option<u8>,
+    // Controller or note number, any if missing
+    pub number: Option<u8>,
+    // `{ch}` and `{num}` are replaced by the message's channel and number
+    pub topic: String,
+    #[serde(default)]
+    pub payload: MidiInputPayload,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiInputMessage {
+    Cc,
+    // NoteOn and NoteOff; a NoteOff arrives with vel 0
+    Note,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MidiInputPayload {
+    // {"ch":0,"control_num":7,"value":100} / {"ch":0,"note":60,"vel":100}
+    #[default]
+    Json,
+    // Just the CC value or the note velocity
+    Value,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    // Human-readable lines (the default)
+    #[default]
+    Pattern,
+    // One JSON object per line, for log shippers
+    Json,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+}
+
+impl LoggingConfig {
+    // Logging is set up before the rest of the config is loaded, so this only reads the
+    // `[logging]` section. Problems are ignored here; the full load reports them.
+    pub fn peek() -> Self {
+        #[derive(Deserialize)]
+        struct LoggingOnly {
+            #[serde(default)]
+            logging: LoggingConfig,
+        }
+        fs::read_to_string(paths::config_file())
+            .ok()
+            .and_then(|text| toml::from_str::<LoggingOnly>(&text).ok())
+            .map(|config| config.logging)
+            .unwrap_or_default()
+    }
+}
+
+// Remembered message IDs, so retried publishes (`PUBID`) don't fire their MIDI twice.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MessageIdsConfig {
+    // How long an executed ID is remembered
+    pub remember_secs: u64,
+    // Upper bound on remembered IDs; the oldest are forgotten first
+    pub max_remembered: usize,
+    // Survives restarts through this file. Empty = memory only.
+    pub file: String,
+}
+
+impl Default for MessageIdsConfig {
+    fn default() -> Self {
+        Self {
+            remember_secs: 3600,
+            max_remembered: 10_000,
+            file: "subpub_message_ids.log".to_string(),
+        }
+    }
+}
+
+// Subscriptions saved to disk and restored on the next start, so clients keep receiving
+// after a restart mid-show without subscribing again.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct PersistSubscriptionsConfig {
+    pub enabled: bool,
+    pub file: String,
+    // How often changes are written; they are also written when the server stops.
+    pub snapshot_interval_ms: u64,
+}
+
+impl Default for PersistSubscriptionsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: "subpub_subscriptions.json".to_string(),
+            snapshot_interval_ms: 5000,
+        }
+    }
+}
+
+// Per-channel payload history, replayed to clients with `HIST:<channel>:<n>`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // Payloads kept per channel. 0 turns history off.
+    pub depth: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self { depth: 20 }
+    }
+}
+
+// Dropping channels that had no publish and no subscriber for a while, see channel_expiry.rs.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ChannelExpiryConfig {
+    // 0 = channels never expire (unless an override says otherwise)
+    pub idle_ttl_secs: u64,
+    pub check_interval_secs: u64,
+    pub overrides: Vec<ChannelTtlOverride>,
+}
+
+impl Default for ChannelExpiryConfig {
+    fn default() -> Self {
+        Self { idle_ttl_secs: 0, check_interval_secs: 60, overrides: Vec::new() }
+    }
+}
+
+// A different TTL for the channels matching `pattern` (`*` at the end matches any suffix).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelTtlOverride {
+    pub pattern: String,
+    // 0 = these channels never expire
+    pub idle_ttl_secs: u64,
+}
+
+// SQLite log of every publish and MIDI message, queryable via the admin API.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct EventLogConfig {
+    pub enabled: bool,
+    pub file: String,
+    // Rows older than this are deleted. 0 = keep everything.
+    pub retention_days: u64,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: "subpub_events.sqlite".to_string(),
+            retention_days: 0,
+        }
+    }
+}
+
+// Recordings made and replayed with the `_control/record` and `_control/replay` topics.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct SessionReplayConfig {
+    pub directory: String,
+}
+
+impl Default for SessionReplayConfig {
+    fn default() -> Self {
+        Self { directory: "recordings".to_string() }
+    }
+}
+
+// Desktop notifications for server start/stop, MIDI failures and mapping reload errors.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    pub enabled: bool,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+// In-memory buffer of the last pub/sub and MIDI events (tray "Dump Recent Events").
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct RecentEventsConfig {
+    // Number of events kept. 0 turns the buffer off.
+    pub capacity: usize,
+}
+
+impl Default for RecentEventsConfig {
+    fn default() -> Self {
+        Self { capacity: 500 }
+    }
+}
+
+// A message published internally when the server starts, as if a client had sent
+// `PUB:<topic>:<payload>`. Goes through mappings and sequence control topics.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct StartupPublish {
+    pub topic: String,
+    #[serde(default)]
+    pub payload: String,
+}
+
+// What happens on launch without anyone touching the tray.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct StartupConfig {
+    // Start the server right after launch instead of waiting for "Start Server".
+    pub auto_start_server: bool,
+    // Initialization sequence, sent in order every time the server starts.
+    pub publish: Vec<StartupPublish>,
+}
+
+// Top level server configuration, loaded from `subpub_server.toml`.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub paths: PathsConfig,
+    #[serde(default)]
+    pub show_mode: ShowModeConfig,
+    #[serde(default)]
+    pub startup_retry: StartupRetryConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub midi_queue: MidiQueueConfig,
+    #[serde(default)]
+    pub http_api: HttpApiConfig,
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+    #[serde(default)]
+    pub startup: StartupConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+    #[serde(default)]
+    pub signing: SigningConfig,
+    #[serde(default)]
+    pub acl: AclConfig,
+    #[serde(default)]
+    pub ip_filter: IpFilterConfig,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub delayed_publish: DelayedPublishConfig,
+    #[serde(default)]
+    pub datagrams: DatagramConfig,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    #[serde(default)]
+    pub sequence_numbers: SequenceConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
+    #[serde(default)]
+    pub mdns: MdnsConfig,
+    #[serde(default)]
+    pub pipe_bridge: PipeBridgeConfig,
+    #[serde(default)]
+    pub midi_input: MidiInputConfig,
+    #[serde(default)]
+    pub serial_input: SerialInputConfig,
+    #[serde(default)]
+    pub midi_mirror: MidiMirrorConfig,
+    #[serde(default)]
+    pub transforms: Vec<TransformConfig>,
+    #[serde(default)]
+    pub hotkeys: Vec<HotkeyConfig>,
+    #[serde(default)]
+    pub url_handler: UrlHandlerConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub federation: FederationConfig,
+    #[serde(default)]
+    pub failover: FailoverConfig,
+    #[serde(default)]
+    pub websocket_bridge: WebSocketBridgeConfig,
+    #[serde(default)]
+    pub sacn: SacnConfig,
+    #[serde(default)]
+    pub message_ids: MessageIdsConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub recent_events: RecentEventsConfig,
+    #[serde(default)]
+    pub persist_subscriptions: PersistSubscriptionsConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub channel_expiry: ChannelExpiryConfig,
+    #[serde(default)]
+    pub event_log: EventLogConfig,
+    #[serde(default)]
+    pub session_replay: SessionReplayConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+}
+
+impl ServerConfig {
+    // Loads the config file. If it can't be read, falls back to defaults and enters safe mode.
+    pub fn load(safe_mode: &SafeMode) -> Self {
+        let path = paths::config_file();
+        Self::load_from_file(&path).unwrap_or_else(|e| {
+            warn!("Failed to load server config from {:?}: {:?}. Using defaults.", path, e);
+            safe_mode.enter(SafeModeCause::Config, format!("{} is invalid: {:#}", path.display(), e));
+            ServerConfig::default()
+        })
+    }
+
+    fn load_from_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            warn!("Server config file not found at {:?}. Creating a default one.", path);
+            let default_config = ServerConfig::default();
+            let toml_string = toml::to_string_pretty(&default_config)?;
+            fs::write(path, toml_string)
+                .with_context(|| format!("Failed to write default server config to {:?}", path))?;
+            info!("Created default server config at {:?}", path);
+            return Ok(default_config);
+        }
+
+        let toml_str = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read server config from {:?}", path))?;
+        let config: ServerConfig = toml::from_str(&toml_str)
+            .with_context(|| format!("Failed to parse server config TOML from {:?}", path))?;
+        info!("Successfully loaded server config from {:?}", path);
+        Ok(config)
+    }
+}
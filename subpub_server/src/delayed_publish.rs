@@ -0,0 +1,137 @@
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use log::info;
+use serde::Deserialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::task::AbortHandle;
+use tokio::time::{sleep, Duration};
+
+use crate::config::DelayedPublishConfig;
+use crate::frames::WireFormat;
+use crate::message_ids;
+use crate::server::{handle_publish, ServerContext};
+
+// The JSON after `SCHEDULE:<channel>:`. Exactly one of delay_ms and at.
+#[derive(Deserialize)]
+struct ScheduleRequest {
+    delay_ms: Option<u64>,
+    at: Option<At>,
+    payload: String,
+    // Passed on like a PUBID id, so a retried SCHEDULE still publishes once
+    id: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum At {
+    UnixMs(i64),
+    // RFC 3339, e.g. "2026-10-15T21:30:00+02:00"
+    Timestamp(String),
+}
+
+pub enum ScheduleError {
+    Invalid(String),
+    TooMany,
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Invalid(reason) => write!(f, "{}", reason),
+            ScheduleError::TooMany => write!(f, "too many scheduled publishes"),
+        }
+    }
+}
+
+struct Pending {
+    owner: SocketAddr,
+    task: AbortHandle,
+}
+
+// Publishes clients asked to have run later (SCHEDULE), so a cue can be armed ahead of
+// time without a timer on the client. Each one waits in its own task and then goes
+// through `handle_publish` like a PUB from the client that scheduled it. Pending
+// publishes are kept in memory only; a restart drops them.
+pub struct DelayedPublishes {
+    max_pending: usize,
+    max_delay: Duration,
+    pending: DashMap<u64, Pending>,
+    next_id: AtomicU64,
+}
+
+impl DelayedPublishes {
+    pub fn new(config: &DelayedPublishConfig) -> Self {
+        Self {
+            max_pending: config.max_pending,
+            max_delay: Duration::from_secs(config.max_delay_secs),
+            pending: DashMap::new(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    // Parses the request and arms it. Returns the number UNSCHEDULE takes.
+    pub fn schedule(
+        &self,
+        ctx: &ServerContext,
+        owner: SocketAddr,
+        format: WireFormat,
+        channel: &str,
+        request: &str,
+    ) -> Result<u64, ScheduleError> {
+        let request: ScheduleRequest =
+            serde_json::from_str(request).map_err(|e| ScheduleError::Invalid(format!("expected {{\"delay_ms\"|\"at\": ..., \"payload\": ...}}: {}", e)))?;
+        let delay = match (request.delay_ms, &request.at) {
+            (Some(delay_ms), None) => Duration::from_millis(delay_ms),
+            (None, Some(at)) => delay_until(at)?,
+            _ => return Err(ScheduleError::Invalid("give exactly one of delay_ms and at".to_string())),
+        };
+        if delay > self.max_delay {
+            return Err(ScheduleError::Invalid(format!("more than max_delay_secs ({}s) ahead", self.max_delay.as_secs())));
+        }
+        if let Some(id) = &request.id
+            && !message_ids::is_valid_id(id)
+        {
+            return Err(ScheduleError::Invalid(format!("invalid id '{}'", id)));
+        }
+        if self.pending.len() >= self.max_pending {
+            return Err(ScheduleError::TooMany);
+        }
+
+        let n = self.next_id.fetch_add(1, Ordering::Relaxed);
+        // Holding the entry until the task is in it, so a task that's due at once can't
+        // remove itself before it was added.
+        let entry = self.pending.entry(n);
+        let (task_ctx, channel) = (ctx.clone(), channel.to_string());
+        let task = ctx.runtime_handle.spawn(async move {
+            sleep(delay).await;
+            task_ctx.delayed_publishes.pending.remove(&n);
+            info!(topic = channel.as_str(), client:% = owner; "Running scheduled publish {} on '{}': {}", n, channel, request.payload);
+            handle_publish(&task_ctx, Some(owner), &channel, &request.payload, request.id.as_deref(), Some(format)).await;
+        });
+        entry.insert(Pending { owner, task: task.abort_handle() });
+        Ok(n)
+    }
+
+    // Cancels publish `n` if `owner` scheduled it and it hasn't run yet.
+    pub fn cancel(&self, owner: SocketAddr, n: u64) -> bool {
+        match self.pending.remove_if(&n, |_, pending| pending.owner == owner) {
+            Some((_, pending)) => {
+                pending.task.abort();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn delay_until(at: &At) -> Result<Duration, ScheduleError> {
+    let at = match at {
+        At::UnixMs(ms) => DateTime::<Utc>::from_timestamp_millis(*ms),
+        At::Timestamp(text) => DateTime::parse_from_rfc3339(text).ok().map(|at| at.with_timezone(&Utc)),
+    }
+    .ok_or_else(|| ScheduleError::Invalid("at must be unix milliseconds or an RFC 3339 time".to_string()))?;
+    // A time that has just passed (clock skew, a slow network) publishes right away.
+    Ok((at - Utc::now()).to_std().unwrap_or(Duration::ZERO))
+}
@@ -0,0 +1,96 @@
+use dashmap::DashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+// Per-subscriber delivery rate limit for one channel, requested with `SUB:<channel>:max_hz=10`.
+struct RateLimit {
+    min_interval: Duration,
+    last_sent: Option<Instant>,
+    // Latest payload held back while the subscriber is throttled
+    pending: Option<String>,
+}
+
+// What the caller should do with a message for a throttled subscriber.
+pub enum Delivery {
+    // Send it right away.
+    SendNow,
+    // Held back; schedule a flush (`take_pending`) after this delay.
+    Deferred(Duration),
+    // Replaced an already held-back message whose flush is already scheduled.
+    Coalesced,
+}
+
+// Downsamples delivery per (channel, subscriber) so slow clients only get the latest value.
+#[derive(Default)]
+pub struct DeliveryLimiter {
+    limits: DashMap<(String, SocketAddr), RateLimit>,
+}
+
+impl DeliveryLimiter {
+    // Sets or clears (`None`) the limit for a subscriber on a channel.
+    pub fn set_limit(&self, channel: &str, addr: SocketAddr, max_hz: Option<f64>) {
+        let key = (channel.to_string(), addr);
+        match max_hz.filter(|hz| *hz > 0.0) {
+            Some(hz) => {
+                self.limits.insert(key, RateLimit {
+                    min_interval: Duration::from_secs_f64(1.0 / hz),
+                    last_sent: None,
+                    pending: None,
+                });
+            }
+            None => {
+                self.limits.remove(&key);
+            }
+        }
+    }
+
+    // The max_hz a subscriber asked for on a channel, if it is limited.
+    pub fn max_hz(&self, channel: &str, addr: SocketAddr) -> Option<f64> {
+        let limit = self.limits.get(&(channel.to_string(), addr))?;
+        Some(1.0 / limit.min_interval.as_secs_f64())
+    }
+
+    pub fn remove_client(&self, addr: &SocketAddr) {
+        self.limits.retain(|(_, limited_addr), _| limited_addr != addr);
+    }
+
+    pub fn offer(&self, channel: &str, addr: SocketAddr, payload: &str) -> Delivery {
+        let Some(mut limit) = self.limits.get_mut(&(channel.to_string(), addr)) else {
+            return Delivery::SendNow;
+        };
+        let now = Instant::now();
+        let next_allowed = limit.last_sent.map(|t| t + limit.min_interval);
+        match next_allowed {
+            Some(next) if next > now => {
+                let already_scheduled = limit.pending.is_some();
+                limit.pending = Some(payload.to_string());
+                if already_scheduled {
+                    Delivery::Coalesced
+                } else {
+                    Delivery::Deferred(next - now)
+                }
+            }
+            _ => {
+                limit.last_sent = Some(now);
+                Delivery::SendNow
+            }
+        }
+    }
+
+    // Takes the held-back payload for a scheduled flush and marks it as sent.
+    pub fn take_pending(&self, channel: &str, addr: SocketAddr) -> Option<String> {
+        let mut limit = self.limits.get_mut(&(channel.to_string(), addr))?;
+        let payload = limit.pending.take()?;
+        limit.last_sent = Some(Instant::now());
+        Some(payload)
+    }
+}
+
+// Parses SUB options like `max_hz=10`. Unknown options are ignored.
+pub fn max_hz_from_sub_options(options: &str) -> Option<f64> {
+    options
+        .split(',')
+        .filter_map(|option| option.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("max_hz"))
+        .and_then(|(_, value)| value.trim().parse::<f64>().ok())
+}
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use chrono::Local;
+use log::info;
+use std::fs;
+use std::path::PathBuf;
+use toml::{Table, Value};
+
+use crate::http_api::render_prometheus_metrics;
+use crate::midi_handler::mapping_file_path;
+use crate::paths;
+use crate::stats::Stats;
+
+// Only the tail of the log goes into the bundle, it can grow large on long installations.
+const MAX_LOG_BYTES: usize = 2 * 1024 * 1024;
+// Config keys whose values are replaced before export: keys made of words like these
+// ("auth_secret", "override_passphrase", "tokens").
+const SECRET_KEY_MARKERS: [&str; 7] = ["passphrase", "password", "secret", "secrets", "token", "tokens", "key"];
+const REDACTED: &str = "<redacted>";
+
+// Bundles logs, config, mappings, a stats snapshot and the recent events into a zip for bug reports.
+// Returns the path of the written file.
+pub fn export_diagnostics(stats: &Stats) -> Result<PathBuf> {
+    let mut zip = ZipWriter::default();
+
+    let log = fs::read(paths::log_file()).unwrap_or_default();
+    let log_tail = &log[log.len().saturating_sub(MAX_LOG_BYTES)..];
+    zip.add_file("subpub_server.log", scrub_text(&String::from_utf8_lossy(log_tail)).as_bytes());
+
+    if let Ok(config) = fs::read_to_string(paths::config_file()) {
+        zip.add_file("subpub_server.toml", redact_secrets(&config).as_bytes());
+    }
+    let mapping_path = mapping_file_path();
+    if let Ok(mappings) = fs::read_to_string(&mapping_path) {
+        let name = mapping_path.file_name().and_then(|n| n.to_str()).unwrap_or("midi_mapping.toml");
+        zip.add_file(name, redact_secrets(&mappings).as_bytes());
+    }
+    zip.add_file("stats.txt", render_prometheus_metrics(stats).as_bytes());
+    zip.add_file("recent_events.txt", stats.recent_events().render_text().as_bytes());
+
+    let path = paths::data_path(format!("subpub_diagnostics_{}.zip", Local::now().format("%Y%m%d_%H%M%S")));
+    fs::write(&path, zip.finish())
+        .with_context(|| format!("Failed to write diagnostics bundle to {:?}", path))?;
+    info!("Exported diagnostics bundle to {:?}", path);
+    Ok(path)
+}
+
+// Replaces the values of secret-looking keys wherever they are nested, arrays and
+// tables included, and scrubs every other string (URL queries, e.g. a relay token).
+// The file is re-serialized, so its comments are lost. A file that isn't valid TOML
+// (like the one that started safe mode) is redacted line by line instead.
+fn redact_secrets(toml_text: &str) -> String {
+    match toml::from_str::<Table>(toml_text) {
+        Ok(mut table) => {
+            redact_table(&mut table);
+            toml::to_string_pretty(&table).unwrap_or_else(|e| format!("# Failed to write the redacted file: {}\n", e))
+        }
+        Err(_) => redact_lines(toml_text),
+    }
+}
+
+fn redact_table(table: &mut Table) {
+    for (key, value) in table.iter_mut() {
+        if is_secret_key(key) {
+            redact_all(value);
+        } else {
+            redact_value(value);
+        }
+    }
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::String(text) => *text = scrub_text(text),
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        Value::Table(table) => redact_table(table),
+        _ => {}
+    }
+}
+
+// Every element of a secret, so the bundle still shows e.g. how many tokens there are.
+fn redact_all(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(redact_all),
+        Value::Table(table) => table.iter_mut().for_each(|(_, value)| redact_all(value)),
+        _ => *value = Value::String(REDACTED.to_string()),
+    }
+}
+
+// The fallback for unparsable files: `key = value` lines with a secret key lose their
+// value, along with the rest of a multi-line array, and other lines are scrubbed.
+fn redact_lines(toml_text: &str) -> String {
+    let mut in_secret_array = false;
+    let mut lines = Vec::new();
+    for line in toml_text.lines() {
+        if in_secret_array {
+            in_secret_array = !line.contains(']');
+            continue;
+        }
+        let trimmed = line.trim_start();
+        match trimmed.split_once('=') {
+            Some((key, value)) if !trimmed.starts_with('#') && is_secret_key(key) => {
+                let value = value.trim();
+                in_secret_array = value.starts_with('[') && !value.contains(']');
+                lines.push(format!("{}= \"{}\"", &line[..line.len() - trimmed.len() + key.len()], REDACTED));
+            }
+            _ => lines.push(scrub_text(line)),
+        }
+    }
+    lines.join("\n")
+}
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.trim().trim_matches(|c: char| !c.is_alphanumeric() && c != '_').to_lowercase();
+    key.split(['_', '-', '.']).any(|word| SECRET_KEY_MARKERS.contains(&word))
+}
+
+// Scrubs free text (the log tail, string values) of the secrets it may hold, word by
+// word: AUTH messages logged by older versions, URL passwords and query values, and
+// `token=...` style pairs.
+fn scrub_text(text: &str) -> String {
+    text.split_inclusive(char::is_whitespace)
+        .map(|piece| {
+            // Quotes and brackets around the word stay as they are.
+            let word = piece.trim_end().trim_end_matches(['"', '\'', ',', ';', ')', ']', '}', '>']);
+            scrub_word(word) + &piece[word.len()..]
+        })
+        .collect()
+}
+
+fn scrub_word(word: &str) -> String {
+    if let Some(start) = word.to_ascii_uppercase().find("AUTH:")
+        && let Some(user_len) = word[start + 5..].find(':')
+    {
+        return format!("{}{}", &word[..start + 5 + user_len + 1], REDACTED);
+    }
+    if let Some(scheme_end) = word.find("://") {
+        return scrub_url(word, scheme_end + 3);
+    }
+    match word.split_once('?') {
+        Some((path, query)) => format!("{}?{}", path, scrub_query(query, false)),
+        None => scrub_query(word, false),
+    }
+}
+
+// A URL without the password of its user info and without any query values.
+fn scrub_url(url: &str, authority_start: usize) -> String {
+    let (address, query) = url.split_once('?').unwrap_or((url, ""));
+    let authority_end = address[authority_start..].find('/').map_or(address.len(), |end| authority_start + end);
+    let mut scrubbed = match address[authority_start..authority_end].rsplit_once('@') {
+        Some((user_info, host)) if user_info.contains(':') => {
+            let user = user_info.split(':').next().unwrap_or_default();
+            format!("{}{}:{}@{}{}", &address[..authority_start], user, REDACTED, host, &address[authority_end..])
+        }
+        _ => address.to_string(),
+    };
+    if url.contains('?') {
+        scrubbed.push('?');
+        scrubbed.push_str(&scrub_query(query, true));
+    }
+    scrubbed
+}
+
+// `a=1&token=2` pairs; `all` redacts every value, otherwise only those of secret keys.
+fn scrub_query(query: &str, all: bool) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if all || is_secret_key(key) => format!("{}={}", key, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// Minimal zip writer (stored entries, no compression). Enough for a handful of text files.
+#[derive(Default)]
+struct ZipWriter {
+    data: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    fn add_file(&mut self, name: &str, contents: &[u8]) {
+        let crc = crc32fast::hash(contents);
+        let size = contents.len() as u32;
+        let offset = self.data.len() as u32;
+        let name_bytes = name.as_bytes();
+
+        // Local file header
+        self.data.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        self.data.extend_from_slice(&20u16.to_le_bytes()); // Version needed
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // Flags
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // Method: stored
+        self.data.extend_from_slice(&0u32.to_le_bytes()); // Mod time/date
+        self.data.extend_from_slice(&crc.to_le_bytes());
+        self.data.extend_from_slice(&size.to_le_bytes()); // Compressed size
+        self.data.extend_from_slice(&size.to_le_bytes()); // Uncompressed size
+        self.data.extend_from_slice(&(name_bytes.len() as u16).to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // Extra field length
+        self.data.extend_from_slice(name_bytes);
+        self.data.extend_from_slice(contents);
+
+        // Central directory entry
+        let cd = &mut self.central_directory;
+        cd.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        cd.extend_from_slice(&20u16.to_le_bytes()); // Version made by
+        cd.extend_from_slice(&20u16.to_le_bytes()); // Version needed
+        cd.extend_from_slice(&0u16.to_le_bytes()); // Flags
+        cd.extend_from_slice(&0u16.to_le_bytes()); // Method: stored
+        cd.extend_from_slice(&0u32.to_le_bytes()); // Mod time/date
+        cd.extend_from_slice(&crc.to_le_bytes());
+        cd.extend_from_slice(&size.to_le_bytes());
+        cd.extend_from_slice(&size.to_le_bytes());
+        cd.extend_from_slice(&(name_bytes.len() as u16).to_le_bytes());
+        cd.extend_from_slice(&0u16.to_le_bytes()); // Extra field length
+        cd.extend_from_slice(&0u16.to_le_bytes()); // Comment length
+        cd.extend_from_slice(&0u16.to_le_bytes()); // Disk number
+        cd.extend_from_slice(&0u16.to_le_bytes()); // Internal attributes
+        cd.extend_from_slice(&0u32.to_le_bytes()); // External attributes
+        cd.extend_from_slice(&offset.to_le_bytes());
+        cd.extend_from_slice(name_bytes);
+
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let cd_offset = self.data.len() as u32;
+        let cd_size = self.central_directory.len() as u32;
+        self.data.append(&mut self.central_directory);
+
+        // End of central directory record
+        self.data.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // Disk number
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // Disk with central directory
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&cd_size.to_le_bytes());
+        self.data.extend_from_slice(&cd_offset.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes()); // Comment length
+        self.data
+    }
+}
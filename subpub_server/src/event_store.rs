@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{error, info, warn};
+use rusqlite::{params, Connection, OpenFlags};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::config::EventLogConfig;
+use crate::paths;
+use crate::recent_events::describe_midi;
+
+// Writes are batched into one transaction, up to this many rows at a time.
+const MAX_BATCH: usize = 500;
+// How often rows older than `retention_days` are deleted
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+// Largest page the admin API hands out
+const MAX_QUERY_LIMIT: usize = 10_000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY,
+        ts REAL NOT NULL,          -- unix seconds
+        kind TEXT NOT NULL,        -- 'pub' or 'midi'
+        topic TEXT,                -- pub only
+        payload TEXT,              -- the payload, or the decoded MIDI message
+        source TEXT,               -- publishing client address, if any
+        message_id TEXT,           -- pub only
+        midi BLOB                  -- raw MIDI bytes
+    );
+    CREATE INDEX IF NOT EXISTS events_ts ON events (ts);
+    CREATE INDEX IF NOT EXISTS events_topic_ts ON events (topic, ts);
+";
+
+enum NewEvent {
+    Publish { ts: f64, topic: String, payload: String, source: Option<SocketAddr>, message_id: String },
+    Midi { ts: f64, bytes: Vec<u8> },
+}
+
+// One row of the event log, as returned by `query`.
+#[derive(Debug, Clone)]
+pub struct StoredEvent {
+    pub ts: f64,
+    pub kind: String,
+    pub topic: Option<String>,
+    pub payload: Option<String>,
+    pub source: Option<String>,
+    pub message_id: Option<String>,
+}
+
+// Filter for `query`. `topic` takes an exact name or a prefix like "sensors/*".
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    pub kind: Option<String>,
+    pub topic: Option<String>,
+    pub since: Option<f64>,
+    pub until: Option<f64>,
+    pub limit: usize,
+}
+
+// Every publish and every MIDI message sent, kept in an SQLite file for analysis after
+// a show. Writes go through a background thread so the MIDI path never waits on disk.
+pub struct EventStore {
+    path: PathBuf,
+    tx: Sender<NewEvent>,
+}
+
+fn now_unix() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+impl EventStore {
+    // None if the event log is disabled or the database can't be opened.
+    pub fn open(config: &EventLogConfig) -> Option<Arc<Self>> {
+        if !config.enabled {
+            return None;
+        }
+        let path = paths::data_path(&config.file);
+        let conn = match Connection::open(&path).and_then(|conn| conn.execute_batch(SCHEMA).map(|_| conn)) {
+            Ok(conn) => conn,
+            Err(e) => {
+                error!("Failed to open event log database {:?}: {}. Event logging is off.", path, e);
+                return None;
+            }
+        };
+        // WAL lets the admin API read while the writer thread is writing.
+        if let Err(e) = conn.query_row("PRAGMA journal_mode = WAL", params![], |row| row.get::<_, String>(0)) {
+            warn!("Failed to switch the event log to WAL mode: {}", e);
+        }
+        let (tx, rx) = unbounded();
+        let retention_days = config.retention_days;
+        let spawned = thread::Builder::new()
+            .name("event-log-writer".to_string())
+            .spawn(move || run_writer(conn, rx, retention_days));
+        if let Err(e) = spawned {
+            error!("Failed to start event log writer: {}", e);
+            return None;
+        }
+        info!("Recording publishes and MIDI messages to {:?}", path);
+        Some(Arc::new(Self { path, tx }))
+    }
+
+    pub fn record_publish(&self, topic: &str, payload: &str, source: Option<SocketAddr>, message_id: &str) {
+        let _ = self.tx.send(NewEvent::Publish {
+            ts: now_unix(),
+            topic: topic.to_string(),
+            payload: payload.to_string(),
+            source,
+            message_id: message_id.to_string(),
+        });
+    }
+
+    pub fn record_midi(&self, message: &[u8]) {
+        let _ = self.tx.send(NewEvent::Midi { ts: now_unix(), bytes: message.to_vec() });
+    }
+
+    // The latest `limit` matching events, oldest first. Uses its own read-only
+    // connection, so it is safe to call (from a blocking task) while writes go on.
+    pub fn query(&self, filter: &EventQuery) -> Result<Vec<StoredEvent>> {
+        let conn = Connection::open_with_flags(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open event log database {:?}", self.path))?;
+        let (exact_topic, topic_prefix) = match filter.topic.as_deref() {
+            Some(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => (None, Some(prefix.to_string())),
+                None => (Some(pattern.to_string()), None),
+            },
+            None => (None, None),
+        };
+        let limit = filter.limit.min(MAX_QUERY_LIMIT) as i64;
+        let mut statement = conn.prepare(
+            "SELECT ts, kind, topic, payload, source, message_id FROM events
+             WHERE (?1 IS NULL OR kind = ?1)
+               AND (?2 IS NULL OR topic = ?2)
+               AND (?3 IS NULL OR substr(topic, 1, length(?3)) = ?3)
+               AND (?4 IS NULL OR ts >= ?4)
+               AND (?5 IS NULL OR ts <= ?5)
+             ORDER BY id DESC LIMIT ?6",
+        )?;
+        let rows = statement.query_map(
+            params![filter.kind, exact_topic, topic_prefix, filter.since, filter.until, limit],
+            |row| {
+                Ok(StoredEvent {
+                    ts: row.get(0)?,
+                    kind: row.get(1)?,
+                    topic: row.get(2)?,
+                    payload: row.get(3)?,
+                    source: row.get(4)?,
+                    message_id: row.get(5)?,
+                })
+            },
+        )?;
+        let mut events = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        events.reverse();
+        Ok(events)
+    }
+}
+
+// Drains the queue into the database, one transaction per batch.
+fn run_writer(mut conn: Connection, rx: Receiver<NewEvent>, retention_days: u64) {
+    let mut last_prune: Option<Instant> = None;
+    while let Ok(first) = rx.recv() {
+        let mut batch = vec![first];
+        batch.extend(rx.try_iter().take(MAX_BATCH - 1));
+        if let Err(e) = write_batch(&mut conn, &batch) {
+            error!("Failed to write {} event(s) to the event log: {}", batch.len(), e);
+        }
+
+        if retention_days > 0 && last_prune.is_none_or(|t| t.elapsed() >= PRUNE_INTERVAL) {
+            last_prune = Some(Instant::now());
+            let cutoff = now_unix() - (retention_days * 86_400) as f64;
+            match conn.execute("DELETE FROM events WHERE ts < ?1", params![cutoff]) {
+                Ok(0) => {}
+                Ok(pruned) => info!("Pruned {} event log row(s) older than {} days", pruned, retention_days),
+                Err(e) => warn!("Failed to prune the event log: {}", e),
+            }
+        }
+    }
+}
+
+fn write_batch(conn: &mut Connection, batch: &[NewEvent]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    for event in batch {
+        match event {
+            NewEvent::Publish { ts, topic, payload, source, message_id } => {
+                tx.execute(
+                    "INSERT INTO events (ts, kind, topic, payload, source, message_id) VALUES (?1, 'pub', ?2, ?3, ?4, ?5)",
+                    params![ts, topic, payload, source.map(|addr| addr.to_string()), message_id],
+                )?;
+            }
+            NewEvent::Midi { ts, bytes } => {
+                tx.execute(
+                    "INSERT INTO events (ts, kind, payload, midi) VALUES (?1, 'midi', ?2, ?3)",
+                    params![ts, describe_midi(bytes), bytes],
+                )?;
+            }
+        }
+    }
+    tx.commit()
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use subpub_client::{discover_all, Client};
+use tokio::net::lookup_host;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, timeout, Duration, MissedTickBehavior};
+
+use crate::config::{FailoverConfig, FailoverRole, MdnsConfig, ServerConfig};
+use crate::mdns::MdnsAdvertiser;
+use crate::network;
+use crate::server::ServerContext;
+use crate::sys_events::{SysEvents, SYS_FAILOVER_HEARTBEAT, SYS_FAILOVER_STATUS};
+
+// Two servers with the same config and mappings, for installations that must not go
+// silent: the primary publishes a heartbeat on $SYS/failover/heartbeat, the standby
+// subscribes to it. While the primary is alive the standby keeps the virtual MIDI port
+// closed and doesn't answer discovery or advertise over mDNS, so clients and the DAW only
+// ever see one server. When the heartbeat stops the standby takes all three over, and
+// hands them back once the primary is heard again.
+pub struct Failover {
+    active: AtomicBool,
+    mdns_config: MdnsConfig,
+    own_addr: SocketAddr,
+    mdns: Mutex<Option<MdnsAdvertiser>>,
+}
+
+impl Failover {
+    // Starts mDNS right away, unless this server is a standby.
+    pub fn new(config: &FailoverConfig, mdns_config: &MdnsConfig, own_addr: SocketAddr) -> Arc<Self> {
+        let active = !config.is_standby();
+        let mdns = if active { MdnsAdvertiser::start(mdns_config, own_addr) } else { None };
+        Arc::new(Self {
+            active: AtomicBool::new(active),
+            mdns_config: mdns_config.clone(),
+            own_addr,
+            mdns: Mutex::new(mdns),
+        })
+    }
+
+    // False only on a standby whose primary is alive.
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    fn take_over(&self, ctx: &ServerContext, sys_events: &SysEvents) {
+        if self.active.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        ctx.midi.execute(|handler| handler.set_output_suspended(false));
+        *self.mdns.lock().unwrap() = MdnsAdvertiser::start(&self.mdns_config, self.own_addr);
+        sys_events.emit(SYS_FAILOVER_STATUS, "active");
+    }
+
+    fn stand_by(&self, ctx: &ServerContext, sys_events: &SysEvents) {
+        if !self.active.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        ctx.midi.execute(|handler| handler.set_output_suspended(true));
+        self.stop_mdns();
+        sys_events.emit(SYS_FAILOVER_STATUS, "standby");
+    }
+
+    // Withdraws the mDNS advertisement, if this server has one. Also called on shutdown.
+    pub fn stop_mdns(&self) {
+        if let Some(mdns) = self.mdns.lock().unwrap().take() {
+            mdns.stop();
+        }
+    }
+}
+
+// The standby watches the primary with subpub_client, which only speaks the plain
+// protocol. With [signing] or [encryption] the primary would drop its probes and the
+// standby would take over while the primary is still running, so refuse to start.
+pub fn check_config(config: &ServerConfig) -> Result<()> {
+    if config.failover.enabled && (config.signing.enabled || config.encryption.enabled) {
+        bail!("[failover] can't be used together with [signing] or [encryption]: the standby's heartbeat subscription is sent unsigned and unencrypted");
+    }
+    Ok(())
+}
+
+// The primary's heartbeat, or the standby's watch on it.
+pub fn spawn(
+    config: &FailoverConfig,
+    failover: Arc<Failover>,
+    ctx: ServerContext,
+    sys_events: SysEvents,
+) -> Option<JoinHandle<()>> {
+    if !config.enabled {
+        return None;
+    }
+    let config = config.clone();
+    let runtime_handle = ctx.runtime_handle.clone();
+    let task = match config.role {
+        FailoverRole::Primary => {
+            info!("Failover primary: publishing a heartbeat every {}ms.", config.heartbeat_ms);
+            sys_events.emit(SYS_FAILOVER_STATUS, "active");
+            runtime_handle.spawn(async move {
+                let mut heartbeat = interval(Duration::from_millis(config.heartbeat_ms.max(1)));
+                heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                loop {
+                    heartbeat.tick().await;
+                    sys_events.emit(SYS_FAILOVER_HEARTBEAT, "alive");
+                }
+            })
+        }
+        FailoverRole::Standby => {
+            info!("Failover standby: taking over if the primary is silent for {}ms.", config.timeout_ms);
+            sys_events.emit(SYS_FAILOVER_STATUS, "standby");
+            runtime_handle.spawn(async move {
+                let retry = Duration::from_millis(config.timeout_ms.max(1));
+                loop {
+                    if let Err(e) = watch_primary(&config, &failover, &ctx, &sys_events).await
+                        && !failover.is_active()
+                    {
+                        warn!("Failover: {:#}. Taking over from the primary.", e);
+                        failover.take_over(&ctx, &sys_events);
+                    }
+                    sleep(retry).await;
+                }
+            })
+        }
+    };
+    Some(task)
+}
+
+// Follows the primary's heartbeat until the primary stops answering. Every heartbeat
+// hands the roles back to it, in case this server had taken over.
+async fn watch_primary(config: &FailoverConfig, failover: &Failover, ctx: &ServerContext, sys_events: &SysEvents) -> Result<()> {
+    let primary = primary_address(config, failover.own_addr).await?;
+    let client = Client::connect(primary).await?;
+    if !config.auth_user.is_empty() {
+        client.login(&config.auth_user, &config.auth_secret).await?;
+    }
+    client.hello("failover-standby").await?;
+    let mut subscription = client.subscribe(SYS_FAILOVER_HEARTBEAT).await?;
+    let silence = Duration::from_millis(config.timeout_ms.max(1));
+    loop {
+        match timeout(silence, subscription.recv()).await {
+            Ok(Some(_)) => failover.stand_by(ctx, sys_events),
+            Ok(None) => return Err(anyhow!("{} refused the heartbeat subscription", primary)),
+            Err(_) => {
+                // A primary that restarted has forgotten the subscription, but still answers.
+                client.ping().await.with_context(|| format!("The primary at {} went silent", primary))?;
+                subscription = client.subscribe(SYS_FAILOVER_HEARTBEAT).await?;
+            }
+        }
+    }
+}
+
+// The configured primary, or the first other server that answers discovery.
+async fn primary_address(config: &FailoverConfig, own_addr: SocketAddr) -> Result<SocketAddr> {
+    if !config.primary.is_empty() {
+        return lookup_host(&config.primary)
+            .await
+            .with_context(|| format!("Failed to resolve '{}'", config.primary))?
+            .next()
+            .ok_or_else(|| anyhow!("'{}' has no address", config.primary));
+    }
+    let servers = discover_all(Duration::from_millis(config.discovery_wait_ms)).await?;
+    servers
+        .into_iter()
+        .find(|server| *server != network::advertised_address(own_addr, *server))
+        .ok_or_else(|| anyhow!("No primary answered discovery"))
+}
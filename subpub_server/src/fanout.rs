@@ -0,0 +1,78 @@
+use std::io;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+// Sends a batch of datagrams, e.g. one publish to every subscriber of a channel, without
+// awaiting a separate send per subscriber. Linux hands the whole batch to the kernel in
+// one sendmmsg call (up to MAX_BATCH at a time); elsewhere the sends run concurrently.
+// Returns the targets that failed, with their error. The caller has already mapped
+// targets to the socket's address family.
+#[cfg(target_os = "linux")]
+pub async fn send_batch(socket: &UdpSocket, datagrams: &[(&[u8], SocketAddr)]) -> Vec<(SocketAddr, io::Error)> {
+    use socket2::SockAddr;
+    use std::os::fd::AsRawFd;
+    use tokio::io::Interest;
+
+    let addrs: Vec<SockAddr> = datagrams.iter().map(|(_, target)| SockAddr::from(*target)).collect();
+    let mut failures = Vec::new();
+    let mut next = 0;
+    while next < datagrams.len() {
+        let end = (next + MAX_BATCH).min(datagrams.len());
+        // WouldBlock is retried by async_io once the socket is writable again.
+        let result = socket
+            .async_io(Interest::WRITABLE, || sendmmsg(socket.as_raw_fd(), &datagrams[next..end], &addrs[next..end]))
+            .await;
+        match result {
+            Ok(sent) => next += sent.max(1),
+            // sendmmsg only fails for the first datagram of a batch; skip it and go on.
+            Err(e) => {
+                failures.push((datagrams[next].1, e));
+                next += 1;
+            }
+        }
+    }
+    failures
+}
+
+// Most datagrams one sendmmsg call takes (UIO_MAXIOV).
+#[cfg(target_os = "linux")]
+const MAX_BATCH: usize = 1024;
+
+// Returns how many datagrams from the front of `datagrams` were sent.
+#[cfg(target_os = "linux")]
+fn sendmmsg(fd: std::os::fd::RawFd, datagrams: &[(&[u8], SocketAddr)], addrs: &[socket2::SockAddr]) -> io::Result<usize> {
+    let mut iovecs: Vec<libc::iovec> = datagrams
+        .iter()
+        .map(|(data, _)| libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() })
+        .collect();
+    let mut headers: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(addrs)
+        .map(|(iovec, addr)| {
+            // SAFETY: msghdr is plain data; all-zero is a valid empty header.
+            let mut header: libc::msghdr = unsafe { std::mem::zeroed() };
+            header.msg_name = addr.as_ptr() as *mut libc::c_void;
+            header.msg_namelen = addr.len();
+            header.msg_iov = iovec;
+            header.msg_iovlen = 1;
+            libc::mmsghdr { msg_hdr: header, msg_len: 0 }
+        })
+        .collect();
+    // SAFETY: every header points into `iovecs`, `addrs` and `datagrams`, which outlive the call.
+    let sent = unsafe { libc::sendmmsg(fd, headers.as_mut_ptr(), headers.len() as libc::c_uint, 0) };
+    if sent < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(sent as usize)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn send_batch(socket: &UdpSocket, datagrams: &[(&[u8], SocketAddr)]) -> Vec<(SocketAddr, io::Error)> {
+    let sends = datagrams.iter().map(|(data, target)| async move { (*target, socket.send_to(data, *target).await) });
+    futures_util::future::join_all(sends)
+        .await
+        .into_iter()
+        .filter_map(|(target, result)| result.err().map(|e| (target, e)))
+        .collect()
+}
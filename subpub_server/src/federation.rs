@@ -0,0 +1,166 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error, info, warn};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, RwLock};
+use subpub_client::{discover_all, Client};
+use tokio::net::lookup_host;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+
+use crate::config::{FederationConfig, ServerConfig};
+use crate::network;
+use crate::server::{handle_publish, topic_matches, ServerContext};
+
+// How often the link checks that the remote server still answers
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+// Links this server with another one, e.g. the stage rig with the FOH rig on another
+// subnet: `import` topics are subscribed to on the remote server and published here,
+// local publishes on `export` topics are published there. Publishes that came from the
+// remote server's host are never exported back, so two servers that federate with each
+// other (or import and export the same topic) don't bounce messages forever.
+pub struct Federation {
+    export: Vec<String>,
+    outgoing: Option<tokio_mpsc::UnboundedSender<(String, String)>>,
+    // The host of the connected remote server, None while disconnected
+    remote_ip: RwLock<Option<IpAddr>>,
+}
+
+impl Federation {
+    // The receiver goes to `spawn`, along with the context holding the Federation.
+    pub fn new(config: &FederationConfig) -> (Arc<Self>, Option<tokio_mpsc::UnboundedReceiver<(String, String)>>) {
+        let (outgoing, rx) = if config.enabled {
+            let (tx, rx) = tokio_mpsc::unbounded_channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let federation = Self { export: config.export.clone(), outgoing, remote_ip: RwLock::new(None) };
+        (Arc::new(federation), rx)
+    }
+
+    // Called for every local publish; queues it for the remote server if it's exported.
+    pub fn export(&self, channel: &str, payload: &str, publisher: Option<SocketAddr>) {
+        let Some(outgoing) = &self.outgoing else {
+            return;
+        };
+        if !self.export.iter().any(|pattern| topic_matches(pattern, channel)) {
+            return;
+        }
+        let remote_ip = *self.remote_ip.read().unwrap();
+        if remote_ip.is_none() || publisher.is_some_and(|addr| Some(addr.ip()) == remote_ip) {
+            return; // Disconnected, or it came from there
+        }
+        let _ = outgoing.send((channel.to_string(), payload.to_string()));
+    }
+}
+
+// The link is a subpub_client, which only speaks the plain protocol. Linked servers
+// normally share their config, so with [signing] or [encryption] on here the remote
+// would drop every request and the link would never come up.
+pub fn check_config(config: &ServerConfig) -> Result<()> {
+    if config.federation.enabled && (config.signing.enabled || config.encryption.enabled) {
+        bail!("[federation] can't be used together with [signing] or [encryption]: the link's requests are sent unsigned and unencrypted");
+    }
+    Ok(())
+}
+
+// Keeps the link up for as long as the server runs, reconnecting after failures.
+pub fn spawn(
+    config: &FederationConfig,
+    ctx: ServerContext,
+    own_addr: SocketAddr,
+    outgoing: Option<tokio_mpsc::UnboundedReceiver<(String, String)>>,
+) -> Option<JoinHandle<()>> {
+    let mut outgoing = outgoing?;
+    let config = config.clone();
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        let retry = Duration::from_secs(config.reconnect_secs.max(1));
+        loop {
+            if let Err(e) = run_link(&config, &ctx, own_addr, &mut outgoing).await {
+                warn!("Federation link: {:#}. Retrying in {}s.", e, retry.as_secs());
+            }
+            *ctx.federation.remote_ip.write().unwrap() = None;
+            sleep(retry).await;
+            // Whatever was exported while disconnected is stale by now.
+            while outgoing.try_recv().is_ok() {}
+        }
+    }))
+}
+
+// One connection to the remote server, until it stops answering.
+async fn run_link(
+    config: &FederationConfig,
+    ctx: &ServerContext,
+    own_addr: SocketAddr,
+    outgoing: &mut tokio_mpsc::UnboundedReceiver<(String, String)>,
+) -> Result<()> {
+    let remote = remote_address(config, own_addr).await?;
+    let client = Client::connect(remote)
+        .await
+        .context("The link only works with a remote server that has neither [signing] nor [encryption] enabled")?;
+    if !config.auth_user.is_empty() {
+        client.login(&config.auth_user, &config.auth_secret).await?;
+    }
+    client.hello(&config.name).await?;
+
+    let mut imports = Vec::new();
+    for topic in &config.import {
+        let mut subscription = client.subscribe(topic).await.with_context(|| format!("Failed to subscribe to '{}'", topic))?;
+        let import_ctx = ctx.clone();
+        imports.push(AbortOnDrop(ctx.runtime_handle.spawn(async move {
+            while let Some(message) = subscription.recv().await {
+                let Some(payload) = message.text() else {
+                    debug!("Federation: skipping a binary payload on '{}'.", message.topic);
+                    continue;
+                };
+                // No RESULT or error replies: the remote server can't use them.
+                handle_publish(&import_ctx, Some(remote), &message.topic, payload, None, None).await;
+            }
+        })));
+    }
+    *ctx.federation.remote_ip.write().unwrap() = Some(remote.ip());
+    info!("Federation linked with {}: importing {:?}, exporting {:?}", remote, config.import, config.export);
+
+    let mut ping = interval(PING_INTERVAL);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            Some((topic, payload)) = outgoing.recv() => {
+                if let Err(e) = client.publish(&topic, &payload).await {
+                    error!("Federation: failed to export '{}' to {}: {}", topic, remote, e);
+                }
+            }
+            _ = ping.tick() => {
+                client.ping().await.with_context(|| format!("{} stopped answering", remote))?;
+            }
+        }
+    }
+}
+
+// The configured remote, or the first other server that answers discovery.
+async fn remote_address(config: &FederationConfig, own_addr: SocketAddr) -> Result<SocketAddr> {
+    if !config.remote.is_empty() {
+        return lookup_host(&config.remote)
+            .await
+            .with_context(|| format!("Failed to resolve '{}'", config.remote))?
+            .next()
+            .ok_or_else(|| anyhow!("'{}' has no address", config.remote));
+    }
+    let servers = discover_all(Duration::from_millis(config.discovery_wait_ms)).await?;
+    servers
+        .into_iter()
+        .find(|server| *server != network::advertised_address(own_addr, *server))
+        .ok_or_else(|| anyhow!("No other SubPub server answered discovery"))
+}
+
+// Import tasks end with the link that started them.
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
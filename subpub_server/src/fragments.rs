@@ -0,0 +1,96 @@
+use anyhow::{anyhow, bail, Result};
+use log::warn;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use crate::config::DatagramConfig;
+
+// A large message split over several datagrams: `FRAG:<id>:<n>/<total>:<chunk>`, n from 1.
+// Joined in order, the chunks form the original message (e.g. `PUB:<channel>:<big json>`).
+pub const FRAGMENT_PREFIX: &str = "FRAG:";
+// Upper bound on `total`, so one header can't make the server reserve a huge table
+const MAX_FRAGMENTS: usize = 1024;
+
+struct PartialMessage {
+    chunks: Vec<Option<String>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+// Collects fragments per (client, id) until a message is complete. Incomplete messages
+// are dropped after `fragment_timeout_ms`.
+pub struct Reassembler {
+    timeout: Duration,
+    max_message_bytes: usize,
+    partial: HashMap<(SocketAddr, String), PartialMessage>,
+}
+
+impl Reassembler {
+    pub fn new(config: &DatagramConfig) -> Self {
+        Self {
+            timeout: Duration::from_millis(config.fragment_timeout_ms),
+            max_message_bytes: config.max_message_bytes,
+            partial: HashMap::new(),
+        }
+    }
+
+    // Takes a fragment (without the prefix). Returns the whole message once the last
+    // missing fragment arrives.
+    pub fn add(&mut self, addr: SocketAddr, fragment: &str) -> Result<Option<String>> {
+        self.expire();
+        let mut parts = fragment.splitn(3, ':');
+        let (Some(id), Some(position), Some(chunk)) = (parts.next(), parts.next(), parts.next()) else {
+            bail!("expected FRAG:<id>:<n>/<total>:<chunk>");
+        };
+        let (n, total) = position
+            .split_once('/')
+            .and_then(|(n, total)| Some((n.parse::<usize>().ok()?, total.parse::<usize>().ok()?)))
+            .filter(|(n, total)| (1..=*total).contains(n) && *total <= MAX_FRAGMENTS)
+            .ok_or_else(|| anyhow!("invalid fragment position '{}'", position))?;
+
+        let key = (addr, id.to_string());
+        let partial = self.partial.entry(key.clone()).or_insert_with(|| PartialMessage {
+            chunks: vec![None; total],
+            received: 0,
+            bytes: 0,
+            started: Instant::now(),
+        });
+        if partial.chunks.len() != total {
+            self.partial.remove(&key);
+            bail!("fragments of '{}' disagree on the total", id);
+        }
+        if partial.chunks[n - 1].is_none() {
+            partial.bytes += chunk.len();
+            partial.received += 1;
+            partial.chunks[n - 1] = Some(chunk.to_string());
+        }
+        if partial.bytes > self.max_message_bytes {
+            self.partial.remove(&key);
+            bail!("message '{}' is larger than {} bytes", id, self.max_message_bytes);
+        }
+        if partial.received < total {
+            return Ok(None);
+        }
+        let complete = self.partial.remove(&key).map(|partial| partial.chunks.into_iter().flatten().collect());
+        Ok(complete)
+    }
+
+    fn expire(&mut self) {
+        let timeout = self.timeout;
+        self.partial.retain(|(addr, id), partial| {
+            let alive = partial.started.elapsed() < timeout;
+            if !alive {
+                warn!(
+                    "Dropped message '{}' from {}: only {} of {} fragments arrived in time.",
+                    id,
+                    addr,
+                    partial.received,
+                    partial.chunks.len()
+                );
+            }
+            alive
+        });
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use subpub_client::frame::{is_frame, Frame};
+
+use crate::protobuf;
+
+// How a datagram is encoded, told apart by its first byte. Replies go back the same way.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WireFormat {
+    Text,
+    // MessagePack, see below
+    Frame,
+    // Only with `[datagrams] protobuf = true`, see protobuf.rs
+    Protobuf,
+}
+
+impl WireFormat {
+    pub fn of(datagram: &[u8], protobuf_enabled: bool) -> Self {
+        if is_frame(datagram) {
+            WireFormat::Frame
+        } else if protobuf_enabled && protobuf::is_protobuf(datagram) {
+            WireFormat::Protobuf
+        } else {
+            WireFormat::Text
+        }
+    }
+}
+
+// A binary frame (see subpub_client/src/frame.rs) as the text message the processing
+// loop understands, e.g. ["PUB", "drums", b"..."] as `PUB:drums:...`, so frames go
+// through the same auth, ACL and rate limit checks. A payload that isn't UTF-8 can't be
+// written into the text; it rides along in `binary_payload` and the text gets a
+// placeholder for the logs. Signing covers text messages only, so with [signing]
+// enabled frames fail verification and are dropped.
+pub struct DecodedFrame {
+    pub text: String,
+    pub binary_payload: Option<Vec<u8>>,
+}
+
+pub fn decode(datagram: &[u8]) -> Result<DecodedFrame> {
+    from_frame(Frame::decode(datagram)?)
+}
+
+pub fn from_frame(frame: Frame) -> Result<DecodedFrame> {
+    if frame.action.contains(':') || frame.channel.is_some_and(|channel| channel.contains(':')) {
+        bail!("action and channel can't contain ':'");
+    }
+    let mut text = frame.action.to_string();
+    if let Some(channel) = frame.channel {
+        text.push(':');
+        text.push_str(channel);
+    }
+    // ["PUB", channel, payload, id] is the same as a PUBID.
+    let publish = frame.action.eq_ignore_ascii_case("PUB") || frame.action.eq_ignore_ascii_case("PUBID");
+    if let Some(id) = frame.id.filter(|_| publish) {
+        if frame.action.eq_ignore_ascii_case("PUB") {
+            text.replace_range(..frame.action.len(), "PUBID");
+        }
+        text.push(':');
+        text.push_str(id);
+    }
+    let mut binary_payload = None;
+    if let Some(payload) = frame.payload {
+        text.push(':');
+        match std::str::from_utf8(payload) {
+            Ok(payload) => text.push_str(payload),
+            Err(_) if publish => {
+                text.push_str(&format!("<{} bytes>", payload.len()));
+                binary_payload = Some(payload.to_vec());
+            }
+            Err(_) => bail!("only PUB and PUBID can carry a payload that isn't UTF-8"),
+        }
+    }
+    Ok(DecodedFrame { text, binary_payload })
+}
+
+// The processing loop's reusable reply buffers. Replies go back in the format the
+// request came in: `text` as is, as a frame of it, or as its protobuf Envelope if the
+// schema has one.
+#[derive(Default)]
+pub struct ReplyBuffer {
+    pub text: String,
+    encoded: Vec<u8>,
+}
+
+impl ReplyBuffer {
+    pub fn bytes(&mut self, format: WireFormat) -> &[u8] {
+        match format {
+            WireFormat::Text => self.text.as_bytes(),
+            WireFormat::Frame => {
+                Frame::from_text(&self.text).encode_into(&mut self.encoded);
+                &self.encoded
+            }
+            WireFormat::Protobuf => match protobuf::encode_reply(&self.text) {
+                Some(envelope) => {
+                    self.encoded = envelope;
+                    &self.encoded
+                }
+                None => self.text.as_bytes(),
+            },
+        }
+    }
+}
+
+// `reply` as the bytes to send, for replies built outside the loop.
+pub fn reply_bytes(reply: String, format: WireFormat) -> Vec<u8> {
+    match format {
+        WireFormat::Text => reply.into_bytes(),
+        WireFormat::Frame => Frame::from_text(&reply).encode(),
+        WireFormat::Protobuf => protobuf::encode_reply(&reply).unwrap_or_else(|| reply.into_bytes()),
+    }
+}
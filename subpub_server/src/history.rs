@@ -0,0 +1,42 @@
+use dashmap::DashMap;
+use std::collections::VecDeque;
+
+// The last `depth` payloads published on each channel, so a client that starts late
+// (e.g. a visualizer) can catch up with `HIST:<channel>:<n>`.
+pub struct ChannelHistory {
+    depth: usize,
+    channels: DashMap<String, VecDeque<String>>,
+}
+
+impl ChannelHistory {
+    pub fn new(depth: usize) -> Self {
+        Self { depth, channels: DashMap::new() }
+    }
+
+    pub fn record(&self, channel: &str, payload: &str) {
+        if self.depth == 0 {
+            return;
+        }
+        let mut payloads = self.channels.entry(channel.to_string()).or_default();
+        if payloads.len() >= self.depth {
+            payloads.pop_front();
+        }
+        payloads.push_back(payload.to_string());
+    }
+
+    // Up to `n` of the most recent payloads on `channel`, oldest first.
+    pub fn last(&self, channel: &str, n: usize) -> Vec<String> {
+        let Some(payloads) = self.channels.get(channel) else {
+            return Vec::new();
+        };
+        payloads.iter().skip(payloads.len().saturating_sub(n)).cloned().collect()
+    }
+
+    pub fn forget(&self, channel: &str) {
+        self.channels.remove(channel);
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
@@ -0,0 +1,122 @@
+use global_hotkey::hotkey::HotKey;
+use global_hotkey::{GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+
+use crate::config::HotkeyConfig;
+use crate::server::{handle_publish, ServerContext};
+
+const HOTKEY_PUBLISH_CAPACITY: usize = 64;
+
+// Global keyboard shortcuts, so an operator can fire cues from the keyboard of the
+// machine running the tray app, whichever app has the focus. Registered once at
+// startup; the shortcuts are checked by the tray's event loop.
+pub struct Hotkeys {
+    // Unregisters the shortcuts when dropped
+    _manager: GlobalHotKeyManager,
+    bindings: HashMap<u32, HotkeyConfig>,
+}
+
+impl Hotkeys {
+    // Must run on the main thread, before the event loop starts. None if no hotkeys are
+    // configured or none could be registered.
+    pub fn register(config: &[HotkeyConfig]) -> Option<Self> {
+        if config.is_empty() {
+            return None;
+        }
+        let manager = match GlobalHotKeyManager::new() {
+            Ok(manager) => manager,
+            Err(e) => {
+                error!("Failed to set up global hotkeys: {}", e);
+                return None;
+            }
+        };
+        let mut bindings = HashMap::new();
+        for binding in config {
+            let hotkey = match binding.keys.parse::<HotKey>() {
+                Ok(hotkey) => hotkey,
+                Err(e) => {
+                    warn!("Ignoring hotkey '{}' for '{}': {}", binding.keys, binding.topic, e);
+                    continue;
+                }
+            };
+            // Taken by another app, or listed twice
+            if let Err(e) = manager.register(hotkey) {
+                warn!("Failed to register hotkey '{}' for '{}': {}", binding.keys, binding.topic, e);
+                continue;
+            }
+            bindings.insert(hotkey.id(), binding.clone());
+        }
+        if bindings.is_empty() {
+            return None;
+        }
+        info!("Registered {} global hotkey(s).", bindings.len());
+        Some(Self { _manager: manager, bindings })
+    }
+
+    // The bindings whose shortcut was pressed since the last call.
+    pub fn pressed(&self) -> Vec<&HotkeyConfig> {
+        GlobalHotKeyEvent::receiver()
+            .try_iter()
+            .filter(|event| event.state == HotKeyState::Pressed)
+            .filter_map(|event| self.bindings.get(&event.id))
+            .collect()
+    }
+}
+
+// Publishes fired in the tray's event loop, by hotkeys and subpub:// links (see
+// url_scheme.rs), on their way to the running server: (topic, payload)
+#[derive(Clone)]
+pub struct HotkeyPublishes {
+    tx: broadcast::Sender<(String, String)>,
+}
+
+impl HotkeyPublishes {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(HOTKEY_PUBLISH_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn send(&self, binding: &HotkeyConfig) {
+        info!(topic = binding.topic.as_str(); "Hotkey '{}' pressed: publishing to '{}'", binding.keys, binding.topic);
+        if !self.publish(&binding.topic, &binding.payload) {
+            warn!("Hotkey '{}' ignored: the server isn't running.", binding.keys);
+        }
+    }
+
+    // False if no server is running to publish it.
+    pub fn publish(&self, topic: &str, payload: &str) -> bool {
+        self.tx.send((topic.to_string(), payload.to_string())).is_ok()
+    }
+
+    // True once a running server has subscribed.
+    pub fn server_listening(&self) -> bool {
+        self.tx.receiver_count() > 0
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<(String, String)> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for HotkeyPublishes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Publishes what the hotkeys and links fired, as if a local client had sent it.
+pub async fn run_publisher(ctx: ServerContext, mut rx: broadcast::Receiver<(String, String)>) {
+    loop {
+        match rx.recv().await {
+            Ok((topic, payload)) => {
+                handle_publish(&ctx, None, &topic, &payload, None, None).await;
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Hotkey publisher lagged behind, skipped {} publishes.", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
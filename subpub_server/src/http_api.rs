@@ -0,0 +1,730 @@
+use std::io;
+use std::fs;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use anyhow::{Context, Result};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::auth::secrets_match;
+use crate::client_stats;
+use crate::config::{HttpApiConfig, ServerConfig, WebhookConfig, CONFIG_FILE_NAME};
+use crate::event_store::{EventQuery, StoredEvent};
+use crate::message_ids;
+use crate::midi_handler::{MidiHandler, MAPPING_FILE_NAME};
+use crate::paths;
+use crate::safe_mode::{SafeMode, SAFE_MODE_HTTP_BIND_ADDRESS};
+use crate::show_mode::{ShowMode, PASSPHRASE_HEADER};
+use crate::server::{handle_publish, topic_matches, ServerContext, CONTROL_TOPIC_PREFIX};
+use crate::stats::Stats;
+use crate::sys_events::SYS_TOPIC_PREFIX;
+use crate::zones::Zones;
+
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+// Uploaded config/mapping files
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+// Carries `[http_api] admin_token`
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+// Minimal parsed HTTP request. Only what the API endpoints need.
+struct HttpRequest {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl HttpRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+struct HttpResponse {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl HttpResponse {
+    fn text(status: &'static str, body: impl Into<String>) -> Self {
+        Self { status, content_type: "text/plain; charset=utf-8", body: body.into() }
+    }
+
+    fn json<T: Serialize>(value: &T) -> Self {
+        match serde_json::to_string_pretty(value) {
+            Ok(body) => Self { status: "200 OK", content_type: "application/json", body },
+            Err(e) => Self::text("500 Internal Server Error", format!("Failed to serialize response: {}", e)),
+        }
+    }
+}
+
+// Shared state the HTTP endpoints read from.
+#[derive(Clone)]
+pub struct HttpApiContext {
+    pub server: ServerContext,
+    pub safe_mode: Arc<SafeMode>,
+    pub show_mode: Arc<ShowMode>,
+    pub webhooks: Arc<WebhookConfig>,
+    // See `[http_api] admin_token`
+    pub admin_token: Arc<str>,
+}
+
+// HTTP listener for metrics and the admin API.
+pub async fn run_http_api(bind_address: String, context: HttpApiContext) -> Result<()> {
+    let listener = TcpListener::bind(&bind_address)
+        .await
+        .with_context(|| format!("Failed to bind HTTP API to {}", bind_address))?;
+    info!("HTTP API listening on http://{}", listener.local_addr()?);
+
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        // Same [ip_filter] as the datagram socket; refused connections are just closed.
+        if let Some(ip_filter) = &context.server.ip_filter
+            && !ip_filter.permits(addr.ip())
+        {
+            debug!("HTTP API connection from {} refused by the IP filter.", addr);
+            continue;
+        }
+        let context = context.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &context).await {
+                warn!("HTTP API request from {} failed: {:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, context: &HttpApiContext) -> Result<()> {
+    let response = match read_request(&stream).await? {
+        Some(request) => {
+            // Without the query, which can carry a webhook token
+            let path = request.path.split('?').next().unwrap_or_default();
+            debug!("HTTP API {} {}", request.method, path);
+            route(&request, context).await
+        }
+        None => HttpResponse::text("400 Bad Request", "Malformed request\n"),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    write_all(&stream, head.as_bytes()).await?;
+    write_all(&stream, response.body.as_bytes()).await?;
+    Ok(())
+}
+
+async fn route(request: &HttpRequest, context: &HttpApiContext) -> HttpResponse {
+    let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+    // With an admin token, everything but the metrics and webhooks (which have a token
+    // of their own) needs it.
+    let open = (request.method == "GET" && path == "/metrics") || (request.method == "POST" && path.starts_with("/webhook/"));
+    if !open && !context.admin_token.is_empty() && !has_admin_token(request, context) {
+        warn!("Refused HTTP API {} {}: missing or wrong admin token.", request.method, path);
+        return HttpResponse::text("401 Unauthorized", format!("Missing or wrong {}\n", ADMIN_TOKEN_HEADER));
+    }
+    match (request.method.as_str(), path) {
+        ("POST", _) if path.starts_with("/publish/") => publish(path, query, &request.body, context).await,
+        ("POST", _) if path.starts_with("/webhook/") => webhook(path, query, request, context).await,
+        ("GET", "/channels") => HttpResponse::json(&channels_json(&context.server)),
+        ("GET", "/subscribers") => HttpResponse::json(&subscribers_json(&context.server)),
+        ("POST", "/mappings/reload") => reload_mappings(request, context).await,
+        ("GET", "/metrics") => HttpResponse {
+            status: "200 OK",
+            content_type: "text/plain; version=0.0.4",
+            body: render_prometheus_metrics(&context.server.stats),
+        },
+        ("GET", "/admin/mappings") => HttpResponse::json(&mapping_stats_json(&context.server.stats)),
+        ("GET", "/admin/clients") => HttpResponse::json(&client_stats::client_reports(&context.server)),
+        ("GET", "/admin/events") => HttpResponse::json(&recent_events_json(&context.server.stats)),
+        ("GET", "/admin/event_log") => event_log(query, context).await,
+        ("GET", "/admin/zones") => HttpResponse::json(&zones_json(&context.server.zones)),
+        ("POST", _) if path.starts_with("/admin/zones/") => set_zone(path, request, context),
+        ("GET", "/admin/safe_mode") => HttpResponse::json(&SafeModeJson {
+            active: context.safe_mode.is_active(),
+            reason: context.safe_mode.summary(),
+        }),
+        ("PUT", _) if path.starts_with("/admin/files/") => upload_file(path, request, context).await,
+        _ => HttpResponse::text("404 Not Found", "Not found\n"),
+    }
+}
+
+fn has_admin_token(request: &HttpRequest, context: &HttpApiContext) -> bool {
+    request.header(ADMIN_TOKEN_HEADER).is_some_and(|token| secrets_match(token, &context.admin_token))
+}
+
+#[derive(Serialize)]
+struct PublishJson {
+    channel: String,
+    id: String,
+    // false if a publish with this id already ran
+    executed: bool,
+}
+
+// POST /publish/{channel}?id=<id>, with the payload as the body.
+// Same as `PUB:<channel>:<payload>`, or `PUBID` when an id is given. The _control/
+// topics (recording, renders, transpose) are left to UDP clients, where [auth] and
+// [acl] apply.
+async fn publish(path: &str, query: &str, body: &str, context: &HttpApiContext) -> HttpResponse {
+    let channel = percent_decode(path.trim_start_matches("/publish/"));
+    if channel.is_empty() || channel.starts_with(SYS_TOPIC_PREFIX) {
+        return HttpResponse::text("400 Bad Request", "Missing or reserved channel\n");
+    }
+    if channel.starts_with(CONTROL_TOPIC_PREFIX) {
+        warn!(topic = channel.as_str(); "Refused HTTP publish to control topic '{}'.", channel);
+        return HttpResponse::text("403 Forbidden", "Control topics can't be published over HTTP\n");
+    }
+    let client_id = query_param(query, "id");
+    if let Some(id) = client_id.as_deref()
+        && !message_ids::is_valid_id(id)
+    {
+        return HttpResponse::text("400 Bad Request", "Invalid id\n");
+    }
+    info!("HTTP API published to channel '{}': {}", channel, body);
+    let executed_id = handle_publish(&context.server, None, &channel, body, client_id.as_deref(), None).await;
+    HttpResponse::json(&PublishJson {
+        executed: executed_id.is_some(),
+        id: executed_id.or(client_id).unwrap_or_default(),
+        channel,
+    })
+}
+
+// POST /webhook/{channel}?token=<token>&id=<id>, with a JSON body, for cloud services
+// (IFTTT, GitHub, ticketing systems). Publishes the JSON on the channel, compacted.
+async fn webhook(path: &str, query: &str, request: &HttpRequest, context: &HttpApiContext) -> HttpResponse {
+    let webhooks = &context.webhooks;
+    if !webhooks.token.is_empty() {
+        let token = query_param(query, "token").or_else(|| request.header("X-Webhook-Token").map(str::to_string));
+        if !token.is_some_and(|token| secrets_match(&token, &webhooks.token)) {
+            warn!("Refused webhook to '{}': missing or wrong token.", path);
+            return HttpResponse::text("401 Unauthorized", "Missing or wrong token\n");
+        }
+    }
+    let channel = percent_decode(path.trim_start_matches("/webhook/"));
+    if channel.is_empty() || channel.starts_with(SYS_TOPIC_PREFIX) {
+        return HttpResponse::text("400 Bad Request", "Missing or reserved channel\n");
+    }
+    if !webhooks.channels.iter().any(|pattern| topic_matches(pattern, &channel)) {
+        warn!(topic = channel.as_str(); "Refused webhook to '{}': not in [http_api.webhooks] channels.", channel);
+        return HttpResponse::text("403 Forbidden", "Webhooks may not publish to this channel\n");
+    }
+    let payload = match serde_json::from_str::<serde_json::Value>(&request.body) {
+        Ok(json) => json.to_string(),
+        Err(e) => return HttpResponse::text("400 Bad Request", format!("Body is not JSON: {}\n", e)),
+    };
+    let client_id = query_param(query, "id");
+    if let Some(id) = client_id.as_deref()
+        && !message_ids::is_valid_id(id)
+    {
+        return HttpResponse::text("400 Bad Request", "Invalid id\n");
+    }
+    info!(topic = channel.as_str(); "Webhook published to channel '{}': {}", channel, payload);
+    let executed_id = handle_publish(&context.server, None, &channel, &payload, client_id.as_deref(), None).await;
+    HttpResponse::json(&PublishJson {
+        executed: executed_id.is_some(),
+        id: executed_id.or(client_id).unwrap_or_default(),
+        channel,
+    })
+}
+
+#[derive(Serialize)]
+struct ChannelJson {
+    channel: String,
+    subscribers: usize,
+}
+
+fn channels_json(server: &ServerContext) -> Vec<ChannelJson> {
+    let mut channels: Vec<ChannelJson> = server
+        .subscribers
+        .iter()
+        .map(|entry| ChannelJson { channel: entry.key().clone(), subscribers: entry.value().len() })
+        .collect();
+    channels.sort_by(|a, b| a.channel.cmp(&b.channel));
+    channels
+}
+
+#[derive(Serialize)]
+struct SubscriberJson {
+    addr: String,
+    name: Option<String>,
+    user: Option<String>,
+    channels: Vec<String>,
+}
+
+fn subscribers_json(server: &ServerContext) -> Vec<SubscriberJson> {
+    let mut by_addr: BTreeMap<SocketAddr, Vec<String>> = BTreeMap::new();
+    for entry in server.subscribers.iter() {
+        for addr in entry.value() {
+            by_addr.entry(*addr).or_default().push(entry.key().clone());
+        }
+    }
+    by_addr
+        .into_iter()
+        .map(|(addr, mut channels)| {
+            channels.sort();
+            SubscriberJson { addr: addr.to_string(), name: server.clients.name(&addr), user: server.clients.user(&addr), channels }
+        })
+        .collect()
+}
+
+// POST /mappings/reload, same as the tray's "Reload Mappings".
+// During Show Mode the override passphrase goes in the X-Show-Mode-Passphrase header.
+async fn reload_mappings(request: &HttpRequest, context: &HttpApiContext) -> HttpResponse {
+    if let Err(e) = context.show_mode.authorize("Reload MIDI Mappings", request.header(PASSPHRASE_HEADER)) {
+        return HttpResponse::text("423 Locked", format!("{}\n", e));
+    }
+    match context.server.midi.reload().await {
+        Ok(()) => HttpResponse::text("200 OK", "Mappings reloaded\n"),
+        Err(e) => HttpResponse::text("400 Bad Request", format!("Reload failed, keeping the previous mappings: {:#}\n", e)),
+    }
+}
+
+pub fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+// Decodes %XX escapes. '+' is kept as is.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// Reads the request line, headers and (if Content-Length is set) the body.
+// Returns None if the request is malformed.
+async fn read_request(stream: &TcpStream) -> Result<Option<HttpRequest>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    let header_end = loop {
+        if let Some(pos) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if data.len() > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        let n = read_some(stream, &mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..header_end]).to_string();
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    if content_length > MAX_BODY_BYTES {
+        return Ok(None);
+    }
+    while data.len() < header_end + content_length {
+        let n = read_some(stream, &mut buf).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let body = String::from_utf8_lossy(&data[header_end..header_end + content_length]).to_string();
+
+    let headers = head
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    match (request_line.next(), request_line.next()) {
+        (Some(method), Some(path)) => Ok(Some(HttpRequest {
+            method: method.to_uppercase(),
+            path: path.to_string(),
+            headers,
+            body,
+        })),
+        _ => Ok(None),
+    }
+}
+
+async fn read_some(stream: &TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        stream.readable().await?;
+        match stream.try_read(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+async fn write_all(stream: &TcpStream, mut data: &[u8]) -> io::Result<()> {
+    while !data.is_empty() {
+        stream.writable().await?;
+        match stream.try_write(data) {
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Escapes a value for use inside a Prometheus label.
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+pub fn render_prometheus_metrics(stats: &Stats) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP subpub_midi_messages_sent_total MIDI messages sent per output port.\n");
+    out.push_str("# TYPE subpub_midi_messages_sent_total counter\n");
+    for (output, output_stats) in stats.midi_outputs_snapshot() {
+        out.push_str(&format!(
+            "subpub_midi_messages_sent_total{{output=\"{}\"}} {}\n",
+            escape_label(&output),
+            output_stats.messages_sent
+        ));
+    }
+
+    let (queue_depth, queue_dropped, queue_coalesced) = stats.midi_queue_counts();
+    out.push_str("# HELP subpub_midi_queue_depth MIDI actions waiting for the MIDI thread.\n");
+    out.push_str("# TYPE subpub_midi_queue_depth gauge\n");
+    out.push_str(&format!("subpub_midi_queue_depth {}\n", queue_depth));
+    out.push_str("# HELP subpub_midi_queue_dropped_total MIDI actions dropped because the queue was full.\n");
+    out.push_str("# TYPE subpub_midi_queue_dropped_total counter\n");
+    out.push_str(&format!("subpub_midi_queue_dropped_total {}\n", queue_dropped));
+    out.push_str("# HELP subpub_midi_queue_coalesced_total MIDI actions replaced by a newer one for the same CC or topic.\n");
+    out.push_str("# TYPE subpub_midi_queue_coalesced_total counter\n");
+    out.push_str(&format!("subpub_midi_queue_coalesced_total {}\n", queue_coalesced));
+
+    let (compressed, saved_bytes) = stats.compression_counts();
+    out.push_str("# HELP subpub_compressed_datagrams_total Payloads sent to subscribers compressed (SUB option compress=deflate).\n");
+    out.push_str("# TYPE subpub_compressed_datagrams_total counter\n");
+    out.push_str(&format!("subpub_compressed_datagrams_total {}\n", compressed));
+    out.push_str("# HELP subpub_compression_saved_bytes_total Bytes compression kept off the network.\n");
+    out.push_str("# TYPE subpub_compression_saved_bytes_total counter\n");
+    out.push_str(&format!("subpub_compression_saved_bytes_total {}\n", saved_bytes));
+
+    let mappings = stats.mapping_triggers_snapshot();
+    out.push_str("# HELP subpub_mapping_triggers_total Times each mapping was triggered.\n");
+    out.push_str("# TYPE subpub_mapping_triggers_total counter\n");
+    for (topic, trigger_stats) in &mappings {
+        out.push_str(&format!(
+            "subpub_mapping_triggers_total{{sub_topic=\"{}\"}} {}\n",
+            escape_label(topic),
+            trigger_stats.count
+        ));
+    }
+    out.push_str("# HELP subpub_mapping_last_trigger_timestamp_seconds Unix time of the last trigger per mapping.\n");
+    out.push_str("# TYPE subpub_mapping_last_trigger_timestamp_seconds gauge\n");
+    for (topic, trigger_stats) in &mappings {
+        if let Some(last) = trigger_stats.last_triggered {
+            let secs = last.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+            out.push_str(&format!(
+                "subpub_mapping_last_trigger_timestamp_seconds{{sub_topic=\"{}\"}} {:.3}\n",
+                escape_label(topic),
+                secs
+            ));
+        }
+    }
+    out.push_str("# HELP subpub_mapping_malformed_payloads_total Triggers per mapping whose JSON payload was malformed.\n");
+    out.push_str("# TYPE subpub_mapping_malformed_payloads_total counter\n");
+    for (topic, trigger_stats) in &mappings {
+        out.push_str(&format!(
+            "subpub_mapping_malformed_payloads_total{{sub_topic=\"{}\"}} {}\n",
+            escape_label(topic),
+            trigger_stats.malformed_payloads
+        ));
+    }
+    out.push_str("# HELP subpub_mapping_invalid_payloads_total Publishes per mapping rejected by its payload schema.\n");
+    out.push_str("# TYPE subpub_mapping_invalid_payloads_total counter\n");
+    for (topic, trigger_stats) in &mappings {
+        out.push_str(&format!(
+            "subpub_mapping_invalid_payloads_total{{sub_topic=\"{}\"}} {}\n",
+            escape_label(topic),
+            trigger_stats.invalid_payloads
+        ));
+    }
+    let fanout = stats.fanout_snapshot();
+    out.push_str("# HELP subpub_fanout_duration_seconds Time to send one publish to a channel's subscribers.\n");
+    out.push_str("# TYPE subpub_fanout_duration_seconds summary\n");
+    for (channel, fanout_stats) in &fanout {
+        let channel = escape_label(channel);
+        out.push_str(&format!("subpub_fanout_duration_seconds_sum{{channel=\"{}\"}} {:.6}\n", channel, fanout_stats.total.as_secs_f64()));
+        out.push_str(&format!("subpub_fanout_duration_seconds_count{{channel=\"{}\"}} {}\n", channel, fanout_stats.publishes));
+    }
+    out.push_str("# HELP subpub_fanout_duration_max_seconds Slowest fanout per channel.\n");
+    out.push_str("# TYPE subpub_fanout_duration_max_seconds gauge\n");
+    for (channel, fanout_stats) in &fanout {
+        out.push_str(&format!("subpub_fanout_duration_max_seconds{{channel=\"{}\"}} {:.6}\n", escape_label(channel), fanout_stats.max.as_secs_f64()));
+    }
+    out.push_str("# HELP subpub_fanout_datagrams_total Datagrams sent to subscribers per channel.\n");
+    out.push_str("# TYPE subpub_fanout_datagrams_total counter\n");
+    for (channel, fanout_stats) in &fanout {
+        out.push_str(&format!("subpub_fanout_datagrams_total{{channel=\"{}\"}} {}\n", escape_label(channel), fanout_stats.datagrams));
+    }
+    out.push_str("# HELP subpub_publishes_rate_limited_total Publishes dropped by the per-client rate limit.\n");
+    out.push_str("# TYPE subpub_publishes_rate_limited_total counter\n");
+    let clients = stats.clients().snapshot();
+    for (client, counters) in clients.iter().filter(|(_, counters)| counters.rate_limited > 0) {
+        out.push_str(&format!("subpub_publishes_rate_limited_total{{client=\"{}\"}} {}\n", client, counters.rate_limited));
+    }
+    out.push_str("# HELP subpub_messages_processed_total Client messages processed by the server.\n");
+    out.push_str("# TYPE subpub_messages_processed_total counter\n");
+    out.push_str(&format!("subpub_messages_processed_total {}\n", stats.messages_processed()));
+    let receive_loops = stats.receive_loops_snapshot();
+    out.push_str("# HELP subpub_receive_loop_datagrams_total Datagrams received per receive loop.\n");
+    out.push_str("# TYPE subpub_receive_loop_datagrams_total counter\n");
+    for (index, (datagrams, _)) in receive_loops.iter().enumerate() {
+        out.push_str(&format!("subpub_receive_loop_datagrams_total{{loop=\"{}\"}} {}\n", index, datagrams));
+    }
+    out.push_str("# HELP subpub_receive_loop_messages_processed_total Client messages processed per receive loop.\n");
+    out.push_str("# TYPE subpub_receive_loop_messages_processed_total counter\n");
+    for (index, (_, processed)) in receive_loops.iter().enumerate() {
+        out.push_str(&format!("subpub_receive_loop_messages_processed_total{{loop=\"{}\"}} {}\n", index, processed));
+    }
+    out.push_str("# HELP subpub_duplicates_dropped_total Messages dropped as duplicates by their sequence number.\n");
+    out.push_str("# TYPE subpub_duplicates_dropped_total counter\n");
+    for (client, counters) in clients.iter().filter(|(_, counters)| counters.duplicates > 0) {
+        out.push_str(&format!("subpub_duplicates_dropped_total{{client=\"{}\"}} {}\n", client, counters.duplicates));
+    }
+    out.push_str("# HELP subpub_client_publishes_total Publishes per client.\n");
+    out.push_str("# TYPE subpub_client_publishes_total counter\n");
+    for (client, counters) in clients.iter().filter(|(_, counters)| counters.published > 0) {
+        out.push_str(&format!("subpub_client_publishes_total{{client=\"{}\"}} {}\n", client, counters.published));
+    }
+    out
+}
+
+#[derive(Serialize)]
+struct MappingStatsJson {
+    sub_topic: String,
+    trigger_count: u64,
+    last_triggered_unix: Option<f64>,
+    malformed_payloads: u64,
+    invalid_payloads: u64,
+}
+
+fn mapping_stats_json(stats: &Stats) -> Vec<MappingStatsJson> {
+    stats
+        .mapping_triggers_snapshot()
+        .into_iter()
+        .map(|(sub_topic, trigger_stats)| MappingStatsJson {
+            sub_topic,
+            trigger_count: trigger_stats.count,
+            last_triggered_unix: trigger_stats
+                .last_triggered
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs_f64()),
+            malformed_payloads: trigger_stats.malformed_payloads,
+            invalid_payloads: trigger_stats.invalid_payloads,
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct RecentEventJson {
+    time_unix: f64,
+    kind: &'static str,
+    topic: String,
+    detail: String,
+    source: Option<String>,
+}
+
+fn recent_events_json(stats: &Stats) -> Vec<RecentEventJson> {
+    stats
+        .recent_events()
+        .snapshot()
+        .into_iter()
+        .map(|event| RecentEventJson {
+            time_unix: event.time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0),
+            kind: event.kind.as_str(),
+            topic: event.topic,
+            detail: event.detail,
+            source: event.source.map(|addr| addr.to_string()),
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct EventLogJson {
+    time_unix: f64,
+    kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic: Option<String>,
+    payload: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_id: Option<String>,
+}
+
+impl From<StoredEvent> for EventLogJson {
+    fn from(event: StoredEvent) -> Self {
+        Self {
+            time_unix: event.ts,
+            kind: event.kind,
+            topic: event.topic,
+            payload: event.payload,
+            source: event.source,
+            message_id: event.message_id,
+        }
+    }
+}
+
+// GET /admin/event_log?kind=pub|midi&topic=sensors/*&since=<unix>&until=<unix>&limit=100
+// The latest `limit` matching rows of the SQLite event log, oldest first.
+async fn event_log(query: &str, context: &HttpApiContext) -> HttpResponse {
+    let Some(event_store) = context.server.event_store.clone() else {
+        return HttpResponse::text("404 Not Found", "The event log is disabled ([event_log] in subpub_server.toml)\n");
+    };
+    let number = |name: &str| query_param(query, name).and_then(|value| value.parse::<f64>().ok());
+    let filter = EventQuery {
+        kind: query_param(query, "kind"),
+        topic: query_param(query, "topic"),
+        since: number("since"),
+        until: number("until"),
+        limit: query_param(query, "limit").and_then(|value| value.parse().ok()).unwrap_or(100),
+    };
+    match tokio::task::spawn_blocking(move || event_store.query(&filter)).await {
+        Ok(Ok(events)) => HttpResponse::json(&events.into_iter().map(EventLogJson::from).collect::<Vec<_>>()),
+        Ok(Err(e)) => {
+            error!("Event log query failed: {:?}", e);
+            HttpResponse::text("500 Internal Server Error", "Event log query failed\n")
+        }
+        Err(e) => {
+            error!("Event log query task failed: {:?}", e);
+            HttpResponse::text("500 Internal Server Error", "Event log query failed\n")
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ZoneJson {
+    zone: String,
+    enabled: bool,
+}
+
+fn zones_json(zones: &Zones) -> Vec<ZoneJson> {
+    zones
+        .snapshot()
+        .into_iter()
+        .map(|(zone, enabled)| ZoneJson { zone, enabled })
+        .collect()
+}
+
+// POST /admin/zones/{zone}/enable or /admin/zones/{zone}/disable
+// Locked by Show Mode like /mappings/reload.
+fn set_zone(path: &str, request: &HttpRequest, context: &HttpApiContext) -> HttpResponse {
+    let rest = path.trim_start_matches("/admin/zones/");
+    let enabled = match rest.rsplit_once('/') {
+        Some((zone, "enable")) if !zone.is_empty() => Some((zone, true)),
+        Some((zone, "disable")) if !zone.is_empty() => Some((zone, false)),
+        _ => None,
+    };
+    match enabled {
+        Some((zone, enabled)) => {
+            let action = format!("{} zone '{}'", if enabled { "Enable" } else { "Disable" }, zone);
+            if let Err(e) = context.show_mode.authorize(&action, request.header(PASSPHRASE_HEADER)) {
+                return HttpResponse::text("423 Locked", format!("{}\n", e));
+            }
+            context.server.zones.set_enabled(zone, enabled);
+            HttpResponse::json(&ZoneJson { zone: zone.to_string(), enabled })
+        }
+        None => HttpResponse::text("404 Not Found", "Use /admin/zones/{zone}/enable or /disable\n"),
+    }
+}
+
+#[derive(Serialize)]
+struct SafeModeJson {
+    active: bool,
+    reason: Option<String>,
+}
+
+// PUT /admin/files/midi_mapping.toml or /admin/files/subpub_server.toml
+// Only allowed in safe mode and with an admin token (checked by `route`), to replace a
+// broken file. The upload is validated first, so a bad file never overwrites the current
+// one. Locked by Show Mode like /mappings/reload.
+async fn upload_file(path: &str, request: &HttpRequest, context: &HttpApiContext) -> HttpResponse {
+    if context.admin_token.is_empty() {
+        return HttpResponse::text("403 Forbidden", "File uploads need an [http_api] admin_token\n");
+    }
+    if let Err(e) = context.show_mode.authorize("Upload a file", request.header(PASSPHRASE_HEADER)) {
+        return HttpResponse::text("423 Locked", format!("{}\n", e));
+    }
+    if !context.safe_mode.is_active() {
+        return HttpResponse::text("409 Conflict", "File uploads are only allowed in safe mode\n");
+    }
+    let body = request.body.as_str();
+    let name = path.trim_start_matches("/admin/files/");
+    let validation = match name {
+        MAPPING_FILE_NAME => MidiHandler::validate_mappings(body),
+        CONFIG_FILE_NAME => toml::from_str::<ServerConfig>(body)
+            .map(|_| ())
+            .context("Failed to parse server config TOML"),
+        _ => return HttpResponse::text("404 Not Found", "Unknown file\n"),
+    };
+    if let Err(e) = validation {
+        return HttpResponse::text("400 Bad Request", format!("Rejected {}: {:#}\n", name, e));
+    }
+    if let Err(e) = fs::write(paths::config_dir().join(name), body) {
+        error!("Failed to write uploaded {}: {:?}", name, e);
+        return HttpResponse::text("500 Internal Server Error", format!("Failed to write {}: {}\n", name, e));
+    }
+    info!("Replaced {} via the admin API.", name);
+
+    if name == MAPPING_FILE_NAME {
+        if let Err(e) = context.server.midi.reload().await {
+            return HttpResponse::text("500 Internal Server Error", format!("Saved, but reload failed: {:#}\n", e));
+        }
+        return HttpResponse::text("200 OK", "Mappings replaced and reloaded\n");
+    }
+    HttpResponse::text("200 OK", "Config replaced. Restart the application to apply it.\n")
+}
+
+// Spawns the HTTP API if it's enabled in the config, or if safe mode needs it for recovery.
+pub fn spawn_if_enabled(
+    runtime_handle: &Handle,
+    config: &HttpApiConfig,
+    context: HttpApiContext,
+) -> Option<JoinHandle<()>> {
+    let bind_address = if config.enabled {
+        config.bind_address.clone()
+    } else if context.safe_mode.is_active() {
+        warn!("HTTP API is disabled, but opening it on {} for safe mode recovery.", SAFE_MODE_HTTP_BIND_ADDRESS);
+        SAFE_MODE_HTTP_BIND_ADDRESS.to_string()
+    } else {
+        return None;
+    };
+    Some(runtime_handle.spawn(async move {
+        if let Err(e) = run_http_api(bind_address, context).await {
+            error!("HTTP API failed: {:?}", e);
+        }
+    }))
+}
@@ -0,0 +1,33 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Small random variations so dense machine-generated triggers don't sound robotic.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct HumanizeConfig {
+    // Note velocities are moved by up to +/- this amount.
+    pub velocity: u8,
+    // Actions are delayed by a random 0..=timing_ms. Notes can't be sent early.
+    pub timing_ms: u64,
+}
+
+impl HumanizeConfig {
+    // Stays within 1-127 so a humanized NoteOn never turns into a NoteOff.
+    pub fn jitter_velocity(&self, velocity: u8) -> u8 {
+        if self.velocity == 0 {
+            return velocity;
+        }
+        let range = self.velocity as i16;
+        let offset = rand::thread_rng().gen_range(-range..=range);
+        (velocity as i16 + offset).clamp(1, 127) as u8
+    }
+
+    pub fn timing_offset(&self) -> Duration {
+        if self.timing_ms == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(rand::thread_rng().gen_range(0..=self.timing_ms))
+    }
+}
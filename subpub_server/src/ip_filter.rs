@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::config::IpFilterConfig;
+
+// An address ("192.168.0.20") or a CIDR range ("192.168.0.0/24", "fd00::/8").
+#[derive(Debug, Clone, Copy)]
+struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    fn parse(text: &str) -> Result<Self> {
+        let (address, prefix_len) = match text.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (text, None),
+        };
+        let network: IpAddr = address.trim().parse().with_context(|| format!("'{}' is not an IP address", text))?;
+        let max_len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.trim().parse::<u8>().ok().filter(|len| *len <= max_len),
+            None => Some(max_len),
+        }
+        .ok_or_else(|| anyhow!("'{}' has an invalid prefix length", text))?;
+        Ok(Self { network: network.to_canonical(), prefix_len })
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix_len)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix_len)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// Source address filter, applied to every datagram before it is parsed, to discovery
+// pings and to HTTP API connections. `block` wins over `allow`; an empty `allow` lets everyone else in.
+pub struct IpFilter {
+    allow: Vec<IpRange>,
+    block: Vec<IpRange>,
+}
+
+impl IpFilter {
+    // None if nothing is configured, so the common case costs nothing.
+    pub fn from_config(config: &IpFilterConfig) -> Result<Option<Arc<Self>>> {
+        if config.allow.is_empty() && config.block.is_empty() {
+            return Ok(None);
+        }
+        let parse_all = |list: &[String]| list.iter().map(|entry| IpRange::parse(entry)).collect::<Result<Vec<_>>>();
+        let filter = Self {
+            allow: parse_all(&config.allow).context("Invalid [ip_filter] allow entry")?,
+            block: parse_all(&config.block).context("Invalid [ip_filter] block entry")?,
+        };
+        info!("IP filter enabled: {} allowed, {} blocked range(s).", filter.allow.len(), filter.block.len());
+        Ok(Some(Arc::new(filter)))
+    }
+
+    pub fn permits(&self, ip: IpAddr) -> bool {
+        if self.block.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+}
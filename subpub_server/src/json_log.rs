@@ -0,0 +1,35 @@
+use chrono::{SecondsFormat, Utc};
+use log::kv::{self, Key, Value, VisitSource};
+use log::Record;
+use log4rs::encode::{Encode, Write};
+use serde_json::{Map, Value as JsonValue};
+
+// Structured log lines for Loki/Elastic, one object per line:
+// {"ts":"2026-01-01T20:00:00.123Z","level":"INFO","module":"subpub_server::server","message":"...","topic":"drums/kick","client":"192.168.0.12:50123"}
+// `topic`, `client` and any other fields come from key-values on the log call,
+// e.g. info!(topic = channel, client:% = addr; "...").
+#[derive(Debug, Default)]
+pub struct JsonLineEncoder;
+
+impl Encode for JsonLineEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> anyhow::Result<()> {
+        let mut line = Map::new();
+        line.insert("ts".into(), Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true).into());
+        line.insert("level".into(), record.level().as_str().into());
+        line.insert("module".into(), record.module_path().unwrap_or(record.target()).into());
+        line.insert("message".into(), record.args().to_string().into());
+        record.key_values().visit(&mut Fields(&mut line))?;
+        serde_json::to_writer(&mut *w, &line)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+struct Fields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), JsonValue::String(value.to_string()));
+        Ok(())
+    }
+}
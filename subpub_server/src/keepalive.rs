@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use log::{debug, error, info};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::server::{disconnect_client, handle_publish, ServerContext};
+
+pub use subpub_client::protocol::KEEPALIVE_MESSAGE;
+
+// Periodically sends a keepalive datagram to every subscriber so NAT/firewall
+// pinholes stay open for clients behind consumer routers.
+pub async fn run_keepalive_sender(ctx: ServerContext, every: Duration) {
+    info!("Sending keepalives to subscribers every {}ms", every.as_millis());
+    let mut ticker = interval(every);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        let targets: HashSet<SocketAddr> = ctx
+            .subscribers
+            .iter()
+            .flat_map(|entry| entry.value().iter().cloned().collect::<Vec<_>>())
+            .collect();
+        for addr in targets {
+            if let Err(e) = ctx.socket.send_to(KEEPALIVE_MESSAGE.as_bytes(), addr).await {
+                error!("Failed to send keepalive to {}: {}", addr, e);
+            }
+        }
+    }
+}
+
+// Drops subscribers that haven't sent anything within the liveness TTL.
+pub async fn run_subscriber_expiry(ctx: ServerContext, ttl: Duration) {
+    info!("Expiring subscribers that are silent for more than {}ms", ttl.as_millis());
+    let mut ticker = interval((ttl / 4).max(Duration::from_millis(250)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        for addr in ctx.clients.expired(ttl) {
+            let who = ctx.clients.label(addr);
+            let will = ctx.clients.take_will(&addr);
+            let channels = disconnect_client(&ctx, addr);
+            if channels.is_empty() {
+                debug!("Client {} expired (no subscriptions).", who);
+            } else {
+                info!("Client {} expired. Removed from channels: {:?}", who, channels);
+            }
+            // The client dropped off without saying goodbye: run its last will.
+            if let Some((topic, payload)) = will {
+                info!(topic = topic.as_str(), client:% = addr; "Publishing the will of {} to '{}': {}", who, topic, payload);
+                handle_publish(&ctx, Some(addr), &topic, &payload, None, None).await; // Nobody left to reply to
+            }
+        }
+    }
+}
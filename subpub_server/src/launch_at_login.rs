@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::env;
+use std::path::PathBuf;
+
+// Starts the tray app when the user logs in, so an installation machine comes back by
+// itself after a power cut (together with `[startup] auto_start_server`). Registered
+// for the current user only, with the current working directory, since that's where
+// the config and mapping files are read from.
+//   macOS    ~/Library/LaunchAgents/com.subpub.server.plist (a LaunchAgent)
+//   Linux    ~/.config/autostart/subpub-server.desktop (XDG autostart)
+//   Windows  HKCU\Software\Microsoft\Windows\CurrentVersion\Run, value "SubPubServer"
+
+#[cfg(target_os = "macos")]
+const LAUNCH_AGENT_LABEL: &str = "com.subpub.server";
+#[cfg(windows)]
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+#[cfg(windows)]
+const RUN_VALUE: &str = "SubPubServer";
+
+// The executable and working directory to register.
+fn launch_target() -> Result<(PathBuf, PathBuf)> {
+    let exe = env::current_exe().context("Failed to find the application executable")?;
+    let dir = env::current_dir().context("Failed to get the working directory")?;
+    Ok((exe, dir))
+}
+
+pub fn set_enabled(enabled: bool) -> Result<()> {
+    if enabled {
+        install()?;
+        info!("Start at Login enabled.");
+    } else {
+        uninstall()?;
+        info!("Start at Login disabled.");
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn entry_path() -> Result<PathBuf> {
+    let home = env::var("HOME").context("HOME is not set")?;
+    Ok(PathBuf::from(home).join("Library/LaunchAgents").join(format!("{}.plist", LAUNCH_AGENT_LABEL)))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn entry_path() -> Result<PathBuf> {
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var("HOME").context("HOME is not set")?).join(".config"),
+    };
+    Ok(config_dir.join("autostart/subpub-server.desktop"))
+}
+
+#[cfg(target_os = "macos")]
+fn entry_contents(exe: &std::path::Path, dir: &std::path::Path) -> String {
+    let escape = |path: &std::path::Path| {
+        path.display().to_string().replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    };
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+    </array>
+    <key>WorkingDirectory</key>
+    <string>{}</string>
+    <key>RunAtLoad</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        LAUNCH_AGENT_LABEL,
+        escape(exe),
+        escape(dir)
+    )
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn entry_contents(exe: &std::path::Path, dir: &std::path::Path) -> String {
+    format!(
+        "[Desktop Entry]\nType=Application\nName=SubPub Server\nExec=\"{}\"\nPath={}\nX-GNOME-Autostart-enabled=true\n",
+        exe.display(),
+        dir.display()
+    )
+}
+
+#[cfg(unix)]
+pub fn is_enabled() -> bool {
+    entry_path().is_ok_and(|path| path.is_file())
+}
+
+// Only writes the entry; it takes effect at the next login, so this doesn't start a
+// second copy of the app now.
+#[cfg(unix)]
+fn install() -> Result<()> {
+    let path = entry_path()?;
+    let (exe, dir) = launch_target()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {:?}", parent))?;
+    }
+    std::fs::write(&path, entry_contents(&exe, &dir)).with_context(|| format!("Failed to write {:?}", path))
+}
+
+#[cfg(unix)]
+fn uninstall() -> Result<()> {
+    let path = entry_path()?;
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e).with_context(|| format!("Failed to remove {:?}", path)),
+        _ => Ok(()),
+    }
+}
+
+// The registry is edited with reg.exe rather than pulling in a registry crate.
+#[cfg(windows)]
+pub fn reg(args: &[&str]) -> Result<bool> {
+    let status = std::process::Command::new("reg")
+        .args(args)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .context("Failed to run reg.exe")?;
+    Ok(status.success())
+}
+
+#[cfg(windows)]
+pub fn is_enabled() -> bool {
+    reg(&["query", RUN_KEY, "/v", RUN_VALUE]).unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn install() -> Result<()> {
+    let (exe, dir) = launch_target()?;
+    // Run entries have no working directory of their own, so `start /d` sets it.
+    let command = format!("cmd.exe /c start \"\" /d \"{}\" \"{}\"", dir.display(), exe.display());
+    if !reg(&["add", RUN_KEY, "/v", RUN_VALUE, "/t", "REG_SZ", "/d", &command, "/f"])? {
+        anyhow::bail!("reg.exe could not add {}\\{}", RUN_KEY, RUN_VALUE);
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn uninstall() -> Result<()> {
+    if is_enabled() && !reg(&["delete", RUN_KEY, "/v", RUN_VALUE, "/f"])? {
+        anyhow::bail!("reg.exe could not delete {}\\{}", RUN_KEY, RUN_VALUE);
+    }
+    Ok(())
+}
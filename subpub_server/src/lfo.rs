@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::midi_actor::MidiHandle;
+use crate::zones::Zones;
+
+const DEFAULT_UPDATE_HZ: u32 = 50;
+const MAX_UPDATE_HZ: u32 = 1000;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LfoShape {
+    #[default]
+    Sine,
+    Triangle,
+    SawUp,
+    SawDown,
+    Square,
+}
+
+impl LfoShape {
+    // Wave value (-1.0 to 1.0) at the given phase (0.0 to 1.0).
+    fn sample(self, phase: f64) -> f64 {
+        match self {
+            LfoShape::Sine => (phase * TAU).sin(),
+            LfoShape::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            LfoShape::SawUp => 2.0 * phase - 1.0,
+            LfoShape::SawDown => 1.0 - 2.0 * phase,
+            LfoShape::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sine" => Some(LfoShape::Sine),
+            "triangle" => Some(LfoShape::Triangle),
+            "saw_up" => Some(LfoShape::SawUp),
+            "saw_down" => Some(LfoShape::SawDown),
+            "square" => Some(LfoShape::Square),
+            _ => None,
+        }
+    }
+}
+
+// An LFO modulating one CC, controlled by publishing to `control_topic`:
+// > PUB:lfo/filter:start / stop
+// > PUB:lfo/filter:rate:0.5        (Hz)
+// > PUB:lfo/filter:bpm:128         (beat-synced, one cycle every `beats` beats)
+// > PUB:lfo/filter:depth:40 / center:64 / shape:triangle
+// > PUB:lfo/filter:cc:74 / channel:2
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LfoConfig {
+    pub name: String,
+    pub control_topic: String,
+    pub channel: u8,
+    pub control_num: u8,
+    #[serde(default)]
+    pub shape: LfoShape,
+    // Free-running rate in Hz. Ignored when `bpm` is set.
+    pub rate_hz: Option<f64>,
+    // Beat-synced rate: one cycle every `beats` beats (default 1) at this tempo.
+    pub bpm: Option<f64>,
+    pub beats: Option<f64>,
+    // Swing around `center`, both in CC units (0-127).
+    pub depth: u8,
+    pub center: Option<u8>, // Defaults to 64
+    pub update_hz: Option<u32>, // CC values sent per second, defaults to 50
+    #[serde(default)]
+    pub autostart: bool,
+    // Room/zone this LFO plays into. Nothing is sent while the zone is disabled.
+    pub zone: Option<String>,
+}
+
+// Parameters that can be retargeted while the LFO runs.
+#[derive(Debug, Clone)]
+struct LfoParams {
+    shape: LfoShape,
+    rate_hz: f64,
+    beats: f64,
+    bpm: Option<f64>,
+    depth: u8,
+    center: u8,
+    channel: u8,
+    control_num: u8,
+}
+
+impl LfoParams {
+    fn cycles_per_second(&self) -> f64 {
+        match self.bpm {
+            Some(bpm) => bpm / 60.0 / self.beats,
+            None => self.rate_hz,
+        }
+    }
+
+    fn value_at(&self, phase: f64) -> u8 {
+        let value = self.center as f64 + self.shape.sample(phase) * self.depth as f64;
+        value.round().clamp(0.0, 127.0) as u8
+    }
+}
+
+struct LfoState {
+    config: LfoConfig,
+    params: Mutex<LfoParams>,
+    running: AtomicBool,
+    wake: Notify,
+}
+
+// Owns all configured LFOs for the lifetime of a server run.
+pub struct Lfos {
+    by_topic: HashMap<String, Arc<LfoState>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Lfos {
+    pub fn start(
+        configs: Vec<LfoConfig>,
+        midi: MidiHandle,
+        zones: Arc<Zones>,
+        runtime_handle: &Handle,
+    ) -> Self {
+        let mut by_topic = HashMap::new();
+        let mut tasks = Vec::new();
+
+        for config in configs {
+            if by_topic.contains_key(&config.control_topic) {
+                warn!("LFO '{}' reuses control topic '{}'. Skipping.", config.name, config.control_topic);
+                continue;
+            }
+            let params = LfoParams {
+                shape: config.shape,
+                rate_hz: config.rate_hz.unwrap_or(1.0).max(0.0),
+                beats: config.beats.unwrap_or(1.0).max(f64::EPSILON),
+                bpm: config.bpm,
+                depth: config.depth.min(127),
+                center: config.center.unwrap_or(64).min(127),
+                channel: config.channel & 0x0F,
+                control_num: config.control_num.min(127),
+            };
+            let state = Arc::new(LfoState {
+                running: AtomicBool::new(config.autostart),
+                config,
+                params: Mutex::new(params),
+                wake: Notify::new(),
+            });
+            info!("Loaded LFO '{}' on control topic '{}'", state.config.name, state.config.control_topic);
+            tasks.push(runtime_handle.spawn(run_lfo(state.clone(), midi.clone(), zones.clone())));
+            by_topic.insert(state.config.control_topic.clone(), state);
+        }
+
+        Self { by_topic, tasks }
+    }
+
+    // Handles a publish on an LFO control topic. Returns false if the topic isn't one.
+    pub fn handle_publish(&self, topic: &str, payload: &str) -> bool {
+        let Some(state) = self.by_topic.get(topic) else {
+            return false;
+        };
+        let name = &state.config.name;
+        let command = payload.trim().to_lowercase();
+
+        match command.as_str() {
+            "start" => {
+                state.running.store(true, Ordering::SeqCst);
+                state.wake.notify_one();
+                info!("LFO '{}' started.", name);
+            }
+            "stop" => {
+                state.running.store(false, Ordering::SeqCst);
+                info!("LFO '{}' stopped.", name);
+            }
+            _ => {
+                let Some((param, value)) = command.split_once(':') else {
+                    warn!("Unknown command '{}' for LFO '{}'.", command, name);
+                    return true;
+                };
+                let value = value.trim();
+                let mut params = state.params.lock().unwrap();
+                let applied = match param {
+                    "rate" => value.parse::<f64>().ok().filter(|v| *v >= 0.0).map(|v| {
+                        params.rate_hz = v;
+                        params.bpm = None;
+                    }),
+                    "bpm" => value.parse::<f64>().ok().filter(|v| *v > 0.0).map(|v| params.bpm = Some(v)),
+                    "depth" => value.parse::<u8>().ok().map(|v| params.depth = v.min(127)),
+                    "center" => value.parse::<u8>().ok().map(|v| params.center = v.min(127)),
+                    "shape" => LfoShape::parse(value).map(|v| params.shape = v),
+                    "cc" => value.parse::<u8>().ok().map(|v| params.control_num = v.min(127)),
+                    "channel" => value.parse::<u8>().ok().map(|v| params.channel = v & 0x0F),
+                    _ => None,
+                };
+                match applied {
+                    Some(()) => info!("LFO '{}' set {} to {}.", name, param, value),
+                    None => warn!("Invalid command '{}' for LFO '{}'.", command, name),
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Drop for Lfos {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// Playback loop for one LFO. Sleeps while stopped, restarts at phase 0 on start.
+async fn run_lfo(state: Arc<LfoState>, midi: MidiHandle, zones: Arc<Zones>) {
+    let config = &state.config;
+    let update_hz = config.update_hz.unwrap_or(DEFAULT_UPDATE_HZ).clamp(1, MAX_UPDATE_HZ);
+
+    loop {
+        while !state.running.load(Ordering::SeqCst) {
+            state.wake.notified().await;
+        }
+
+        let mut ticker = interval(Duration::from_secs(1) / update_hz);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut phase = 0.0f64;
+        let mut last_sent: Option<(u8, u8, u8)> = None;
+
+        while state.running.load(Ordering::SeqCst) {
+            ticker.tick().await;
+
+            // Phase is accumulated so rate changes don't make the wave jump.
+            let params = state.params.lock().unwrap().clone();
+            let value = params.value_at(phase);
+            phase = (phase + params.cycles_per_second() / update_hz as f64).fract();
+
+            if !zones.allows(config.zone.as_deref()) {
+                continue;
+            }
+            let target = (params.channel, params.control_num, value);
+            if last_sent == Some(target) {
+                continue;
+            }
+            midi.send_event(vec![0xB0 + params.channel, params.control_num, value]);
+            last_sent = Some(target);
+        }
+        debug!("LFO '{}' paused.", config.name);
+    }
+}
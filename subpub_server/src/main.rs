@@ -4,86 +4,381 @@ use anyhow::{Result, Context};
 use crossbeam_channel::unbounded;
 use tokio::runtime::Runtime;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 // tray-icon specific imports
 use tray_icon::{
-    menu::{Menu, MenuItem, MenuEvent, PredefinedMenuItem}, // Changed CustomMenuItem to MenuItem
+    menu::{Menu, MenuItem, CheckMenuItem, MenuEvent, PredefinedMenuItem, Submenu}, // Changed CustomMenuItem to MenuItem
     TrayIconBuilder, TrayIconEvent,
     Icon, // Changed icon::Icon to Icon
 };
 use image::GenericImageView; // For loading icon data
 
 // Tao for event loop
-use tao::{
-    event_loop::{ControlFlow, EventLoopBuilder},
-    platform::macos::EventLoopExtMacOS, // For set_activation_policy
-};
+use tao::event::Event;
+use tao::event_loop::{ControlFlow, EventLoopBuilder};
+#[cfg(target_os = "macos")]
+use tao::platform::macos::EventLoopExtMacOS; // For set_activation_policy
 
 // Logging specific imports
-use log4rs::append::console::ConsoleAppender;
+use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::encode::Encode;
 
 // MIDI Handler
 use crate::midi_handler::MidiHandler;
+// Server config and Show Mode
+use crate::config::{LogFormat, LoggingConfig, ServerConfig};
+use crate::json_log::JsonLineEncoder;
+use crate::show_mode::ShowMode;
+use crate::sys_events::{SysEvents, SYS_ALERT, SYS_MIDI_STATUS, SYS_SERVER_STATUS};
+use crate::notifications::Notifier;
+use crate::single_instance::{Instance, Request};
+use crate::safe_mode::SafeMode;
+use crate::server::AppServices;
+use crate::stats::{MidiOutputStats, Stats};
+use crate::zones::Zones;
+use crate::event_store::EventStore;
+use crate::hotkeys::{HotkeyPublishes, Hotkeys};
 
 // Declare the server module
 mod server;
 // Declare the MIDI handler module
 mod midi_handler;
+// Declare the server config module
+mod config;
+// Declare the Show Mode module
+mod show_mode;
+// Declare the $SYS events module
+mod sys_events;
+// Declare the step sequencer module
+mod sequencer;
+// Declare the stats collector module
+mod stats;
+// Declare the scale quantization module
+mod scale;
+// Declare the HTTP metrics/admin API module
+mod http_api;
+// Declare the payload normalizer module
+mod normalizer;
+// Declare the mapping schedule module
+mod schedule;
+// Declare the zones module
+mod zones;
+// Declare the client liveness registry module
+mod clients;
+// Declare the keepalive module
+mod keepalive;
+// Declare the diagnostics module
+mod diagnostics;
+// Declare the ramp module
+mod ramp;
+// Declare the lfo module
+mod lfo;
+// Declare the auth module
+mod auth;
+// Declare the humanize module
+mod humanize;
+// Declare the rotation module
+mod rotation;
+// Declare the safe_mode module
+mod safe_mode;
+// Declare the delivery module
+mod delivery;
+// Declare the pipe_bridge module
+mod pipe_bridge;
+// Declare the midi_input module
+mod midi_input;
+// Declare the midi_mirror module
+mod midi_mirror;
+// Declare the transforms module
+mod transforms;
+// Declare the federation module
+mod federation;
+// Declare the websocket_bridge module
+mod websocket_bridge;
+// Declare the failover module
+mod failover;
+// Declare the sacn module
+mod sacn;
+// Declare the hotkeys module
+mod hotkeys;
+// Declare the scheduler module
+mod scheduler;
+// Declare the serial_input module
+mod serial_input;
+// Declare the url_scheme module
+mod url_scheme;
+// Declare the payload_schema module
+mod payload_schema;
+// Declare the midi_file module
+mod midi_file;
+// Declare the auto_channels module
+mod auto_channels;
+// Declare the mapping_check module
+mod mapping_check;
+// Declare the mapping_schema module
+mod mapping_schema;
+// Declare the json_log module
+mod json_log;
+// Declare the message_ids module
+mod message_ids;
+// Declare the recent_events module
+mod recent_events;
+// Declare the subscription_store module
+mod subscription_store;
+// Declare the history module
+mod history;
+// Declare the channel_expiry module
+mod channel_expiry;
+// Declare the event_store module
+mod event_store;
+// Declare the session_replay module
+mod session_replay;
+// Declare the signing module
+mod signing;
+// Declare the acl module
+mod acl;
+// Declare the ip_filter module
+mod ip_filter;
+// Declare the transport module
+mod transport;
+// Declare the rate_limit module
+mod rate_limit;
+// Declare the fragments module
+mod fragments;
+// Declare the sequence module
+mod sequence;
+// Declare the network module
+mod network;
+// Declare the frames module
+mod frames;
+// Declare the protobuf module
+mod protobuf;
+// Declare the compression module
+mod compression;
+// Declare the delayed_publish module
+mod delayed_publish;
+// Declare the mdns module
+mod mdns;
+// Declare the client_stats module
+mod client_stats;
+// Declare the notifications module
+mod notifications;
+// Declare the single_instance module
+mod single_instance;
+// Declare the launch_at_login module
+mod launch_at_login;
+// Declare the watchdog module
+mod watchdog;
+// Declare the paths module
+mod paths;
+// Declare the midi_actor module
+mod midi_actor;
+// Declare the fanout module
+mod fanout;
+// Declare the bench module
+mod bench;
+
+// Menu ids of the per-zone check items are "zone:<name>"
+const MENU_ITEM_ZONE_PREFIX: &str = "zone:";
+
+// How often the tray's live entries are refreshed from the stats collector
+const TRAY_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+// An output counts as "active" if it sent something within this window
+const MIDI_ACTIVITY_WINDOW: Duration = Duration::from_secs(1);
+
+// Tray label for one MIDI output, e.g. "● Zerver — 42 msgs"
+fn midi_output_label(name: &str, output_stats: &MidiOutputStats) -> String {
+    let active = output_stats
+        .last_activity
+        .is_some_and(|t| t.elapsed() < MIDI_ACTIVITY_WINDOW);
+    let led = if active { "●" } else { "○" };
+    format!("{} {} — {} msgs", led, name, output_stats.messages_sent)
+}
+
+// Server state as reported by the server task, shown by tinting the tray icon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ServerState {
+    Running,
+    Stopped,
+    // run_server_application returned an error, e.g. the port was taken
+    Error,
+}
+
+// Tray icon for a server state, from the (already inverted) base RGBA. Running keeps the
+// icon as is, stopped dims it to half opacity and error turns it red.
+fn server_state_icon(rgba: &[u8], width: u32, height: u32, state: ServerState) -> Result<Icon> {
+    let mut pixels = rgba.to_vec();
+    for chunk in pixels.chunks_mut(4) {
+        if chunk.len() != 4 {
+            continue;
+        }
+        match state {
+            ServerState::Running => {}
+            ServerState::Stopped => chunk[3] /= 2,
+            ServerState::Error => {
+                // Keep the brightness so the icon's shape survives the tint
+                let luma = ((u16::from(chunk[0]) + u16::from(chunk[1]) + u16::from(chunk[2])) / 3) as u8;
+                chunk[0] = luma.max(160);
+                chunk[1] = luma / 4;
+                chunk[2] = luma / 4;
+            }
+        }
+    }
+    Icon::from_rgba(pixels, width, height).with_context(|| format!("Failed to create {:?} tray icon", state))
+}
 
-fn init_logging() -> Result<()> {
+// Status line at the top of the tray menu, from the $SYS/server/status text,
+// e.g. "Listening on 192.168.1.20:7878"
+fn server_status_label(status: &str) -> String {
+    let mut chars = status.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+// Levels offered in the tray's "Log Level" submenu, menu id "log_level:<level>"
+const MENU_ITEM_LOG_LEVEL_PREFIX: &str = "log_level:";
+const LOG_LEVELS: [(LevelFilter, &str); 5] = [
+    (LevelFilter::Error, "Error"),
+    (LevelFilter::Warn, "Warn"),
+    (LevelFilter::Info, "Info"),
+    (LevelFilter::Debug, "Debug"),
+    (LevelFilter::Trace, "Trace"),
+];
+// Root level at startup
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+
+// Builds the log4rs config for the given root level. Called again on every level change,
+// since log4rs can only swap whole configs through its handle.
+fn log_config(level: LevelFilter) -> Result<Config> {
     // Pattern for log messages
     let log_pattern = "{d(%Y-%m-%d %H:%M:%S%.3f %Z)(utc)} [{l}] {M} - {m}{n}";
-    // Log file path
-    let log_file_path = "subpub_server.log";
-
+    let log_format = LoggingConfig::peek().format;
+    let encoder = || -> Box<dyn Encode> {
+        match log_format {
+            LogFormat::Pattern => Box::new(PatternEncoder::new(log_pattern)),
+            LogFormat::Json => Box::new(JsonLineEncoder),
+        }
+    };
     // Console appender
+    // Logs go to stderr so stdout stays clean for the pipe bridge's NDJSON output.
     let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_pattern)))
+        .encoder(encoder())
+        .target(Target::Stderr)
         .build();
 
-    // File appender
+    // File appender, in the data directory (see paths.rs)
     // TODO: Add log rotation in the future if needed
     let file_appender = FileAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(log_pattern)))
-        .build(log_file_path)
-        .context(format!("Failed to create file appender at {}", log_file_path))?;
+        .encoder(encoder())
+        .build(paths::log_file())
+        .context(format!("Failed to create file appender at {}", paths::log_file().display()))?;
 
     // Log4rs config
-    let config = Config::builder()
+    Config::builder()
         .appender(Appender::builder().build("stdout", Box::new(stdout)))
         .appender(Appender::builder().build("file", Box::new(file_appender)))
         .build(
             Root::builder()
                 .appender("stdout")
                 .appender("file")
-                .build(LevelFilter::Debug), // Set global log level to Debug for more verbose output
+                .build(level),
         )
-        .context("Failed to build log4rs config")?;
+        .context("Failed to build log4rs config")
+}
 
-    // Initialize log4rs
-    log4rs::init_config(config).context("Failed to initialize log4rs")?;
+// Returns the handle used by the tray to change the log level at runtime
+fn init_logging() -> Result<log4rs::Handle> {
+    let config = log_config(DEFAULT_LOG_LEVEL)?;
 
-    Ok(())
+    // Initialize log4rs
+    log4rs::init_config(config).context("Failed to initialize log4rs")
 }
 
-
 fn main() -> Result<()> {
+    // Resolves the config and data directories before anything reads or writes a file
+    let path_notes = paths::init();
+
+    // `--check-mappings [file]` validates a mapping file and exits without starting the server.
+    // `--migrate-mappings [file]` rewrites an older mapping file in the current format.
+    // `--bench [options]` load-tests a running server (see bench.rs).
+    let args: Vec<String> = std::env::args().collect();
+    let default_path = midi_handler::mapping_file_path().display().to_string();
+    let file_arg = |pos: usize| args.get(pos + 1).cloned().unwrap_or_else(|| default_path.clone());
+    if let Some(pos) = args.iter().position(|arg| arg == "--check-mappings") {
+        std::process::exit(mapping_check::run_cli(&file_arg(pos)));
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--migrate-mappings") {
+        std::process::exit(mapping_schema::run_migrate_cli(&file_arg(pos)));
+    }
+    if let Some(pos) = args.iter().position(|arg| arg == "--bench") {
+        std::process::exit(bench::run_cli(&args[pos + 1..]));
+    }
+
     // Initialize logging
-    init_logging().context("Failed to initialize application logging")?;
+    let log_handle = init_logging().context("Failed to initialize application logging")?;
+    info!("Config directory: {:?}, data directory: {:?}", paths::config_dir(), paths::data_dir());
+    for note in path_notes {
+        info!("{}", note);
+    }
+
+    // A second launch asks the running instance to show its status (or to open the
+    // subpub:// link it was started with) and exits, before it creates a second MIDI port
+    // and tray icon.
+    let launch_url = url_scheme::launch_url(&args);
+    let instance_rx = match single_instance::acquire(launch_url.as_deref()) {
+        Instance::Primary(instance_rx) => instance_rx,
+        Instance::AlreadyRunning if launch_url.is_some() => {
+            info!("SubPub Server is already running; handed it the link. Exiting.");
+            return Ok(());
+        }
+        Instance::AlreadyRunning => {
+            info!("SubPub Server is already running; asked it to show its status. Exiting.");
+            return Ok(());
+        }
+    };
+
+    // $SYS status events, also used to drive the tray tooltip
+    let sys_events = SysEvents::new();
+    let mut sys_events_rx = sys_events.subscribe();
+    // Entered below if the config or the mappings can't be loaded
+    let safe_mode = SafeMode::new(sys_events.clone());
+
+    // Load server config and set up Show Mode
+    let server_config = Arc::new(ServerConfig::load(&safe_mode));
+    let show_mode = ShowMode::new(&server_config.show_mode);
+
+    // Stats collector shared with the MIDI handler and server
+    let stats = Stats::new(server_config.recent_events.capacity);
+    // Zone-enable matrix shared with the MIDI handler, server and tray
+    let zones = Zones::new();
+    // SQLite log of publishes and MIDI messages, if enabled
+    let event_store = EventStore::open(&server_config.event_log);
 
     // Initialize MIDI Handler
-    let midi_handler_arc = MidiHandler::new().context("Failed to initialize MIDI handler")?;
-    info!("MIDI Handler creation attempted."); // MidiHandler::new() already logs its own success/failure
+    let midi_handler_arc = MidiHandler::start(
+        &server_config,
+        sys_events.clone(),
+        stats.clone(),
+        zones.clone(),
+        safe_mode.clone(),
+        event_store.clone(),
+    )
+    .context("Failed to initialize MIDI handler")?;
+    info!("MIDI Handler creation attempted."); // MidiHandler::start() already logs its own success/failure
 
     info!("Starting SubPub Tray Icon Application with tray-icon...");
 
-    // Icon
+    // Icon, built into the binary so it works from any working directory
     let icon_path = "src/subpub.ico";
-    let img = image::open(icon_path)
-        .with_context(|| format!("Failed to load image from path: {}", icon_path))?;
+    let img = image::load_from_memory(include_bytes!("subpub.ico"))
+        .with_context(|| format!("Failed to load image from {}", icon_path))?;
     let (width, height) = img.dimensions();
     let mut rgba = img.to_rgba8().into_raw(); // Make rgba mutable
 
@@ -100,33 +395,75 @@ fn main() -> Result<()> {
         }
     }
     
-    let icon = Icon::from_rgba(rgba, width, height)
+    // One variant per server state, swapped in as the server starts and stops
+    let running_icon = server_state_icon(&rgba, width, height, ServerState::Running)
         .with_context(|| format!("Failed to create icon from RGBA data from path: {}", icon_path))?;
+    let stopped_icon = server_state_icon(&rgba, width, height, ServerState::Stopped)?;
+    let error_icon = server_state_icon(&rgba, width, height, ServerState::Error)?;
 
     // Menu items
     const MENU_ITEM_START_ID: &str = "start_server";
     const MENU_ITEM_STOP_ID: &str = "stop_server";
     const MENU_ITEM_RELOAD_MIDI_ID: &str = "reload_midi_mappings"; // New ID
+    const MENU_ITEM_SHOW_MODE_ID: &str = "show_mode";
+    const MENU_ITEM_START_AT_LOGIN_ID: &str = "start_at_login";
+    const MENU_ITEM_EXPORT_DIAGNOSTICS_ID: &str = "export_diagnostics";
+    const MENU_ITEM_DUMP_RECENT_EVENTS_ID: &str = "dump_recent_events";
     const MENU_ITEM_QUIT_ID: &str = "quit_app";
 
     let tray_menu = Menu::new();
     // Corrected MenuItem::with_id arguments: id, text, enabled, accelerator
     let start_item = MenuItem::with_id(MENU_ITEM_START_ID, "Start Server", true, None);
-    let stop_item = MenuItem::with_id(MENU_ITEM_STOP_ID, "Stop Server", true, None);
+    // Only one of Start/Stop is enabled at a time, following the server task's state
+    let stop_item = MenuItem::with_id(MENU_ITEM_STOP_ID, "Stop Server", false, None);
+    // Read-only status line, updated from $SYS/server/status
+    let server_status_item = MenuItem::new(server_status_label("stopped"), false, None);
     let reload_midi_item = MenuItem::with_id(MENU_ITEM_RELOAD_MIDI_ID, "Reload MIDI Mappings", true, None); // New item
+    let show_mode_item = CheckMenuItem::with_id(MENU_ITEM_SHOW_MODE_ID, "Show Mode", true, show_mode.is_active(), None);
+    let start_at_login_item =
+        CheckMenuItem::with_id(MENU_ITEM_START_AT_LOGIN_ID, "Start at Login", true, launch_at_login::is_enabled(), None);
+    let export_diagnostics_item = MenuItem::with_id(MENU_ITEM_EXPORT_DIAGNOSTICS_ID, "Export Diagnostics", true, None);
+    let dump_recent_events_item = MenuItem::with_id(MENU_ITEM_DUMP_RECENT_EVENTS_ID, "Dump Recent Events", true, None);
     let quit_item = MenuItem::with_id(MENU_ITEM_QUIT_ID, "Quit", true, None);
     
+    tray_menu.append(&server_status_item).context("Failed to add server status menu item")?;
+    // Live subscription counts, refreshed by the event loop
+    let channels_item = MenuItem::new("Channels: 0", false, None);
+    let subscribers_item = MenuItem::new("Subscribers: 0", false, None);
+    tray_menu.append(&channels_item).context("Failed to add channel count menu item")?;
+    tray_menu.append(&subscribers_item).context("Failed to add subscriber count menu item")?;
+    tray_menu.append(&PredefinedMenuItem::separator()).context("Failed to add separator")?;
     tray_menu.append(&start_item).context("Failed to add 'Start Server' menu item")?;
     tray_menu.append(&stop_item).context("Failed to add 'Stop Server' menu item")?;
     tray_menu.append(&reload_midi_item).context("Failed to add 'Reload MIDI Mappings' menu item")?; // Add new item
     tray_menu.append(&PredefinedMenuItem::separator()).context("Failed to add separator")?;
+    // Per-output activity indicators, filled in and refreshed by the event loop
+    let midi_outputs_submenu = Submenu::new("MIDI Outputs", true);
+    tray_menu.append(&midi_outputs_submenu).context("Failed to add 'MIDI Outputs' submenu")?;
+    // Per-zone enable toggles, filled in and kept in sync by the event loop
+    let zones_submenu = Submenu::new("Zones", true);
+    tray_menu.append(&zones_submenu).context("Failed to add 'Zones' submenu")?;
+    tray_menu.append(&show_mode_item).context("Failed to add 'Show Mode' menu item")?;
+    tray_menu.append(&start_at_login_item).context("Failed to add 'Start at Login' menu item")?;
+    tray_menu.append(&PredefinedMenuItem::separator()).context("Failed to add separator")?;
+    // Root log level, switchable without a restart (e.g. Debug while chasing a mis-firing mapping)
+    let log_level_submenu = Submenu::new("Log Level", true);
+    let mut log_level_items: Vec<(LevelFilter, CheckMenuItem)> = Vec::new();
+    for (level, label) in LOG_LEVELS {
+        let id = format!("{}{}", MENU_ITEM_LOG_LEVEL_PREFIX, level);
+        let item = CheckMenuItem::with_id(id, label, true, level == DEFAULT_LOG_LEVEL, None);
+        log_level_submenu.append(&item).with_context(|| format!("Failed to add '{}' log level menu item", label))?;
+        log_level_items.push((level, item));
+    }
+    tray_menu.append(&log_level_submenu).context("Failed to add 'Log Level' submenu")?;
+    tray_menu.append(&dump_recent_events_item).context("Failed to add 'Dump Recent Events' menu item")?;
+    tray_menu.append(&export_diagnostics_item).context("Failed to add 'Export Diagnostics' menu item")?;
     tray_menu.append(&quit_item).context("Failed to add 'Quit' menu item")?;
 
     // Channels for communication with server task
     let (server_shutdown_tx, server_shutdown_rx) = unbounded::<()>();
-    // server_status_tx might be useful for updating menu item states (e.g., enable/disable Start/Stop)
-    // For now, let's keep it simple.
-    let (server_status_tx, _server_status_rx) = unbounded::<bool>(); 
+    // The server task reports Running when it starts and Stopped/Error when it ends
+    let (server_status_tx, server_status_rx) = unbounded::<ServerState>();
 
 
     // Arc to hold the Tokio runtime handle and the server task handle
@@ -135,22 +472,41 @@ fn main() -> Result<()> {
     
     let quit_flag = Arc::new(AtomicBool::new(false));
     
-    // Create tao event loop & set activation policy for macOS
+    // Create tao event loop & set activation policy for macOS.
+    // On Linux the event loop also initializes GTK, which the tray icon needs.
+    #[allow(unused_mut)]
     let mut event_loop = EventLoopBuilder::<()>::with_user_event().build(); // Use with_user_event for custom events if needed later
+    // Tray-only app: no Dock icon
+    #[cfg(target_os = "macos")]
     event_loop.set_activation_policy(tao::platform::macos::ActivationPolicy::Accessory);
     
     // The _tray_icon variable needs to be kept alive.
     // It's created here and its lifetime is tied to the main function's scope,
     // which is fine as event_loop.run will block.
-    let _tray_icon_instance = TrayIconBuilder::new()
+    let tray_icon_instance = TrayIconBuilder::new()
         .with_menu(Box::new(tray_menu)) // tray_menu was defined earlier
         .with_tooltip("SubPub Server")
-        .with_icon(icon.clone()) // icon was defined earlier, clone if Icon is not Copy
+        .with_icon(stopped_icon.clone()) // Not running until Start (or auto-start)
         .build()
         .context("Failed to build tray icon")?;
     
     info!("Tray icon created. Starting tao event loop.");
 
+    // Global hotkeys, registered on the main thread like the tray icon
+    let hotkeys = Hotkeys::register(&server_config.hotkeys);
+    let hotkey_publishes = HotkeyPublishes::new();
+    // subpub:// links
+    let url_handler_config = server_config.url_handler.clone();
+    if url_handler_config.enabled
+        && url_handler_config.register
+        && let Err(e) = url_scheme::register()
+    {
+        warn!("Failed to register the handler for subpub:// links: {:#}", e);
+    }
+    let mut launch_urls: Vec<String> = launch_url.into_iter().collect();
+    // Held while the server is starting up
+    let mut pending_url_publishes: Vec<url_scheme::UrlPublish> = Vec::new();
+
     // Clone Arcs and other variables needed for the event loop closure
     let rt_handle_arc_clone = rt_handle_arc.clone();
     let server_task_handle_arc_clone = server_task_handle_arc.clone();
@@ -159,8 +515,33 @@ fn main() -> Result<()> {
     let server_shutdown_rx_clone_for_start = server_shutdown_rx.clone(); // Specifically for start
     let quit_flag_clone_for_event_loop = quit_flag.clone();
     let midi_handler_clone_for_event_loop = midi_handler_arc.clone(); // Clone for event loop
-
-    event_loop.run(move |_event, _, control_flow| {
+    let show_mode_clone_for_event_loop = show_mode.clone();
+    // Long-lived services handed to every server run
+    let services_clone_for_event_loop = AppServices {
+        config: server_config.clone(),
+        sys_events: sys_events.clone(),
+        stats: stats.clone(),
+        zones: zones.clone(),
+        safe_mode: safe_mode.clone(),
+        show_mode: show_mode.clone(),
+        event_store: event_store.clone(),
+        hotkey_publishes: hotkey_publishes.clone(),
+    };
+    // Latest status lines shown in the tray tooltip
+    let mut midi_status = String::from("starting");
+    let mut server_status = String::from("stopped");
+    let stats_clone_for_event_loop = stats.clone();
+    let mut midi_output_items: HashMap<String, MenuItem> = HashMap::new();
+    let zones_clone_for_event_loop = zones.clone();
+    let mut zone_items: HashMap<String, CheckMenuItem> = HashMap::new();
+    let mut log_level = DEFAULT_LOG_LEVEL;
+    let mut last_tray_refresh = Instant::now() - TRAY_REFRESH_INTERVAL;
+    // In safe mode the server always comes up, so the admin API is reachable for recovery.
+    let mut auto_start_pending = server_config.startup.auto_start_server || safe_mode.is_active();
+    let mut alert_status = safe_mode.summary();
+    let notifier = Notifier::new(&server_config.notifications);
+
+    event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll; 
 
         if quit_flag_clone_for_event_loop.load(Ordering::SeqCst) {
@@ -188,9 +569,16 @@ fn main() -> Result<()> {
         }
 
         // Process menu events
-        if let Ok(menu_event) = MenuEvent::receiver().try_recv() {
-            // Removed verbose: info!("Menu event: {:?}", menu_event);
-            match menu_event.id.0.as_str() {
+        // A configured auto-start is handled like a click on "Start Server".
+        let menu_id = if std::mem::take(&mut auto_start_pending) {
+            info!("Auto-starting server (startup.auto_start_server).");
+            Some(MENU_ITEM_START_ID.to_string())
+        } else {
+            MenuEvent::receiver().try_recv().ok().map(|menu_event| menu_event.id.0)
+        };
+        if let Some(menu_id) = menu_id {
+            // Removed verbose: info!("Menu event: {:?}", menu_id);
+            match menu_id.as_str() {
                 MENU_ITEM_START_ID => {
                     let mut rt_guard = rt_handle_arc_clone.lock().unwrap();
                     let mut task_guard = server_task_handle_arc_clone.lock().unwrap();
@@ -206,17 +594,23 @@ fn main() -> Result<()> {
                         let shutdown_rx_for_task = server_shutdown_rx_clone_for_start.clone();
                         let status_tx_for_task = server_status_tx_clone_for_start.clone();
                         let midi_handler_for_task = midi_handler_clone_for_event_loop.clone(); // Clone for server task
+                        let services_for_task = services_clone_for_event_loop.clone();
 
                         let task = handle_for_spawn_call.spawn(async move {
-                            status_tx_for_task.send(true).unwrap_or_else(|e| error!("Failed to send server start status: {}",e));
+                            status_tx_for_task.send(ServerState::Running).unwrap_or_else(|e| error!("Failed to send server start status: {}",e));
                             // Pass midi_handler_for_task to run_server_application
                             // The signature of run_server_application will need to be updated
                             let result = server::run_server_application(
                                 handle_for_async_block, 
                                 shutdown_rx_for_task,
-                                midi_handler_for_task // New argument
+                                midi_handler_for_task, // New argument
+                                services_for_task,
                             ).await;
-                            status_tx_for_task.send(false).unwrap_or_else(|e| error!("Failed to send server stop status: {}",e));
+                            if let Err(e) = &result {
+                                error!("Server stopped with an error: {:#}", e);
+                            }
+                            let state = if result.is_err() { ServerState::Error } else { ServerState::Stopped };
+                            status_tx_for_task.send(state).unwrap_or_else(|e| error!("Failed to send server stop status: {}",e));
                             result
                         });
                         *task_guard = Some(task);
@@ -226,6 +620,10 @@ fn main() -> Result<()> {
                     }
                 }
                 MENU_ITEM_STOP_ID => {
+                    if let Err(e) = show_mode_clone_for_event_loop.authorize_interactively("Stop Server") {
+                        warn!("{}. Disable Show Mode first.", e);
+                        return;
+                    }
                     let mut rt_guard = rt_handle_arc_clone.lock().unwrap();
                     let mut task_guard = server_task_handle_arc_clone.lock().unwrap();
 
@@ -246,28 +644,256 @@ fn main() -> Result<()> {
                     }
                 }
                 MENU_ITEM_QUIT_ID => {
+                    // Quitting stops the server too, so it is locked like Stop.
+                    if let Err(e) = show_mode_clone_for_event_loop.authorize_interactively("Quit") {
+                        warn!("{}. Disable Show Mode first.", e);
+                        return;
+                    }
                     info!("Quit menu item selected. Setting quit flag.");
                     quit_flag_clone_for_event_loop.store(true, Ordering::SeqCst);
                     // The actual server stop and exit will happen at the start of the next loop iteration.
                 }
                 MENU_ITEM_RELOAD_MIDI_ID => {
                     info!("Reload MIDI Mappings menu item selected.");
-                    match midi_handler_clone_for_event_loop.lock() {
-                        Ok(mut handler) => {
-                            if let Err(e) = handler.reload_mappings() {
-                                error!("Failed to reload MIDI mappings: {:?}", e);
-                            } else {
-                                info!("MIDI mappings reloaded successfully via menu.");
+                    if let Err(e) = show_mode_clone_for_event_loop.authorize_interactively("Reload MIDI Mappings") {
+                        warn!("{}. Disable Show Mode first.", e);
+                        return;
+                    }
+                    match midi_handler_clone_for_event_loop.reload_blocking() {
+                        Ok(()) => info!("MIDI mappings reloaded successfully via menu."),
+                        Err(e) => error!("Failed to reload MIDI mappings: {:?}", e),
+                    }
+                }
+                MENU_ITEM_EXPORT_DIAGNOSTICS_ID => {
+                    info!("Export Diagnostics menu item selected.");
+                    if let Err(e) = diagnostics::export_diagnostics(&stats_clone_for_event_loop) {
+                        error!("Failed to export diagnostics: {:?}", e);
+                    }
+                }
+                MENU_ITEM_DUMP_RECENT_EVENTS_ID => {
+                    info!("Dump Recent Events menu item selected.");
+                    if let Err(e) = stats_clone_for_event_loop.recent_events().dump_to_file() {
+                        error!("Failed to dump recent events: {:?}", e);
+                    }
+                }
+                MENU_ITEM_SHOW_MODE_ID => {
+                    // The check mark is toggled by the menu itself. Leaving Show Mode is
+                    // itself locked, so put the check mark back when that is refused.
+                    let enable = show_mode_item.is_checked();
+                    if !enable && let Err(e) = show_mode_clone_for_event_loop.authorize_interactively("Disable Show Mode") {
+                        warn!("{}.", e);
+                        show_mode_item.set_checked(true);
+                        return;
+                    }
+                    show_mode_clone_for_event_loop.set_active(enable);
+                }
+                MENU_ITEM_START_AT_LOGIN_ID => {
+                    let enabled = start_at_login_item.is_checked();
+                    if let Err(e) = launch_at_login::set_enabled(enabled) {
+                        error!("Failed to change Start at Login: {:?}", e);
+                    }
+                    // Show what is actually registered, also after a failure.
+                    start_at_login_item.set_checked(launch_at_login::is_enabled());
+                }
+                id if id.starts_with(MENU_ITEM_LOG_LEVEL_PREFIX) => {
+                    let requested = id[MENU_ITEM_LOG_LEVEL_PREFIX.len()..].parse::<LevelFilter>().ok();
+                    if let Some(requested) = requested.filter(|requested| *requested != log_level) {
+                        info!("Changing log level from {} to {}.", log_level, requested);
+                        match log_config(requested) {
+                            Ok(config) => {
+                                log_handle.set_config(config);
+                                log_level = requested;
                             }
+                            Err(e) => error!("Failed to change log level: {:?}", e),
                         }
-                        Err(e) => {
-                            error!("Failed to lock MIDI handler for reloading mappings: {:?}", e);
+                    }
+                    // Check items toggle themselves, so keep exactly the active level checked.
+                    for (level, item) in &log_level_items {
+                        item.set_checked(*level == log_level);
+                    }
+                }
+                id if id.starts_with(MENU_ITEM_ZONE_PREFIX) => {
+                    let zone = &id[MENU_ITEM_ZONE_PREFIX.len()..];
+                    if let Some(item) = zone_items.get(zone) {
+                        let enabled = item.is_checked();
+                        let action = format!("{} zone '{}'", if enabled { "Enable" } else { "Disable" }, zone);
+                        match show_mode_clone_for_event_loop.authorize_interactively(&action) {
+                            Ok(()) => zones_clone_for_event_loop.set_enabled(zone, enabled),
+                            Err(e) => {
+                                warn!("{}. Disable Show Mode first.", e);
+                                item.set_checked(!enabled);
+                            }
                         }
                     }
                 }
                 _ => {
-                    warn!("Unhandled menu event id: {:?}", menu_event.id);
+                    warn!("Unhandled menu event id: {:?}", menu_id);
+                }
+            }
+        }
+
+        // Update the tooltip from $SYS status events
+        let mut status_changed = false;
+        while let Ok(sys_event) = sys_events_rx.try_recv() {
+            notifier.on_sys_event(&sys_event);
+            match sys_event.topic.as_str() {
+                SYS_MIDI_STATUS => midi_status = sys_event.payload,
+                SYS_SERVER_STATUS => {
+                    server_status_item.set_text(server_status_label(&sys_event.payload));
+                    server_status = sys_event.payload;
+                }
+                SYS_ALERT => alert_status = (sys_event.payload != "ok").then_some(sys_event.payload),
+                _ => continue,
+            }
+            status_changed = true;
+        }
+        if status_changed {
+            let mut tooltip = format!("SubPub Server\nMIDI: {}\nServer: {}", midi_status, server_status);
+            if let Some(alert) = &alert_status {
+                tooltip = format!("⚠️ {}\n{}", alert, tooltip);
+            }
+            if let Err(e) = tray_icon_instance.set_tooltip(Some(tooltip)) {
+                error!("Failed to update tray tooltip: {:?}", e);
+            }
+        }
+
+        // Links from a later launch (Windows, Linux), from macOS, or the one this app was started with
+        let mut opened_urls = std::mem::take(&mut launch_urls);
+        if let Event::Opened { urls } = &event {
+            opened_urls.extend(urls.iter().map(|url| url.to_string()));
+        }
+
+        // Later launches were turned away; show what this instance is doing instead, or take their link
+        for request in instance_rx.try_iter() {
+            match request {
+                Request::ShowStatus => {
+                    let status = format!("MIDI: {}\nServer: {}", midi_status, server_status);
+                    notifier.notify("SubPub Server is already running", &status);
+                }
+                Request::OpenUrl(url) => opened_urls.push(url),
+            }
+        }
+
+        // Swap the tray icon when the server task reports a new state
+        if let Some(state) = server_status_rx.try_iter().last() {
+            let icon = match state {
+                ServerState::Running => &running_icon,
+                ServerState::Stopped => &stopped_icon,
+                ServerState::Error => &error_icon,
+            };
+            if let Err(e) = tray_icon_instance.set_icon(Some(icon.clone())) {
+                error!("Failed to update tray icon for {:?} server: {:?}", state, e);
+            }
+            let running = state == ServerState::Running;
+            start_item.set_enabled(!running);
+            stop_item.set_enabled(running);
+            // A server that ended on its own (e.g. bind failed) still holds its runtime;
+            // release it so "Start Server" works again. After a Stop the runtime is already gone.
+            if !running {
+                let mut rt_guard = rt_handle_arc_clone.lock().unwrap();
+                let mut task_guard = server_task_handle_arc_clone.lock().unwrap();
+                if let Some(rt) = rt_guard.take() {
+                    if let Some(task_handle) = task_guard.take() {
+                        match rt.block_on(task_handle) {
+                            Ok(Err(e)) => {
+                                error!("Server stopped with an error: {:?}", e);
+                                notifier.notify("Server stopped with an error", &format!("{:#}", e));
+                            }
+                            Err(e) => error!("Server task failed to join: {:?}", e),
+                            Ok(Ok(())) => {}
+                        }
+                    }
+                    rt.shutdown_background();
+                }
+            }
+        }
+
+        // Refresh per-output MIDI activity entries
+        let refresh_tray = last_tray_refresh.elapsed() >= TRAY_REFRESH_INTERVAL;
+        if refresh_tray {
+            last_tray_refresh = Instant::now();
+            for (name, output_stats) in stats_clone_for_event_loop.midi_outputs_snapshot() {
+                let label = midi_output_label(&name, &output_stats);
+                match midi_output_items.get(&name) {
+                    Some(item) => {
+                        if item.text() != label {
+                            item.set_text(label);
+                        }
+                    }
+                    None => {
+                        let item = MenuItem::new(label, false, None);
+                        if let Err(e) = midi_outputs_submenu.append(&item) {
+                            error!("Failed to add MIDI output '{}' to tray menu: {:?}", name, e);
+                        }
+                        midi_output_items.insert(name, item);
+                    }
+                }
+            }
+        }
+
+        // Refresh channel and subscriber counts
+        if refresh_tray {
+            let (channels, subscribers) = stats_clone_for_event_loop.subscription_counts();
+            let channels_label = format!("Channels: {}", channels);
+            let subscribers_label = format!("Subscribers: {}", subscribers);
+            if channels_item.text() != channels_label {
+                channels_item.set_text(channels_label);
+            }
+            if subscribers_item.text() != subscribers_label {
+                subscribers_item.set_text(subscribers_label);
+            }
+        }
+
+        // Keep zone toggles in sync (zones can also be changed via the admin API)
+        if refresh_tray {
+            for (zone, enabled) in zones_clone_for_event_loop.snapshot() {
+                match zone_items.get(&zone) {
+                    Some(item) => {
+                        if item.is_checked() != enabled {
+                            item.set_checked(enabled);
+                        }
+                    }
+                    None => {
+                        let id = format!("{}{}", MENU_ITEM_ZONE_PREFIX, zone);
+                        let item = CheckMenuItem::with_id(id, &zone, true, enabled, None);
+                        if let Err(e) = zones_submenu.append(&item) {
+                            error!("Failed to add zone '{}' to tray menu: {:?}", zone, e);
+                        }
+                        zone_items.insert(zone, item);
+                    }
+                }
+            }
+        }
+
+        // Hotkeys fire their publishes on the running server
+        if let Some(hotkeys) = &hotkeys {
+            for binding in hotkeys.pressed() {
+                hotkey_publishes.send(binding);
+            }
+        }
+
+        // So do links, once the server has started
+        for url in opened_urls {
+            if !url_handler_config.enabled {
+                warn!("Ignoring link {}: [url_handler] is not enabled.", url);
+                continue;
+            }
+            match url_scheme::parse(&url, &url_handler_config) {
+                Ok(publish) => {
+                    info!(topic = publish.topic.as_str(); "Link opened: publishing to '{}'", publish.topic);
+                    pending_url_publishes.push(publish);
+                }
+                Err(e) => warn!("Ignoring link {}: {:#}", url, e),
+            }
+        }
+        if !pending_url_publishes.is_empty() {
+            if hotkey_publishes.server_listening() {
+                for publish in pending_url_publishes.drain(..) {
+                    hotkey_publishes.publish(&publish.topic, &publish.payload);
                 }
+            } else if rt_handle_arc_clone.lock().unwrap().is_none() {
+                warn!("Ignoring {} link(s): the server isn't running.", pending_url_publishes.len());
+                pending_url_publishes.clear();
             }
         }
 
@@ -0,0 +1,446 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::Spanned;
+
+use crate::mapping_schema::{self, CURRENT_MAPPING_VERSION};
+use crate::midi_handler::{MidiAction, MidiActionType, MidiMappingConfig};
+
+// One problem found in a mapping file.
+pub struct MappingIssue {
+    pub file: Option<PathBuf>, // Set for problems in included files
+    pub line: Option<usize>,   // 1-based, when it can be pinned down
+    pub message: String,
+}
+
+impl fmt::Display for MappingIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// Mapping files can be TOML (the default), or JSON / YAML for generated mappings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MappingFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl MappingFormat {
+    // Picked by file extension; anything unknown is read as TOML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("json") => MappingFormat::Json,
+            Some("yaml") | Some("yml") => MappingFormat::Yaml,
+            _ => MappingFormat::Toml,
+        }
+    }
+}
+
+// Just enough of the file to point mapping issues at a line.
+#[derive(Deserialize)]
+struct MappingLines {
+    #[serde(default)]
+    mappings: Vec<EntryLine>,
+}
+
+#[derive(Deserialize)]
+struct EntryLine {
+    sub_topic: Spanned<String>,
+}
+
+// Parses a mapping file and checks it for things that would only go wrong at send time:
+// out-of-range channels and values, fields the action type ignores, duplicate topics.
+// Unknown keys are already rejected by the parse itself. Older files are migrated first.
+pub fn check_mappings(toml_str: &str) -> Result<MidiMappingConfig, Vec<MappingIssue>> {
+    let migrated = match mapping_schema::migrate(toml_str, false) {
+        Ok(migrated) => migrated.text,
+        // Syntax errors are reported with their line by the parse below.
+        Err(e) if toml_str.parse::<toml::Table>().is_ok() => {
+            return Err(vec![MappingIssue { file: None, line: None, message: format!("{:#}", e) }]);
+        }
+        Err(_) => toml_str.to_string(),
+    };
+    let toml_str = migrated.as_str();
+    let mut config: MidiMappingConfig = toml::from_str(toml_str).map_err(|e| {
+        vec![MappingIssue {
+            file: None,
+            line: e.span().map(|span| line_of(toml_str, span.start)),
+            message: e.message().trim().to_string(),
+        }]
+    })?;
+    let entry_lines: Vec<usize> = toml::from_str::<MappingLines>(toml_str)
+        .map(|lines| lines.mappings.iter().map(|m| line_of(toml_str, m.sub_topic.span().start)).collect())
+        .unwrap_or_default();
+
+    let issues = validate(&config, &entry_lines);
+    if !issues.is_empty() {
+        return Err(issues);
+    }
+    config.version = Some(CURRENT_MAPPING_VERSION);
+    Ok(config)
+}
+
+// Loads a mapping file together with everything it includes.
+pub fn check_mapping_file(path: &Path) -> Result<MidiMappingConfig, Vec<MappingIssue>> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| vec![MappingIssue { file: None, line: None, message: format!("Failed to read {:?}: {}", path, e) }])?;
+    check_mapping_tree(path, &text)
+}
+
+// The `include` entries of a mapping file as written, without loading or checking anything.
+pub fn include_entries(path: &Path) -> Vec<String> {
+    #[derive(Deserialize)]
+    struct IncludesOnly {
+        #[serde(default)]
+        include: Vec<String>,
+    }
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let parsed: Option<IncludesOnly> = match MappingFormat::from_path(path) {
+        MappingFormat::Toml => toml::from_str(&text).ok(),
+        MappingFormat::Json => serde_json::from_str(&text).ok(),
+        MappingFormat::Yaml => serde_yaml::from_str(&text).ok(),
+    };
+    parsed.map(|parsed| parsed.include).unwrap_or_default()
+}
+
+// Checks `text` (the contents of `path`) and merges in its `include`d files, which are
+// resolved relative to `path`. A topic mapped in two different files is an error.
+pub fn check_mapping_tree(path: &Path, text: &str) -> Result<MidiMappingConfig, Vec<MappingIssue>> {
+    let mut loader = IncludeLoader::default();
+    let config = loader.load(path, text, true);
+    match config {
+        Some(config) if loader.issues.is_empty() => Ok(config),
+        _ => Err(loader.issues),
+    }
+}
+
+#[derive(Default)]
+struct IncludeLoader {
+    // Files currently being loaded, to catch include cycles
+    stack: Vec<PathBuf>,
+    // Which file each topic came from
+    topic_files: HashMap<String, PathBuf>,
+    issues: Vec<MappingIssue>,
+}
+
+impl IncludeLoader {
+    fn load(&mut self, path: &Path, text: &str, is_root: bool) -> Option<MidiMappingConfig> {
+        let file = (!is_root).then(|| path.to_path_buf());
+        let report = |issues: &mut Vec<MappingIssue>, message: String| {
+            issues.push(MappingIssue { file: file.clone(), line: None, message });
+        };
+        let mut config = match check_mappings_in(MappingFormat::from_path(path), text) {
+            Ok(config) => config,
+            Err(issues) => {
+                self.issues.extend(issues.into_iter().map(|issue| MappingIssue { file: file.clone(), ..issue }));
+                return None;
+            }
+        };
+        if !is_root && (config.scale.is_some() || config.timezone.is_some()) {
+            report(&mut self.issues, "`scale` and `timezone` can only be set in the main mapping file".to_string());
+        }
+        for entry in &config.mappings {
+            if let Some(other) = self.topic_files.get(&entry.sub_topic).filter(|other| other.as_path() != path) {
+                let message = format!("topic '{}' is also mapped in {}", entry.sub_topic, other.display());
+                report(&mut self.issues, message);
+            } else {
+                self.topic_files.insert(entry.sub_topic.clone(), path.to_path_buf());
+            }
+        }
+
+        self.stack.push(canonical(path));
+        let base_dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        for include in std::mem::take(&mut config.include) {
+            let included_paths = match expand_include(&base_dir.join(&include)) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    report(&mut self.issues, format!("include '{}': {}", include, e));
+                    continue;
+                }
+            };
+            for included in included_paths {
+                if self.stack.contains(&canonical(&included)) {
+                    report(&mut self.issues, format!("include '{}' would include itself", included.display()));
+                    continue;
+                }
+                let part = match fs::read_to_string(&included) {
+                    Ok(part_text) => self.load(&included, &part_text, false),
+                    Err(e) => {
+                        report(&mut self.issues, format!("include '{}': {}", included.display(), e));
+                        None
+                    }
+                };
+                if let Some(part) = part {
+                    merge_mappings(&mut config, part);
+                }
+            }
+        }
+        self.stack.pop();
+        Some(config)
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// A directory includes every mapping file in it, in name order.
+fn expand_include(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            let ext = p.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+            matches!(ext.as_deref(), Some("toml") | Some("json") | Some("yaml") | Some("yml"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn merge_mappings(into: &mut MidiMappingConfig, part: MidiMappingConfig) {
+    into.mappings.extend(part.mappings);
+    into.sequences.extend(part.sequences);
+    into.lfos.extend(part.lfos);
+    into.normalizers.extend(part.normalizers);
+    into.polyphony.extend(part.polyphony);
+    into.auto_channels.extend(part.auto_channels);
+}
+
+// Like `check_mappings`, for a file in any supported format.
+pub fn check_mappings_in(format: MappingFormat, text: &str) -> Result<MidiMappingConfig, Vec<MappingIssue>> {
+    let single = |line: Option<usize>, message: String| vec![MappingIssue { file: None, line, message }];
+    let value: serde_json::Value = match format {
+        MappingFormat::Toml => return check_mappings(text),
+        MappingFormat::Json => serde_json::from_str(text)
+            .map_err(|e| single(Some(e.line()).filter(|l| *l > 0), e.to_string()))?,
+        MappingFormat::Yaml => serde_yaml::from_str(text)
+            .map_err(|e| single(e.location().map(|l| l.line()), e.to_string()))?,
+    };
+
+    let version = value.get("version").and_then(serde_json::Value::as_u64).unwrap_or(1);
+    if version < u64::from(CURRENT_MAPPING_VERSION) {
+        // Migrations work on TOML, so older files take a detour through it.
+        // Line numbers wouldn't match the original file anymore.
+        let toml_text = toml::to_string(&without_nulls(value))
+            .map_err(|e| single(None, format!("Can't upgrade version {} file: {}", version, e)))?;
+        return check_mappings(&toml_text).map_err(|issues| {
+            issues.into_iter().map(|issue| MappingIssue { line: None, ..issue }).collect()
+        });
+    }
+    if version > u64::from(CURRENT_MAPPING_VERSION) {
+        return Err(single(None, format!(
+            "Mapping file is version {}, but this server only understands up to version {}",
+            version, CURRENT_MAPPING_VERSION
+        )));
+    }
+
+    // Parse the text again (instead of the value) so errors keep their line.
+    let mut config: MidiMappingConfig = match format {
+        MappingFormat::Yaml => serde_yaml::from_str(text)
+            .map_err(|e| single(e.location().map(|l| l.line()), e.to_string()))?,
+        _ => serde_json::from_str(text).map_err(|e| single(Some(e.line()).filter(|l| *l > 0), e.to_string()))?,
+    };
+    let issues = validate(&config, &[]);
+    if !issues.is_empty() {
+        return Err(issues);
+    }
+    config.version = Some(CURRENT_MAPPING_VERSION);
+    Ok(config)
+}
+
+// TOML has no null, and generated JSON often spells out unset optional fields as null.
+fn without_nulls(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => serde_json::Value::Object(
+            map.into_iter().filter(|(_, v)| !v.is_null()).map(|(k, v)| (k, without_nulls(v))).collect(),
+        ),
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.into_iter().map(without_nulls).collect()),
+        other => other,
+    }
+}
+
+// Semantic checks on a parsed config. `entry_lines` holds the line of each mapping, if known.
+fn validate(config: &MidiMappingConfig, entry_lines: &[usize]) -> Vec<MappingIssue> {
+    let mut issues = Vec::new();
+    let mut first_seen: HashMap<&str, Option<usize>> = HashMap::new();
+    for (index, entry) in config.mappings.iter().enumerate() {
+        let line = entry_lines.get(index).copied();
+        let mut report = |message: String| {
+            issues.push(MappingIssue { file: None, line, message: format!("mapping '{}': {}", entry.sub_topic, message) });
+        };
+        if let Some(first_line) = first_seen.insert(&entry.sub_topic, line) {
+            // Only one of them would ever be used.
+            let previous = first_line.map(|l| format!(" (first defined on line {})", l)).unwrap_or_default();
+            report(format!("duplicate topic{}", previous));
+        }
+        if entry.actions.is_empty() {
+            report("has no actions".to_string());
+        }
+        for (action_index, action) in entry.actions.iter().enumerate() {
+            for problem in action_problems(action) {
+                report(format!("action {}: {}", action_index + 1, problem));
+            }
+        }
+        if let Some(rotation) = &entry.rotate {
+            if rotation.channels.is_empty() && rotation.notes.is_empty() {
+                report("rotate has no channels or notes".to_string());
+            }
+            for channel in rotation.channels.iter().filter(|c| **c > 15) {
+                report(format!("rotate: channel {} is out of range (0-15)", channel));
+            }
+            for note in rotation.notes.iter().filter(|n| **n > 127) {
+                report(format!("rotate: note {} is out of range (0-127)", note));
+            }
+        }
+        if let Some(schema) = &entry.payload_schema {
+            for problem in schema.problems() {
+                report(format!("payload_schema: {}", problem));
+            }
+        }
+    }
+
+    for pool in &config.auto_channels {
+        let mut report = |message: String| {
+            issues.push(MappingIssue { file: None, line: None, message: format!("auto_channels '{}': {}", pool.pattern, message) });
+        };
+        if pool.channels.is_empty() {
+            report("has no channels".to_string());
+        }
+        for channel in pool.channels.iter().filter(|c| **c > 15) {
+            report(format!("channel {} is out of range (0-15)", channel));
+        }
+        for (action_index, action) in pool.actions.iter().enumerate() {
+            for problem in action_problems(action) {
+                report(format!("action {}: {}", action_index + 1, problem));
+            }
+        }
+    }
+    for limit in config.polyphony.iter().filter(|l| l.channel > 15) {
+        issues.push(MappingIssue { file: None, line: None, message: format!("polyphony: channel {} is out of range (0-15)", limit.channel) });
+    }
+    for lfo in config.lfos.iter().filter(|l| l.channel > 15) {
+        issues.push(MappingIssue { file: None, line: None, message: format!("lfo '{}': channel {} is out of range (0-15)", lfo.name, lfo.channel) });
+    }
+    issues
+}
+
+// Multi-line summary for logs and API responses.
+pub fn describe_issues(issues: &[MappingIssue]) -> String {
+    let lines: Vec<String> = issues.iter().map(|issue| format!("  {}", issue)).collect();
+    format!("{} problem(s):\n{}", issues.len(), lines.join("\n"))
+}
+
+// `--check-mappings [file]`: validates without starting the server. Returns the exit code.
+pub fn run_cli(path: &str) -> i32 {
+    let text = match fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return 2;
+        }
+    };
+    match check_mapping_tree(Path::new(path), &text) {
+        Ok(config) => {
+            println!("{}: OK ({} mappings)", path, config.mappings.len());
+            0
+        }
+        Err(issues) => {
+            for issue in &issues {
+                let file = issue.file.as_ref().map(|f| f.display().to_string()).unwrap_or_else(|| path.to_string());
+                match issue.line {
+                    Some(line) => eprintln!("{}:{}: {}", file, line, issue.message),
+                    None => eprintln!("{}: {}", file, issue.message),
+                }
+            }
+            eprintln!("{}: {} problem(s) found", path, issues.len());
+            1
+        }
+    }
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+fn action_type_name(action_type: &MidiActionType) -> String {
+    serde_json::to_value(action_type)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_else(|| format!("{:?}", action_type))
+}
+
+fn action_problems(action: &MidiAction) -> Vec<String> {
+    use MidiActionType::*;
+    let mut problems = Vec::new();
+    let type_name = action_type_name(&action.action_type);
+
+    if action.channel > 15 {
+        problems.push(format!("channel {} is out of range (0-15)", action.channel));
+    }
+    // 14-bit messages carry values up to 16383, everything else is plain 7-bit.
+    let value_max = match action.action_type {
+        Cc14 | Nrpn | Rpn => 16383,
+        _ => 127,
+    };
+    let control_max = match action.action_type {
+        Cc14 => 31, // The LSB goes out on control_num + 32
+        _ => 127,
+    };
+    let ranges = [
+        ("note", action.note.map(u16::from), 127),
+        ("velocity", action.velocity.map(u16::from), 127),
+        ("control_num", action.control_num.map(u16::from), control_max),
+        ("value", action.value, value_max),
+        ("from_value", action.from_value, value_max),
+        ("param_num", action.param_num, 16383),
+        ("bank_msb", action.bank_msb.map(u16::from), 127),
+        ("bank_lsb", action.bank_lsb.map(u16::from), 127),
+    ];
+    for (name, value, max) in ranges {
+        if let Some(value) = value.filter(|v| *v > max) {
+            problems.push(format!("{} {} is out of range (0-{}) for {}", name, value, max, type_name));
+        }
+    }
+
+    // Fields an action type never reads are usually a wrong action_type or a copy-paste leftover.
+    let allowed: &[&str] = match action.action_type {
+        NoteOn | NoteOff | NoteToggle => &["note", "velocity"],
+        NoteOnOff => &["note", "velocity", "duration_ms"],
+        Cc | Cc14 => &["control_num", "value"],
+        Nrpn | Rpn => &["param_num", "value"],
+        ProgramChange => &["value", "bank_msb", "bank_lsb"],
+        CcRamp => &["control_num", "value", "duration_ms", "from_value", "curve", "rate_hz"],
+    };
+    let present = [
+        ("note", action.note.is_some()),
+        ("velocity", action.velocity.is_some()),
+        ("duration_ms", action.duration_ms.is_some()),
+        ("control_num", action.control_num.is_some()),
+        ("value", action.value.is_some()),
+        ("param_num", action.param_num.is_some()),
+        ("bank_msb", action.bank_msb.is_some()),
+        ("bank_lsb", action.bank_lsb.is_some()),
+        ("from_value", action.from_value.is_some()),
+        ("curve", action.curve.is_some()),
+        ("rate_hz", action.rate_hz.is_some()),
+    ];
+    for (name, _) in present.iter().filter(|(name, set)| *set && !allowed.contains(name)) {
+        problems.push(format!("{} doesn't apply to {}", name, type_name));
+    }
+    problems
+}
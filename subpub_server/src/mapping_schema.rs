@@ -0,0 +1,126 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use std::fs;
+use std::path::Path;
+use toml_edit::{value, Document, Item};
+
+use crate::mapping_check::{self, MappingFormat};
+
+// Bump this (and add a migration below) whenever the mapping file format changes in a
+// way existing files would no longer load.
+pub const CURRENT_MAPPING_VERSION: u32 = 2;
+
+type Migration = fn(&mut Document) -> Result<()>;
+
+// Step `i` upgrades a file from version i + 1 to i + 2.
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("rename [[mapping]] tables to [[mappings]]", migrate_v1_to_v2),
+];
+
+// A mapping file brought up to the current schema.
+pub struct Migrated {
+    pub text: String,
+    pub from_version: u32,
+    pub applied: Vec<&'static str>,
+}
+
+// Upgrades mapping TOML to the current version. Formatting and comments are kept, so
+// line numbers in later errors still match the file. With `stamp_version` the result
+// also gets `version = <current>` (for rewriting the file).
+pub fn migrate(toml_str: &str, stamp_version: bool) -> Result<Migrated> {
+    let mut doc: Document = toml_str.parse().context("Failed to parse MIDI mapping TOML")?;
+    // Files from before versioning don't have the field.
+    let from_version = match doc.get("version") {
+        None => 1,
+        Some(item) => item
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| anyhow!("`version` must be a positive integer"))?,
+    };
+    if from_version > CURRENT_MAPPING_VERSION {
+        bail!(
+            "Mapping file is version {}, but this server only understands up to version {}",
+            from_version,
+            CURRENT_MAPPING_VERSION
+        );
+    }
+
+    let mut applied = Vec::new();
+    for (description, migration) in &MIGRATIONS[(from_version - 1) as usize..] {
+        migration(&mut doc).with_context(|| format!("Migration '{}' failed", description))?;
+        applied.push(*description);
+    }
+    if !applied.is_empty() {
+        info!(
+            "Upgraded MIDI mappings from version {} to {} in memory: {}",
+            from_version,
+            CURRENT_MAPPING_VERSION,
+            applied.join(", ")
+        );
+    }
+    if stamp_version {
+        doc["version"] = value(i64::from(CURRENT_MAPPING_VERSION));
+    }
+    Ok(Migrated { text: doc.to_string(), from_version, applied })
+}
+
+// v1 files (and the old example) used `[[mapping]]`.
+fn migrate_v1_to_v2(doc: &mut Document) -> Result<()> {
+    let Some(old) = doc.remove("mapping") else {
+        return Ok(());
+    };
+    let old = old.into_array_of_tables().map_err(|_| anyhow!("`mapping` must be a list of tables"))?;
+    match doc.get_mut("mappings") {
+        Some(Item::ArrayOfTables(existing)) => {
+            for table in old.iter() {
+                existing.push(table.clone());
+            }
+        }
+        Some(_) => bail!("`mappings` must be a list of tables"),
+        None => {
+            doc.insert("mappings", Item::ArrayOfTables(old));
+        }
+    }
+    Ok(())
+}
+
+// `--migrate-mappings [file]`: rewrites the file in the current format, keeping a backup.
+pub fn run_migrate_cli(path: &str) -> i32 {
+    if MappingFormat::from_path(Path::new(path)) != MappingFormat::Toml {
+        eprintln!("{}: only TOML files can be rewritten. JSON and YAML files are upgraded in memory on load.", path);
+        return 2;
+    }
+    let result = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path))
+        .and_then(|text| migrate(&text, true));
+    let migrated = match result {
+        Ok(migrated) => migrated,
+        Err(e) => {
+            eprintln!("{}: {:#}", path, e);
+            return 2;
+        }
+    };
+    if migrated.applied.is_empty() {
+        println!("{}: already at version {}", path, CURRENT_MAPPING_VERSION);
+        return 0;
+    }
+    // Don't write a file that still wouldn't load.
+    if let Err(issues) = mapping_check::check_mappings(&migrated.text) {
+        eprintln!("{}: not migrated, the upgraded file has {}", path, mapping_check::describe_issues(&issues));
+        return 1;
+    }
+    let backup = format!("{}.v{}.bak", path, migrated.from_version);
+    if let Err(e) = fs::copy(path, &backup).and_then(|_| fs::write(path, &migrated.text)) {
+        eprintln!("{}: failed to write: {}", path, e);
+        return 2;
+    }
+    for step in &migrated.applied {
+        println!("{}: {}", path, step);
+    }
+    println!(
+        "{}: upgraded from version {} to {} (backup in {})",
+        path, migrated.from_version, CURRENT_MAPPING_VERSION, backup
+    );
+    0
+}
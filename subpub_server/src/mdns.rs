@@ -0,0 +1,69 @@
+use log::{info, warn};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::net::SocketAddr;
+
+use crate::config::MdnsConfig;
+
+pub const SERVICE_TYPE: &str = "_subpub._udp.local.";
+
+// Advertises the server over mDNS/DNS-SD, so standard service browsers find it
+// (`dns-sd -B _subpub._udp` on macOS, `avahi-browse _subpub._udp` on Linux, NSNetService
+// on iOS). Runs next to the multicast discovery, which legacy clients still use.
+pub struct MdnsAdvertiser {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl MdnsAdvertiser {
+    // None if mDNS is disabled or can't start; the server runs on without it.
+    pub fn start(config: &MdnsConfig, main_server_addr: SocketAddr) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        match Self::register(config, main_server_addr) {
+            Ok(advertiser) => {
+                info!("Advertising '{}' via mDNS on port {}", advertiser.fullname, main_server_addr.port());
+                Some(advertiser)
+            }
+            Err(e) => {
+                warn!("Failed to start mDNS advertising: {}. Clients can still use multicast discovery.", e);
+                None
+            }
+        }
+    }
+
+    fn register(config: &MdnsConfig, main_server_addr: SocketAddr) -> Result<Self, mdns_sd::Error> {
+        let daemon = ServiceDaemon::new()?;
+        // Host names must be unique on the network, so the LAN address goes into it.
+        let local_ip = match main_server_addr.ip() {
+            ip if ip.is_unspecified() => local_ip_address::local_ip().ok(),
+            ip => Some(ip),
+        };
+        let host_label = match local_ip {
+            Some(ip) => format!("subpub-{}", ip.to_string().replace(['.', ':'], "-")),
+            None => "subpub".to_string(),
+        };
+        let host_name = format!("{}.local.", host_label);
+        let properties: &[(&str, &str)] = &[("version", env!("CARGO_PKG_VERSION"))];
+        let port = main_server_addr.port();
+        let service = if main_server_addr.ip().is_unspecified() {
+            // Bound to every interface: announce all of their addresses.
+            ServiceInfo::new(SERVICE_TYPE, &config.instance_name, &host_name, "", port, properties)?.enable_addr_auto()
+        } else {
+            ServiceInfo::new(SERVICE_TYPE, &config.instance_name, &host_name, main_server_addr.ip(), port, properties)?
+        };
+        let fullname = service.get_fullname().to_string();
+        daemon.register(service)?;
+        Ok(Self { daemon, fullname })
+    }
+
+    // Sends the goodbye packets, so browsers drop the server right away.
+    pub fn stop(self) {
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            warn!("Failed to withdraw the mDNS advertisement: {}", e);
+        }
+        if let Err(e) = self.daemon.shutdown() {
+            warn!("Failed to stop the mDNS daemon: {}", e);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use log::{info, warn};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::MessageIdsConfig;
+use crate::paths;
+
+// Client-provided IDs are kept short and free of whitespace so they fit one log line.
+const MAX_ID_LEN: usize = 128;
+
+struct SeenIds {
+    order: VecDeque<(u64, String)>, // (unix seconds, id), oldest first
+    ids: HashSet<String>,
+    file: Option<File>,
+    appended_since_compaction: usize,
+}
+
+// Message IDs for publishes. Clients can send their own with
+// `PUBID:<channel>:<id>:<payload>`; a publish with an ID that already ran is acknowledged
+// but not executed again, so retries of cue messages never fire twice. Executed IDs are
+// persisted so this holds across restarts too. Other publishes get a server-assigned ID.
+pub struct MessageIds {
+    config: MessageIdsConfig,
+    seen: Mutex<SeenIds>,
+    boot: u64,
+    next: AtomicU64,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_ID_LEN && !id.chars().any(char::is_whitespace)
+}
+
+impl MessageIds {
+    pub fn load(config: &MessageIdsConfig) -> Arc<Self> {
+        let mut seen = SeenIds { order: VecDeque::new(), ids: HashSet::new(), file: None, appended_since_compaction: 0 };
+        if !config.file.is_empty() {
+            if let Ok(contents) = fs::read_to_string(paths::data_path(&config.file)) {
+                for line in contents.lines() {
+                    let Some((ts, id)) = line.split_once(' ') else { continue };
+                    let Ok(ts) = ts.parse::<u64>() else { continue };
+                    if seen.ids.insert(id.to_string()) {
+                        seen.order.push_back((ts, id.to_string()));
+                    }
+                }
+            }
+            Self::expire(config, &mut seen);
+            info!("Loaded {} remembered message IDs from {:?}", seen.order.len(), paths::data_path(&config.file));
+            seen.file = Self::rewrite_file(config, &seen.order);
+        }
+        let boot = now_secs();
+        Arc::new(Self { config: config.clone(), seen: Mutex::new(seen), boot, next: AtomicU64::new(1) })
+    }
+
+    // Unique across restarts because of the boot timestamp prefix.
+    pub fn assign(&self) -> String {
+        format!("s{}-{}", self.boot, self.next.fetch_add(1, Ordering::Relaxed))
+    }
+
+    // Records `id` and returns true if it hasn't been executed before.
+    pub fn first_time(&self, id: &str) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        Self::expire(&self.config, &mut seen);
+        if seen.ids.contains(id) {
+            return false;
+        }
+        let ts = now_secs();
+        seen.ids.insert(id.to_string());
+        seen.order.push_back((ts, id.to_string()));
+        Self::expire(&self.config, &mut seen);
+
+        if let Some(file) = seen.file.as_mut()
+            && let Err(e) = writeln!(file, "{} {}", ts, id)
+        {
+            warn!("Failed to persist message ID '{}': {}", id, e);
+        }
+        seen.appended_since_compaction += 1;
+        // Keep the file from growing without bound on long runs.
+        if seen.appended_since_compaction > self.config.max_remembered.max(1) {
+            seen.file = Self::rewrite_file(&self.config, &seen.order);
+            seen.appended_since_compaction = 0;
+        }
+        true
+    }
+
+    fn expire(config: &MessageIdsConfig, seen: &mut SeenIds) {
+        let cutoff = now_secs().saturating_sub(config.remember_secs);
+        while let Some((ts, _)) = seen.order.front() {
+            if *ts >= cutoff && seen.order.len() <= config.max_remembered {
+                break;
+            }
+            if let Some((_, id)) = seen.order.pop_front() {
+                seen.ids.remove(&id);
+            }
+        }
+    }
+
+    // Writes the remembered IDs out fresh and returns the file opened for appending.
+    fn rewrite_file(config: &MessageIdsConfig, order: &VecDeque<(u64, String)>) -> Option<File> {
+        let contents: String = order.iter().map(|(ts, id)| format!("{} {}\n", ts, id)).collect();
+        let path = paths::data_path(&config.file);
+        let result = fs::write(&path, contents)
+            .and_then(|_| OpenOptions::new().append(true).open(&path));
+        match result {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("Can't persist message IDs to {:?}: {}. Keeping them in memory only.", path, e);
+                None
+            }
+        }
+    }
+}
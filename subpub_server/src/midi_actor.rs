@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Result};
+use log::{debug, error, info, warn};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use tokio::sync::oneshot;
+
+use crate::config::{MidiOverflow, MidiQueueConfig};
+use crate::midi_handler::MidiHandler;
+use crate::stats::Stats;
+
+type Job = Box<dyn FnOnce(&mut MidiHandler) + Send>;
+
+enum MidiCommand {
+    // Sends a raw MIDI message, e.g. an LFO value
+    SendEvent(Vec<u8>),
+    // Reloads the mapping file and reports the outcome
+    Reload(oneshot::Sender<Result<()>>),
+    // Runs a closure against the handler: mapping lookups, state changes, reads
+    Query(Job),
+    // A publish to run through the mappings; the topic is what `coalesce` compares
+    Publish { topic: String, job: Job },
+}
+
+impl MidiCommand {
+    // Publishes and raw messages come straight from traffic and are bounded by
+    // [midi_queue]. Everything else (NoteOffs, ramps, reloads, ...) is never dropped,
+    // or notes would hang.
+    fn is_droppable(&self) -> bool {
+        matches!(self, MidiCommand::SendEvent(_) | MidiCommand::Publish { .. })
+    }
+
+    // Whether `newer` may take this command's place under `coalesce`: a CC for the same
+    // channel and controller, or a publish on the same topic. Only the latest value counts.
+    fn is_replaced_by(&self, newer: &MidiCommand) -> bool {
+        match (self, newer) {
+            (MidiCommand::SendEvent(old), MidiCommand::SendEvent(new)) => is_cc(old) && is_cc(new) && old[..2] == new[..2],
+            (MidiCommand::Publish { topic: old, .. }, MidiCommand::Publish { topic: new, .. }) => old == new,
+            _ => false,
+        }
+    }
+}
+
+fn is_cc(message: &[u8]) -> bool {
+    message.len() == 3 && message[0] & 0xF0 == 0xB0
+}
+
+// Commands in the order they were sent, plus how many of them count against the capacity.
+#[derive(Default)]
+struct Queue {
+    commands: VecDeque<MidiCommand>,
+    droppable: usize,
+    // Set while the queue is full, so the warning is logged once per burst
+    overflowing: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+    capacity: usize,
+    overflow: MidiOverflow,
+    stats: Arc<Stats>,
+}
+
+// The MIDI handler lives on its own thread and is only reached through this handle,
+// so nothing on the tokio workers ever waits for a lock held across a (blocking) MIDI
+// send, and delay tasks no longer need the handler itself. Commands run in the order
+// they were sent; a burst of publishes that outruns the MIDI output is cut down by the
+// `[midi_queue] overflow` policy instead of queueing up seconds of stale notes.
+// Cheap to clone.
+#[derive(Clone)]
+pub struct MidiHandle {
+    shared: Arc<Shared>,
+}
+
+impl MidiHandle {
+    // Moves the handler onto the actor thread.
+    pub fn spawn(handler: MidiHandler, config: &MidiQueueConfig, stats: Arc<Stats>) -> Result<Self> {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue::default()),
+            ready: Condvar::new(),
+            capacity: config.capacity.max(1),
+            overflow: config.overflow,
+            stats,
+        });
+        let actor_shared = shared.clone();
+        thread::Builder::new()
+            .name("midi-actor".to_string())
+            .spawn(move || run_actor(handler, actor_shared))
+            .map_err(|e| anyhow!("Failed to start the MIDI thread: {}", e))?;
+        Ok(Self { shared })
+    }
+
+    // Fire and forget; send errors are logged by the actor. May be dropped when the
+    // queue is full.
+    pub fn send_event(&self, message: Vec<u8>) {
+        self.submit(MidiCommand::SendEvent(message));
+    }
+
+    // Runs `job` on the actor without waiting for it.
+    pub fn execute(&self, job: impl FnOnce(&mut MidiHandler) + Send + 'static) {
+        self.submit(MidiCommand::Query(Box::new(job)));
+    }
+
+    // Runs `job` on the actor and waits for its result without blocking the runtime.
+    pub async fn query<R: Send + 'static>(&self, job: impl FnOnce(&mut MidiHandler) -> R + Send + 'static) -> Result<R> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.execute(move |handler| {
+            let _ = reply_tx.send(job(handler));
+        });
+        reply_rx.await.map_err(|_| anyhow!("MIDI thread stopped"))
+    }
+
+    // Like `execute`, for the mapping work of a publish on `topic`. Never waited for, so
+    // a burst of publishes fills the queue and the overflow policy can cut it down.
+    pub fn publish(&self, topic: &str, job: impl FnOnce(&mut MidiHandler) + Send + 'static) {
+        self.submit(MidiCommand::Publish { topic: topic.to_string(), job: Box::new(job) });
+    }
+
+    pub async fn reload(&self) -> Result<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.submit(MidiCommand::Reload(reply_tx));
+        reply_rx.await.map_err(|_| anyhow!("MIDI thread stopped"))?
+    }
+
+    pub fn reload_blocking(&self) -> Result<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.submit(MidiCommand::Reload(reply_tx));
+        reply_rx.blocking_recv().map_err(|_| anyhow!("MIDI thread stopped"))?
+    }
+
+    fn submit(&self, command: MidiCommand) {
+        let shared = &self.shared;
+        let mut queue = shared.queue.lock().unwrap();
+        if command.is_droppable() && queue.droppable >= shared.capacity {
+            if !queue.overflowing {
+                warn!("MIDI queue is full ({} actions), applying overflow policy {:?}.", shared.capacity, shared.overflow);
+                queue.overflowing = true;
+            }
+            let replace_at = match shared.overflow {
+                MidiOverflow::DropNewest => {
+                    debug!("MIDI queue full, dropped the new action.");
+                    shared.stats.record_midi_queue_dropped();
+                    return;
+                }
+                MidiOverflow::DropOldest => None,
+                MidiOverflow::Coalesce => queue.commands.iter().rposition(|queued| queued.is_replaced_by(&command)),
+            };
+            if let Some(index) = replace_at {
+                // Same place in the queue, newer value
+                queue.commands[index] = command;
+                shared.stats.record_midi_queue_coalesced();
+                return;
+            }
+            if let Some(oldest) = queue.commands.iter().position(MidiCommand::is_droppable) {
+                queue.commands.remove(oldest);
+                queue.droppable -= 1;
+                debug!("MIDI queue full, dropped the oldest action.");
+                shared.stats.record_midi_queue_dropped();
+            }
+        }
+        if command.is_droppable() {
+            queue.droppable += 1;
+            shared.stats.set_midi_queue_depth(queue.droppable);
+        }
+        queue.commands.push_back(command);
+        drop(queue);
+        shared.ready.notify_one();
+    }
+}
+
+// Takes the next command, waiting for one if the queue is empty.
+fn next_command(shared: &Shared) -> MidiCommand {
+    let mut queue = shared.queue.lock().unwrap();
+    loop {
+        if let Some(command) = queue.commands.pop_front() {
+            if command.is_droppable() {
+                queue.droppable -= 1;
+                shared.stats.set_midi_queue_depth(queue.droppable);
+            }
+            if queue.overflowing && queue.droppable < shared.capacity / 2 {
+                info!("MIDI queue caught up.");
+                queue.overflowing = false;
+            }
+            return command;
+        }
+        queue = shared.ready.wait(queue).unwrap();
+    }
+}
+
+// The actor runs for the whole life of the app.
+fn run_actor(mut handler: MidiHandler, shared: Arc<Shared>) {
+    info!("MIDI thread started.");
+    loop {
+        match next_command(&shared) {
+            MidiCommand::SendEvent(message) => {
+                if let Err(e) = handler.send_midi_message(&message) {
+                    error!("Failed to send MIDI message {:?}: {:?}", message, e);
+                }
+            }
+            MidiCommand::Reload(reply) => {
+                let _ = reply.send(handler.reload_mappings());
+            }
+            MidiCommand::Query(job) | MidiCommand::Publish { job, .. } => job(&mut handler),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::Path;
+
+// 1000 ticks per quarter note at one quarter note per second, so a tick is a millisecond
+// and event times don't need converting.
+const TICKS_PER_QUARTER: u16 = 1000;
+const MICROSECONDS_PER_QUARTER: u32 = 1_000_000;
+
+// Writes timed MIDI messages (milliseconds from the start) as a Standard MIDI File with
+// a single track. Channel messages and SysEx are kept; clock and other system messages
+// have no place in a file and are left out.
+pub fn write(path: &Path, events: &[(u64, Vec<u8>)]) -> Result<()> {
+    let mut track = Vec::new();
+    // Tempo meta event
+    track.extend_from_slice(&[0x00, 0xFF, 0x51, 0x03]);
+    track.extend_from_slice(&MICROSECONDS_PER_QUARTER.to_be_bytes()[1..]);
+
+    let mut last_ms = 0;
+    for (ms, message) in events {
+        let Some(&status) = message.first() else {
+            continue;
+        };
+        let is_channel_message = (0x80..0xF0).contains(&status);
+        let is_sysex = status == 0xF0 && message.len() > 1;
+        if !is_channel_message && !is_sysex {
+            continue;
+        }
+        write_variable_length(&mut track, (ms.saturating_sub(last_ms)).min(0x0FFF_FFFF) as u32);
+        last_ms = (*ms).max(last_ms);
+        if is_sysex {
+            // F0, the length of the rest, the rest (ending in F7)
+            track.push(0xF0);
+            write_variable_length(&mut track, (message.len() - 1) as u32);
+            track.extend_from_slice(&message[1..]);
+        } else {
+            track.extend_from_slice(message);
+        }
+    }
+    // End of track
+    track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+    let mut file = Vec::with_capacity(track.len() + 22);
+    file.extend_from_slice(b"MThd");
+    file.extend_from_slice(&6u32.to_be_bytes());
+    file.extend_from_slice(&0u16.to_be_bytes()); // Format 0: one track
+    file.extend_from_slice(&1u16.to_be_bytes()); // Track count
+    file.extend_from_slice(&TICKS_PER_QUARTER.to_be_bytes());
+    file.extend_from_slice(b"MTrk");
+    file.extend_from_slice(&(track.len() as u32).to_be_bytes());
+    file.extend_from_slice(&track);
+    fs::write(path, file).with_context(|| format!("Failed to write MIDI file {:?}", path))
+}
+
+// Delta times: 7 bits per byte, most significant first, the high bit set on all but the last.
+fn write_variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = [0u8; 4];
+    let mut len = 0;
+    let mut rest = value;
+    loop {
+        bytes[len] = (rest & 0x7F) as u8;
+        len += 1;
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+    for (index, byte) in bytes[..len].iter().enumerate().rev() {
+        out.push(if index > 0 { byte | 0x80 } else { *byte });
+    }
+}
@@ -1,15 +1,63 @@
-use anyhow::{Context, Result};
-use log::{error, info, warn};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error, info, warn};
+#[cfg(unix)]
 use midir::os::unix::VirtualOutput;
 use midir::{MidiOutput, MidiOutputConnection}; // Reverted from wildcard
 use serde::{Deserialize, Serialize}; // Added Serialize
-use std::collections::HashMap; // Will be useful for quick lookups
+use std::collections::{BTreeMap, HashMap, VecDeque}; // Will be useful for quick lookups
 use std::fs;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc as tokio_mpsc;
+
+use crate::auto_channels::{AllocatedSlot, AutoChannelAllocator, AutoChannelConfig};
+use crate::config::{ServerConfig, StartupRetryConfig};
+use crate::event_store::EventStore;
+use crate::humanize::HumanizeConfig;
+use crate::lfo::LfoConfig;
+use crate::mapping_check;
+use crate::midi_actor::MidiHandle;
+use crate::mapping_schema::CURRENT_MAPPING_VERSION;
+use crate::paths;
+use crate::ramp::RampCurve;
+use crate::rotation::RotationConfig;
+use crate::safe_mode::{SafeMode, SafeModeCause};
+use crate::normalizer::{self, ChannelNormalizers, NormalizerConfig};
+use crate::payload_schema::PayloadSchema;
+use crate::scale::ScaleConfig;
+use crate::schedule::{self, ScheduleConfig};
+use crate::sequencer::SequenceConfig;
+use crate::server::topic_matches;
+use crate::stats::Stats;
+use crate::zones::Zones;
+use crate::sys_events::{SysEvents, SYS_MAPPINGS_STATUS, SYS_MIDI_STATUS};
 
 const MIDI_CLIENT_NAME: &str = "ZerverClient";
-const MAPPING_FILE_PATH: &str = "midi_mapping.toml";
+// Mapping files live in the config directory, see paths.rs
+pub const MAPPING_FILE_NAME: &str = "midi_mapping.toml";
+// Checked in this order; generated mappings can be JSON or YAML instead of TOML.
+pub const MAPPING_FILE_CANDIDATES: &[&str] = &[MAPPING_FILE_NAME, "midi_mapping.json", "midi_mapping.yaml", "midi_mapping.yml"];
+const MIDI_PORT_NAME: &str = "Zerver";
+
+// The catch-all mapping, for topics no other mapping matches
+const FALLBACK_SUB_TOPIC: &str = "*";
+
+// `sensors/*` style sub_topics, see `topic_matches`.
+fn is_pattern(sub_topic: &str) -> bool {
+    sub_topic.ends_with('*')
+}
+
+// The mapping file in use: the first candidate that exists, or the TOML default.
+pub fn mapping_file_path() -> PathBuf {
+    let config_dir = paths::config_dir();
+    MAPPING_FILE_CANDIDATES
+        .iter()
+        .map(|name| config_dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| config_dir.join(MAPPING_FILE_NAME))
+}
 
 #[derive(Deserialize, Serialize, Debug, Clone)] // Added Serialize
 #[serde(rename_all = "snake_case")]
@@ -17,11 +65,17 @@ pub enum MidiActionType {
     NoteOn,
     NoteOff,
     NoteOnOff,
+    NoteToggle, // First trigger sends NoteOn, the next one NoteOff
     Cc,
+    Cc14, // 14-bit CC: MSB on control_num, LSB on control_num + 32
+    Nrpn, // CC 99/98 (parameter) + CC 6/38 (value)
+    Rpn,  // CC 101/100 (parameter) + CC 6/38 (value)
     ProgramChange,
+    CcRamp, // Glides a CC from its current (or from_value) to value over duration_ms
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)] // Added Serialize
+#[serde(deny_unknown_fields)]
 pub struct MidiAction {
     pub action_type: MidiActionType,
     pub channel: u8, // MIDI channel 0-15 (usually presented as 1-16 to users)
@@ -30,64 +84,333 @@ pub struct MidiAction {
     #[serde(default)] // If not present, defaults to 0 or a suitable value
     pub duration_ms: Option<u64>,
     pub control_num: Option<u8>,
-    pub value: Option<u8>, // Can be direct value or derived from pubsub message
+    pub value: Option<u16>, // Can be direct value or derived from pubsub message (0-127, or 0-16383 for cc14/nrpn/rpn)
+    pub param_num: Option<u16>, // NRPN/RPN parameter number (0-16383)
+    pub bank_msb: Option<u8>, // Bank select (CC0) sent before a program change
+    pub bank_lsb: Option<u8>, // Bank select (CC32) sent before a program change
+    pub from_value: Option<u16>, // CC ramp start value. Defaults to the last value sent on that CC
+    pub curve: Option<RampCurve>, // CC ramp shape: linear (default), ease_in, ease_out, ease_in_out
+    pub rate_hz: Option<u32>, // CC ramp update rate, defaults to DEFAULT_RAMP_RATE_HZ
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)] // Added Serialize
+#[serde(deny_unknown_fields)]
 pub struct MappingEntry {
+    // An exact topic, or a pattern like `sensors/*` matching every topic with that prefix
     pub sub_topic: String,
+    // Where several mappings match one topic, higher priorities come first (default 0).
+    #[serde(default)]
+    pub priority: i32,
     // Optional: further filter by message content (e.g., JSON path, regex)
     // pub message_filter: Option<String>, 
     pub actions: Vec<MidiAction>,
+    // Snap payload-provided notes into this scale. Falls back to the global scale.
+    pub scale: Option<ScaleConfig>,
+    // Only active during this time window / on these days or dates.
+    pub schedule: Option<ScheduleConfig>,
+    // Room/zone this mapping plays into. Silenced zones don't produce MIDI.
+    pub zone: Option<String>,
+    // Reply to the publisher with what was sent (`RESULT:<topic>:<json>`).
+    #[serde(default)]
+    pub echo: bool,
+    // Random velocity / timing variation for this mapping's actions.
+    pub humanize: Option<HumanizeConfig>,
+    // Semitones added to this mapping's notes, on top of the global transpose.
+    #[serde(default)]
+    pub transpose: i8,
+    // Cycle the actions over these channels/notes on successive triggers.
+    pub rotate: Option<RotationConfig>,
+    // What a malformed JSON payload does. Falls back to the file's `on_payload_error`.
+    pub on_payload_error: Option<PayloadErrorPolicy>,
+    // Publishes whose payload doesn't match are rejected before any MIDI is sent.
+    pub payload_schema: Option<PayloadSchema>,
+    // Set on mappings synthesized for auto-allocated topics
+    #[serde(skip)]
+    pub slot: Option<AllocatedSlot>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)] // Added Serialize
+#[serde(deny_unknown_fields)]
 pub struct MidiMappingConfig {
+    // Schema version of the file. Missing means version 1, from before versioning.
+    pub version: Option<u32>,
+    // More mapping files (or directories of them) merged into this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
     #[serde(default)]
     pub mappings: Vec<MappingEntry>,
+    #[serde(default)]
+    pub sequences: Vec<SequenceConfig>,
+    #[serde(default)]
+    pub lfos: Vec<LfoConfig>,
+    // Global scale applied to every mapping that doesn't set its own.
+    pub scale: Option<ScaleConfig>,
+    #[serde(default)]
+    pub normalizers: Vec<ChannelNormalizers>,
+    // Timezone for mapping schedules: "local" (default), "UTC" or a fixed offset like "+01:00".
+    pub timezone: Option<String>,
+    #[serde(default)]
+    pub polyphony: Vec<PolyphonyLimit>,
+    // Channel pools for topics that aren't listed individually, e.g. `players/*`
+    #[serde(default)]
+    pub auto_channels: Vec<AutoChannelConfig>,
+    // What runs when several mappings match a topic
+    #[serde(default)]
+    pub match_policy: MatchPolicy,
+    // What a malformed JSON payload does, for mappings that don't set their own
+    #[serde(default)]
+    pub on_payload_error: PayloadErrorPolicy,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchPolicy {
+    // Only the best match (highest priority, then exact before patterns, then the longest pattern)
+    #[default]
+    FirstMatch,
+    // Every matching mapping, best match first
+    AllMatches,
+}
+
+// What happens to a publish whose payload is meant as JSON overrides but is broken, or
+// has keys that aren't overrides (a typo like "veloctiy"). Plain payloads that don't
+// start with `{` are triggers without overrides, never malformed.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PayloadErrorPolicy {
+    // Run the mapping with the overrides that could be read, silently
+    Fallback,
+    // The same, with a warning in the log
+    #[default]
+    Warn,
+    // Log a warning and don't run the mapping
+    Reject,
+}
+
+// Max simultaneous notes on a MIDI channel. Further NoteOns steal the oldest note.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PolyphonyLimit {
+    pub channel: u8,
+    pub max_notes: usize,
 }
 
 pub struct MidiHandler {
     conn: Option<MidiOutputConnection>,
     mappings: MidiMappingConfig, // Store loaded mappings
     // For quick lookup of mappings by topic
-    topic_to_actions: HashMap<String, Vec<MidiAction>>,
+    topic_to_mapping: HashMap<String, MappingEntry>,
+    // Mappings whose sub_topic is a pattern, best match first
+    pattern_mappings: Vec<MappingEntry>,
+    // The `sub_topic = "*"` mapping, for topics nothing else maps
+    fallback_mapping: Option<MappingEntry>,
+    // Payload normalizer chains by channel
+    channel_normalizers: HashMap<String, Vec<NormalizerConfig>>,
+    stats: Arc<Stats>,
+    zones: Arc<Zones>,
+    // Last value sent per (channel, controller), the starting point for CC ramps
+    cc_values: HashMap<(u8, u8), u8>,
+    // Bumped whenever something new takes over a controller, so older ramps stop
+    cc_ramp_generations: HashMap<(u8, u8), u64>,
+    // Global transpose in semitones, set at runtime via the control topic
+    transpose: i8,
+    // MIDI output is muted while safe mode is active
+    safe_mode: Arc<SafeMode>,
+    // Notes held by note_toggle actions, by (sub_topic, action index) -> (channel, note)
+    latched_notes: HashMap<(String, usize), (u8, u8)>,
+    // Triggers so far of mappings with `rotate`, by sub_topic
+    rotation_steps: HashMap<String, usize>,
+    // NoteOffs of note_on_off actions still waiting for their duration, by id. Flushed
+    // on shutdown so aborted delay tasks don't leave notes hanging.
+    pending_note_offs: HashMap<u64, Vec<u8>>,
+    next_note_off_id: u64,
+    // Max notes per channel, and the sounding notes per channel (oldest first)
+    polyphony_limits: HashMap<u8, usize>,
+    active_notes: HashMap<u8, VecDeque<u8>>,
+    // Channels handed out to topics matching an auto_channels pattern
+    auto_channels: AutoChannelAllocator,
+    // SQLite event log, if enabled
+    event_store: Option<Arc<EventStore>>,
+    // Reports mapping reloads on $SYS/mappings/status
+    sys_events: SysEvents,
+    // Copies of sent messages for the bus, see midi_mirror.rs
+    midi_mirror: Option<tokio_mpsc::UnboundedSender<Vec<u8>>>,
+    // The port stays closed while this server is a failover standby, see failover.rs
+    output_suspended: bool,
+    // Only set on a handler built by `for_render`, see session_replay.rs
+    render: Option<Render>,
+}
+
+type RenderJob = Box<dyn FnOnce(&mut MidiHandler) + Send>;
+
+// A recording re-rendered offline: sent messages are captured on a virtual clock,
+// the recording's own timestamps, instead of reaching a port.
+#[derive(Default)]
+struct Render {
+    // Milliseconds into the recording
+    now_ms: u64,
+    // Delayed work (NoteOffs, humanized actions, ramp steps) by due time, then by
+    // the order it was scheduled in
+    pending: BTreeMap<(u64, u64), RenderJob>,
+    next_job: u64,
+    // Milliseconds into the recording, and the message
+    events: Vec<(u64, Vec<u8>)>,
 }
 
 impl MidiHandler {
-    pub fn new() -> Result<Arc<Mutex<Self>>> {
-        let mappings = Self::load_mappings_from_file(Path::new(MAPPING_FILE_PATH))
+    // Loads the mappings, opens the MIDI output and moves the handler onto its own thread.
+    // A failover standby leaves the output closed until it takes over.
+    pub fn start(
+        config: &ServerConfig,
+        sys_events: SysEvents,
+        stats: Arc<Stats>,
+        zones: Arc<Zones>,
+        safe_mode: Arc<SafeMode>,
+        event_store: Option<Arc<EventStore>>,
+    ) -> Result<MidiHandle> {
+        let mappings = Self::load_mappings_from_file(&mapping_file_path())
             .unwrap_or_else(|e| {
-                warn!("Failed to load MIDI mappings from '{}': {:?}. Using default empty mappings.", MAPPING_FILE_PATH, e);
+                let path = mapping_file_path();
+                warn!("Failed to load MIDI mappings from {:?}: {:?}. Using default empty mappings.", path, e);
+                safe_mode.enter(SafeModeCause::Mappings, format!("{} is invalid: {:#}", path.display(), e));
                 MidiMappingConfig::default()
             });
         
-        let topic_to_actions = Self::build_topic_map(&mappings);
+        let standby = config.failover.is_standby();
+        let mut midi_handler = Self::new(mappings, stats, zones, safe_mode, event_store, sys_events.clone());
+        midi_handler.output_suspended = standby;
+        midi_handler.stats.register_midi_output(MIDI_PORT_NAME);
+        let needs_retry = if standby {
+            info!("Failover standby: the MIDI output stays closed until this server takes over.");
+            sys_events.emit(SYS_MIDI_STATUS, "standby");
+            false
+        } else {
+            match Self::init_midi() {
+                Ok(conn) => {
+                    midi_handler.conn = Some(conn);
+                    info!("MIDI Handler initialized successfully.");
+                    sys_events.emit(SYS_MIDI_STATUS, "ready");
+                    false
+                }
+                Err(e) => {
+                    // On boot-time autostart the MIDI service may simply not be up yet,
+                    // so keep the app running and retry in the background.
+                    error!("Failed to initialize MIDI output: {:?}", e);
+                    sys_events.emit(SYS_MIDI_STATUS, format!("failed to initialize: {:#}", e));
+                    true
+                }
+            }
+        };
+        let stats = midi_handler.stats.clone();
+        let handle = MidiHandle::spawn(midi_handler, &config.midi_queue, stats)?;
+        if needs_retry {
+            Self::spawn_init_retry(handle.clone(), config.startup_retry.clone(), sys_events);
+        }
+        Ok(handle)
+    }
 
-        let mut midi_handler = Self { 
+    // A handler without a port, for re-rendering a recording through the mappings in
+    // `path` (see session_replay.rs). It shares nothing with the live handler: zones,
+    // stats and safe mode are its own, and what it sends is captured on the virtual
+    // clock that `advance_render` moves forward.
+    pub fn for_render(path: &Path) -> Result<Self> {
+        if !path.is_file() {
+            bail!("no mapping file at {:?}", path);
+        }
+        let mappings = Self::load_mappings_from_file(path)?;
+        let sys_events = SysEvents::new();
+        let safe_mode = SafeMode::new(sys_events.clone());
+        let mut handler = Self::new(mappings, Stats::new(0), Zones::new(), safe_mode, None, sys_events);
+        handler.render = Some(Render::default());
+        Ok(handler)
+    }
+
+    fn new(
+        mappings: MidiMappingConfig,
+        stats: Arc<Stats>,
+        zones: Arc<Zones>,
+        safe_mode: Arc<SafeMode>,
+        event_store: Option<Arc<EventStore>>,
+        sys_events: SysEvents,
+    ) -> Self {
+        let topic_to_mapping = Self::build_topic_map(&mappings);
+        let pattern_mappings = Self::build_pattern_list(&mappings);
+        let fallback_mapping = Self::find_fallback(&mappings);
+        let channel_normalizers = Self::build_normalizer_map(&mappings);
+        let polyphony_limits = Self::build_polyphony_map(&mappings);
+        let auto_channels = AutoChannelAllocator::new(mappings.auto_channels.clone());
+
+        let handler = Self {
             conn: None,
             mappings,
-            topic_to_actions,
+            topic_to_mapping,
+            pattern_mappings,
+            fallback_mapping,
+            channel_normalizers,
+            stats,
+            zones,
+            cc_values: HashMap::new(),
+            cc_ramp_generations: HashMap::new(),
+            transpose: 0,
+            safe_mode,
+            latched_notes: HashMap::new(),
+            rotation_steps: HashMap::new(),
+            pending_note_offs: HashMap::new(),
+            next_note_off_id: 0,
+            polyphony_limits,
+            active_notes: HashMap::new(),
+            auto_channels,
+            event_store,
+            sys_events,
+            midi_mirror: None,
+            output_suspended: false,
+            render: None,
         };
-        match midi_handler.init_midi() {
-            Ok(conn) => {
-                midi_handler.conn = Some(conn);
-                info!("MIDI Handler initialized successfully.");
-            }
-            Err(e) => {
-                error!("Failed to initialize MIDI output: {:?}", e);
-                // We can decide if this is a fatal error or if the app can run without MIDI
-                // For now, let's let it run but log the error.
+        handler.register_mapping_stats();
+        handler.register_zones();
+        handler
+    }
+
+    // Retries MIDI initialization with exponential backoff on a background thread.
+    fn spawn_init_retry(handle: MidiHandle, retry: StartupRetryConfig, sys_events: SysEvents) {
+        thread::spawn(move || {
+            for attempt in 2..=retry.max_attempts.max(1) {
+                let delay = retry.delay_after_attempt(attempt - 1);
+                sys_events.emit(
+                    SYS_MIDI_STATUS,
+                    format!("retrying in {}ms (attempt {}/{})", delay.as_millis(), attempt, retry.max_attempts),
+                );
+                thread::sleep(delay);
+                match Self::init_midi() {
+                    Ok(conn) => {
+                        handle.execute(move |handler| handler.conn = Some(conn));
+                        info!("MIDI output initialized on attempt {}/{}.", attempt, retry.max_attempts);
+                        sys_events.emit(SYS_MIDI_STATUS, "ready");
+                        return;
+                    }
+                    Err(e) => {
+                        warn!("MIDI init attempt {}/{} failed: {:?}", attempt, retry.max_attempts, e);
+                    }
+                }
             }
-        }
-        Ok(Arc::new(Mutex::new(midi_handler)))
+            error!("Giving up on MIDI output after {} attempts. Running without MIDI.", retry.max_attempts);
+            sys_events.emit(SYS_MIDI_STATUS, "unavailable");
+        });
+    }
+
+    // Checks that `toml_str` is a loadable mapping file (includes and all), without applying it.
+    pub fn validate_mappings(toml_str: &str) -> Result<()> {
+        mapping_check::check_mapping_tree(&paths::config_dir().join(MAPPING_FILE_NAME), toml_str)
+            .map_err(|issues| anyhow!(mapping_check::describe_issues(&issues)))
+            .context("Invalid MIDI mapping TOML")?;
+        Ok(())
     }
 
     fn load_mappings_from_file(path: &Path) -> Result<MidiMappingConfig> {
         if !path.exists() {
             warn!("MIDI mapping file not found at {:?}. Creating a default empty one.", path);
             // Create a default empty TOML file if it doesn't exist
-            let default_config = MidiMappingConfig::default();
+            let default_config = MidiMappingConfig { version: Some(CURRENT_MAPPING_VERSION), ..Default::default() };
             let toml_string = toml::to_string_pretty(&default_config)?;
             fs::write(path, toml_string)
                 .with_context(|| format!("Failed to write default MIDI mapping file to {:?}", path))?;
@@ -95,43 +418,249 @@ impl MidiHandler {
             return Ok(default_config);
         }
 
-        let toml_str = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read MIDI mapping file from {:?}", path))?;
-        let config: MidiMappingConfig = toml::from_str(&toml_str)
-            .with_context(|| format!("Failed to parse MIDI mapping TOML from {:?}", path))?;
+        // Validated with line-level errors, and merged with any included files.
+        let config = mapping_check::check_mapping_file(path)
+            .map_err(|issues| anyhow!(mapping_check::describe_issues(&issues)))
+            .with_context(|| format!("Invalid MIDI mappings in {:?}", path))?;
         info!("Successfully loaded MIDI mappings from {:?}", path);
         Ok(config)
     }
     
-    fn build_topic_map(config: &MidiMappingConfig) -> HashMap<String, Vec<MidiAction>> {
+    // Resolves the global fallbacks once here so lookups don't have to.
+    fn resolve_entry(config: &MidiMappingConfig, entry: &MappingEntry) -> MappingEntry {
+        let mut resolved = entry.clone();
+        resolved.scale = entry.scale.clone().or_else(|| config.scale.clone());
+        resolved.on_payload_error = entry.on_payload_error.or(Some(config.on_payload_error));
+        if let Some(Err(e)) = entry.schedule.as_ref().map(ScheduleConfig::validate) {
+            warn!("Mapping '{}' has an invalid schedule and will stay inactive: {:?}", entry.sub_topic, e);
+        }
+        resolved
+    }
+
+    fn build_topic_map(config: &MidiMappingConfig) -> HashMap<String, MappingEntry> {
+        let mut map = HashMap::new();
+        for entry in config.mappings.iter().filter(|entry| !is_pattern(&entry.sub_topic)) {
+            map.insert(entry.sub_topic.clone(), Self::resolve_entry(config, entry));
+        }
+        map
+    }
+
+    // Longer patterns are more specific; ties keep the order of the file.
+    fn build_pattern_list(config: &MidiMappingConfig) -> Vec<MappingEntry> {
+        let mut patterns: Vec<MappingEntry> = config
+            .mappings
+            .iter()
+            .filter(|entry| is_pattern(&entry.sub_topic) && entry.sub_topic != FALLBACK_SUB_TOPIC)
+            .map(|entry| Self::resolve_entry(config, entry))
+            .collect();
+        patterns.sort_by_key(|entry| (std::cmp::Reverse(entry.priority), std::cmp::Reverse(entry.sub_topic.len())));
+        patterns
+    }
+
+    fn find_fallback(config: &MidiMappingConfig) -> Option<MappingEntry> {
+        let entry = config.mappings.iter().find(|entry| entry.sub_topic == FALLBACK_SUB_TOPIC)?;
+        Some(Self::resolve_entry(config, entry))
+    }
+
+    fn build_normalizer_map(config: &MidiMappingConfig) -> HashMap<String, Vec<NormalizerConfig>> {
         let mut map = HashMap::new();
-        for entry in &config.mappings {
-            map.insert(entry.sub_topic.clone(), entry.actions.clone());
+        for entry in &config.normalizers {
+            map.insert(entry.channel.clone(), entry.chain.clone());
         }
         map
     }
 
+    fn build_polyphony_map(config: &MidiMappingConfig) -> HashMap<u8, usize> {
+        config
+            .polyphony
+            .iter()
+            .filter(|limit| limit.max_notes > 0)
+            .map(|limit| (limit.channel & 0x0F, limit.max_notes))
+            .collect()
+    }
+
+    fn register_mapping_stats(&self) {
+        let patterns = self.pattern_mappings.iter().chain(&self.fallback_mapping).map(|entry| &entry.sub_topic);
+        for topic in self.topic_to_mapping.keys().chain(patterns) {
+            self.stats.register_mapping(topic);
+        }
+    }
+
+    // Zones referenced by mappings, sequences, LFOs and auto channel pools show up in the tray and admin API.
+    fn register_zones(&self) {
+        let mapping_zones = self.mappings.mappings.iter().filter_map(|m| m.zone.as_deref());
+        let sequence_zones = self.mappings.sequences.iter().filter_map(|s| s.zone.as_deref());
+        let lfo_zones = self.mappings.lfos.iter().filter_map(|l| l.zone.as_deref());
+        let auto_zones = self.mappings.auto_channels.iter().filter_map(|a| a.zone.as_deref());
+        for zone in mapping_zones.chain(sequence_zones).chain(lfo_zones).chain(auto_zones) {
+            self.zones.register(zone);
+        }
+    }
+
+    // Reloads the mapping file and reports the outcome on $SYS/mappings/status.
     pub fn reload_mappings(&mut self) -> Result<()> {
+        let result = self.load_new_mappings();
+        match &result {
+            Ok(()) => self.sys_events.emit(SYS_MAPPINGS_STATUS, "reloaded"),
+            Err(e) => self.sys_events.emit(SYS_MAPPINGS_STATUS, format!("reload failed: {:#}", e)),
+        }
+        result
+    }
+
+    fn load_new_mappings(&mut self) -> Result<()> {
         info!("Attempting to reload MIDI mappings...");
-        let new_mappings = Self::load_mappings_from_file(Path::new(MAPPING_FILE_PATH))?;
+        let new_mappings = Self::load_mappings_from_file(&mapping_file_path())?;
         self.mappings = new_mappings;
-        self.topic_to_actions = Self::build_topic_map(&self.mappings);
+        self.topic_to_mapping = Self::build_topic_map(&self.mappings);
+        self.pattern_mappings = Self::build_pattern_list(&self.mappings);
+        self.fallback_mapping = Self::find_fallback(&self.mappings);
+        self.channel_normalizers = Self::build_normalizer_map(&self.mappings);
+        self.polyphony_limits = Self::build_polyphony_map(&self.mappings);
+        // Allocations start over; topics get a slot again on their next publish.
+        self.auto_channels = AutoChannelAllocator::new(self.mappings.auto_channels.clone());
+        self.rotation_steps.clear();
+        self.register_mapping_stats();
+        self.register_zones();
+        self.safe_mode.resolve(SafeModeCause::Mappings);
         info!("MIDI mappings reloaded successfully.");
         Ok(())
     }
 
-    pub fn get_actions_for_topic(&self, topic: &str) -> Option<Vec<MidiAction>> {
-        self.topic_to_actions.get(topic).cloned()
+    // The server's, except on a render handler
+    pub fn stats(&self) -> Arc<Stats> {
+        self.stats.clone()
+    }
+
+    pub fn zones(&self) -> Arc<Zones> {
+        self.zones.clone()
+    }
+
+    pub fn is_rendering(&self) -> bool {
+        self.render.is_some()
+    }
+
+    // Queues `job` to run `delay` after the render's current time. Only for render handlers.
+    pub fn render_later(&mut self, delay: Duration, job: impl FnOnce(&mut MidiHandler) + Send + 'static) {
+        let Some(render) = &mut self.render else {
+            return;
+        };
+        render.next_job += 1;
+        let due = render.now_ms + delay.as_millis() as u64;
+        render.pending.insert((due, render.next_job), Box::new(job));
+    }
+
+    // Runs the delayed work due up to `t_ms`, each at its own time, and moves the clock to `t_ms`.
+    pub fn advance_render(&mut self, t_ms: u64) {
+        while let Some(render) = &mut self.render
+            && let Some(entry) = render.pending.first_entry()
+            && entry.key().0 <= t_ms
+        {
+            let ((due, _), job) = entry.remove_entry();
+            render.now_ms = render.now_ms.max(due);
+            job(self);
+        }
+        if let Some(render) = &mut self.render {
+            render.now_ms = render.now_ms.max(t_ms);
+        }
+    }
+
+    // Lets every delayed NoteOff and ramp finish, then returns the captured messages.
+    pub fn finish_render(mut self) -> Vec<(u64, Vec<u8>)> {
+        self.advance_render(u64::MAX);
+        self.render.map(|render| render.events).unwrap_or_default()
+    }
+
+    // Whether `topic` starts or stops one of the mapping file's sequences or LFOs.
+    pub fn is_control_topic(&self, topic: &str) -> bool {
+        self.mappings.sequences.iter().any(|sequence| sequence.control_topic == topic)
+            || self.mappings.lfos.iter().any(|lfo| lfo.control_topic == topic)
+    }
+
+    // The mappings to run for a publish on `topic`, best match first: the exact mapping
+    // and the matching patterns by priority (an exact mapping wins a tie), then cut down
+    // to the first one unless `match_policy = "all_matches"`. Mappings outside their
+    // schedule don't count, so the next match takes over. Topics no mapping or auto
+    // channel pool covers get the `sub_topic = "*"` mapping, if there is one and
+    // `use_fallback` allows it.
+    pub fn get_mappings_for_topic(&mut self, topic: &str, use_fallback: bool) -> Vec<MappingEntry> {
+        let exact = self.topic_to_mapping.get(topic).into_iter();
+        let patterns = self.pattern_mappings.iter().filter(|entry| topic_matches(&entry.sub_topic, topic));
+        let matches: Vec<&MappingEntry> = exact.chain(patterns).collect();
+        let mapped = !matches.is_empty();
+        let mut candidates: Vec<&MappingEntry> = matches.into_iter().filter(|entry| self.is_scheduled(entry)).collect();
+        // Stable, so exact-before-pattern and the pattern order hold within a priority.
+        candidates.sort_by_key(|entry| std::cmp::Reverse(entry.priority));
+        if self.mappings.match_policy == MatchPolicy::FirstMatch {
+            candidates.truncate(1);
+        }
+        let mappings: Vec<MappingEntry> = candidates.into_iter().cloned().collect();
+        if mapped {
+            return mappings;
+        }
+        // Not mapped at all; maybe it falls into an auto channel pool.
+        if let Some(mut mapping) = self.auto_channels.mapping_for(topic) {
+            mapping.scale = self.mappings.scale.clone();
+            mapping.on_payload_error = Some(self.mappings.on_payload_error);
+            return vec![mapping];
+        }
+        match &self.fallback_mapping {
+            Some(fallback) if use_fallback && self.is_scheduled(fallback) => {
+                debug!("No mapping for '{}', using the fallback mapping.", topic);
+                vec![fallback.clone()]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn is_scheduled(&self, mapping: &MappingEntry) -> bool {
+        let Some(schedule) = &mapping.schedule else {
+            return true;
+        };
+        let active = schedule::now_in_timezone(self.mappings.timezone.as_deref())
+            .and_then(|now| schedule.is_active_at(now))
+            .unwrap_or_else(|e| {
+                warn!("Failed to evaluate schedule for '{}': {:?}", mapping.sub_topic, e);
+                false
+            });
+        if !active {
+            debug!("Mapping '{}' is outside its schedule. Skipping.", mapping.sub_topic);
+        }
+        active
+    }
+
+    // Runs the channel's normalizer chain (if any) over the payload.
+    // If normalization fails the raw payload is used, like any other unparseable payload.
+    pub fn normalize_payload(&self, topic: &str, payload: &str) -> String {
+        let Some(chain) = self.channel_normalizers.get(topic) else {
+            return payload.to_string();
+        };
+        match normalizer::apply_chain(chain, payload) {
+            Ok(normalized) => {
+                debug!("Normalized payload on '{}': {} -> {}", topic, payload, normalized);
+                normalized
+            }
+            Err(e) => {
+                warn!("Failed to normalize payload on '{}': {:?}. Using it as-is.", topic, e);
+                payload.to_string()
+            }
+        }
     }
 
-    fn init_midi(&mut self) -> Result<MidiOutputConnection> {
+    // Sequences are picked up when the server starts.
+    pub fn get_sequences(&self) -> Vec<SequenceConfig> {
+        self.mappings.sequences.clone()
+    }
+
+    pub fn get_lfos(&self) -> Vec<LfoConfig> {
+        self.mappings.lfos.clone()
+    }
+
+    // On macOS (CoreMIDI) and Linux (ALSA) the server creates its own virtual port that
+    // other apps can see.
+    #[cfg(unix)]
+    fn init_midi() -> Result<MidiOutputConnection> {
         let midi_out = MidiOutput::new(MIDI_CLIENT_NAME)?;
-        
-        // For now, let's just create a virtual port.
-        // On macOS, this should make it visible to other apps.
-        // On Windows, it might require specific drivers or loopMIDI.
-        // On Linux, ALSA handles this.
-        let port_name = "Zerver";
+        let port_name = MIDI_PORT_NAME;
         let conn = midi_out.create_virtual(port_name).map_err(|e| {
             anyhow::anyhow!("Failed to create virtual MIDI output port with name '{}': {}", port_name, e)
         })?;
@@ -140,10 +669,198 @@ impl MidiHandler {
         Ok(conn)
     }
 
+    // Windows has no virtual ports, so the server connects to an existing port whose name
+    // contains "Zerver", e.g. one created in loopMIDI. Until it exists, init is retried.
+    #[cfg(not(unix))]
+    fn init_midi() -> Result<MidiOutputConnection> {
+        let midi_out = MidiOutput::new(MIDI_CLIENT_NAME)?;
+        let port_name = MIDI_PORT_NAME;
+        let ports = midi_out.ports();
+        let port = ports
+            .iter()
+            .find(|port| midi_out.port_name(port).is_ok_and(|name| name.contains(port_name)))
+            .ok_or_else(|| anyhow!("No MIDI output port named '{}' found. Create one in loopMIDI (or similar).", port_name))?;
+        let actual_name = midi_out.port_name(port).unwrap_or_else(|_| port_name.to_string());
+        let conn = midi_out.connect(port, port_name).map_err(|e| {
+            anyhow::anyhow!("Failed to connect to MIDI output port '{}': {}", actual_name, e)
+        })?;
+
+        info!("Connected to MIDI output port: {}", actual_name);
+        Ok(conn)
+    }
+
+    pub fn set_transpose(&mut self, semitones: i8) {
+        self.transpose = semitones;
+        info!("Global transpose set to {:+} semitones.", semitones);
+    }
+
+    // Applies the global transpose plus a per-mapping offset. Notes out of range are clamped.
+    pub fn transpose_note(&self, note: u8, mapping_transpose: i8) -> u8 {
+        (note as i16 + self.transpose as i16 + mapping_transpose as i16).clamp(0, 127) as u8
+    }
+
+    // Flips a note_toggle action. Returns the (channel, note) to release if it was latched,
+    // otherwise latches the given note and returns None (the caller sends the NoteOn).
+    pub fn toggle_latch(&mut self, sub_topic: &str, action_index: usize, channel: u8, note: u8) -> Option<(u8, u8)> {
+        let key = (sub_topic.to_string(), action_index);
+        match self.latched_notes.remove(&key) {
+            Some(latched) => Some(latched),
+            None => {
+                self.latched_notes.insert(key, (channel, note));
+                None
+            }
+        }
+    }
+
+    // The rotation step for this trigger of a `rotate` mapping; the next one gets the next.
+    pub fn next_rotation_step(&mut self, sub_topic: &str) -> usize {
+        let step = self.rotation_steps.entry(sub_topic.to_string()).or_insert(0);
+        let current = *step;
+        *step = step.wrapping_add(1);
+        current
+    }
+
+    // Remembers a NoteOff that a delay task will send. Returns the id to claim it with.
+    pub fn schedule_note_off(&mut self, message: Vec<u8>) -> u64 {
+        self.next_note_off_id += 1;
+        self.pending_note_offs.insert(self.next_note_off_id, message);
+        self.next_note_off_id
+    }
+
+    // Claims a scheduled NoteOff when it is due. None if it was already flushed.
+    pub fn take_note_off(&mut self, id: u64) -> Option<Vec<u8>> {
+        self.pending_note_offs.remove(&id)
+    }
+
+    // Sends every NoteOff that is still waiting, right now. Returns how many were sent.
+    pub fn flush_pending_note_offs(&mut self) -> usize {
+        let pending: Vec<Vec<u8>> = self.pending_note_offs.drain().map(|(_, message)| message).collect();
+        for message in &pending {
+            if let Err(e) = self.send_midi_message(message) {
+                error!("Failed to flush pending NoteOff {:?}: {:?}", message, e);
+            }
+        }
+        pending.len()
+    }
+
+    // Closes the output when a failover standby hands back to the primary, and opens it
+    // when the standby takes over. Sounding notes are released before the port goes.
+    pub fn set_output_suspended(&mut self, suspended: bool) {
+        if suspended == self.output_suspended {
+            return;
+        }
+        self.output_suspended = suspended;
+        if suspended {
+            self.flush_pending_note_offs();
+            if let Some(mut conn) = self.conn.take() {
+                // All Notes Off on every channel, for latched and held notes
+                for channel in 0..16u8 {
+                    let _ = conn.send(&[0xB0 | channel, 123, 0]);
+                }
+            }
+            self.latched_notes.clear();
+            self.active_notes.clear();
+            info!("MIDI output closed: this server is the failover standby again.");
+            self.sys_events.emit(SYS_MIDI_STATUS, "standby");
+            return;
+        }
+        match Self::init_midi() {
+            Ok(conn) => {
+                self.conn = Some(conn);
+                info!("MIDI output opened: this server took over.");
+                self.sys_events.emit(SYS_MIDI_STATUS, "ready");
+            }
+            Err(e) => {
+                error!("Failed to open MIDI output on failover: {:?}", e);
+                self.sys_events.emit(SYS_MIDI_STATUS, format!("failed to initialize: {:#}", e));
+            }
+        }
+    }
+
+    pub fn set_midi_mirror(&mut self, mirror: Option<tokio_mpsc::UnboundedSender<Vec<u8>>>) {
+        self.midi_mirror = mirror;
+    }
+
+    pub fn last_cc_value(&self, channel: u8, control_num: u8) -> Option<u8> {
+        self.cc_values.get(&(channel & 0x0F, control_num)).copied()
+    }
+
+    // Stops any ramp currently running on this controller.
+    pub fn cancel_cc_ramp(&mut self, channel: u8, control_num: u8) {
+        *self.cc_ramp_generations.entry((channel & 0x0F, control_num)).or_insert(0) += 1;
+    }
+
+    // Claims a controller for a new ramp. Returns the generation the ramp task checks against.
+    pub fn begin_cc_ramp(&mut self, channel: u8, control_num: u8) -> u64 {
+        self.cancel_cc_ramp(channel, control_num);
+        self.cc_ramp_generations[&(channel & 0x0F, control_num)]
+    }
+
+    pub fn is_current_cc_ramp(&self, channel: u8, control_num: u8, generation: u64) -> bool {
+        self.cc_ramp_generations.get(&(channel & 0x0F, control_num)) == Some(&generation)
+    }
+
     pub fn send_midi_message(&mut self, message: &[u8]) -> Result<()> {
+        if self.safe_mode.is_active() {
+            return Ok(()); // Muted until the broken config/mappings are fixed
+        }
+        if let [status, note, velocity] = *message {
+            let channel = status & 0x0F;
+            match status & 0xF0 {
+                0x90 if velocity > 0 => self.start_voice(channel, note)?,
+                0x80 | 0x90 => self.end_voice(channel, note),
+                _ => {}
+            }
+        }
+        self.write_midi(message)
+    }
+
+    // Tracks a new note and steals the oldest one if the channel is over its polyphony limit.
+    fn start_voice(&mut self, channel: u8, note: u8) -> Result<()> {
+        let Some(&max_notes) = self.polyphony_limits.get(&channel) else {
+            return Ok(());
+        };
+        let voices = self.active_notes.entry(channel).or_default();
+        voices.retain(|&n| n != note); // A retriggered note takes its old voice
+        let mut stolen = Vec::new();
+        while voices.len() >= max_notes {
+            stolen.extend(voices.pop_front());
+        }
+        voices.push_back(note);
+        for stolen_note in stolen {
+            debug!("Polyphony limit on channel {}: stealing note {}", channel, stolen_note);
+            self.write_midi(&[0x80 + channel, stolen_note, 0])?;
+        }
+        Ok(())
+    }
+
+    fn end_voice(&mut self, channel: u8, note: u8) {
+        if let Some(voices) = self.active_notes.get_mut(&channel) {
+            voices.retain(|&n| n != note);
+        }
+    }
+
+    fn write_midi(&mut self, message: &[u8]) -> Result<()> {
+        if let Some(render) = &mut self.render {
+            render.events.push((render.now_ms, message.to_vec()));
+            self.remember_cc(message);
+            return Ok(());
+        }
         if let Some(conn) = &mut self.conn {
             conn.send(message)
                 .with_context(|| "Failed to send MIDI message")?;
+            self.stats.record_midi_sent(MIDI_PORT_NAME);
+            self.stats.recent_events().record_midi(message);
+            if let Some(event_store) = &self.event_store {
+                event_store.record_midi(message);
+            }
+            self.remember_cc(message);
+            // The server that set the mirror has stopped once its receiver is gone.
+            if let Some(mirror) = &self.midi_mirror
+                && mirror.send(message.to_vec()).is_err()
+            {
+                self.midi_mirror = None;
+            }
             // info!("Sent MIDI: {:?}", message); // Potentially too verbose
         } else {
             // error!("MIDI connection not available. Cannot send message.");
@@ -152,6 +869,15 @@ impl MidiHandler {
         }
         Ok(())
     }
+
+    // The starting point for CC ramps
+    fn remember_cc(&mut self, message: &[u8]) {
+        if let [status, control_num, value] = *message
+            && status & 0xF0 == 0xB0
+        {
+            self.cc_values.insert((status & 0x0F, control_num), value);
+        }
+    }
 }
 
 // Example MIDI messages (Note On, Note Off)
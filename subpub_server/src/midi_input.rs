@@ -0,0 +1,131 @@
+use anyhow::{anyhow, Result};
+use log::{debug, error, info};
+#[cfg(unix)]
+use midir::os::unix::VirtualInput;
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use serde_json::json;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::{MidiInputConfig, MidiInputMessage, MidiInputPayload, MidiInputRoute};
+use crate::server::{handle_publish, ServerContext};
+
+const MIDI_INPUT_CLIENT_NAME: &str = "ZerverClient In";
+
+// A CC or note that came in on the input port.
+#[derive(Debug, Clone, Copy)]
+struct Incoming {
+    message: MidiInputMessage,
+    channel: u8,
+    number: u8,
+    // CC value, or note velocity (0 for NoteOff)
+    value: u8,
+}
+
+fn parse(message: &[u8]) -> Option<Incoming> {
+    let [status, number, value] = *message else {
+        return None; // Clock, sysex, program changes and the like aren't routed
+    };
+    let channel = status & 0x0F;
+    let (message, value) = match status & 0xF0 {
+        0xB0 => (MidiInputMessage::Cc, value),
+        0x90 => (MidiInputMessage::Note, value),
+        0x80 => (MidiInputMessage::Note, 0),
+        _ => return None,
+    };
+    Some(Incoming { message, channel, number, value })
+}
+
+impl MidiInputRoute {
+    fn matches(&self, incoming: &Incoming) -> bool {
+        self.message == incoming.message
+            && self.channel.is_none_or(|channel| channel & 0x0F == incoming.channel)
+            && self.number.is_none_or(|number| number == incoming.number)
+    }
+
+    // The publish for this message: (topic, payload)
+    fn publish_for(&self, incoming: &Incoming) -> (String, String) {
+        let topic = self.topic.replace("{ch}", &incoming.channel.to_string()).replace("{num}", &incoming.number.to_string());
+        // Same keys as the MIDI overrides of a PUB payload, so a route can feed a mapping as is.
+        let payload = match (self.payload, incoming.message) {
+            (MidiInputPayload::Value, _) => incoming.value.to_string(),
+            (MidiInputPayload::Json, MidiInputMessage::Cc) => {
+                json!({ "ch": incoming.channel, "control_num": incoming.number, "value": incoming.value }).to_string()
+            }
+            (MidiInputPayload::Json, MidiInputMessage::Note) => {
+                json!({ "ch": incoming.channel, "note": incoming.number, "vel": incoming.value }).to_string()
+            }
+        };
+        (topic, payload)
+    }
+}
+
+// Opens the MIDI input and publishes what its routes match, in the order it arrives.
+// The port stays open until the returned task is aborted.
+pub fn spawn(config: &MidiInputConfig, ctx: ServerContext) -> Option<JoinHandle<()>> {
+    if !config.enabled {
+        return None;
+    }
+    let (tx, mut rx) = tokio_mpsc::unbounded_channel::<(String, String)>();
+    let routes = config.routes.clone();
+    let callback = move |_timestamp: u64, message: &[u8], _: &mut ()| {
+        let Some(incoming) = parse(message) else {
+            return;
+        };
+        for route in routes.iter().filter(|route| route.matches(&incoming)) {
+            let _ = tx.send(route.publish_for(&incoming));
+        }
+    };
+    let connection = match open_port(&config.port_name, callback) {
+        Ok(connection) => connection,
+        Err(e) => {
+            error!("Failed to open MIDI input: {:?}", e);
+            return None;
+        }
+    };
+
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        let _connection = connection; // Closed when the server stops
+        while let Some((topic, payload)) = rx.recv().await {
+            debug!(topic = topic.as_str(); "MIDI input published to channel '{}': {}", topic, payload);
+            handle_publish(&ctx, None, &topic, &payload, None, None).await;
+        }
+    }))
+}
+
+// Like the output, a virtual port on macOS and Linux that the DAW can send to.
+#[cfg(unix)]
+fn open_port<F>(port_name: &str, callback: F) -> Result<MidiInputConnection<()>>
+where
+    F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+{
+    let mut midi_in = MidiInput::new(MIDI_INPUT_CLIENT_NAME)?;
+    midi_in.ignore(Ignore::All);
+    let connection = midi_in
+        .create_virtual(port_name, callback, ())
+        .map_err(|e| anyhow!("Failed to create virtual MIDI input port with name '{}': {}", port_name, e))?;
+    info!("Created virtual MIDI input port: {}", port_name);
+    Ok(connection)
+}
+
+// Windows has no virtual ports: connect to an existing one, e.g. a second loopMIDI port.
+#[cfg(not(unix))]
+fn open_port<F>(port_name: &str, callback: F) -> Result<MidiInputConnection<()>>
+where
+    F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+{
+    let mut midi_in = MidiInput::new(MIDI_INPUT_CLIENT_NAME)?;
+    midi_in.ignore(Ignore::All);
+    let ports = midi_in.ports();
+    let port = ports
+        .iter()
+        .find(|port| midi_in.port_name(port).is_ok_and(|name| name.contains(port_name)))
+        .ok_or_else(|| anyhow!("No MIDI input port named '{}' found. Create one in loopMIDI (or similar).", port_name))?;
+    let actual_name = midi_in.port_name(port).unwrap_or_else(|_| port_name.to_string());
+    let connection = midi_in
+        .connect(port, port_name, callback, ())
+        .map_err(|e| anyhow!("Failed to connect to MIDI input port '{}': {}", actual_name, e))?;
+    info!("Connected to MIDI input port: {}", actual_name);
+    Ok(connection)
+}
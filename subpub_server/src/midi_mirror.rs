@@ -0,0 +1,59 @@
+use log::{error, info};
+use serde_json::json;
+use std::net::SocketAddr;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::MidiMirrorConfig;
+use crate::server::ServerContext;
+
+// One sent MIDI message as bus publishes: (specific topic, its payload, summary JSON).
+// Channels in topics count from 1 like on a synth, the JSON keeps 0-15 like PUB overrides.
+fn describe(prefix: &str, message: &[u8]) -> Option<(String, String, String)> {
+    let status = *message.first()?;
+    let channel = status & 0x0F;
+    let (kind, number, value) = match (status & 0xF0, message) {
+        (0x90, [_, note, velocity]) => ("note", Some(*note), *velocity),
+        (0x80, [_, note, _]) => ("note", Some(*note), 0),
+        (0xB0, [_, control_num, value]) => ("cc", Some(*control_num), *value),
+        (0xC0, [_, program]) => ("program", None, *program),
+        _ => return None,
+    };
+    let topic = match number {
+        Some(number) => format!("{}/ch{}/{}{}", prefix, channel + 1, kind, number),
+        None => format!("{}/ch{}/{}", prefix, channel + 1, kind),
+    };
+    let summary = json!({ "ch": channel, "type": kind, "num": number, "value": value }).to_string();
+    Some((topic, value.to_string(), summary))
+}
+
+// Republishes every MIDI message the handler sends to subscribers of the mirror topics,
+// so a visualizer can show exactly what the synths got. Mirror publishes only go out to
+// subscribers; they don't run mappings, so mapping a mirror topic can't loop.
+pub fn spawn(config: &MidiMirrorConfig, ctx: ServerContext) -> Option<JoinHandle<()>> {
+    if !config.enabled {
+        return None;
+    }
+    let prefix = config.prefix.trim_end_matches('/').to_string();
+    let (tx, mut rx) = tokio_mpsc::unbounded_channel::<Vec<u8>>();
+    ctx.midi.execute(move |handler| handler.set_midi_mirror(Some(tx)));
+    info!("Mirroring sent MIDI on '{}/...'", prefix);
+
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        while let Some(message) = rx.recv().await {
+            let Some((topic, payload, summary)) = describe(&prefix, &message) else {
+                continue;
+            };
+            for (channel, payload) in [(topic.as_str(), payload.as_str()), (prefix.as_str(), summary.as_str())] {
+                let targets: Vec<SocketAddr> = match ctx.subscribers.get(channel) {
+                    Some(channel_set_ref) => channel_set_ref.value().iter().cloned().collect(),
+                    None => continue,
+                };
+                for (subscriber_addr, e) in ctx.socket.send_to_many(payload.as_bytes(), &targets).await {
+                    error!("Failed to send MIDI mirror of '{}' to {}: {}", channel, subscriber_addr, e);
+                }
+            }
+        }
+    }))
+}
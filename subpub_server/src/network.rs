@@ -0,0 +1,103 @@
+use anyhow::{bail, Context, Result};
+use log::warn;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+
+use crate::config::{DiscoveryConfig, IpMode};
+
+// Whether several sockets can share the main port, see `[datagrams] receive_loops`.
+pub const REUSE_PORT_SUPPORTED: bool = cfg!(unix);
+
+// Binds a non-blocking UDP socket. For IPv6 addresses `only_v6` decides whether IPv4
+// peers can reach it too (as ::ffff:a.b.c.d); the OS default for that differs per
+// platform, so it is always set explicitly. With `reuse_port` other sockets that set
+// it too can bind the same address (SO_REUSEPORT, unix only).
+pub fn bind_udp(addr: SocketAddr, only_v6: bool, reuse_port: bool) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "SO_REUSEPORT is not available on this platform"));
+    }
+    socket.bind(&SockAddr::from(addr))?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+// IPv4 peers of a dual-stack socket show up as IPv4-mapped IPv6 addresses. The rest of
+// the server (ACLs, IP filter, client tracking) sees them as plain IPv4.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+// The address to hand a client looking for the server. A socket bound to a wildcard
+// address answers with this machine's address of the client's IP version.
+pub fn advertised_address(bound: SocketAddr, peer: SocketAddr) -> SocketAddr {
+    if !bound.ip().is_unspecified() {
+        return bound;
+    }
+    let local_ip = match peer.ip().to_canonical() {
+        IpAddr::V4(_) => local_ip_address::local_ip(),
+        IpAddr::V6(_) => local_ip_address::local_ipv6(),
+    };
+    match local_ip {
+        Ok(ip) => SocketAddr::new(ip, bound.port()),
+        Err(e) => {
+            warn!("Could not get a local address to advertise to {}: {}", peer, e);
+            bound
+        }
+    }
+}
+
+// The multicast groups to listen on for discovery pings, one per IP version in use.
+// Empty when discovery is disabled.
+pub fn discovery_groups(discovery: &DiscoveryConfig, ip_mode: IpMode) -> Result<Vec<SocketAddr>> {
+    if !discovery.enabled {
+        return Ok(Vec::new());
+    }
+    let configured: &[(&str, &str)] = match ip_mode {
+        IpMode::Ipv4 => &[("group", &discovery.group)],
+        IpMode::Ipv6 => &[("group_v6", &discovery.group_v6)],
+        IpMode::Dual => &[("group", &discovery.group), ("group_v6", &discovery.group_v6)],
+    };
+    let mut groups = Vec::new();
+    for (key, value) in configured {
+        let group: SocketAddr = value
+            .parse()
+            .with_context(|| format!("[discovery] {} '{}' must be <address>:<port>", key, value))?;
+        let expected_family = if *key == "group" { group.is_ipv4() } else { group.is_ipv6() };
+        if !expected_family {
+            bail!("[discovery] {} '{}' has the wrong IP version", key, value);
+        }
+        if !group.ip().is_multicast() {
+            bail!("[discovery] {} '{}' isn't a multicast address (224.0.0.0/4 or ff00::/8)", key, value);
+        }
+        if group.port() == 0 {
+            bail!("[discovery] {} '{}' needs a port", key, value);
+        }
+        groups.push(group);
+    }
+    Ok(groups)
+}
+
+// The port for broadcast discovery, if it is on. Broadcast is IPv4 only, so it is off
+// in IPv6-only mode.
+pub fn broadcast_discovery_port(discovery: &DiscoveryConfig, ip_mode: IpMode) -> Result<Option<u16>> {
+    if !discovery.enabled || !discovery.broadcast || ip_mode == IpMode::Ipv6 {
+        return Ok(None);
+    }
+    if discovery.broadcast_port == 0 {
+        bail!("[discovery] broadcast_port must be set");
+    }
+    let group_port = discovery.group.parse::<SocketAddr>().map(|group| group.port()).ok();
+    if group_port == Some(discovery.broadcast_port) {
+        bail!("[discovery] broadcast_port {} is already taken by the multicast group", discovery.broadcast_port);
+    }
+    Ok(Some(discovery.broadcast_port))
+}
@@ -0,0 +1,193 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value};
+
+// Built-in payload normalizers. A channel can chain several of these to turn a
+// vendor-specific payload into the clean JSON the mapping logic expects.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NormalizerConfig {
+    // Removes a fixed prefix from a text payload, e.g. a vendor header.
+    StripPrefix { prefix: String },
+    // Splits a text payload into named fields: "0.1,0.5" -> {"x": 0.1, "y": 0.5}
+    Split { delimiter: String, fields: Vec<String> },
+    // Parses an OSC-style text blob: "/accel ,ff 0.1 0.5" -> {"address": "/accel", "args": [0.1, 0.5]}
+    OscText,
+    // Extracts a value by dotted path ("data.sensors.0.value") into a new object under `into`.
+    JsonField {
+        path: String,
+        #[serde(default = "default_field_name")]
+        into: String,
+    },
+    // Linearly maps a numeric field from one range to another, rounded and clamped.
+    Scale {
+        field: String,
+        in_min: f64,
+        in_max: f64,
+        out_min: f64,
+        out_max: f64,
+    },
+    // Renames a top-level field, e.g. "velocity" -> "vel".
+    Rename { from: String, to: String },
+    // Builds a new payload from a template: `{path}` is replaced by that (dotted path)
+    // field, `{}` by the whole payload. `{"level": {args.0}}` -> {"level": 0.5}
+    Template { template: String },
+}
+
+fn default_field_name() -> String {
+    "value".to_string()
+}
+
+// Normalizer chain for one channel.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelNormalizers {
+    pub channel: String,
+    pub chain: Vec<NormalizerConfig>,
+}
+
+// Runs `payload` through the chain and returns the normalized payload.
+pub fn apply_chain(chain: &[NormalizerConfig], payload: &str) -> Result<String> {
+    let mut value = serde_json::from_str(payload).unwrap_or_else(|_| Value::String(payload.to_string()));
+    for normalizer in chain {
+        value = normalizer.apply(value)?;
+    }
+    Ok(match value {
+        Value::String(s) => s,
+        other => other.to_string(),
+    })
+}
+
+// Parses a text token as an integer or float where possible, otherwise keeps it as a string.
+fn parse_scalar(token: &str) -> Value {
+    let token = token.trim();
+    if let Ok(i) = token.parse::<i64>() {
+        return Value::Number(i.into());
+    }
+    if let Some(n) = token.parse::<f64>().ok().and_then(Number::from_f64) {
+        return Value::Number(n);
+    }
+    Value::String(token.to_string())
+}
+
+// The value at a dotted path like "data.sensors.0.value".
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = value;
+    for segment in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get(segment),
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None,
+        }?;
+    }
+    Some(current)
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+// Text is inserted without quotes, anything else as JSON.
+fn render_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn expect_text(value: &Value, normalizer: &str) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        other => bail!("'{}' expects a text payload, got {}", normalizer, other),
+    }
+}
+
+fn expect_object(value: Value, normalizer: &str) -> Result<Map<String, Value>> {
+    match value {
+        Value::Object(map) => Ok(map),
+        other => bail!("'{}' expects a JSON object, got {}", normalizer, other),
+    }
+}
+
+impl NormalizerConfig {
+    fn apply(&self, value: Value) -> Result<Value> {
+        match self {
+            NormalizerConfig::StripPrefix { prefix } => {
+                let text = expect_text(&value, "strip_prefix")?;
+                Ok(Value::String(text.strip_prefix(prefix.as_str()).unwrap_or(&text).to_string()))
+            }
+            NormalizerConfig::Split { delimiter, fields } => {
+                let text = expect_text(&value, "split")?;
+                let mut map = Map::new();
+                for (name, token) in fields.iter().zip(text.split(delimiter.as_str())) {
+                    map.insert(name.clone(), parse_scalar(token));
+                }
+                Ok(Value::Object(map))
+            }
+            NormalizerConfig::OscText => {
+                let text = expect_text(&value, "osc_text")?;
+                let mut tokens = text.split_whitespace();
+                let address = tokens.next().filter(|a| a.starts_with('/'))
+                    .ok_or_else(|| anyhow!("'osc_text' payload has no OSC address: {}", text))?;
+                // The type tag string (",iff") is optional in text form.
+                let args: Vec<Value> = tokens
+                    .filter(|t| !t.starts_with(','))
+                    .map(parse_scalar)
+                    .collect();
+                let mut map = Map::new();
+                map.insert("address".to_string(), Value::String(address.to_string()));
+                map.insert("args".to_string(), Value::Array(args));
+                Ok(Value::Object(map))
+            }
+            NormalizerConfig::JsonField { path, into } => {
+                let current = lookup(&value, path).ok_or_else(|| anyhow!("'json_field' path '{}' not found in payload", path))?;
+                let mut map = Map::new();
+                map.insert(into.clone(), current.clone());
+                Ok(Value::Object(map))
+            }
+            NormalizerConfig::Scale { field, in_min, in_max, out_min, out_max } => {
+                let mut map = expect_object(value, "scale")?;
+                let input = map.get(field).and_then(Value::as_f64)
+                    .ok_or_else(|| anyhow!("'scale' field '{}' is missing or not a number", field))?;
+                if in_max == in_min {
+                    bail!("'scale' for field '{}' has an empty input range", field);
+                }
+                let t = ((input - in_min) / (in_max - in_min)).clamp(0.0, 1.0);
+                let scaled = (out_min + t * (out_max - out_min)).round() as i64;
+                map.insert(field.clone(), Value::Number(scaled.into()));
+                Ok(Value::Object(map))
+            }
+            NormalizerConfig::Rename { from, to } => {
+                let mut map = expect_object(value, "rename")?;
+                if let Some(v) = map.remove(from) {
+                    map.insert(to.clone(), v);
+                }
+                Ok(Value::Object(map))
+            }
+            NormalizerConfig::Template { template } => {
+                let mut rendered = String::new();
+                let mut rest = template.as_str();
+                while let Some(start) = rest.find('{') {
+                    // Braces around anything but a path are kept as text, so JSON templates
+                    // can use braces of their own.
+                    let Some(end) = rest[start + 1..].find(|c: char| !is_path_char(c)).filter(|&end| rest[start + 1 + end..].starts_with('}')) else {
+                        rendered.push_str(&rest[..=start]);
+                        rest = &rest[start + 1..];
+                        continue;
+                    };
+                    let path = &rest[start + 1..start + 1 + end];
+                    rendered.push_str(&rest[..start]);
+                    if path.is_empty() {
+                        rendered.push_str(&render_value(&value));
+                    } else {
+                        let field = lookup(&value, path).ok_or_else(|| anyhow!("'template' field '{}' not found in payload", path))?;
+                        rendered.push_str(&render_value(field));
+                    }
+                    rest = &rest[start + 1 + end + 1..];
+                }
+                rendered.push_str(rest);
+                Ok(serde_json::from_str(&rendered).unwrap_or(Value::String(rendered)))
+            }
+        }
+    }
+}
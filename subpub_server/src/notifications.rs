@@ -0,0 +1,75 @@
+use log::warn;
+use notify_rust::Notification;
+use std::thread;
+
+use crate::config::NotificationsConfig;
+use crate::sys_events::{SysEvent, SYS_MAPPINGS_STATUS, SYS_MIDI_STATUS, SYS_SERVER_STATUS};
+
+const APP_NAME: &str = "SubPub Server";
+
+// Native desktop notifications for the events someone running a show should not miss,
+// since the log file is not where anyone looks mid-show. Fed from the tray's $SYS
+// receiver, plus server errors that only the tray sees.
+pub struct Notifier {
+    enabled: bool,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        Self { enabled: config.enabled }
+    }
+
+    // Shows a notification if `event` is one worth interrupting for.
+    pub fn on_sys_event(&self, event: &SysEvent) {
+        if let Some((summary, body)) = describe(event) {
+            self.notify(summary, &body);
+        }
+    }
+
+    // Shows the notification from a short-lived thread: on some platforms `show`
+    // waits for the notification daemon, and the caller is the tray event loop.
+    pub fn notify(&self, summary: &str, body: &str) {
+        if !self.enabled {
+            return;
+        }
+        let (summary, body) = (summary.to_string(), body.to_string());
+        thread::spawn(move || {
+            if let Err(e) = Notification::new().appname(APP_NAME).summary(&summary).body(&body).show() {
+                warn!("Failed to show notification '{}': {}", summary, e);
+            }
+        });
+    }
+}
+
+// Summary and body for the $SYS events that get a notification. Bind failures and a
+// watchdog that gives up ("failed") are left out here; they end the server, which the
+// tray reports along with the error itself.
+fn describe(event: &SysEvent) -> Option<(&'static str, String)> {
+    let payload = event.payload.as_str();
+    match event.topic.as_str() {
+        SYS_SERVER_STATUS => {
+            if let Some(addr) = payload.strip_prefix("listening on ") {
+                Some(("Server started", format!("Listening on {}", addr)))
+            } else if let Some(retry) = payload.strip_prefix("loop failed, ") {
+                Some(("Server loop failed", format!("The server stopped receiving, {}.", retry)))
+            } else if payload == "stopped" {
+                Some(("Server stopped", "Clients can no longer publish.".to_string()))
+            } else {
+                None
+            }
+        }
+        SYS_MIDI_STATUS => {
+            if let Some(reason) = payload.strip_prefix("failed to initialize: ") {
+                Some(("MIDI output failed", format!("{}. Retrying in the background.", reason)))
+            } else if payload == "unavailable" {
+                Some(("MIDI output unavailable", "Gave up retrying, running without MIDI.".to_string()))
+            } else {
+                None
+            }
+        }
+        SYS_MAPPINGS_STATUS => payload
+            .strip_prefix("reload failed: ")
+            .map(|reason| ("Mapping reload failed", format!("Keeping the previous mappings. {}", reason))),
+        _ => None,
+    }
+}
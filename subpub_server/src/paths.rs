@@ -0,0 +1,186 @@
+use directories::ProjectDirs;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::{PathsConfig, ServerConfig, CONFIG_FILE_NAME};
+use crate::mapping_check;
+use crate::midi_handler::MAPPING_FILE_CANDIDATES;
+
+// Overrides the config directory, e.g. to run a second setup side by side.
+const CONFIG_DIR_ENV: &str = "SUBPUB_CONFIG_DIR";
+const LOG_FILE_NAME: &str = "subpub_server.log";
+
+// Where the app keeps its files. Launched from a .app bundle or a LaunchAgent the
+// working directory is `/` or the bundle, so nothing is resolved against it anymore:
+//   config dir  subpub_server.toml and the mapping files (with their includes)
+//               macOS   ~/Library/Application Support/com.SubPub.SubPub-Server
+//               Linux   ~/.config/subpubserver
+//               Windows %APPDATA%\SubPub\SubPub Server\config
+//   data dir    log, event log, subscriptions, message IDs, recordings, dumps;
+//               the platform's data directory unless `[paths] data_dir` says otherwise
+struct Paths {
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+    log_file: PathBuf,
+}
+
+static PATHS: OnceLock<Paths> = OnceLock::new();
+
+fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("com", "SubPub", "SubPub Server")
+}
+
+fn resolve_config_dir() -> PathBuf {
+    match env::var(CONFIG_DIR_ENV) {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => project_dirs().map(|dirs| dirs.config_dir().to_path_buf()).unwrap_or_else(|| PathBuf::from(".")),
+    }
+}
+
+// Reads the `[paths]` overrides, which have to be known before the config is loaded.
+fn peek_paths_config(config_file: &Path) -> PathsConfig {
+    #[derive(Deserialize)]
+    struct PathsOnly {
+        #[serde(default)]
+        paths: PathsConfig,
+    }
+    fs::read_to_string(config_file)
+        .ok()
+        .and_then(|text| toml::from_str::<PathsOnly>(&text).ok())
+        .map(|config| config.paths)
+        .unwrap_or_default()
+}
+
+fn resolve(config_dir: PathBuf) -> Paths {
+    let overrides = peek_paths_config(&config_dir.join(CONFIG_FILE_NAME));
+    let data_dir = if overrides.data_dir.is_empty() {
+        project_dirs().map(|dirs| dirs.data_dir().to_path_buf()).unwrap_or_else(|| config_dir.clone())
+    } else {
+        config_dir.join(&overrides.data_dir)
+    };
+    let log_file = if overrides.log_file.is_empty() {
+        data_dir.join(LOG_FILE_NAME)
+    } else {
+        data_dir.join(&overrides.log_file)
+    };
+    Paths { config_dir, data_dir, log_file }
+}
+
+fn paths() -> &'static Paths {
+    PATHS.get_or_init(|| resolve(resolve_config_dir()))
+}
+
+// Resolves the directories, creates them and copies the files of an older install from
+// the working directory. Runs before logging is set up (the log file lives here too), so
+// it returns what it did for the caller to log.
+pub fn init() -> Vec<String> {
+    let mut notes = Vec::new();
+    let config_dir = resolve_config_dir();
+    if let Err(e) = fs::create_dir_all(&config_dir) {
+        notes.push(format!("Failed to create config directory {:?}: {}", config_dir, e));
+    }
+    // Before resolving, so `[paths]` in a copied config takes effect right away.
+    migrate_config_files(&config_dir, &mut notes);
+    let paths = PATHS.get_or_init(|| resolve(config_dir));
+    if let Err(e) = fs::create_dir_all(&paths.data_dir) {
+        notes.push(format!("Failed to create data directory {:?}: {}", paths.data_dir, e));
+    }
+    migrate_data_files(&mut notes);
+    notes
+}
+
+pub fn config_dir() -> &'static Path {
+    &paths().config_dir
+}
+
+pub fn data_dir() -> &'static Path {
+    &paths().data_dir
+}
+
+pub fn config_file() -> PathBuf {
+    config_dir().join(CONFIG_FILE_NAME)
+}
+
+pub fn log_file() -> &'static Path {
+    &paths().log_file
+}
+
+// Paths from the config (event log, subscriptions, ...) are relative to the data dir.
+pub fn data_path(path: impl AsRef<Path>) -> PathBuf {
+    data_dir().join(path)
+}
+
+// Copies `from` to `to` unless `to` exists already. Directories are copied one level
+// deep, which is all mapping includes and recordings use. The originals stay in place.
+fn copy_if_missing(from: &Path, to: &Path, notes: &mut Vec<String>) {
+    if to.exists() || !from.exists() {
+        return;
+    }
+    let result = if from.is_dir() {
+        fs::create_dir_all(to).and_then(|_| {
+            for entry in fs::read_dir(from)?.flatten() {
+                if entry.path().is_file() {
+                    fs::copy(entry.path(), to.join(entry.file_name()))?;
+                }
+            }
+            Ok(())
+        })
+    } else {
+        to.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::copy(from, to).map(|_| ()))
+    };
+    match result {
+        Ok(()) => notes.push(format!("Copied {:?} to {:?}; the old copy is no longer used.", from, to)),
+        Err(e) => notes.push(format!("Failed to copy {:?} to {:?}: {}", from, to, e)),
+    }
+}
+
+// Earlier versions kept the config and mappings in the working directory. Copied over
+// on the first start with the new layout, together with the files the mappings include.
+fn migrate_config_files(config_dir: &Path, notes: &mut Vec<String>) {
+    let old_dir = match env::current_dir() {
+        Ok(dir) if fs::canonicalize(&dir).ok() != fs::canonicalize(config_dir).ok() => dir,
+        _ => return,
+    };
+    if config_dir.join(CONFIG_FILE_NAME).exists() {
+        return;
+    }
+    copy_if_missing(&old_dir.join(CONFIG_FILE_NAME), &config_dir.join(CONFIG_FILE_NAME), notes);
+    for name in MAPPING_FILE_CANDIDATES {
+        let old_mapping = old_dir.join(name);
+        if !old_mapping.exists() {
+            continue;
+        }
+        copy_if_missing(&old_mapping, &config_dir.join(name), notes);
+        for include in mapping_check::include_entries(&old_mapping) {
+            copy_if_missing(&old_dir.join(&include), &config_dir.join(&include), notes);
+        }
+    }
+}
+
+// State files named in the config, if they were relative to the working directory.
+fn migrate_data_files(notes: &mut Vec<String>) {
+    let Ok(old_dir) = env::current_dir() else { return };
+    if fs::canonicalize(&old_dir).ok() == fs::canonicalize(data_dir()).ok() {
+        return;
+    }
+    let config = fs::read_to_string(config_file())
+        .ok()
+        .and_then(|text| toml::from_str::<ServerConfig>(&text).ok())
+        .unwrap_or_default();
+    let files = [
+        &config.persist_subscriptions.file,
+        &config.message_ids.file,
+        &config.event_log.file,
+        &config.auth.htpasswd_file,
+        &config.session_replay.directory,
+    ];
+    for file in files {
+        if !Path::new(file).is_relative() {
+            continue;
+        }
+        copy_if_missing(&old_dir.join(file), &data_path(file), notes);
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+// The payload a mapping expects, as a list of typed fields. A publish that doesn't
+// conform is rejected before the mapping emits any MIDI:
+//   [mappings.payload_schema]
+//   fields = [
+//       { name = "note", type = "integer", min = 0, max = 127, required = true },
+//       { name = "mode", type = "string", values = ["soft", "hard"] },
+//   ]
+//   deny_other_fields = true
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PayloadSchema {
+    pub fields: Vec<SchemaField>,
+    // Reject keys that aren't listed
+    #[serde(default)]
+    pub deny_other_fields: bool,
+    // Tell the publisher: ERROR:<topic>:invalid_payload
+    #[serde(default)]
+    pub reply_error: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    #[serde(default)]
+    pub required: bool,
+    // Bounds for numbers
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    // The only values allowed, if set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    String,
+    Integer,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl FieldType {
+    fn matches(self, value: &Value) -> bool {
+        match self {
+            FieldType::String => value.is_string(),
+            FieldType::Integer => value.is_i64() || value.is_u64(),
+            FieldType::Number => value.is_number(),
+            FieldType::Boolean => value.is_boolean(),
+            FieldType::Array => value.is_array(),
+            FieldType::Object => value.is_object(),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            FieldType::String => "a string",
+            FieldType::Integer => "an integer",
+            FieldType::Number => "a number",
+            FieldType::Boolean => "a boolean",
+            FieldType::Array => "an array",
+            FieldType::Object => "an object",
+        }
+    }
+}
+
+impl PayloadSchema {
+    // Why `payload` doesn't conform, if it doesn't. Stops at the first problem.
+    pub fn check(&self, payload: &str) -> Result<(), String> {
+        let value: Value = serde_json::from_str(payload).map_err(|e| format!("not JSON ({})", e))?;
+        let Value::Object(object) = value else {
+            return Err("not a JSON object".to_string());
+        };
+        for field in &self.fields {
+            match object.get(&field.name) {
+                Some(value) => field.check(value)?,
+                None if field.required => return Err(format!("'{}' is missing", field.name)),
+                None => {}
+            }
+        }
+        if self.deny_other_fields
+            && let Some(key) = object.keys().find(|key| !self.fields.iter().any(|field| &field.name == *key))
+        {
+            return Err(format!("'{}' is not an expected field", key));
+        }
+        Ok(())
+    }
+
+    // Mistakes in the schema itself, for the mapping file check.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut names = HashSet::new();
+        for field in &self.fields {
+            if !names.insert(field.name.as_str()) {
+                problems.push(format!("field '{}' is listed twice", field.name));
+            }
+            let numeric = matches!(field.field_type, FieldType::Integer | FieldType::Number);
+            if !numeric && (field.min.is_some() || field.max.is_some()) {
+                problems.push(format!("field '{}': min/max only apply to integers and numbers", field.name));
+            }
+            if let (Some(min), Some(max)) = (field.min, field.max)
+                && min > max
+            {
+                problems.push(format!("field '{}': min {} is above max {}", field.name, min, max));
+            }
+            for value in field.values.iter().filter(|value| !field.field_type.matches(value)) {
+                problems.push(format!("field '{}': value {} is not {}", field.name, value, field.field_type.name()));
+            }
+        }
+        problems
+    }
+}
+
+impl SchemaField {
+    fn check(&self, value: &Value) -> Result<(), String> {
+        if !self.field_type.matches(value) {
+            return Err(format!("'{}' should be {}, got {}", self.name, self.field_type.name(), value));
+        }
+        if let Some(number) = value.as_f64()
+            && (self.min.is_some_and(|min| number < min) || self.max.is_some_and(|max| number > max))
+        {
+            let bound = |bound: Option<f64>| bound.map(|b| b.to_string()).unwrap_or_default();
+            return Err(format!("'{}' is {}, outside {}..{}", self.name, number, bound(self.min), bound(self.max)));
+        }
+        if !self.values.is_empty() && !self.values.contains(value) {
+            return Err(format!("'{}' is {}, not one of the allowed values", self.name, value));
+        }
+        Ok(())
+    }
+}
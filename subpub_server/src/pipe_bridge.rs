@@ -0,0 +1,158 @@
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::PipeBridgeConfig;
+use crate::message_ids;
+use crate::server::{handle_publish, topic_matches, ServerContext};
+use crate::sys_events::SYS_TOPIC_PREFIX;
+
+// `output` / `input` value that means the process's own stdout / stdin instead of a named pipe.
+const STDIO: &str = "stdio";
+
+// Writes publishes on selected channels as NDJSON lines to stdout or a named pipe:
+// {"channel":"sensors/door","payload":"open","source":"192.168.0.12:50123","id":"s1767225000-42","ts":1767225600.123}
+pub struct PipeBridge {
+    channels: Vec<String>,
+    tx: mpsc::Sender<String>,
+}
+
+impl PipeBridge {
+    pub fn start(config: &PipeBridgeConfig) -> Option<Arc<Self>> {
+        if !config.enabled || config.output.is_empty() {
+            return None;
+        }
+        let (tx, rx) = mpsc::channel::<String>();
+        let output = config.output.clone();
+        info!("Pipe bridge writing channels {:?} to '{}'", config.channels, output);
+        thread::spawn(move || run_writer(&output, rx));
+        Some(Arc::new(Self { channels: config.channels.clone(), tx }))
+    }
+
+    pub fn forward(&self, channel: &str, payload: &str, source: Option<SocketAddr>, id: &str) {
+        if !self.channels.iter().any(|pattern| topic_matches(pattern, channel)) {
+            return;
+        }
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        let line = serde_json::json!({
+            "channel": channel,
+            "payload": payload,
+            "source": source.map(|addr| addr.to_string()),
+            "id": id,
+            "ts": ts,
+        })
+        .to_string();
+        // Only fails once the writer thread is gone, which it already logged.
+        let _ = self.tx.send(line);
+    }
+}
+
+fn open_output(output: &str) -> io::Result<Box<dyn Write>> {
+    if output == STDIO {
+        return Ok(Box::new(io::stdout()));
+    }
+    // Opening a named pipe blocks until a reader shows up, which is why this runs on its own thread.
+    Ok(Box::new(OpenOptions::new().write(true).open(output)?))
+}
+
+fn run_writer(output: &str, rx: mpsc::Receiver<String>) {
+    let mut writer: Option<Box<dyn Write>> = None;
+    for line in rx {
+        if writer.is_none() {
+            match open_output(output) {
+                Ok(w) => writer = Some(w),
+                Err(e) => {
+                    error!("Pipe bridge failed to open '{}': {}. Dropping message.", output, e);
+                    continue;
+                }
+            }
+        }
+        if let Some(w) = writer.as_mut()
+            && let Err(e) = writeln!(w, "{}", line).and_then(|_| w.flush())
+        {
+            // Usually the reading program went away; reopen on the next message.
+            warn!("Pipe bridge write to '{}' failed: {}", output, e);
+            writer = None;
+        }
+    }
+    debug!("Pipe bridge writer for '{}' stopped.", output);
+}
+
+// One line of bridge input: {"channel": "lights/scene", "payload": "3"}
+// A non-string payload is published as its JSON text. An optional "id" works like PUBID.
+#[derive(Deserialize)]
+struct BridgeInput {
+    channel: String,
+    payload: serde_json::Value,
+    id: Option<String>,
+}
+
+// Reads NDJSON publishes from stdin or a named pipe and runs them like a client PUB.
+pub fn spawn_input(config: &PipeBridgeConfig, ctx: ServerContext) -> Option<JoinHandle<()>> {
+    let input = config.input.clone().filter(|i| config.enabled && !i.is_empty())?;
+    let (tx, mut rx) = tokio_mpsc::unbounded_channel::<String>();
+    info!("Pipe bridge reading publishes from '{}'", input);
+    thread::spawn(move || run_reader(&input, tx));
+
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        while let Some(line) = rx.recv().await {
+            let parsed: BridgeInput = match serde_json::from_str(&line) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    warn!("Ignoring malformed pipe bridge input {:?}: {}", line, e);
+                    continue;
+                }
+            };
+            if parsed.channel.starts_with(SYS_TOPIC_PREFIX) {
+                warn!("Pipe bridge tried to publish to reserved channel '{}'. Ignoring.", parsed.channel);
+                continue;
+            }
+            let payload = match parsed.payload {
+                serde_json::Value::String(text) => text,
+                other => other.to_string(),
+            };
+            info!("Pipe bridge published to channel '{}': {}", parsed.channel, payload);
+            let id = parsed.id.filter(|id| message_ids::is_valid_id(id));
+            handle_publish(&ctx, None, &parsed.channel, &payload, id.as_deref(), None).await;
+        }
+    }))
+}
+
+fn run_reader(input: &str, tx: tokio_mpsc::UnboundedSender<String>) {
+    loop {
+        let reader: Box<dyn BufRead> = if input == STDIO {
+            Box::new(io::stdin().lock())
+        } else {
+            match File::open(input) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(e) => {
+                    error!("Pipe bridge failed to open input '{}': {}", input, e);
+                    return;
+                }
+            }
+        };
+        for line in reader.lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if tx.send(line).is_err() {
+                return; // Server stopped
+            }
+        }
+        // stdin is done for good; a named pipe is reopened for the next writer.
+        if input == STDIO {
+            debug!("Pipe bridge input reached end of stdin.");
+            return;
+        }
+    }
+}
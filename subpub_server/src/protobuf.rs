@@ -0,0 +1,124 @@
+use anyhow::{bail, Result};
+use prost::Message;
+use serde::Deserialize;
+use subpub_client::frame::Frame;
+
+use crate::frames::{self, DecodedFrame};
+
+// Protobuf datagrams (`[datagrams] protobuf = true`): this byte, then an Envelope.
+// 0xF5 never occurs in UTF-8 and isn't FRAME_MAGIC, so the formats can't be confused.
+pub const PROTOBUF_MAGIC: u8 = 0xF5;
+
+// The messages of proto/subpub.proto, with prost's derives instead of generated code.
+// Keep the two in step; the tags are what's on the wire.
+#[derive(Clone, PartialEq, Message)]
+pub struct Envelope {
+    #[prost(oneof = "envelope::Body", tags = "1, 2, 3, 4, 5, 6")]
+    pub body: Option<envelope::Body>,
+}
+
+pub mod envelope {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Body {
+        #[prost(message, tag = "1")]
+        Sub(super::Sub),
+        #[prost(message, tag = "2")]
+        Unsub(super::Unsub),
+        #[prost(message, tag = "3")]
+        Pub(super::Pub),
+        #[prost(message, tag = "4")]
+        Ack(super::Ack),
+        #[prost(message, tag = "5")]
+        StatsRequest(super::StatsRequest),
+        #[prost(message, tag = "6")]
+        Stats(super::Stats),
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sub {
+    #[prost(string, tag = "1")]
+    pub channel: String,
+    #[prost(string, tag = "2")]
+    pub options: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Unsub {
+    #[prost(string, tag = "1")]
+    pub channel: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Pub {
+    #[prost(string, tag = "1")]
+    pub channel: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub payload: Vec<u8>,
+    #[prost(string, tag = "3")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Ack {
+    #[prost(string, tag = "1")]
+    pub channel: String,
+    #[prost(string, tag = "2")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StatsRequest {}
+
+// Also read from the text STATS reply, see `encode_reply`.
+#[derive(Clone, PartialEq, Message, Deserialize)]
+pub struct Stats {
+    #[prost(uint64, tag = "1")]
+    pub uptime_secs: u64,
+    #[prost(uint64, tag = "2")]
+    pub messages_processed: u64,
+    #[prost(uint64, tag = "3")]
+    pub channels: u64,
+    #[prost(uint64, tag = "4")]
+    pub subscribers: u64,
+    #[prost(uint64, tag = "5")]
+    pub midi_messages_sent: u64,
+}
+
+pub fn is_protobuf(datagram: &[u8]) -> bool {
+    datagram.first() == Some(&PROTOBUF_MAGIC)
+}
+
+// proto3 strings are never missing, only empty.
+fn non_empty(s: &str) -> Option<&str> {
+    (!s.is_empty()).then_some(s)
+}
+
+// An Envelope as the text message the processing loop understands, the same way as a
+// binary frame (see frames.rs).
+pub fn decode(datagram: &[u8]) -> Result<DecodedFrame> {
+    let envelope = Envelope::decode(&datagram[1..])?;
+    let frame = match &envelope.body {
+        Some(envelope::Body::Sub(sub)) => Frame { action: "SUB", channel: Some(&sub.channel), payload: non_empty(&sub.options).map(str::as_bytes), id: None },
+        Some(envelope::Body::Unsub(unsub)) => Frame { action: "UNSUB", channel: Some(&unsub.channel), ..Frame::default() },
+        Some(envelope::Body::Pub(publish)) => Frame { action: "PUB", channel: Some(&publish.channel), payload: Some(&publish.payload), id: non_empty(&publish.id) },
+        Some(envelope::Body::StatsRequest(_)) => Frame { action: "STATS", ..Frame::default() },
+        Some(envelope::Body::Ack(_) | envelope::Body::Stats(_)) => bail!("Ack and Stats are replies, not requests"),
+        None => bail!("empty Envelope"),
+    };
+    frames::from_frame(frame)
+}
+
+// The Envelope for a text reply, if the schema has one (ACK and STATS).
+pub fn encode_reply(reply: &str) -> Option<Vec<u8>> {
+    let body = if let Some(ack) = reply.strip_prefix("ACK:") {
+        let (channel, id) = ack.split_once(':')?;
+        envelope::Body::Ack(Ack { channel: channel.to_string(), id: id.to_string() })
+    } else {
+        // STATS:clients:[...] doesn't parse as Stats and stays text.
+        envelope::Body::Stats(serde_json::from_str(reply.strip_prefix("STATS:")?).ok()?)
+    };
+    let mut datagram = vec![PROTOBUF_MAGIC];
+    datagram.extend(Envelope { body: Some(body) }.encode_to_vec());
+    Some(datagram)
+}
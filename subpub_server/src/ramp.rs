@@ -0,0 +1,140 @@
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+use crate::midi_actor::MidiHandle;
+use crate::midi_handler::MidiHandler;
+
+// Default number of intermediate CC values sent per second during a ramp.
+pub const DEFAULT_RAMP_RATE_HZ: u32 = 50;
+// Upper bound for rate_hz, above this MIDI DIN bandwidth is the limit anyway.
+const MAX_RAMP_RATE_HZ: u32 = 1000;
+
+// Shape of a CC ramp between its start and target value.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RampCurve {
+    #[default]
+    Linear,
+    EaseIn,    // Slow start, fast end
+    EaseOut,   // Fast start, slow end
+    EaseInOut, // Slow at both ends (smoothstep)
+}
+
+impl RampCurve {
+    // Maps progress t (0.0-1.0) to eased progress (0.0-1.0).
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            RampCurve::Linear => t,
+            RampCurve::EaseIn => t * t,
+            RampCurve::EaseOut => t * (2.0 - t),
+            RampCurve::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+// One CC transition from `from` to `to` over `duration` on a single controller.
+pub struct CcRamp {
+    pub channel: u8,
+    pub control_num: u8,
+    pub from: u8,
+    pub to: u8,
+    pub duration: Duration,
+    pub curve: RampCurve,
+    pub rate_hz: u32,
+}
+
+impl CcRamp {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(1) / self.rate_hz.clamp(1, MAX_RAMP_RATE_HZ)
+    }
+
+    // The value `elapsed` into the ramp, and whether it has reached its target.
+    fn value_at(&self, elapsed: Duration) -> (u8, bool) {
+        let t = if self.duration.is_zero() { 1.0 } else { elapsed.as_secs_f64() / self.duration.as_secs_f64() };
+        let eased = self.curve.apply(t);
+        let value = (self.from as f64 + (self.to as f64 - self.from as f64) * eased).round() as u8;
+        (value, t >= 1.0)
+    }
+
+    // Every value the ramp sends and how far into it, for a render that runs on a
+    // virtual clock instead of a ticker (see session_replay.rs).
+    fn steps(&self) -> Vec<(Duration, u8)> {
+        let mut steps: Vec<(Duration, u8)> = Vec::new();
+        let mut elapsed = Duration::ZERO;
+        loop {
+            let (value, done) = self.value_at(elapsed);
+            if steps.last().is_none_or(|&(_, last)| last != value) {
+                steps.push((elapsed, value));
+            }
+            if done {
+                return steps;
+            }
+            elapsed += self.interval();
+        }
+    }
+}
+
+// Streams the intermediate values of a ramp. Stops early if a newer ramp
+// (or a plain CC) takes over the same controller, identified by `generation`.
+pub async fn run_cc_ramp(ramp: CcRamp, generation: u64, midi: MidiHandle) {
+    let status = 0xB0 + (ramp.channel & 0x0F);
+    let mut ticker = interval(ramp.interval());
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let started = Instant::now();
+    let mut last_sent: Option<u8> = None;
+    loop {
+        ticker.tick().await;
+        let (value, done) = ramp.value_at(started.elapsed());
+
+        // Checked and sent in one job, so a takeover can't slip in between
+        let (channel, control_num) = (ramp.channel, ramp.control_num);
+        let send = last_sent != Some(value); // Only send when the 7-bit value actually changes
+        let current = match midi
+            .query(move |handler| {
+                if !handler.is_current_cc_ramp(channel, control_num, generation) {
+                    return false;
+                }
+                if send && let Err(e) = handler.send_midi_message(&[status, control_num, value]) {
+                    error!("Failed to send CC ramp value on ch {} cc {}: {:?}", channel, control_num, e);
+                }
+                true
+            })
+            .await
+        {
+            Ok(current) => current,
+            Err(e) => {
+                error!("CC ramp on ch {} cc {} stopped: {:?}", ramp.channel, ramp.control_num, e);
+                return;
+            }
+        };
+        if !current {
+            debug!("CC ramp on ch {} cc {} superseded.", ramp.channel, ramp.control_num);
+            return;
+        }
+        last_sent = Some(value);
+
+        if done {
+            debug!("CC ramp on ch {} cc {} reached {}.", ramp.channel, ramp.control_num, ramp.to);
+            return;
+        }
+    }
+}
+
+// A ramp in a render: every step is queued on the render's virtual clock, and each still
+// checks that nothing newer took over the controller in the meantime.
+pub fn schedule_render_ramp(handler: &mut MidiHandler, ramp: CcRamp, generation: u64) {
+    let status = 0xB0 + (ramp.channel & 0x0F);
+    let (channel, control_num) = (ramp.channel, ramp.control_num);
+    for (offset, value) in ramp.steps() {
+        handler.render_later(offset, move |handler| {
+            if handler.is_current_cc_ramp(channel, control_num, generation)
+                && let Err(e) = handler.send_midi_message(&[status, control_num, value])
+            {
+                error!("Failed to render CC ramp value on ch {} cc {}: {:?}", channel, control_num, e);
+            }
+        });
+    }
+}
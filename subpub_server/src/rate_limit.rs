@@ -0,0 +1,64 @@
+use dashmap::DashMap;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::config::RateLimitConfig;
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    // Set while the client is being dropped, so the warning is logged once per episode
+    limited: bool,
+}
+
+// Token bucket per client address for PUB/PUBID: `publishes_per_sec` tokens are added
+// per second up to `burst`, and each publish takes one. A runaway sensor gets its excess
+// dropped instead of flooding the MIDI output.
+pub struct ClientRateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: DashMap<SocketAddr, Bucket>,
+}
+
+impl ClientRateLimiter {
+    pub fn from_config(config: &RateLimitConfig) -> Option<Arc<Self>> {
+        if config.publishes_per_sec <= 0.0 {
+            return None;
+        }
+        let burst = f64::from(config.burst.max(1));
+        info!("Rate limiting publishes to {}/s per client (burst {}).", config.publishes_per_sec, burst);
+        Some(Arc::new(Self { rate: config.publishes_per_sec, burst, buckets: DashMap::new() }))
+    }
+
+    // Takes a token for `addr`. False means the publish should be dropped.
+    pub fn allow(&self, addr: SocketAddr) -> bool {
+        let now = Instant::now();
+        let mut bucket = self
+            .buckets
+            .entry(addr)
+            .or_insert_with(|| Bucket { tokens: self.burst, last_refill: now, limited: false });
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            if bucket.limited {
+                bucket.limited = false;
+                info!("Client {} is back under the publish rate limit.", addr);
+            }
+            true
+        } else {
+            if !bucket.limited {
+                bucket.limited = true;
+                warn!(client:% = addr; "Client {} exceeds {} publishes/s. Dropping its excess publishes.", addr, self.rate);
+            }
+            false
+        }
+    }
+
+    pub fn remove_client(&self, addr: &SocketAddr) {
+        self.buckets.remove(addr);
+    }
+}
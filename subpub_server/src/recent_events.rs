@@ -0,0 +1,133 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::info;
+use std::collections::VecDeque;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::paths;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Sub,
+    Unsub,
+    Pub,
+    Midi,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EventKind::Sub => "SUB",
+            EventKind::Unsub => "UNSUB",
+            EventKind::Pub => "PUB",
+            EventKind::Midi => "MIDI",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecentEvent {
+    pub time: SystemTime,
+    pub kind: EventKind,
+    // Empty for MIDI messages, which aren't tied to a topic by the time they're sent
+    pub topic: String,
+    // Payload for publishes, the decoded message for MIDI
+    pub detail: String,
+    // None for publishes from inside the server (HTTP API, pipe bridge, startup)
+    pub source: Option<SocketAddr>,
+}
+
+// The last `capacity` pub/sub and MIDI events, oldest first, to see what just happened
+// when a synth misbehaves. Dumped from the tray, bundled in diagnostics and served by
+// the admin API.
+pub struct RecentEvents {
+    capacity: usize,
+    events: Mutex<VecDeque<RecentEvent>>,
+}
+
+impl RecentEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, events: Mutex::new(VecDeque::with_capacity(capacity)) }
+    }
+
+    pub fn record(&self, kind: EventKind, topic: &str, detail: &str, source: Option<SocketAddr>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(RecentEvent {
+            time: SystemTime::now(),
+            kind,
+            topic: topic.to_string(),
+            detail: detail.to_string(),
+            source,
+        });
+    }
+
+    pub fn record_midi(&self, message: &[u8]) {
+        self.record(EventKind::Midi, "", &describe_midi(message), None);
+    }
+
+    pub fn snapshot(&self) -> Vec<RecentEvent> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+
+    // One event per line, e.g.
+    // 2026-01-01 20:00:00.123  PUB    drums/kick  1  (192.168.0.12:50123)
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        for event in self.snapshot() {
+            let time = DateTime::<Local>::from(event.time).format("%Y-%m-%d %H:%M:%S%.3f");
+            let mut line = format!("{}  {:<5}  ", time, event.kind.as_str());
+            if !event.topic.is_empty() {
+                line.push_str(&event.topic);
+                line.push_str("  ");
+            }
+            line.push_str(&event.detail);
+            if let Some(source) = event.source {
+                line.push_str(&format!("  ({})", source));
+            }
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+
+    // Writes the buffer to a timestamped text file and returns its path.
+    pub fn dump_to_file(&self) -> Result<PathBuf> {
+        let path = paths::data_path(format!("subpub_recent_events_{}.txt", Local::now().format("%Y%m%d_%H%M%S")));
+        fs::write(&path, self.render_text())
+            .with_context(|| format!("Failed to write recent events to {:?}", path))?;
+        info!("Dumped recent events to {:?}", path);
+        Ok(path)
+    }
+}
+
+// "NoteOn ch 1 note 60 vel 100 [90 3C 64]", channels counted from 1 like on the synths.
+pub fn describe_midi(message: &[u8]) -> String {
+    let hex: Vec<String> = message.iter().map(|b| format!("{:02X}", b)).collect();
+    let hex = hex.join(" ");
+    let (Some(&status), data1, data2) = (message.first(), message.get(1), message.get(2)) else {
+        return String::new();
+    };
+    let channel = (status & 0x0F) + 1;
+    let (data1, data2) = (data1.copied().unwrap_or(0), data2.copied().unwrap_or(0));
+    let decoded = match status & 0xF0 {
+        0x80 => format!("NoteOff ch {} note {} vel {}", channel, data1, data2),
+        0x90 => format!("NoteOn ch {} note {} vel {}", channel, data1, data2),
+        0xA0 => format!("Aftertouch ch {} note {} value {}", channel, data1, data2),
+        0xB0 => format!("CC ch {} cc {} value {}", channel, data1, data2),
+        0xC0 => format!("ProgramChange ch {} program {}", channel, data1),
+        0xD0 => format!("ChannelPressure ch {} value {}", channel, data1),
+        0xE0 => format!("PitchBend ch {} value {}", channel, (u16::from(data2) << 7) | u16::from(data1)),
+        _ => return hex,
+    };
+    format!("{} [{}]", decoded, hex)
+}
@@ -0,0 +1,28 @@
+use serde::{Deserialize, Serialize};
+
+// Spreads successive triggers of a mapping over several channels and/or notes, so a
+// fast run of the same event lands on different synth voices instead of retriggering
+// one mono voice. Every trigger moves one step on; each list wraps around on its own.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct RotationConfig {
+    // Replaces the actions' channel
+    pub channels: Vec<u8>,
+    // Replaces the actions' note. A note in the payload still wins.
+    pub notes: Vec<u8>,
+}
+
+impl RotationConfig {
+    pub fn channel(&self, step: usize) -> Option<u8> {
+        pick(&self.channels, step)
+    }
+
+    pub fn note(&self, step: usize) -> Option<u8> {
+        pick(&self.notes, step)
+    }
+}
+
+fn pick(values: &[u8], step: usize) -> Option<u8> {
+    (!values.is_empty()).then(|| values[step % values.len()])
+}
@@ -0,0 +1,271 @@
+use log::{error, info, warn};
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+use crate::config::{SacnChannel, SacnConfig};
+use crate::server::{topic_matches, ServerContext};
+
+const SACN_PORT: u16 = 5568;
+const SLOTS: usize = 512;
+const MAX_UNIVERSE: u16 = 63999;
+const MAX_PRIORITY: u8 = 200;
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_ROOT_E131_EXTENDED: u32 = 0x0000_0008;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_E131_EXTENDED_SYNCHRONIZATION: u32 = 0x0000_0001;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+
+struct Universe {
+    priority: u8,
+    levels: [u8; SLOTS],
+    sequence: u8,
+    changed: bool,
+    last_sent: Option<Instant>,
+}
+
+// DMX output over sACN (E1.31) multicast, for LED controllers and dimmers: publishes on
+// the configured topics set slots of a universe, and every frame the universes that
+// changed go out to 239.255.<universe>. Unchanged universes are repeated every
+// keepalive so receivers hold their levels. With a sync universe, receivers apply all
+// universes of a frame at once when the sync packet after them arrives.
+pub struct SacnOutput {
+    channels: Vec<SacnChannel>,
+    universes: Mutex<HashMap<u16, Universe>>,
+}
+
+impl SacnOutput {
+    pub fn start(config: &SacnConfig) -> Option<Arc<Self>> {
+        if !config.enabled {
+            return None;
+        }
+        let mut universes = HashMap::new();
+        for universe in &config.universes {
+            if !is_valid_universe(universe.universe) {
+                warn!("[sacn] universe {} is outside 1-{}. Ignoring it.", universe.universe, MAX_UNIVERSE);
+                continue;
+            }
+            universes.insert(
+                universe.universe,
+                Universe {
+                    priority: universe.priority.min(MAX_PRIORITY),
+                    levels: [0; SLOTS],
+                    sequence: 0,
+                    changed: true,
+                    last_sent: None,
+                },
+            );
+        }
+        let channels: Vec<SacnChannel> = config
+            .channels
+            .iter()
+            .filter(|channel| {
+                let valid = universes.contains_key(&channel.universe) && (1..=SLOTS as u16).contains(&channel.slot);
+                if !valid {
+                    warn!(
+                        "[sacn] channel '{}' needs a slot 1-{} in one of the [[sacn.universes]]. Ignoring it.",
+                        channel.topic, SLOTS
+                    );
+                }
+                valid
+            })
+            .cloned()
+            .collect();
+        info!("sACN output on universes {:?}, {} channel(s)", universes.keys().collect::<Vec<_>>(), channels.len());
+        Some(Arc::new(Self { channels, universes: Mutex::new(universes) }))
+    }
+
+    // Called for every publish; sets the slots of the channels mapped to `channel`.
+    pub fn apply(&self, channel: &str, payload: &str) {
+        let mut matching = self.channels.iter().filter(|c| topic_matches(&c.topic, channel)).peekable();
+        if matching.peek().is_none() {
+            return;
+        }
+        let Some(levels) = parse_levels(payload) else {
+            warn!(topic = channel; "sACN: '{}' on '{}' is not a level 0-255 or an array of them.", payload, channel);
+            return;
+        };
+        let mut universes = self.universes.lock().unwrap();
+        for sacn_channel in matching {
+            let Some(universe) = universes.get_mut(&sacn_channel.universe) else {
+                continue;
+            };
+            let first = sacn_channel.slot as usize - 1;
+            for (slot, level) in universe.levels[first..].iter_mut().zip(&levels) {
+                *slot = *level;
+            }
+            universe.changed = true;
+        }
+    }
+
+    // Data packets for the universes that changed or are due for a keepalive.
+    fn due_packets(&self, source: &Source, keepalive: Duration) -> Vec<(u16, Vec<u8>)> {
+        let now = Instant::now();
+        let mut universes = self.universes.lock().unwrap();
+        let mut packets = Vec::new();
+        for (&number, universe) in universes.iter_mut() {
+            let due = universe.changed || universe.last_sent.is_none_or(|sent| now.duration_since(sent) >= keepalive);
+            if !due {
+                continue;
+            }
+            universe.sequence = universe.sequence.wrapping_add(1);
+            universe.changed = false;
+            universe.last_sent = Some(now);
+            packets.push((number, data_packet(source, number, universe)));
+        }
+        packets
+    }
+}
+
+// A level, or an array of levels for consecutive slots. Fractions are rounded.
+fn parse_levels(payload: &str) -> Option<Vec<u8>> {
+    let level = |value: &Value| value.as_f64().filter(|v| (0.0..=255.0).contains(v)).map(|v| v.round() as u8);
+    match serde_json::from_str::<Value>(payload.trim()).ok()? {
+        Value::Array(values) => values.iter().map(level).collect(),
+        value => level(&value).map(|level| vec![level]),
+    }
+}
+
+// What identifies this server to receivers.
+struct Source {
+    // Stable across restarts, so receivers see the same source again
+    cid: [u8; 16],
+    name: [u8; 64],
+    sync_universe: u16,
+}
+
+impl Source {
+    fn new(config: &SacnConfig) -> Self {
+        let mut hasher = Sha1::new();
+        hasher.update(config.source_name.as_bytes());
+        if let Ok(ip) = local_ip_address::local_ip() {
+            hasher.update(ip.to_string().as_bytes());
+        }
+        let mut cid = [0; 16];
+        cid.copy_from_slice(&hasher.finalize()[..16]);
+        // Null-terminated, so at most 63 bytes of the name fit
+        let mut name = [0; 64];
+        let mut length = config.source_name.len().min(63);
+        while !config.source_name.is_char_boundary(length) {
+            length -= 1;
+        }
+        name[..length].copy_from_slice(&config.source_name.as_bytes()[..length]);
+        // 0 turns sync off; anything else has to be a universe receivers can join
+        let mut sync_universe = config.sync_universe;
+        if sync_universe != 0 && !is_valid_universe(sync_universe) {
+            warn!("[sacn] sync_universe {} is outside 1-{}. Sending without sync.", sync_universe, MAX_UNIVERSE);
+            sync_universe = 0;
+        }
+        Self { cid, name, sync_universe }
+    }
+}
+
+fn is_valid_universe(universe: u16) -> bool {
+    (1..=MAX_UNIVERSE).contains(&universe)
+}
+
+// Flags (0x7) and the length of a PDU from `offset` to the end of the packet.
+fn flags_and_length(packet: &mut [u8], offset: usize) {
+    let length = (packet.len() - offset) as u16;
+    packet[offset..offset + 2].copy_from_slice(&(0x7000 | length).to_be_bytes());
+}
+
+fn root_layer(packet: &mut [u8], vector: u32, cid: &[u8; 16]) {
+    packet[0..2].copy_from_slice(&0x0010u16.to_be_bytes()); // Preamble size
+    packet[4..16].copy_from_slice(ACN_PACKET_IDENTIFIER);
+    flags_and_length(packet, 16);
+    packet[18..22].copy_from_slice(&vector.to_be_bytes());
+    packet[22..38].copy_from_slice(cid);
+}
+
+// E1.31 data packet: root layer, framing layer, DMP layer with start code 0 and all 512 slots.
+fn data_packet(source: &Source, number: u16, universe: &Universe) -> Vec<u8> {
+    let mut packet = vec![0; 126 + SLOTS];
+    root_layer(&mut packet, VECTOR_ROOT_E131_DATA, &source.cid);
+    flags_and_length(&mut packet, 38);
+    packet[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+    packet[44..108].copy_from_slice(&source.name);
+    packet[108] = universe.priority;
+    packet[109..111].copy_from_slice(&source.sync_universe.to_be_bytes());
+    packet[111] = universe.sequence;
+    packet[113..115].copy_from_slice(&number.to_be_bytes());
+    flags_and_length(&mut packet, 115);
+    packet[117] = VECTOR_DMP_SET_PROPERTY;
+    packet[118] = 0xA1; // Address and data type
+    packet[121..123].copy_from_slice(&1u16.to_be_bytes()); // Address increment
+    packet[123..125].copy_from_slice(&(SLOTS as u16 + 1).to_be_bytes()); // Start code and slots
+    packet[126..].copy_from_slice(&universe.levels);
+    packet
+}
+
+// E1.31 synchronization packet, telling receivers to apply the frame just sent.
+fn sync_packet(source: &Source, sequence: u8) -> Vec<u8> {
+    let mut packet = vec![0; 49];
+    root_layer(&mut packet, VECTOR_ROOT_E131_EXTENDED, &source.cid);
+    flags_and_length(&mut packet, 38);
+    packet[40..44].copy_from_slice(&VECTOR_E131_EXTENDED_SYNCHRONIZATION.to_be_bytes());
+    packet[44] = sequence;
+    packet[45..47].copy_from_slice(&source.sync_universe.to_be_bytes());
+    packet
+}
+
+// 239.255.<high byte>.<low byte>:5568
+fn multicast_address(universe: u16) -> SocketAddr {
+    let [high, low] = universe.to_be_bytes();
+    SocketAddr::new(Ipv4Addr::new(239, 255, high, low).into(), SACN_PORT)
+}
+
+// Sends the universes every frame for as long as the server runs.
+pub fn spawn(config: &SacnConfig, ctx: ServerContext) -> Option<JoinHandle<()>> {
+    let output = ctx.sacn.clone()?;
+    let source = Source::new(config);
+    let frame = Duration::from_millis(config.frame_ms.max(1));
+    let keepalive = Duration::from_millis(config.keepalive_ms.max(1));
+    Some(ctx.runtime_handle.spawn(async move {
+        let socket = match UdpSocket::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Failed to open the sACN socket: {}", e);
+                return;
+            }
+        };
+        let mut frames = interval(frame);
+        frames.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut sync_sequence = 0u8;
+        // Logged once when sending starts failing (e.g. no network), not every frame
+        let mut failing = false;
+        loop {
+            frames.tick().await;
+            let packets = output.due_packets(&source, keepalive);
+            if packets.is_empty() {
+                continue;
+            }
+            let mut result = Ok(0);
+            for (universe, packet) in &packets {
+                result = result.and(socket.send_to(packet, multicast_address(*universe)).await);
+            }
+            if source.sync_universe != 0 {
+                sync_sequence = sync_sequence.wrapping_add(1);
+                let packet = sync_packet(&source, sync_sequence);
+                result = result.and(socket.send_to(&packet, multicast_address(source.sync_universe)).await);
+            }
+            match result {
+                Err(e) if !failing => {
+                    warn!("Failed to send sACN: {}", e);
+                    failing = true;
+                }
+                Ok(_) if failing => {
+                    info!("Sending sACN again.");
+                    failing = false;
+                }
+                _ => {}
+            }
+        }
+    }))
+}
@@ -0,0 +1,92 @@
+use log::{error, info};
+use std::sync::{Arc, Mutex};
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::sys_events::{SysEvents, SYS_ALERT};
+
+// When the HTTP API is disabled (or the config couldn't be read), safe mode still
+// opens it here so the broken files can be replaced. Loopback only: the API is
+// opened without the user asking for it, so it mustn't be reachable from the LAN.
+pub const SAFE_MODE_HTTP_BIND_ADDRESS: &str = "127.0.0.1:9898";
+// Subscribers joining later still learn about safe mode from the repeated alert.
+const ALERT_REPEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SafeModeCause {
+    Config,
+    Mappings,
+}
+
+// Entered when the server config or the MIDI mappings fail to load at startup.
+// The server still comes up with the HTTP API, but MIDI output is muted until
+// every cause is resolved (e.g. by uploading fixed files through the admin API).
+pub struct SafeMode {
+    causes: Mutex<Vec<(SafeModeCause, String)>>,
+    sys_events: SysEvents,
+}
+
+impl SafeMode {
+    pub fn new(sys_events: SysEvents) -> Arc<Self> {
+        Arc::new(Self { causes: Mutex::new(Vec::new()), sys_events })
+    }
+
+    pub fn enter(&self, cause: SafeModeCause, reason: impl Into<String>) {
+        let reason = reason.into();
+        error!("⚠️ Entering safe mode: {}. MIDI output is muted.", reason);
+        {
+            let mut causes = self.causes.lock().unwrap();
+            causes.retain(|(c, _)| *c != cause);
+            causes.push((cause, reason));
+        }
+        self.emit_alert();
+    }
+
+    pub fn resolve(&self, cause: SafeModeCause) {
+        let still_active = {
+            let mut causes = self.causes.lock().unwrap();
+            let before = causes.len();
+            causes.retain(|(c, _)| *c != cause);
+            if causes.len() == before {
+                return;
+            }
+            !causes.is_empty()
+        };
+        if still_active {
+            info!("Safe mode cause {:?} resolved, but safe mode is still active.", cause);
+        } else {
+            info!("✅ Safe mode cleared. MIDI output is unmuted.");
+        }
+        self.emit_alert();
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.causes.lock().unwrap().is_empty()
+    }
+
+    // One-line description for the tray tooltip and $SYS/alert.
+    pub fn summary(&self) -> Option<String> {
+        let causes = self.causes.lock().unwrap();
+        if causes.is_empty() {
+            return None;
+        }
+        let reasons: Vec<&str> = causes.iter().map(|(_, reason)| reason.as_str()).collect();
+        Some(format!("SAFE MODE (MIDI muted): {}", reasons.join("; ")))
+    }
+
+    fn emit_alert(&self) {
+        let payload = self.summary().unwrap_or_else(|| "ok".to_string());
+        self.sys_events.emit(SYS_ALERT, payload);
+    }
+}
+
+// Repeats the safe mode alert on `$SYS/alert` while it's active.
+pub async fn run_alert_repeater(safe_mode: Arc<SafeMode>) {
+    let mut ticker = interval(ALERT_REPEAT_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        if safe_mode.is_active() {
+            safe_mode.emit_alert();
+        }
+    }
+}
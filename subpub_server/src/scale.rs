@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScaleType {
+    Major,
+    Minor,
+    HarmonicMinor,
+    Dorian,
+    Mixolydian,
+    PentatonicMajor,
+    PentatonicMinor,
+    Blues,
+    Chromatic,
+}
+
+impl ScaleType {
+    // Pitch classes of the scale, relative to the root.
+    fn intervals(self) -> &'static [u8] {
+        match self {
+            ScaleType::Major => &[0, 2, 4, 5, 7, 9, 11],
+            ScaleType::Minor => &[0, 2, 3, 5, 7, 8, 10],
+            ScaleType::HarmonicMinor => &[0, 2, 3, 5, 7, 8, 11],
+            ScaleType::Dorian => &[0, 2, 3, 5, 7, 9, 10],
+            ScaleType::Mixolydian => &[0, 2, 4, 5, 7, 9, 10],
+            ScaleType::PentatonicMajor => &[0, 2, 4, 7, 9],
+            ScaleType::PentatonicMinor => &[0, 3, 5, 7, 10],
+            ScaleType::Blues => &[0, 3, 5, 6, 7, 10],
+            ScaleType::Chromatic => &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+        }
+    }
+}
+
+// Scale used to snap payload-provided notes into key.
+// Either a named scale type or a custom set of pitch classes (0-11, relative to `root`).
+// > scale = { root = 2, scale_type = "minor" }        # D minor
+// > scale = { root = 0, pitches = [0, 3, 5, 7, 10] }  # custom set
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ScaleConfig {
+    #[serde(default)]
+    pub root: u8, // Pitch class of the root note, 0 = C ... 11 = B
+    pub scale_type: Option<ScaleType>,
+    pub pitches: Option<Vec<u8>>,
+}
+
+impl ScaleConfig {
+    fn allows(&self, note: u8) -> bool {
+        let pitch_class = (note as i16 - self.root as i16).rem_euclid(12) as u8;
+        match (&self.pitches, self.scale_type) {
+            (Some(pitches), _) if !pitches.is_empty() => pitches.iter().any(|p| p % 12 == pitch_class),
+            (_, Some(scale_type)) => scale_type.intervals().contains(&pitch_class),
+            _ => true, // Nothing configured, let everything through
+        }
+    }
+
+    // Snaps `note` to the nearest allowed pitch. On a tie the lower pitch wins.
+    pub fn quantize(&self, note: u8) -> u8 {
+        let note = note.min(127);
+        for distance in 0..12u8 {
+            if let Some(lower) = note.checked_sub(distance)
+                && self.allows(lower)
+            {
+                return lower;
+            }
+            let upper = note.saturating_add(distance);
+            if upper <= 127 && self.allows(upper) {
+                return upper;
+            }
+        }
+        note
+    }
+}
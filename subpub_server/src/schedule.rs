@@ -0,0 +1,95 @@
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// Restricts when a mapping is active. All constraints that are set must match.
+// > schedule = { start = "18:00", end = "23:00" }                  # every evening
+// > schedule = { start = "22:00", end = "02:00", days = ["fri", "sat"] }  # wraps past midnight
+// > schedule = { dates = ["2026-12-31"] }                          # one specific day
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    pub start: Option<String>, // "HH:MM", inclusive
+    pub end: Option<String>,   // "HH:MM", exclusive
+    #[serde(default)]
+    pub days: Vec<String>, // "mon" ... "sun"
+    #[serde(default)]
+    pub dates: Vec<String>, // "YYYY-MM-DD"
+}
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s.trim(), "%H:%M").map_err(|e| anyhow!("Invalid time '{}' (expected HH:MM): {}", s, e))
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d").map_err(|e| anyhow!("Invalid date '{}' (expected YYYY-MM-DD): {}", s, e))
+}
+
+// Current wall-clock time in the configured timezone:
+// "local" (default), "UTC", or a fixed offset such as "+01:00".
+pub fn now_in_timezone(timezone: Option<&str>) -> Result<NaiveDateTime> {
+    match timezone.map(str::trim) {
+        None | Some("") => Ok(Local::now().naive_local()),
+        Some(tz) if tz.eq_ignore_ascii_case("local") => Ok(Local::now().naive_local()),
+        Some(tz) if tz.eq_ignore_ascii_case("utc") => Ok(Utc::now().naive_utc()),
+        Some(tz) => {
+            let offset: FixedOffset = tz
+                .parse()
+                .map_err(|e| anyhow!("Invalid timezone '{}' (expected local, UTC or +HH:MM): {}", tz, e))?;
+            Ok(Utc::now().with_timezone(&offset).naive_local())
+        }
+    }
+}
+
+impl ScheduleConfig {
+    // Checks that every time, day and date in the schedule can be parsed.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(start) = &self.start {
+            parse_time(start)?;
+        }
+        if let Some(end) = &self.end {
+            parse_time(end)?;
+        }
+        for day in &self.days {
+            if !DAY_NAMES.contains(&day.to_lowercase().as_str()) {
+                return Err(anyhow!("Invalid day '{}' (expected one of {:?})", day, DAY_NAMES));
+            }
+        }
+        for date in &self.dates {
+            parse_date(date)?;
+        }
+        Ok(())
+    }
+
+    pub fn is_active_at(&self, now: NaiveDateTime) -> Result<bool> {
+        if !self.dates.is_empty() {
+            let mut on_date = false;
+            for date in &self.dates {
+                on_date |= parse_date(date)? == now.date();
+            }
+            if !on_date {
+                return Ok(false);
+            }
+        }
+
+        if !self.days.is_empty() {
+            let today = DAY_NAMES[now.weekday().num_days_from_monday() as usize];
+            if !self.days.iter().any(|d| d.eq_ignore_ascii_case(today)) {
+                return Ok(false);
+            }
+        }
+
+        let time = now.time();
+        let start = self.start.as_deref().map(parse_time).transpose()?;
+        let end = self.end.as_deref().map(parse_time).transpose()?;
+        Ok(match (start, end) {
+            (Some(start), Some(end)) if start <= end => time >= start && time < end,
+            (Some(start), Some(end)) => time >= start || time < end, // Window wraps past midnight
+            (Some(start), None) => time >= start,
+            (None, Some(end)) => time < end,
+            (None, None) => true,
+        })
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use log::{error, info, warn};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::config::{ScheduledPublish, SchedulerConfig};
+use crate::schedule::now_in_timezone;
+use crate::server::{handle_publish, ServerContext};
+use crate::sys_events::SYS_TOPIC_PREFIX;
+
+const MONTH_NAMES: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+const DAY_NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+// A parsed `cron` expression, "minute hour day-of-month month day-of-week", as bit sets
+// of the values each field allows.
+struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // Like cron: if both day fields are restricted, a day matching either one is enough
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl CronSchedule {
+    fn parse(expression: &str) -> Result<Self> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields[..] else {
+            bail!("'{}' needs 5 fields: minute hour day-of-month month day-of-week", expression);
+        };
+        let mut schedule = Self {
+            minutes: parse_field(minutes, 0, 59, &[], 0).context("minute")?,
+            hours: parse_field(hours, 0, 23, &[], 0).context("hour")?,
+            days_of_month: parse_field(days_of_month, 1, 31, &[], 0).context("day of month")?,
+            months: parse_field(months, 1, 12, &MONTH_NAMES, 1).context("month")?,
+            days_of_week: parse_field(days_of_week, 0, 7, &DAY_NAMES, 0).context("day of week")?,
+            any_day_of_month: days_of_month.starts_with('*'),
+            any_day_of_week: days_of_week.starts_with('*'),
+        };
+        // Both 0 and 7 are Sunday
+        if schedule.days_of_week & (1 << 7) != 0 {
+            schedule.days_of_week |= 1;
+        }
+        Ok(schedule)
+    }
+
+    fn matches(&self, at: NaiveDateTime) -> bool {
+        let has = |set: u64, value: u32| set & (1 << value) != 0;
+        let day_of_month = has(self.days_of_month, at.day());
+        let day_of_week = has(self.days_of_week, at.weekday().num_days_from_sunday());
+        let day = if self.any_day_of_month || self.any_day_of_week {
+            day_of_month && day_of_week
+        } else {
+            day_of_month || day_of_week
+        };
+        day && has(self.minutes, at.minute()) && has(self.hours, at.hour()) && has(self.months, at.month())
+    }
+}
+
+// One cron field: "*", values, ranges and steps, separated by commas ("0,30", "9-17",
+// "*/15", "mon-fri"). `names` stand for `first_name_value` onwards.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str], first_name_value: u32) -> Result<u64> {
+    let value = |text: &str| -> Result<u32> {
+        let value = match names.iter().position(|name| name.eq_ignore_ascii_case(text)) {
+            Some(index) => index as u32 + first_name_value,
+            None => text.parse().map_err(|_| anyhow!("'{}' is not a number", text))?,
+        };
+        if !(min..=max).contains(&value) {
+            bail!("{} is outside {}-{}", value, min, max);
+        }
+        Ok(value)
+    };
+    let mut set = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => bail!("Invalid step in '{}'", item),
+            },
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (value(first)?, value(last)?),
+            // "5/15" runs from 5 to the end, like cron
+            None if step > 1 => (value(range)?, max),
+            None => (value(range)?, value(range)?),
+        };
+        if first > last {
+            bail!("Range '{}' runs backwards", range);
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+enum When {
+    Cron(CronSchedule),
+    Every { interval: Duration, next: Instant },
+}
+
+struct Job {
+    when: When,
+    publish: ScheduledPublish,
+}
+
+fn parse_job(publish: &ScheduledPublish) -> Result<Job> {
+    if publish.topic.starts_with(SYS_TOPIC_PREFIX) {
+        bail!("'{}' is a reserved topic", publish.topic);
+    }
+    let when = match (&publish.cron, publish.every_secs) {
+        (Some(cron), None) => When::Cron(CronSchedule::parse(cron)?),
+        (None, Some(secs)) if secs > 0 => {
+            let interval = Duration::from_secs(secs);
+            When::Every { interval, next: Instant::now() + interval }
+        }
+        _ => bail!("Set either `cron` or `every_secs` (above 0)"),
+    };
+    Ok(Job { when, publish: publish.clone() })
+}
+
+// Publishes the configured jobs at their times, for time-of-day scene changes in
+// unattended installations. Cron jobs run at the start of each matching minute in the
+// scheduler's timezone; interval jobs first run one interval after the server starts.
+// Minutes that passed while the server was stopped are not caught up on.
+pub fn spawn(config: &SchedulerConfig, ctx: ServerContext) -> Option<JoinHandle<()>> {
+    if config.jobs.is_empty() {
+        return None;
+    }
+    let timezone = config.timezone.clone();
+    let mut last_minute = match now_in_timezone(Some(&timezone)) {
+        Ok(now) => minute_of(now),
+        Err(e) => {
+            error!("[scheduler] {:#}. Scheduled publishes are disabled.", e);
+            return None;
+        }
+    };
+    let mut jobs = Vec::new();
+    for publish in &config.jobs {
+        match parse_job(publish) {
+            Ok(job) => jobs.push(job),
+            Err(e) => warn!("Ignoring scheduled publish to '{}': {:#}", publish.topic, e),
+        }
+    }
+    if jobs.is_empty() {
+        return None;
+    }
+    info!("Scheduler running {} job(s) in timezone '{}'.", jobs.len(), timezone);
+
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        loop {
+            let now = match now_in_timezone(Some(&timezone)) {
+                Ok(now) => now,
+                Err(e) => {
+                    error!("Scheduler stopped: {:#}", e);
+                    return;
+                }
+            };
+            let minute = minute_of(now);
+            let new_minute = minute != last_minute;
+            last_minute = minute;
+            let instant = Instant::now();
+            for job in &mut jobs {
+                let due = match &mut job.when {
+                    When::Cron(schedule) => new_minute && schedule.matches(minute),
+                    When::Every { interval, next } if *next <= instant => {
+                        *next += *interval;
+                        // After a stall, go on from now rather than firing a burst
+                        if *next <= instant {
+                            *next = instant + *interval;
+                        }
+                        true
+                    }
+                    When::Every { .. } => false,
+                };
+                if due {
+                    info!(topic = job.publish.topic.as_str(); "Scheduled publish to '{}': {}", job.publish.topic, job.publish.payload);
+                    handle_publish(&ctx, None, &job.publish.topic, &job.publish.payload, None, None).await;
+                }
+            }
+
+            // Until the next minute starts, or the next interval job is due
+            let until_next_minute = Duration::from_secs(60) - Duration::new(now.second() as u64, now.nanosecond() % 1_000_000_000);
+            let wake = jobs
+                .iter()
+                .filter_map(|job| match job.when {
+                    When::Every { next, .. } => Some(next.saturating_duration_since(Instant::now())),
+                    When::Cron(_) => None,
+                })
+                .fold(until_next_minute, Duration::min);
+            sleep(wake).await;
+        }
+    }))
+}
+
+fn minute_of(at: NaiveDateTime) -> NaiveDateTime {
+    at.with_second(0).and_then(|at| at.with_nanosecond(0)).unwrap_or(at)
+}
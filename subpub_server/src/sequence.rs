@@ -0,0 +1,70 @@
+use anyhow::{anyhow, Result};
+use dashmap::DashMap;
+use log::info;
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::config::SequenceConfig;
+
+// `SEQ:<n>:<message>`, n counting up per client. Retransmissions reuse n.
+pub const SEQUENCE_PREFIX: &str = "SEQ:";
+
+struct ClientWindow {
+    highest: u64,
+    // Numbers seen within `window` of `highest`
+    seen: BTreeSet<u64>,
+}
+
+// Duplicate suppression for clients that number their messages. A number already seen
+// from the same client within the last `window` numbers is a duplicate (a retransmit, or
+// Wi-Fi delivering a datagram twice) and gets dropped, so a NoteOn can't fire twice.
+// Out-of-order arrivals inside the window still go through. Clients that don't send
+// `SEQ:` aren't affected.
+pub struct SequenceTracker {
+    window: u64,
+    clients: DashMap<SocketAddr, ClientWindow>,
+}
+
+pub enum Sequenced<'a> {
+    // Not numbered, or numbered and seen for the first time; the message without the prefix
+    Fresh(&'a str),
+    Duplicate(u64),
+}
+
+impl SequenceTracker {
+    pub fn new(config: &SequenceConfig) -> Arc<Self> {
+        Arc::new(Self { window: config.window.max(1), clients: DashMap::new() })
+    }
+
+    pub fn check<'a>(&self, addr: SocketAddr, message: &'a str) -> Result<Sequenced<'a>> {
+        let Some(rest) = message.strip_prefix(SEQUENCE_PREFIX) else {
+            return Ok(Sequenced::Fresh(message));
+        };
+        let (n, inner) = rest
+            .split_once(':')
+            .and_then(|(n, inner)| Some((n.parse::<u64>().ok()?, inner)))
+            .ok_or_else(|| anyhow!("expected SEQ:<n>:<message>"))?;
+
+        let mut client = self.clients.entry(addr).or_insert_with(|| ClientWindow { highest: n, seen: BTreeSet::new() });
+        if n.saturating_add(self.window) <= client.highest {
+            // Far behind anything recent: the client restarted and counts from the start again.
+            info!(client:% = addr; "Sequence numbers from {} jumped back from {} to {}; starting a new window.", addr, client.highest, n);
+            client.highest = n;
+            client.seen.clear();
+        }
+        if !client.seen.insert(n) {
+            return Ok(Sequenced::Duplicate(n));
+        }
+        if n > client.highest {
+            client.highest = n;
+            let oldest = n.saturating_sub(self.window);
+            client.seen = client.seen.split_off(&oldest);
+        }
+        Ok(Sequenced::Fresh(inner))
+    }
+
+    pub fn remove_client(&self, addr: &SocketAddr) {
+        self.clients.remove(addr);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Duration, MissedTickBehavior};
+
+use crate::midi_actor::MidiHandle;
+use crate::zones::Zones;
+
+// A single step of a pattern. A step without a note is a rest.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SequenceStep {
+    pub note: Option<u8>,
+    pub velocity: Option<u8>,
+    pub length_ms: Option<u64>, // Defaults to the sequence's step_ms
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SequencePattern {
+    pub name: String,
+    pub steps: Vec<SequenceStep>,
+}
+
+// A step sequence controlled by publishing to `control_topic`:
+// > PUB:seq/bass:start
+// > PUB:seq/bass:stop
+// > PUB:seq/bass:mute / unmute
+// > PUB:seq/bass:pattern:<name>
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SequenceConfig {
+    pub name: String,
+    pub control_topic: String,
+    pub channel: u8,
+    pub step_ms: u64,
+    pub patterns: Vec<SequencePattern>,
+    // Room/zone this sequence plays into. Steps are silent while the zone is disabled.
+    pub zone: Option<String>,
+}
+
+// Runtime state of one sequence, shared between the control path and its playback task.
+struct SequenceState {
+    config: SequenceConfig,
+    running: AtomicBool,
+    muted: AtomicBool,
+    pattern_index: AtomicUsize,
+    wake: Notify,
+}
+
+// Owns all configured sequences for the lifetime of a server run.
+pub struct Sequencer {
+    by_topic: HashMap<String, Arc<SequenceState>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Sequencer {
+    pub fn start(
+        configs: Vec<SequenceConfig>,
+        midi: MidiHandle,
+        zones: Arc<Zones>,
+        runtime_handle: &Handle,
+    ) -> Self {
+        let mut by_topic = HashMap::new();
+        let mut tasks = Vec::new();
+
+        for config in configs {
+            if config.patterns.iter().all(|p| p.steps.is_empty()) || config.step_ms == 0 {
+                warn!("Sequence '{}' has no steps or a zero step_ms. Skipping.", config.name);
+                continue;
+            }
+            if by_topic.contains_key(&config.control_topic) {
+                warn!("Sequence '{}' reuses control topic '{}'. Skipping.", config.name, config.control_topic);
+                continue;
+            }
+            let state = Arc::new(SequenceState {
+                config,
+                running: AtomicBool::new(false),
+                muted: AtomicBool::new(false),
+                pattern_index: AtomicUsize::new(0),
+                wake: Notify::new(),
+            });
+            info!("Loaded sequence '{}' on control topic '{}'", state.config.name, state.config.control_topic);
+            tasks.push(runtime_handle.spawn(run_sequence(
+                state.clone(),
+                midi.clone(),
+                zones.clone(),
+                runtime_handle.clone(),
+            )));
+            by_topic.insert(state.config.control_topic.clone(), state);
+        }
+
+        Self { by_topic, tasks }
+    }
+
+    // Handles a publish on a sequence control topic. Returns false if the topic isn't one.
+    pub fn handle_publish(&self, topic: &str, payload: &str) -> bool {
+        let Some(state) = self.by_topic.get(topic) else {
+            return false;
+        };
+        let name = &state.config.name;
+        let command = payload.trim();
+
+        match command.to_lowercase().as_str() {
+            "start" => {
+                state.running.store(true, Ordering::SeqCst);
+                state.wake.notify_one();
+                info!("Sequence '{}' started.", name);
+            }
+            "stop" => {
+                state.running.store(false, Ordering::SeqCst);
+                info!("Sequence '{}' stopped.", name);
+            }
+            "mute" => {
+                state.muted.store(true, Ordering::SeqCst);
+                info!("Sequence '{}' muted.", name);
+            }
+            "unmute" => {
+                state.muted.store(false, Ordering::SeqCst);
+                info!("Sequence '{}' unmuted.", name);
+            }
+            _ => match command.split_once(':') {
+                Some((cmd, pattern_name)) if cmd.eq_ignore_ascii_case("pattern") => {
+                    let pattern_name = pattern_name.trim();
+                    match state.config.patterns.iter().position(|p| p.name == pattern_name) {
+                        Some(index) => {
+                            state.pattern_index.store(index, Ordering::SeqCst);
+                            info!("Sequence '{}' switched to pattern '{}'.", name, pattern_name);
+                        }
+                        None => warn!("Sequence '{}' has no pattern named '{}'.", name, pattern_name),
+                    }
+                }
+                _ => warn!("Unknown command '{}' for sequence '{}'.", command, name),
+            },
+        }
+        true
+    }
+}
+
+impl Drop for Sequencer {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+// Playback loop for one sequence. Sleeps while stopped and restarts from step 0 on start.
+async fn run_sequence(
+    state: Arc<SequenceState>,
+    midi: MidiHandle,
+    zones: Arc<Zones>,
+    runtime_handle: Handle,
+) {
+    let config = &state.config;
+    let channel = config.channel & 0x0F;
+
+    loop {
+        while !state.running.load(Ordering::SeqCst) {
+            state.wake.notified().await;
+        }
+
+        let mut ticker = interval(Duration::from_millis(config.step_ms));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut step_index = 0usize;
+
+        while state.running.load(Ordering::SeqCst) {
+            ticker.tick().await;
+
+            let pattern_index = state.pattern_index.load(Ordering::SeqCst).min(config.patterns.len() - 1);
+            let pattern = &config.patterns[pattern_index];
+            if pattern.steps.is_empty() {
+                continue;
+            }
+            let step = &pattern.steps[step_index % pattern.steps.len()];
+            step_index = step_index.wrapping_add(1);
+
+            let Some(note) = step.note else {
+                continue; // Rest
+            };
+            if state.muted.load(Ordering::SeqCst) || !zones.allows(config.zone.as_deref()) {
+                continue;
+            }
+
+            let velocity = step.velocity.unwrap_or(100).clamp(0, 127);
+            // Transposed and sent in one go, the NoteOff below needs the transposed note.
+            let sequence_name = config.name.clone();
+            let note = match midi
+                .query(move |handler| {
+                    let note = handler.transpose_note(note, 0);
+                    if let Err(e) = handler.send_midi_message(&[0x90 + channel, note, velocity]) {
+                        error!("Sequence '{}' failed to send NoteOn: {:?}", sequence_name, e);
+                    }
+                    note
+                })
+                .await
+            {
+                Ok(note) => note,
+                Err(e) => {
+                    error!("Sequence '{}' stopped: {:?}", config.name, e);
+                    return;
+                }
+            };
+            debug!("Sequence '{}' step NoteOn: {:?}", config.name, [0x90 + channel, note, velocity]);
+
+            let length_ms = step.length_ms.unwrap_or(config.step_ms);
+            let note_off_msg = vec![0x80 + channel, note, 0];
+            let midi_clone = midi.clone();
+            let sequence_name = config.name.clone();
+            runtime_handle.spawn(async move {
+                sleep(Duration::from_millis(length_ms)).await;
+                midi_clone.execute(move |handler| {
+                    if let Err(e) = handler.send_midi_message(&note_off_msg) {
+                        error!("Sequence '{}' failed to send NoteOff: {:?}", sequence_name, e);
+                    }
+                });
+            });
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, ErrorKind};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+
+use crate::config::{SerialFraming, SerialInputConfig, SerialPortConfig};
+use crate::server::{handle_publish, ServerContext};
+use crate::sys_events::SYS_TOPIC_PREFIX;
+
+// How long a read waits before the reader checks whether the server still runs
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+// Delay between attempts to open a port that's missing, e.g. unplugged
+const REOPEN_DELAY: Duration = Duration::from_secs(2);
+
+// One `json` line: {"channel": "sensors/door", "payload": "open"}
+// A non-string payload is published as its JSON text.
+#[derive(Deserialize)]
+struct SerialJsonLine {
+    channel: Option<String>,
+    payload: serde_json::Value,
+}
+
+// The publish for one line read from `port`: (topic, payload)
+fn parse_line(port: &SerialPortConfig, line: &str) -> Option<(String, String)> {
+    let (topic, payload) = match port.framing {
+        SerialFraming::Line if !port.topic.is_empty() => (port.topic.clone(), line.to_string()),
+        SerialFraming::Line => {
+            let (topic, payload) = line.split_once(':')?;
+            (topic.trim().to_string(), payload.to_string())
+        }
+        SerialFraming::Json => {
+            let parsed: SerialJsonLine = serde_json::from_str(line).ok()?;
+            let payload = match parsed.payload {
+                serde_json::Value::String(text) => text,
+                other => other.to_string(),
+            };
+            (parsed.channel.unwrap_or_else(|| port.topic.clone()), payload)
+        }
+    };
+    (!topic.is_empty()).then_some((topic, payload))
+}
+
+// Publishes the lines that microcontrollers print on their serial ports (an Arduino's
+// `Serial.println("sensors/temp:21.5")`), so USB sensors don't need a script in between.
+// Each port is read on its own thread and reopened after it disappears.
+pub fn spawn(config: &SerialInputConfig, ctx: ServerContext) -> Option<JoinHandle<()>> {
+    if !config.enabled || config.ports.is_empty() {
+        return None;
+    }
+    let (tx, mut rx) = tokio_mpsc::unbounded_channel::<(String, String)>();
+    for port in &config.ports {
+        let port = port.clone();
+        let tx = tx.clone();
+        thread::spawn(move || run_reader(&port, tx));
+    }
+
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        // Dropping the receiver when the server stops also ends the reader threads.
+        while let Some((topic, payload)) = rx.recv().await {
+            if topic.starts_with(SYS_TOPIC_PREFIX) {
+                warn!("Serial input tried to publish to reserved channel '{}'. Ignoring.", topic);
+                continue;
+            }
+            debug!(topic = topic.as_str(); "Serial input published to channel '{}': {}", topic, payload);
+            handle_publish(&ctx, None, &topic, &payload, None, None).await;
+        }
+    }))
+}
+
+fn run_reader(port: &SerialPortConfig, tx: tokio_mpsc::UnboundedSender<(String, String)>) {
+    // Logged once per outage, not on every reopen attempt
+    let mut missing = false;
+    while !tx.is_closed() {
+        let serial = match serialport::new(port.path.as_str(), port.baud).timeout(READ_TIMEOUT).open() {
+            Ok(serial) => serial,
+            Err(e) => {
+                if !std::mem::replace(&mut missing, true) {
+                    warn!("Failed to open serial port '{}': {}. Retrying until it shows up.", port.path, e);
+                }
+                thread::sleep(REOPEN_DELAY);
+                continue;
+            }
+        };
+        missing = false;
+        info!("Reading serial port '{}' at {} baud", port.path, port.baud);
+        let mut reader = BufReader::new(serial);
+        let mut buf = Vec::new();
+        loop {
+            // A timeout keeps what was read so far in `buf`, and the line continues.
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) => break,
+                Ok(_) if buf.ends_with(b"\n") => {}
+                Ok(_) => continue,
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    if tx.is_closed() {
+                        return;
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    warn!("Serial port '{}' failed: {}. Reopening it.", port.path, e);
+                    break;
+                }
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if !line.is_empty() {
+                match parse_line(port, line) {
+                    Some(publish) => {
+                        if tx.send(publish).is_err() {
+                            return;
+                        }
+                    }
+                    None => warn!("Ignoring malformed serial line from '{}': {:?}", port.path, line),
+                }
+            }
+            buf.clear();
+        }
+        thread::sleep(REOPEN_DELAY);
+    }
+}
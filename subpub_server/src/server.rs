@@ -1,112 +1,873 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex}; // Added Mutex
+use std::sync::Arc;
 use tokio::net::UdpSocket;
-use tokio::time::{sleep, Duration}; // For NoteOnOff delay
+use tokio::time::{sleep, Duration, Instant}; // For NoteOnOff delay and fanout timing
 use log::{info, warn, error, debug}; // Added debug
-use serde::Deserialize;
-use crate::midi_handler::{MidiHandler, MidiAction, MidiActionType}; // Added Handler and related types
+use serde::{Deserialize, Serialize};
+use crate::midi_handler::{MappingEntry, MidiHandler, MidiAction, MidiActionType, PayloadErrorPolicy}; // Added Handler and related types
+use crate::midi_actor::MidiHandle;
 use dashmap::DashMap;
-use std::net::{IpAddr, Ipv4Addr};
-use anyhow::{Result, Context}; // Ensure Context is imported
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use anyhow::{anyhow, Result, Context}; // Ensure Context is imported
 use crossbeam_channel::Receiver;
 use tokio::runtime::Handle;
+use tokio::sync::broadcast;
+use crate::config::{DatagramConfig, IpMode, NetworkConfig, ServerConfig, StartupPublish, StartupRetryConfig};
+use crate::sequencer::Sequencer;
+use crate::lfo::Lfos;
+use crate::auth::{self, AuthBackend, FailedLogins};
+use crate::signing::{self, MessageVerifier};
+use crate::acl::{Access, Acl};
+use crate::ip_filter::IpFilter;
+use crate::transport::{Envelope, ServerSocket};
+use crate::network;
+use crate::rate_limit::ClientRateLimiter;
+use crate::fragments::{Reassembler, FRAGMENT_PREFIX};
+use crate::sequence::{Sequenced, SequenceTracker};
+use crate::safe_mode::{self, SafeMode};
+use crate::show_mode::ShowMode;
+use crate::recent_events::EventKind;
+use crate::stats::{ReceiveLoopStats, Stats};
+use crate::client_stats;
+use crate::zones::Zones;
+use crate::http_api::{self, HttpApiContext};
+use crate::clients::ClientRegistry;
+use crate::delivery::{self, Delivery, DeliveryLimiter};
+use crate::compression::{self, PayloadCompressor};
+use crate::delayed_publish::{DelayedPublishes, ScheduleError};
+use crate::pipe_bridge::{self, PipeBridge};
+use crate::keepalive;
+use crate::watchdog;
+use crate::subscription_store;
+use crate::message_ids::{self, MessageIds};
+use crate::history::ChannelHistory;
+use crate::midi_input;
+use crate::serial_input;
+use crate::midi_mirror;
+use crate::transforms::PayloadTransforms;
+use crate::federation::{self, Federation};
+use crate::websocket_bridge::{self, WebSocketBridge};
+use crate::failover::{self, Failover};
+use crate::sacn::{self, SacnOutput};
+use crate::hotkeys::{self, HotkeyPublishes};
+use crate::scheduler;
+use crate::channel_expiry::{self, ChannelExpiry};
+use crate::event_store::EventStore;
+use crate::session_replay::{self, SessionReplay};
+use crate::ramp::{run_cc_ramp, schedule_render_ramp, CcRamp, RampCurve, DEFAULT_RAMP_RATE_HZ};
+use crate::sys_events::{SysEvent, SysEvents, SYS_SERVER_STATUS, SYS_TOPIC_PREFIX};
+use crate::frames::{self, ReplyBuffer, WireFormat};
+use crate::protobuf;
 
 // Constants
 pub const BIND_ADDRESS: &str = "127.0.0.1:7878";
-pub const MULTICAST_ADDRESS: &str = "192.168.0.100:50100";
-pub const DISCOVERY_MESSAGE: &str = "DISCOVER_SUBPUB_SERVER";
-pub const DISCOVERY_RESPONSE_PREFIX: &str = "SUBPUB_SERVER_AT:";
+// Shared with the client library, so the two can't drift apart
+pub use subpub_client::protocol::{DISCOVERY_MESSAGE, DISCOVERY_RESPONSE_PREFIX};
+// Reserved topic that sets the global transpose, e.g. `PUB:_control/transpose:+3`
+pub const CONTROL_TRANSPOSE_TOPIC: &str = "_control/transpose";
+// Topics that steer the server (transpose, recording, replays) rather than carry cues
+pub const CONTROL_TOPIC_PREFIX: &str = "_control/";
 
 // Type alias
 pub type Subscribers = Arc<DashMap<String, HashSet<SocketAddr>>>;
 
+// Long-lived services created at launch and handed to every server run.
+#[derive(Clone)]
+pub struct AppServices {
+    pub config: Arc<ServerConfig>,
+    pub sys_events: SysEvents,
+    pub stats: Arc<Stats>,
+    pub zones: Arc<Zones>,
+    pub safe_mode: Arc<SafeMode>,
+    pub show_mode: Arc<ShowMode>,
+    pub event_store: Option<Arc<EventStore>>, // SQLite event log, if enabled
+    pub hotkey_publishes: HotkeyPublishes, // Fired by the tray's global hotkeys and subpub:// links
+}
+
+// Shared state for one server run, handed to the processing loop and background tasks.
+#[derive(Clone)]
+pub struct ServerContext {
+    pub socket: Arc<ServerSocket>, // Encrypts and decrypts when [encryption] is enabled
+    pub subscribers: Subscribers,
+    pub clients: Arc<ClientRegistry>,
+    pub delivery_limiter: Arc<DeliveryLimiter>, // Per-subscriber max_hz throttling
+    pub compressor: Arc<PayloadCompressor>, // Per-subscriber compress=deflate
+    pub delayed_publishes: Arc<DelayedPublishes>, // SCHEDULE'd publishes waiting to run
+    pub pipe_bridge: Option<Arc<PipeBridge>>, // NDJSON sink for local programs
+    pub message_ids: Arc<MessageIds>,
+    pub history: Arc<ChannelHistory>, // Recent payloads per channel for HIST
+    pub channel_expiry: Arc<ChannelExpiry>, // Forgets channels idle for their TTL
+    pub transforms: Arc<PayloadTransforms>, // Payload rewrites for subscribers
+    pub federation: Arc<Federation>, // Exports to a linked server, if [federation] is enabled
+    pub websocket_bridge: Arc<WebSocketBridge>, // Sends to a WebSocket relay, if [websocket_bridge] is enabled
+    pub sacn: Option<Arc<SacnOutput>>, // DMX levels over E1.31, if [sacn] is enabled
+    pub event_store: Option<Arc<EventStore>>,
+    pub session_replay: Arc<SessionReplay>, // Recording and replay of publishes
+    pub midi: MidiHandle, // The MIDI thread, see midi_actor.rs
+    pub runtime_handle: Handle, // For spawning NoteOnOff delay tasks
+    pub sequencer: Arc<Sequencer>,
+    pub lfos: Arc<Lfos>,
+    pub auth: Option<Arc<dyn AuthBackend>>, // None = authentication disabled
+    pub failed_logins: Arc<FailedLogins>, // AUTH lockout per address
+    pub verifier: Option<Arc<MessageVerifier>>, // None = unsigned messages accepted
+    pub acl: Option<Arc<Acl>>, // None = every client may use every topic
+    pub ip_filter: Option<Arc<IpFilter>>, // None = no source filtering
+    pub rate_limiter: Option<Arc<ClientRateLimiter>>, // None = publishes aren't limited
+    pub sequences: Arc<SequenceTracker>,
+    pub stats: Arc<Stats>,
+    pub zones: Arc<Zones>,
+    pub receive_loop: Arc<ReceiveLoopStats>, // Counters of the receive loop using `socket`
+}
+
+// Removes a client from every channel it is subscribed to and drops channels that become empty.
+// Returns the channels the client was removed from.
+pub fn remove_client_from_all_channels(subscribers: &Subscribers, addr: &SocketAddr) -> Vec<String> {
+    let mut removed_from = Vec::new();
+    for mut channel_set_ref in subscribers.iter_mut() {
+        if channel_set_ref.value_mut().remove(addr) {
+            removed_from.push(channel_set_ref.key().clone());
+        }
+    }
+    subscribers.retain(|_, set| !set.is_empty());
+    removed_from
+}
+
+// UNSUB for every channel the client is subscribed to. Returns those channels.
+pub fn unsubscribe_all(ctx: &ServerContext, addr: SocketAddr) -> Vec<String> {
+    ctx.delivery_limiter.remove_client(&addr);
+    ctx.compressor.remove_client(&addr);
+    let channels = remove_client_from_all_channels(&ctx.subscribers, &addr);
+    for channel in &channels {
+        ctx.stats.recent_events().record(EventKind::Unsub, channel, "", Some(addr));
+    }
+    channels
+}
+
+// Forgets everything about a client: subscriptions, login and per-client limits.
+// Used for DISCONNECT and for clients whose keepalive TTL ran out.
+pub fn disconnect_client(ctx: &ServerContext, addr: SocketAddr) -> Vec<String> {
+    ctx.clients.remove(&addr);
+    if let Some(rate_limiter) = &ctx.rate_limiter {
+        rate_limiter.remove_client(&addr);
+    }
+    ctx.sequences.remove_client(&addr);
+    unsubscribe_all(ctx, addr)
+}
+
+// Every action the processing loop knows, in the spelling it matches on.
+const ACTIONS: &[&str] = &[
+    "AUTH", "SUB", "UNSUB", "HELLO", "UNSUB_ALL", "DISCONNECT", "PUB", "PUBID", "HIST", "PING", "STATS", "LIST", "ALIAS",
+    "SCHEDULE", "UNSCHEDULE",
+];
+// Actions that may come without a channel, e.g. a plain `LIST`.
+const BARE_ACTIONS: &[&str] = &["LIST", "PING", "STATS", "UNSUB_ALL", "DISCONNECT"];
+// Longest name a client can give itself with HELLO
+const MAX_CLIENT_NAME_LEN: usize = 64;
+
+// "*" matches everything, "players/*" matches by prefix, anything else exactly.
+pub fn topic_matches(pattern: &str, topic: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => topic.starts_with(prefix),
+        None => pattern == topic,
+    }
+}
+
+// The message as it goes into the log: the secret of an AUTH is left out.
+fn loggable(message: &str) -> Cow<'_, str> {
+    let mut parts = message.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(action), Some(user), Some(_)) if action.eq_ignore_ascii_case("AUTH") => Cow::Owned(format!("{}:{}:<redacted>", action, user)),
+        _ => Cow::Borrowed(message),
+    }
+}
+
+// Formats a reply into the loop's reusable buffer instead of a new String. A macro
+// rather than a fn taking fmt::Arguments, which isn't Send and can't be held across
+// the send's await.
+// `$format` is the request's WireFormat, which the reply is encoded in (see frames.rs).
+macro_rules! fill_reply {
+    ($buf:expr, $format:expr, $($arg:tt)*) => {{
+        $buf.text.clear();
+        let _ = write!($buf.text, $($arg)*);
+        $buf.bytes($format)
+    }};
+}
+
 // Server processing loop
 pub async fn run_server_processing_loop(
-    socket: Arc<UdpSocket>,
-    subscribers: Subscribers,
-    midi_handler_arc: Arc<Mutex<MidiHandler>>,
-    runtime_handle: Handle, // Added for spawning NoteOnOff delay tasks
+    ctx: ServerContext,
+    datagrams: DatagramConfig,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let mut buf = [0; 1024];
+    let ServerContext { socket, subscribers, clients, auth, verifier, acl, ip_filter, rate_limiter, sequences, delivery_limiter, stats, history, receive_loop, .. } = &ctx;
+    // Both buffers live as long as the loop, so a plain message costs no allocation
+    // until it changes state (a new channel, a new client name, ...).
+    let mut buf = vec![0; datagrams.buffer_size.max(1)];
+    let mut reply = ReplyBuffer::default();
+    let mut reassembler = Reassembler::new(&datagrams);
 
     loop {
         let (len, addr) = socket.recv_from(&mut buf).await?;
-        debug!("Processing message: {} bytes from {}", len, addr);
-        let message_str = match std::str::from_utf8(&buf[..len]) {
-            Ok(s) => s.trim(),
+        receive_loop.record_datagram();
+        if let Some(ip_filter) = ip_filter
+            && !ip_filter.permits(addr.ip())
+        {
+            debug!("Dropped datagram from filtered address {}", addr);
+            continue;
+        }
+        let who = clients.lazy_label(addr);
+        debug!("Processing message: {} bytes from {}", len, who);
+        // Binary frames and protobuf carry on as the equivalent text message, see frames.rs.
+        let format = WireFormat::of(&buf[..len], datagrams.protobuf);
+        let decoded;
+        let (message_str, binary_payload) = match format {
+            WireFormat::Text => match std::str::from_utf8(&buf[..len]) {
+                Ok(s) => (s.trim(), None),
+                Err(e) => {
+                    error!("Received non-UTF8 data from {}: {}", who, e);
+                    continue;
+                }
+            },
+            WireFormat::Frame | WireFormat::Protobuf => {
+                let result = if format == WireFormat::Frame { frames::decode(&buf[..len]) } else { protobuf::decode(&buf[..len]) };
+                match result {
+                    Ok(frame) => {
+                        decoded = frame;
+                        (decoded.text.as_str(), decoded.binary_payload.as_deref())
+                    }
+                    Err(e) => {
+                        warn!(client:% = addr; "Dropped {:?} datagram from {}: {:#}", format, who, e);
+                        continue;
+                    }
+                }
+            }
+        };
+        if len == buf.len() {
+            warn!("Datagram from {} filled the {}-byte receive buffer and was probably truncated", who, len);
+        }
+
+        // Fragments are held back until the whole message is there, which then carries on
+        // as if it had arrived in one datagram.
+        let reassembled;
+        let message_str = match message_str.strip_prefix(FRAGMENT_PREFIX) {
+            Some(fragment) => match reassembler.add(addr, fragment) {
+                Ok(Some(message)) => {
+                    reassembled = message;
+                    reassembled.trim()
+                }
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!(client:% = addr; "Dropped fragment from {}: {:#}", who, e);
+                    continue;
+                }
+            },
+            None => message_str,
+        };
+
+        // With signing enabled, only correctly signed datagrams get any further.
+        let message_str = match verifier {
+            Some(verifier) => match verifier.verify(message_str) {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!(client:% = addr; "Dropped datagram from {}: {:#}", who, e);
+                    continue;
+                }
+            },
+            None => message_str,
+        };
+
+        // Numbered messages seen before are retransmits or Wi-Fi duplicates; run them once.
+        let message_str = match sequences.check(addr, message_str) {
+            Ok(Sequenced::Fresh(message)) => message,
+            Ok(Sequenced::Duplicate(n)) => {
+                stats.clients().record_duplicate(addr);
+                debug!("Dropped duplicate message {} from {}", n, who);
+                continue;
+            }
             Err(e) => {
-                error!("Received non-UTF8 data from {}: {}", addr, e);
+                warn!(client:% = addr; "Dropped datagram from {}: {:#}", who, e);
                 continue;
             }
         };
 
-        info!("Received from {}: {}", addr, message_str);
+        info!(client:% = addr; "Received from {}: {}", who, loggable(message_str));
 
-        let parts: Vec<&str> = message_str.splitn(3, ':').collect();
+        // Sliced out of the message in place; actions match case-insensitively.
+        let mut parts = message_str.splitn(3, ':');
+        let raw_action = parts.next().unwrap_or("");
+        let action = ACTIONS.iter().copied().find(|known| known.eq_ignore_ascii_case(raw_action)).unwrap_or(raw_action);
+        let channel_name = parts.next();
+        let payload = parts.next();
 
-        if parts.len() < 2 {
-            warn!("Invalid message format from {}: {}", addr, message_str);
+        if channel_name.is_none() && !BARE_ACTIONS.contains(&action) {
+            warn!("Invalid message format from {}: {}", who, message_str);
             continue;
         }
 
-        let action = parts[0].to_uppercase();
-        let channel_name = parts[1].to_string();
-        let payload = if parts.len() == 3 { Some(parts[2]) } else { None };
+        let channel_name = channel_name.unwrap_or("");
+        stats.record_message_processed();
+        receive_loop.record_message_processed();
+
+        // `@<n>` stands for the topic the client registered as alias n (see ALIAS). Resolved
+        // before anything else looks at the channel, so ACLs and reserved topics still apply.
+        let aliased;
+        let channel_name = match channel_name.strip_prefix('@').and_then(|n| n.parse::<u16>().ok()) {
+            Some(n) => match clients.alias(&addr, n) {
+                Some(topic) => {
+                    aliased = topic;
+                    &*aliased
+                }
+                None => {
+                    warn!(client:% = addr; "Unknown topic alias @{} from {}: {}", n, who, loggable(message_str));
+                    if let Err(e) = socket.send_to(fill_reply!(reply, format, "ERROR:{}:unknown_alias", channel_name), addr).await {
+                        error!("Failed to send alias error to {}: {}", who, e);
+                    }
+                    continue;
+                }
+            },
+            None => channel_name,
+        };
+
+        // With auth enabled, only AUTH (and PING, to check the connection) is accepted from
+        // clients that haven't logged in.
+        if auth.is_some() && action != "AUTH" && action != "PING" && !clients.is_authenticated(&addr) {
+            warn!(topic = channel_name, client:% = addr; "Refused {} from unauthenticated client {}.", action, who);
+            if let Err(e) = socket.send_to(fill_reply!(reply, format, "ERROR:{}:unauthorized", channel_name), addr).await {
+                error!("Failed to send auth error to {}: {}", who, e);
+            }
+            continue;
+        }
+        // Only datagrams that got this far keep a client alive (see [keepalive]) or list it
+        // on /admin/clients; an AUTH counts once it is accepted.
+        if auth.is_none() || clients.is_authenticated(&addr) {
+            clients.touch(addr);
+        }
+
+        // Topic permissions; HIST reveals payloads, so it needs subscribe access.
+        let access = match action {
+            "PUB" | "PUBID" | "SCHEDULE" => Some(Access::Publish),
+            "SUB" | "HIST" => Some(Access::Subscribe),
+            _ => None,
+        };
+        if let (Some(acl), Some(access)) = (acl, access)
+            && !acl.allows(clients.user(&addr).as_deref(), addr, access, channel_name)
+        {
+            warn!(topic = channel_name, client:% = addr; "Refused {} on '{}' from {}: not allowed by the ACL.", action, channel_name, who);
+            if let Err(e) = socket.send_to(fill_reply!(reply, format, "ERROR:{}:forbidden", channel_name), addr).await {
+                error!("Failed to send ACL error to {}: {}", who, e);
+            }
+            continue;
+        }
+
+        if matches!(action, "PUB" | "PUBID" | "SCHEDULE")
+            && let Some(rate_limiter) = rate_limiter
+            && !rate_limiter.allow(addr)
+        {
+            stats.clients().record_rate_limited(addr);
+            debug!("Dropped publish to '{}' from rate-limited client {}", channel_name, who);
+            continue;
+        }
 
-        match action.as_str() {
+        match action {
+            "AUTH" => {
+                // > AUTH:<user>:<secret>
+                let Some(backend) = auth.clone() else {
+                    debug!("AUTH from {} ignored, authentication is disabled.", who);
+                    continue;
+                };
+                let user = channel_name.to_string();
+                if ctx.failed_logins.is_locked_out(addr.ip()) {
+                    warn!("Refused AUTH as '{}' from {}: too many failed attempts.", user, who);
+                    if let Err(e) = socket.send_to(fill_reply!(reply, format, "AUTH:{}:denied", user), addr).await {
+                        error!("Failed to send auth reply to {}: {}", who, e);
+                    }
+                    continue;
+                }
+                let secret = payload.unwrap_or("").to_string();
+                let ctx_clone = ctx.clone();
+                ctx.runtime_handle.spawn(async move {
+                    let user_for_check = user.clone();
+                    let verdict = tokio::task::spawn_blocking(move || backend.verify(&user_for_check, &secret)).await;
+                    let accepted = match verdict {
+                        Ok(Ok(accepted)) => accepted,
+                        Ok(Err(e)) => {
+                            error!("Auth backend failed for user '{}' from {}: {:?}", user, addr, e);
+                            false
+                        }
+                        Err(e) => {
+                            error!("Auth check task failed: {:?}", e);
+                            false
+                        }
+                    };
+                    ctx_clone.failed_logins.record(addr.ip(), accepted);
+                    if accepted {
+                        info!("Client {} authenticated as '{}'.", addr, user);
+                        ctx_clone.clients.set_authenticated(addr, &user);
+                        ctx_clone.clients.touch(addr);
+                    } else {
+                        warn!("Client {} failed to authenticate as '{}'.", addr, user);
+                    }
+                    let reply = frames::reply_bytes(format!("AUTH:{}:{}", user, if accepted { "ok" } else { "denied" }), format);
+                    if let Err(e) = ctx_clone.socket.send_to(&reply, addr).await {
+                        error!("Failed to send auth reply to {}: {}", addr, e);
+                    }
+                });
+            }
             "SUB" => {
-                info!("Client {} subscribed to channel '{}'", addr, channel_name);
-                subscribers.entry(channel_name.clone()).or_default().value_mut().insert(addr);
+                // > SUB:<channel>:max_hz=10 asks for at most 10 messages/s, coalesced to the latest.
+                // > SUB:<channel>:compress=deflate gets large payloads compressed ([compression]).
+                // Options combine with commas. Every SUB replaces the previous options for that channel.
+                let max_hz = payload.and_then(delivery::max_hz_from_sub_options);
+                let compress = payload.is_some_and(compression::compression_from_sub_options);
+                match max_hz {
+                    Some(hz) => info!(topic = channel_name, client:% = addr; "Client {} subscribed to channel '{}' (max {} Hz)", who, channel_name, hz),
+                    None => info!(topic = channel_name, client:% = addr; "Client {} subscribed to channel '{}'", who, channel_name),
+                }
+                // The channel name is only copied when the channel is new.
+                match subscribers.get_mut(channel_name) {
+                    Some(mut channel_set_ref) => {
+                        channel_set_ref.value_mut().insert(addr);
+                    }
+                    None => {
+                        subscribers.entry(channel_name.to_string()).or_default().value_mut().insert(addr);
+                    }
+                }
+                delivery_limiter.set_limit(channel_name, addr, max_hz);
+                ctx.compressor.set_requested(channel_name, addr, compress);
+                stats.recent_events().record(EventKind::Sub, channel_name, payload.unwrap_or(""), Some(addr));
             }
             "UNSUB" => {
-                info!("Client {} unsubscribed from channel '{}'", addr, channel_name);
+                info!(topic = channel_name, client:% = addr; "Client {} unsubscribed from channel '{}'", who, channel_name);
+                stats.recent_events().record(EventKind::Unsub, channel_name, "", Some(addr));
+                delivery_limiter.set_limit(channel_name, addr, None);
+                ctx.compressor.set_requested(channel_name, addr, false);
                 let mut channel_was_emptied = false;
-                if let Some(mut channel_set_ref) = subscribers.get_mut(&channel_name) {
+                if let Some(mut channel_set_ref) = subscribers.get_mut(channel_name) {
                     let removed = channel_set_ref.value_mut().remove(&addr);
                     if removed && channel_set_ref.value().is_empty() {
                         channel_was_emptied = true;
                     }
                 }
                 if channel_was_emptied {
-                    subscribers.remove(&channel_name);
+                    subscribers.remove(channel_name);
                     info!("Channel '{}' is now empty and removed.", channel_name);
                 }
             }
+            "HELLO" => {
+                // > HELLO:<name> names the client in logs and the admin API, e.g. HELLO:stage-left-ipad.
+                // > HELLO:<name>:<will topic>:<will payload> also leaves a last will, published
+                // if the client's keepalive TTL runs out (not on DISCONNECT).
+                // Replies HELLO:<name>:ok. A later HELLO renames it and replaces the will.
+                let name = channel_name.trim();
+                if name.is_empty() || name.len() > MAX_CLIENT_NAME_LEN || name.chars().any(char::is_whitespace) {
+                    warn!("HELLO from {} has an invalid name '{}'.", who, name);
+                    continue;
+                }
+                let will = match payload.map(|will| will.split_once(':')) {
+                    None => None,
+                    Some(Some((topic, will_payload))) if !topic.is_empty() => Some((topic, will_payload)),
+                    Some(_) => {
+                        warn!("HELLO from {} has an invalid will '{}'. Expected <topic>:<payload>.", who, payload.unwrap_or(""));
+                        continue;
+                    }
+                };
+                if let Some((topic, _)) = will {
+                    let allowed = !topic.starts_with(SYS_TOPIC_PREFIX)
+                        && acl.as_ref().is_none_or(|acl| acl.allows(clients.user(&addr).as_deref(), addr, Access::Publish, topic));
+                    if !allowed {
+                        warn!(topic, client:% = addr; "Refused will on '{}' from {}: the client may not publish there.", topic, who);
+                        if let Err(e) = socket.send_to(fill_reply!(reply, format, "ERROR:{}:forbidden", topic), addr).await {
+                            error!("Failed to send will error to {}: {}", who, e);
+                        }
+                        continue;
+                    }
+                }
+                clients.set_name(addr, name);
+                clients.set_will(addr, will);
+                match will {
+                    Some((topic, will_payload)) => info!(client:% = addr, name; "Client {} is now known as '{}' (will: '{}' on '{}')", addr, name, will_payload, topic),
+                    None => info!(client:% = addr, name; "Client {} is now known as '{}'", addr, name),
+                }
+                if let Err(e) = socket.send_to(fill_reply!(reply, format, "HELLO:{}:ok", name), addr).await {
+                    error!("Failed to send HELLO reply to {}: {}", who, e);
+                }
+            }
+            "ALIAS" => {
+                // > ALIAS:<n>:<topic>  < ALIAS:<n>:ok. From then on `@<n>` (n up to 65535) can
+                // be written instead of the topic in any message from this client, e.g.
+                // ALIAS:42:sensors/stage/left/accel, then PUB:@42:0.12. A later ALIAS with the
+                // same n replaces it; aliases are forgotten when the client disconnects or expires.
+                let Ok(n) = channel_name.parse::<u16>() else {
+                    warn!("ALIAS from {} has an invalid number '{}'. Expected 0 to 65535.", who, channel_name);
+                    continue;
+                };
+                let Some(topic) = payload.map(str::trim).filter(|topic| !topic.is_empty() && !topic.starts_with('@') && !topic.contains(':')) else {
+                    warn!("ALIAS from {} for @{} has an invalid topic '{}'.", who, n, payload.unwrap_or(""));
+                    continue;
+                };
+                if !clients.set_alias(addr, n, topic) {
+                    warn!(client:% = addr; "Refused alias @{} from {}: too many aliases.", n, who);
+                    if let Err(e) = socket.send_to(fill_reply!(reply, format, "ERROR:{}:too_many_aliases", topic), addr).await {
+                        error!("Failed to send alias error to {}: {}", who, e);
+                    }
+                    continue;
+                }
+                info!(topic, client:% = addr; "Client {} aliased @{} to '{}'", who, n, topic);
+                if let Err(e) = socket.send_to(fill_reply!(reply, format, "ALIAS:{}:ok", n), addr).await {
+                    error!("Failed to send ALIAS reply to {}: {}", who, e);
+                }
+            }
+            "UNSUB_ALL" => {
+                // > UNSUB_ALL leaves every channel in one message.
+                let channels = unsubscribe_all(&ctx, addr);
+                info!(client:% = addr; "Client {} unsubscribed from all channels: {:?}", who, channels);
+            }
+            "DISCONNECT" => {
+                // > DISCONNECT also ends the login; the next message starts afresh.
+                let who = clients.label(addr); // Before the name is forgotten
+                let channels = disconnect_client(&ctx, addr);
+                info!(client:% = addr; "Client {} disconnected. Removed from channels: {:?}", who, channels);
+            }
             "PUB" => {
+                if channel_name.starts_with(SYS_TOPIC_PREFIX) {
+                    warn!("Client {} tried to publish to reserved channel '{}'. Ignoring.", who, channel_name);
+                    continue;
+                }
                 if let Some(p) = payload {
-                    info!("Client {} published to channel '{}': {}", addr, channel_name, p);
-                    
-                    // MIDI Processing
-                    process_midi_actions(&channel_name, p, &midi_handler_arc, &runtime_handle).await;
-
-                    // Existing PubSub forwarding
-                    let mut subs_to_notify: Vec<SocketAddr> = Vec::new();
-                    if let Some(channel_set_ref) = subscribers.get(&channel_name) {
-                        subs_to_notify = channel_set_ref.value().iter().cloned().collect();
-                    }
-
-                    if !subs_to_notify.is_empty() {
-                        for subscriber_addr in subs_to_notify {
-                            // info!("Forwarding message to subscriber {} on channel '{}'", subscriber_addr, channel_name); // Can be verbose
-                            if let Err(e) = socket.send_to(p.as_bytes(), subscriber_addr).await {
-                                error!("Failed to send pubsub message to {}: {}", subscriber_addr, e);
-                            }
+                    info!(topic = channel_name, client:% = addr; "Client {} published to channel '{}': {}", who, channel_name, p);
+                    stats.clients().record_published(addr);
+                    match binary_payload {
+                        Some(bytes) => handle_binary_publish(&ctx, addr, channel_name, bytes, None).await,
+                        None => {
+                            handle_publish(&ctx, Some(addr), channel_name, p, None, Some(format)).await;
                         }
-                    } else {
-                        // info!("No subscribers for channel '{}'. Message not forwarded.", channel_name); // Can be verbose
                     }
                 } else {
-                    warn!("PUB action from {} to channel '{}' without payload.", addr, channel_name);
+                    warn!("PUB action from {} to channel '{}' without payload.", who, channel_name);
+                }
+            }
+            "PUBID" => {
+                // > PUBID:<channel>:<id>:<payload> publishes at most once per id and replies
+                // ACK:<channel>:<id>, so clients can retry until they see the ACK.
+                if channel_name.starts_with(SYS_TOPIC_PREFIX) {
+                    warn!("Client {} tried to publish to reserved channel '{}'. Ignoring.", who, channel_name);
+                    continue;
+                }
+                let Some((id, p)) = payload.and_then(|rest| rest.split_once(':')) else {
+                    warn!("PUBID from {} to channel '{}' without id or payload.", who, channel_name);
+                    continue;
+                };
+                if !message_ids::is_valid_id(id) {
+                    warn!("PUBID from {} to channel '{}' has an invalid id '{}'.", who, channel_name, id);
+                    continue;
+                }
+                info!(topic = channel_name, client:% = addr, id; "Client {} published {} to channel '{}': {}", who, id, channel_name, p);
+                stats.clients().record_published(addr);
+                match binary_payload {
+                    Some(bytes) => handle_binary_publish(&ctx, addr, channel_name, bytes, Some(id)).await,
+                    None => {
+                        handle_publish(&ctx, Some(addr), channel_name, p, Some(id), Some(format)).await;
+                    }
+                }
+                if let Err(e) = socket.send_to(fill_reply!(reply, format, "ACK:{}:{}", channel_name, id), addr).await {
+                    error!("Failed to send ACK to {}: {}", who, e);
+                }
+            }
+            "SCHEDULE" => {
+                // > SCHEDULE:<channel>:{"delay_ms":2000,"payload":"go"}  < SCHEDULED:<channel>:<n>
+                // publishes the payload later, as if the client sent the PUB then. Instead of
+                // delay_ms, "at" takes unix milliseconds or an RFC 3339 time; an "id" makes it
+                // a PUBID. > UNSCHEDULE:<n>  < UNSCHEDULED:<n>:ok|unknown cancels it.
+                if channel_name.starts_with(SYS_TOPIC_PREFIX) {
+                    warn!("Client {} tried to schedule a publish to reserved channel '{}'. Ignoring.", who, channel_name);
+                    continue;
+                }
+                let scheduled = match ctx.delayed_publishes.schedule(&ctx, addr, format, channel_name, payload.unwrap_or("")) {
+                    Ok(n) => {
+                        info!(topic = channel_name, client:% = addr; "Client {} scheduled publish {} on channel '{}': {}", who, n, channel_name, payload.unwrap_or(""));
+                        fill_reply!(reply, format, "SCHEDULED:{}:{}", channel_name, n)
+                    }
+                    Err(e) => {
+                        warn!(topic = channel_name, client:% = addr; "Refused SCHEDULE on '{}' from {}: {}", channel_name, who, e);
+                        let code = if matches!(e, ScheduleError::TooMany) { "too_many_scheduled" } else { "invalid_schedule" };
+                        fill_reply!(reply, format, "ERROR:{}:{}", channel_name, code)
+                    }
+                };
+                if let Err(e) = socket.send_to(scheduled, addr).await {
+                    error!("Failed to send SCHEDULE reply to {}: {}", who, e);
+                }
+            }
+            "UNSCHEDULE" => {
+                let cancelled = channel_name.parse::<u64>().is_ok_and(|n| ctx.delayed_publishes.cancel(addr, n));
+                info!(client:% = addr; "Client {} cancelled scheduled publish {}: {}", who, channel_name, if cancelled { "ok" } else { "unknown" });
+                let unscheduled = fill_reply!(reply, format, "UNSCHEDULED:{}:{}", channel_name, if cancelled { "ok" } else { "unknown" });
+                if let Err(e) = socket.send_to(unscheduled, addr).await {
+                    error!("Failed to send UNSCHEDULE reply to {}: {}", who, e);
+                }
+            }
+            "HIST" => {
+                // > HIST:<channel>:<n> replays up to n recent payloads (all kept ones without n),
+                // oldest first, as HIST:<channel>:<payload>, then HIST_END:<channel>:<count>.
+                let requested = match payload.map(str::trim).filter(|n| !n.is_empty()) {
+                    Some(n) => match n.parse::<usize>() {
+                        Ok(n) => n,
+                        Err(_) => {
+                            warn!("HIST from {} for channel '{}' has an invalid count '{}'.", who, channel_name, n);
+                            continue;
+                        }
+                    },
+                    None => history.depth(),
+                };
+                let payloads = history.last(channel_name, requested);
+                info!(topic = channel_name, client:% = addr; "Client {} requested history of channel '{}': sending {} payload(s)", who, channel_name, payloads.len());
+                for p in &payloads {
+                    if let Err(e) = socket.send_to(fill_reply!(reply, format, "HIST:{}:{}", channel_name, p), addr).await {
+                        error!("Failed to send history to {}: {}", who, e);
+                    }
+                }
+                if let Err(e) = socket.send_to(fill_reply!(reply, format, "HIST_END:{}:{}", channel_name, payloads.len()), addr).await {
+                    error!("Failed to send history end to {}: {}", who, e);
+                }
+            }
+            "PING" => {
+                // > PING or PING:<token>  < PONG or PONG:<token>, for round-trip times and as an
+                // explicit keepalive. The token comes back unchanged, colons and all.
+                let pong = match message_str.split_once(':') {
+                    Some((_, token)) => fill_reply!(reply, format, "PONG:{}", token),
+                    None => fill_reply!(reply, format, "PONG"),
+                };
+                debug!("PING from {}", who);
+                if let Err(e) = socket.send_to(pong, addr).await {
+                    error!("Failed to send PONG to {}: {}", who, e);
+                }
+            }
+            "STATS" => {
+                // > STATS  < STATS:{"uptime_secs":..., "messages_processed":..., ...}
+                // > STATS:clients  < STATS:clients:[{"addr":..., "name":..., "published":..., ...}]
+                let stats_reply = match channel_name {
+                    "clients" => format!("STATS:clients:{}", serde_json::to_string(&client_stats::client_reports(&ctx)).unwrap_or_default()),
+                    _ => format!("STATS:{}", server_stats_json(&ctx)),
+                };
+                debug!("STATS from {}", who);
+                if let Err(e) = socket.send_to(fill_reply!(reply, format, "{}", stats_reply), addr).await {
+                    error!("Failed to send stats to {}: {}", who, e);
+                }
+            }
+            "LIST" => {
+                // > LIST or LIST:<pattern> (e.g. LIST:drums/*) lists the channels that have
+                // subscribers as LIST:<channel>:<subscriber count>, then LIST_END:<count>.
+                let pattern = if channel_name.is_empty() { "*" } else { channel_name };
+                let user = clients.user(&addr);
+                let mut channels: Vec<(String, usize)> = subscribers
+                    .iter()
+                    .filter(|entry| topic_matches(pattern, entry.key()))
+                    .filter(|entry| acl.as_ref().is_none_or(|acl| acl.allows(user.as_deref(), addr, Access::Subscribe, entry.key())))
+                    .map(|entry| (entry.key().clone(), entry.value().len()))
+                    .collect();
+                channels.sort();
+                info!(client:% = addr; "Client {} listed channels matching '{}': {} channel(s)", who, pattern, channels.len());
+                for (channel, count) in &channels {
+                    if let Err(e) = socket.send_to(fill_reply!(reply, format, "LIST:{}:{}", channel, count), addr).await {
+                        error!("Failed to send channel list to {}: {}", who, e);
+                    }
+                }
+                if let Err(e) = socket.send_to(fill_reply!(reply, format, "LIST_END:{}", channels.len()), addr).await {
+                    error!("Failed to send channel list end to {}: {}", who, e);
                 }
             }
             _ => {
-                warn!("Unknown action '{}' from {}: {}", action, addr, message_str);
+                warn!("Unknown action '{}' from {}: {}", action, who, message_str);
             }
         }
     }
 }
 
+#[derive(Serialize)]
+struct ServerStatsJson {
+    uptime_secs: u64,
+    messages_processed: u64,
+    channels: usize,
+    subscribers: usize,
+    midi_messages_sent: u64,
+}
+
+// The STATS reply. `subscribers` counts distinct clients, however many channels each has.
+fn server_stats_json(ctx: &ServerContext) -> String {
+    let subscriber_addrs: HashSet<SocketAddr> =
+        ctx.subscribers.iter().flat_map(|entry| entry.value().iter().cloned().collect::<Vec<_>>()).collect();
+    let stats = ServerStatsJson {
+        uptime_secs: ctx.stats.uptime().as_secs(),
+        messages_processed: ctx.stats.messages_processed(),
+        channels: ctx.subscribers.len(),
+        subscribers: subscriber_addrs.len(),
+        midi_messages_sent: ctx.stats.midi_messages_sent(),
+    };
+    serde_json::to_string(&stats).unwrap_or_default()
+}
+
+// Everything a publish triggers: control topics, MIDI, the pipe bridge and subscriber fanout.
+// `publisher` is None for publishes that don't come from a UDP client (e.g. the pipe bridge).
+// A publish with a `client_id` that already ran is dropped, so retries don't fire twice.
+// Returns the message ID, or None if the publish was such a duplicate.
+pub async fn handle_publish(
+    ctx: &ServerContext,
+    publisher: Option<SocketAddr>,
+    channel_name: &str,
+    p: &str,
+    client_id: Option<&str>,
+    reply_format: Option<WireFormat>, // How the publisher reads RESULT and errors; None = no replies, e.g. federated publishes
+) -> Option<String> {
+    let ServerContext { subscribers, sequencer, lfos, delivery_limiter, pipe_bridge, message_ids, stats, history, channel_expiry, transforms, federation, websocket_bridge, sacn, event_store, session_replay, .. } = ctx;
+
+    let message_id = match client_id {
+        Some(id) if !message_ids.first_time(id) => {
+            info!(topic = channel_name, id; "Message {} on '{}' was already executed. Skipping duplicate.", id, channel_name);
+            return None;
+        }
+        Some(id) => id.to_string(),
+        None => message_ids.assign(),
+    };
+    stats.recent_events().record(EventKind::Pub, channel_name, p, publisher);
+    history.record(channel_name, p);
+    channel_expiry.touch(channel_name);
+    if let Some(event_store) = event_store {
+        event_store.record_publish(channel_name, p, publisher, &message_id);
+    }
+    session_replay.record(channel_name, p, publisher);
+
+    // Sequencer control topics
+    let mut control_topic = false;
+    if sequencer.handle_publish(channel_name, p) {
+        debug!("Handled sequencer command on '{}'", channel_name);
+        control_topic = true;
+    }
+    // LFO control topics
+    if lfos.handle_publish(channel_name, p) {
+        debug!("Handled LFO command on '{}'", channel_name);
+        control_topic = true;
+    }
+    // Global transpose
+    if channel_name == CONTROL_TRANSPOSE_TOPIC {
+        handle_transpose_command(ctx, p);
+        control_topic = true;
+    }
+    // Session recording and replay
+    if session_replay.handle_publish(channel_name, p) {
+        debug!("Handled session replay command on '{}'", channel_name);
+        control_topic = true;
+    }
+
+    // MIDI Processing, with an optional result echo (or payload schema error) to the
+    // publisher. Control topics have done their job and don't fall back to the
+    // `sub_topic = "*"` mapping.
+    process_midi_actions(channel_name, p, !control_topic, ctx, publisher.zip(reply_format));
+
+    // Local programs listening on the pipe bridge
+    if let Some(bridge) = pipe_bridge {
+        bridge.forward(channel_name, p, publisher, &message_id);
+    }
+    // A linked server, see [federation]
+    federation.export(channel_name, p, publisher);
+    // A WebSocket relay, see [websocket_bridge]
+    websocket_bridge.forward(channel_name, p);
+    // DMX levels, see [sacn]
+    if let Some(sacn) = sacn {
+        sacn.apply(channel_name, p);
+    }
+
+    // Existing PubSub forwarding
+    let mut subs_to_notify: Vec<SocketAddr> = Vec::new();
+    if let Some(channel_set_ref) = subscribers.get(channel_name) {
+        subs_to_notify = channel_set_ref.value().iter().cloned().collect();
+    }
+
+    if !subs_to_notify.is_empty() {
+        // Subscribers get the payload after the channel's transform, if it has one.
+        let forwarded = transforms.apply(channel_name, p);
+        let p = forwarded.as_ref();
+        // Subscribers due now get the message in one batch, see fanout.rs.
+        let fanout_started = Instant::now();
+        let mut send_now = Vec::with_capacity(subs_to_notify.len());
+        for subscriber_addr in subs_to_notify {
+            // info!("Forwarding message to subscriber {} on channel '{}'", subscriber_addr, channel_name); // Can be verbose
+            match delivery_limiter.offer(channel_name, subscriber_addr, p) {
+                Delivery::SendNow => send_now.push(subscriber_addr),
+                Delivery::Deferred(wait) => {
+                    // Throttled: send whatever is the latest value once the interval is up.
+                    let ctx_clone = ctx.clone();
+                    let channel_clone = channel_name.to_string();
+                    ctx.runtime_handle.spawn(async move {
+                        sleep(wait).await;
+                        if let Some(latest) = ctx_clone.delivery_limiter.take_pending(&channel_clone, subscriber_addr)
+                            && let Err(e) = ctx_clone
+                                .socket
+                                .send_to(&ctx_clone.compressor.datagram_for(&channel_clone, subscriber_addr, latest.as_bytes()), subscriber_addr)
+                                .await
+                        {
+                            error!("Failed to send throttled pubsub message to {}: {}", subscriber_addr, e);
+                        }
+                    });
+                }
+                Delivery::Coalesced => {}
+            }
+        }
+        if !send_now.is_empty() {
+            send_to_subscribers(ctx, channel_name, p.as_bytes(), &send_now).await;
+            stats.record_fanout(channel_name, send_now.len(), fanout_started.elapsed());
+        }
+    } else {
+        // info!("No subscribers for channel '{}'. Message not forwarded.", channel_name); // Can be verbose
+    }
+    Some(message_id)
+}
+
+// A publish from a binary frame whose payload isn't UTF-8. Subscribers get the bytes as
+// they are; mappings, history, the event log and max_hz throttling only handle text
+// payloads and don't see it.
+async fn handle_binary_publish(ctx: &ServerContext, publisher: SocketAddr, channel_name: &str, payload: &[u8], client_id: Option<&str>) {
+    let ServerContext { subscribers, message_ids, stats, channel_expiry, .. } = ctx;
+    if let Some(id) = client_id
+        && !message_ids.first_time(id)
+    {
+        info!(topic = channel_name, id; "Message {} on '{}' was already executed. Skipping duplicate.", id, channel_name);
+        return;
+    }
+    stats.recent_events().record(EventKind::Pub, channel_name, &format!("<{} bytes>", payload.len()), Some(publisher));
+    channel_expiry.touch(channel_name);
+    let targets: Vec<SocketAddr> = match subscribers.get(channel_name) {
+        Some(channel_set_ref) => channel_set_ref.value().iter().cloned().collect(),
+        None => return,
+    };
+    let fanout_started = Instant::now();
+    send_to_subscribers(ctx, channel_name, payload, &targets).await;
+    stats.record_fanout(channel_name, targets.len(), fanout_started.elapsed());
+}
+
+// Sends a publish to the subscribers due now in one batch (see fanout.rs). Those that
+// asked for compression get the compressed datagram instead, if it's worth it.
+async fn send_to_subscribers(ctx: &ServerContext, channel_name: &str, payload: &[u8], targets: &[SocketAddr]) {
+    let ServerContext { socket, compressor, stats, .. } = ctx;
+    let (compress_for, plain_for): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        targets.iter().partition(|subscriber_addr| compressor.requested(channel_name, **subscriber_addr));
+    let compressed = if compress_for.is_empty() { None } else { compressor.compress(payload) };
+    let failures = match compressed {
+        Some(compressed) => {
+            stats.record_compressed(compress_for.len(), payload.len() - compressed.len());
+            let mut failures = socket.send_to_many(&compressed, &compress_for).await;
+            if !plain_for.is_empty() {
+                failures.extend(socket.send_to_many(payload, &plain_for).await);
+            }
+            failures
+        }
+        None => socket.send_to_many(payload, targets).await,
+    };
+    for (subscriber_addr, e) in failures {
+        error!("Failed to send pubsub message to {}: {}", subscriber_addr, e);
+    }
+}
+
 // Represents the optional fields that can be sent in a JSON payload to override the base mapping.
 #[derive(Deserialize, Debug, Default)]
 struct PayloadOverride {
@@ -116,123 +877,617 @@ struct PayloadOverride {
     vel: Option<u8>,
     dur: Option<u64>,
     control_num: Option<u8>,
-    value: Option<u8>,
+    value: Option<u16>,
+    param_num: Option<u16>,
+    bank_msb: Option<u8>,
+    bank_lsb: Option<u8>,
+    from_value: Option<u16>,
+    curve: Option<RampCurve>,
+    rate_hz: Option<u32>,
+    // Any other key is a mistake, like "veloctiy"
+    #[serde(flatten)]
+    unknown: HashMap<String, serde_json::Value>,
 }
 
-async fn process_midi_actions(
-    topic: &str,
-    payload_str: &str,
-    midi_handler_arc: &Arc<Mutex<MidiHandler>>,
-    runtime_handle: &Handle,
-) {
-    let mut handler = midi_handler_arc.lock().unwrap();
-    
-    // 1. Get the base actions from the mapping file for the current topic.
-    if let Some(base_actions) = handler.get_actions_for_topic(topic) {
-        debug!("Found {} base actions for topic '{}'", base_actions.len(), topic);
-
-        // 2. Parse the payload for any overrides.
-        // If parsing fails, overrides remain Default::default() (all None),
-        // so the base action is used as-is. This handles the "simple ping" case.
-        let overrides: PayloadOverride = serde_json::from_str(payload_str).unwrap_or_default();
-
-        for base_action in base_actions {
-            // 3. Merge the base action with any overrides from the payload.
-            let final_action = MidiAction {
-                action_type: overrides.action_type.clone().unwrap_or(base_action.action_type),
-                channel: overrides.ch.unwrap_or(base_action.channel),
-                note: overrides.note.or(base_action.note),
-                velocity: overrides.vel.or(base_action.velocity),
-                duration_ms: overrides.dur.or(base_action.duration_ms),
-                control_num: overrides.control_num.or(base_action.control_num),
-                value: overrides.value.or(base_action.value),
-            };
-
-            // 4. Construct and send the final MIDI message.
-            let midi_msg: Option<Vec<u8>> = match final_action.action_type {
-                MidiActionType::NoteOn => Some(vec![
-                    0x90 + (final_action.channel & 0x0F),
-                    final_action.note.unwrap_or(60),
-                    final_action.velocity.unwrap_or(127).clamp(0, 127),
-                ]),
-                MidiActionType::NoteOff => Some(vec![
-                    0x80 + (final_action.channel & 0x0F),
-                    final_action.note.unwrap_or(60),
-                    final_action.velocity.unwrap_or(0).clamp(0, 127),
-                ]),
-                MidiActionType::NoteOnOff => {
-                    let note = final_action.note.unwrap_or(60);
-                    let vel = final_action.velocity.unwrap_or(127);
-                    let dur = final_action.duration_ms.unwrap_or(50);
-                    let note_on_msg = vec![0x90 + (final_action.channel & 0x0F), note, vel.clamp(0, 127)];
-                    let note_off_msg = vec![0x80 + (final_action.channel & 0x0F), note, 0];
-
-                    if let Err(e) = handler.send_midi_message(&note_on_msg) {
-                        error!("Failed to send merged MIDI NoteOn for {}: {:?}", topic, e);
+// The overrides in a payload, and why it's malformed if it is. A payload that doesn't
+// start with `{` is a plain trigger without overrides. Unknown keys still leave the
+// known ones to use; broken JSON or a wrong value type leaves none.
+fn parse_overrides(payload_str: &str) -> (PayloadOverride, Option<String>) {
+    if !payload_str.trim_start().starts_with('{') {
+        return (PayloadOverride::default(), None);
+    }
+    match serde_json::from_str::<PayloadOverride>(payload_str) {
+        Ok(overrides) if overrides.unknown.is_empty() => (overrides, None),
+        Ok(overrides) => {
+            let mut keys: Vec<&str> = overrides.unknown.keys().map(String::as_str).collect();
+            keys.sort_unstable();
+            let error = format!("unknown key(s) {}", keys.join(", "));
+            (overrides, Some(error))
+        }
+        Err(e) => (PayloadOverride::default(), Some(e.to_string())),
+    }
+}
+
+// What a PUB triggered on the MIDI side. Sent back to the publisher as
+// `RESULT:<topic>:<json>` for mappings with `echo = true`.
+#[derive(Serialize, Debug, Default)]
+struct MidiResult {
+    actions: usize,
+    messages: usize,
+    bytes: usize,
+    errors: Vec<String>,
+}
+
+impl MidiResult {
+    fn merge(&mut self, other: MidiResult) {
+        self.actions += other.actions;
+        self.messages += other.messages;
+        self.bytes += other.bytes;
+        self.errors.extend(other.errors);
+    }
+}
+
+// What the publisher hears back about the MIDI side of its publish.
+#[derive(Debug, Default)]
+struct MidiReply {
+    // For mappings with `echo = true`
+    result: Option<MidiResult>,
+    // A mapping with `payload_schema.reply_error` rejected the payload
+    invalid_payload: bool,
+}
+
+// Queues the mapping for `topic` on the MIDI thread without waiting for it. If a mapping
+// has something to tell `reply_to`, the reply is sent from the runtime once it has run.
+// Nothing is run (or reported) if the MIDI queue overflowed and dropped it, see [midi_queue].
+fn process_midi_actions(topic: &str, payload_str: &str, use_fallback: bool, ctx: &ServerContext, reply_to: Option<(SocketAddr, WireFormat)>) {
+    let (job_topic, payload_str, job_ctx) = (topic.to_string(), payload_str.to_string(), ctx.clone());
+    ctx.midi.publish(topic, move |handler| {
+        let reply = apply_mapping(handler, &job_topic, &payload_str, use_fallback, &job_ctx);
+        if let Some((addr, format)) = reply_to
+            && (reply.invalid_payload || reply.result.is_some())
+        {
+            let runtime_handle = job_ctx.runtime_handle.clone();
+            runtime_handle.spawn(async move { send_midi_reply(&job_ctx, addr, format, &job_topic, reply).await });
+        }
+    });
+}
+
+// In the publisher's wire format, like the replies from the receive loop.
+async fn send_midi_reply(ctx: &ServerContext, addr: SocketAddr, format: WireFormat, topic: &str, reply: MidiReply) {
+    if reply.invalid_payload {
+        let message = format!("ERROR:{}:invalid_payload", topic);
+        if let Err(e) = ctx.socket.send_to(&frames::reply_bytes(message, format), addr).await {
+            error!("Failed to send payload schema error to {}: {}", addr, e);
+        }
+    }
+    if let Some(result) = reply.result {
+        let message = format!("RESULT:{}:{}", topic, serde_json::to_string(&result).unwrap_or_default());
+        if let Err(e) = ctx.socket.send_to(&frames::reply_bytes(message, format), addr).await {
+            error!("Failed to send MIDI result to {}: {}", addr, e);
+        }
+    }
+}
+
+// The mapping logic itself, run with exclusive access to the handler. Anything delayed
+// goes through `run_later`.
+fn apply_mapping(handler: &mut MidiHandler, topic: &str, payload_str: &str, use_fallback: bool, ctx: &ServerContext) -> MidiReply {
+    // Vendor-specific payloads are cleaned up before the mapping logic sees them.
+    let normalized_payload = handler.normalize_payload(topic, payload_str);
+
+    // 1. Get the mappings for the current topic: the best match, or every match with
+    // `match_policy = "all_matches"`. Mappings with `echo` report what they sent, together.
+    let mut reply = MidiReply::default();
+    for mapping in handler.get_mappings_for_topic(topic, use_fallback) {
+        let echo = mapping.echo;
+        let reply_error = mapping.payload_schema.as_ref().is_some_and(|schema| schema.reply_error);
+        let (result, invalid_payload) = run_mapping(handler, mapping, topic, &normalized_payload, ctx);
+        reply.invalid_payload |= invalid_payload && reply_error;
+        if echo {
+            reply.result.get_or_insert_default().merge(result);
+        }
+    }
+    reply
+}
+
+// Runs `job` on the handler once `delay` is up: spawned on the runtime and sent back
+// through the MIDI handle for the live handler, queued on the virtual clock of a render.
+fn run_later(handler: &mut MidiHandler, ctx: &ServerContext, delay: Duration, job: impl FnOnce(&mut MidiHandler) + Send + 'static) {
+    if handler.is_rendering() {
+        handler.render_later(delay, job);
+        return;
+    }
+    let midi = ctx.midi.clone();
+    ctx.runtime_handle.spawn(async move {
+        sleep(delay).await;
+        midi.execute(job);
+    });
+}
+
+// Runs a recorded publish through a render's handler (see session_replay.rs), like
+// `handle_publish` does for the live one, minus replies and everything but MIDI.
+pub fn render_publish(handler: &mut MidiHandler, topic: &str, payload: &str, ctx: &ServerContext) {
+    let mut control_topic = handler.is_control_topic(topic) || session_replay::is_control_topic(topic);
+    if topic == CONTROL_TRANSPOSE_TOPIC {
+        if let Some(semitones) = parse_transpose(payload) {
+            handler.set_transpose(semitones);
+        }
+        control_topic = true;
+    }
+    apply_mapping(handler, topic, payload, !control_topic, ctx);
+}
+
+// Runs one mapping's actions for a publish on `topic`. True if its payload schema rejected the payload.
+fn run_mapping(handler: &mut MidiHandler, mapping: MappingEntry, topic: &str, payload_str: &str, ctx: &ServerContext) -> (MidiResult, bool) {
+    // The handler's zones and stats, which a render keeps to itself
+    let (stats, zones) = (handler.stats(), handler.zones());
+
+    let mut result = MidiResult::default();
+    if !zones.allows(mapping.zone.as_deref()) {
+        let zone = mapping.zone.as_deref().unwrap_or("");
+        debug!("Zone '{}' is silenced. Skipping mapping for '{}'.", zone, topic);
+        result.errors.push(format!("zone '{}' is disabled", zone));
+        return (result, false);
+    }
+    // A payload that doesn't match the mapping's schema doesn't get to send anything.
+    if let Some(Err(error)) = mapping.payload_schema.as_ref().map(|schema| schema.check(payload_str)) {
+        stats.record_invalid_payload(&mapping.sub_topic);
+        warn!(topic = topic; "Rejected payload on '{}' that doesn't match the mapping's payload_schema ({}): {}", topic, error, payload_str);
+        result.errors.push(format!("invalid payload: {}", error));
+        return (result, true);
+    }
+    let base_actions = mapping.actions;
+    stats.record_mapping_trigger(&mapping.sub_topic);
+    debug!("Found {} base actions for topic '{}'", base_actions.len(), topic);
+
+    // 2. Parse the payload for any overrides.
+    // A payload that isn't a JSON object leaves them all None, so the base action is
+    // used as-is. This handles the "simple ping" case. A malformed one is handled as the
+    // mapping's `on_payload_error` says.
+    let (overrides, malformed) = parse_overrides(payload_str);
+    if let Some(error) = malformed {
+        stats.record_malformed_payload(&mapping.sub_topic);
+        match mapping.on_payload_error.unwrap_or_default() {
+            PayloadErrorPolicy::Fallback => {
+                debug!(topic = topic; "Malformed payload on '{}' ({}): {}", topic, error, payload_str);
+            }
+            PayloadErrorPolicy::Warn => {
+                warn!(topic = topic; "Malformed payload on '{}' ({}), running its mapping without the parts that failed: {}", topic, error, payload_str);
+            }
+            PayloadErrorPolicy::Reject => {
+                warn!(topic = topic; "Rejected malformed payload on '{}' ({}): {}", topic, error, payload_str);
+                result.errors.push(format!("malformed payload: {}", error));
+                return (result, false);
+            }
+        }
+    }
+
+    // Notes coming from the payload are snapped into the mapping's scale (if any).
+    let override_note = match (overrides.note, &mapping.scale) {
+        (Some(note), Some(scale)) => {
+            let quantized = scale.quantize(note);
+            if quantized != note {
+                debug!("Quantized note {} to {} for topic '{}'", note, quantized, topic);
+            }
+            Some(quantized)
+        }
+        (note, _) => note,
+    };
+    // All actions of one trigger move to the same rotation step.
+    let rotation = mapping.rotate.as_ref().map(|rotation| (rotation, handler.next_rotation_step(&mapping.sub_topic)));
+    let rotated_channel = rotation.and_then(|(rotation, step)| rotation.channel(step));
+    let rotated_note = rotation.and_then(|(rotation, step)| rotation.note(step));
+
+    for (action_index, base_action) in base_actions.into_iter().enumerate() {
+        // 3. Merge the base action with any overrides from the payload.
+        let mut final_action = MidiAction {
+            action_type: overrides.action_type.clone().unwrap_or(base_action.action_type),
+            channel: overrides.ch.or(rotated_channel).unwrap_or(base_action.channel),
+            note: override_note.or(rotated_note).or(base_action.note).map(|n| handler.transpose_note(n, mapping.transpose)),
+            velocity: overrides.vel.or(base_action.velocity),
+            duration_ms: overrides.dur.or(base_action.duration_ms),
+            control_num: overrides.control_num.or(base_action.control_num),
+            value: overrides.value.or(base_action.value),
+            param_num: overrides.param_num.or(base_action.param_num),
+            bank_msb: overrides.bank_msb.or(base_action.bank_msb),
+            bank_lsb: overrides.bank_lsb.or(base_action.bank_lsb),
+            from_value: overrides.from_value.or(base_action.from_value),
+            curve: overrides.curve.or(base_action.curve),
+            rate_hz: overrides.rate_hz.or(base_action.rate_hz),
+        };
+        // Auto-allocated topics always play on their own channel and note range.
+        if let Some(slot) = mapping.slot {
+            final_action.channel = slot.channel;
+            final_action.note = final_action.note.map(|n| slot.map_note(n));
+        }
+
+        // 4. Humanize: random velocity variation and a small random delay per action.
+        let humanize = mapping.humanize.unwrap_or_default();
+        let delay = humanize.timing_offset();
+
+        // 5. Construct and send the final MIDI message(s).
+        let midi_msgs: Vec<Vec<u8>> = match final_action.action_type {
+            MidiActionType::NoteOn => vec![vec![
+                0x90 + (final_action.channel & 0x0F),
+                final_action.note.unwrap_or(60),
+                humanize.jitter_velocity(final_action.velocity.unwrap_or(127).clamp(0, 127)),
+            ]],
+            MidiActionType::NoteOff => vec![vec![
+                0x80 + (final_action.channel & 0x0F),
+                final_action.note.unwrap_or(60),
+                final_action.velocity.unwrap_or(0).clamp(0, 127),
+            ]],
+            MidiActionType::NoteOnOff => {
+                let note = final_action.note.unwrap_or(60);
+                let vel = humanize.jitter_velocity(final_action.velocity.unwrap_or(127).clamp(0, 127));
+                let dur = final_action.duration_ms.unwrap_or(50);
+                let note_on_msg = vec![0x90 + (final_action.channel & 0x0F), note, vel];
+                let note_off_msg = vec![0x80 + (final_action.channel & 0x0F), note, 0];
+
+                // Registered with the handler, so shutdown can send it early if the task is aborted.
+                let note_off_id = handler.schedule_note_off(note_off_msg);
+                let topic_clone = topic.to_string();
+                run_later(handler, ctx, delay + Duration::from_millis(dur), move |handler| {
+                    if let Some(note_off_msg) = handler.take_note_off(note_off_id)
+                        && let Err(e) = handler.send_midi_message(&note_off_msg)
+                    {
+                        error!("Failed to send merged delayed MIDI NoteOff for {}: {:?}", topic_clone, e);
                     }
-                    
-                    let midi_handler_clone = Arc::clone(midi_handler_arc);
-                    let topic_clone = topic.to_string();
-                    runtime_handle.spawn(async move {
-                        sleep(Duration::from_millis(dur)).await;
-                        let mut handler_clone = midi_handler_clone.lock().unwrap();
-                        if let Err(e) = handler_clone.send_midi_message(&note_off_msg) {
-                            error!("Failed to send merged delayed MIDI NoteOff for {}: {:?}", topic_clone, e);
-                        }
-                    });
-                    None // Handled internally
+                });
+                vec![note_on_msg] // NoteOff is sent by the delayed task
+            }
+            MidiActionType::NoteToggle => {
+                // The NoteOff releases whatever was latched, even if transpose changed since.
+                let channel = final_action.channel & 0x0F;
+                let note = final_action.note.unwrap_or(60);
+                match handler.toggle_latch(&mapping.sub_topic, action_index, channel, note) {
+                    Some((latched_channel, latched_note)) => vec![vec![0x80 + latched_channel, latched_note, 0]],
+                    None => vec![vec![
+                        0x90 + channel,
+                        note,
+                        humanize.jitter_velocity(final_action.velocity.unwrap_or(127).clamp(0, 127)),
+                    ]],
                 }
-                MidiActionType::Cc => Some(vec![
+            }
+            MidiActionType::Cc => {
+                // A direct value wins over a ramp still gliding on the same controller.
+                // The mapping check limits control_num, so only a payload override can be out of range.
+                let control_num = final_action.control_num.unwrap_or(0);
+                if control_num > 127 {
+                    warn!(topic = topic; "Skipped cc action for '{}': control_num {} is out of range (0-127).", topic, control_num);
+                    result.errors.push(format!("cc control_num {} is out of range (0-127)", control_num));
+                    continue;
+                }
+                handler.cancel_cc_ramp(final_action.channel, control_num);
+                vec![vec![
                     0xB0 + (final_action.channel & 0x0F),
-                    final_action.control_num.unwrap_or(0),
-                    final_action.value.unwrap_or(0).clamp(0, 127),
-                ]),
-                MidiActionType::ProgramChange => Some(vec![
+                    control_num,
+                    final_action.value.unwrap_or(0).min(127) as u8,
+                ]]
+            }
+            MidiActionType::CcRamp => {
+                let control_num = final_action.control_num.unwrap_or(0).min(127);
+                let from = final_action
+                    .from_value
+                    .map(|v| v.min(127) as u8)
+                    .or_else(|| handler.last_cc_value(final_action.channel, control_num))
+                    .unwrap_or(0);
+                let ramp = CcRamp {
+                    channel: final_action.channel,
+                    control_num,
+                    from,
+                    to: final_action.value.unwrap_or(0).min(127) as u8,
+                    duration: Duration::from_millis(final_action.duration_ms.unwrap_or(1000)),
+                    curve: final_action.curve.unwrap_or_default(),
+                    rate_hz: final_action.rate_hz.unwrap_or(DEFAULT_RAMP_RATE_HZ),
+                };
+                let generation = handler.begin_cc_ramp(final_action.channel, control_num);
+                debug!("Starting CC ramp for {}: cc {} {} -> {} over {:?}", topic, control_num, ramp.from, ramp.to, ramp.duration);
+                if handler.is_rendering() {
+                    schedule_render_ramp(handler, ramp, generation);
+                } else {
+                    ctx.runtime_handle.spawn(run_cc_ramp(ramp, generation, ctx.midi.clone()));
+                }
+                vec![] // Values are streamed by the ramp task
+            }
+            MidiActionType::Cc14 => {
+                // MSB on controller N, LSB on N+32. Only controllers 0-31 have an LSB pair;
+                // the mapping check refuses others, so only a payload override gets here.
+                let control_num = final_action.control_num.unwrap_or(0);
+                if control_num > 31 {
+                    warn!(topic = topic; "Skipped cc14 action for '{}': control_num {} is out of range (0-31).", topic, control_num);
+                    result.errors.push(format!("cc14 control_num {} is out of range (0-31)", control_num));
+                    continue;
+                }
+                let value = final_action.value.unwrap_or(0).min(16383);
+                vec![
+                    vec![0xB0 + (final_action.channel & 0x0F), control_num, (value >> 7) as u8],
+                    vec![0xB0 + (final_action.channel & 0x0F), control_num + 32, (value & 0x7F) as u8],
+                ]
+            }
+            MidiActionType::Nrpn | MidiActionType::Rpn => {
+                let (param_msb_cc, param_lsb_cc) = match final_action.action_type {
+                    MidiActionType::Nrpn => (99, 98),
+                    _ => (101, 100),
+                };
+                let status = 0xB0 + (final_action.channel & 0x0F);
+                let param = final_action.param_num.unwrap_or(0).min(16383);
+                let value = final_action.value.unwrap_or(0).min(16383);
+                vec![
+                    vec![status, param_msb_cc, (param >> 7) as u8],
+                    vec![status, param_lsb_cc, (param & 0x7F) as u8],
+                    vec![status, 6, (value >> 7) as u8],   // Data Entry MSB
+                    vec![status, 38, (value & 0x7F) as u8], // Data Entry LSB
+                ]
+            }
+            MidiActionType::ProgramChange => {
+                // Bank select (CC0 / CC32) has to come before the program change itself.
+                let mut msgs = Vec::new();
+                if let Some(msb) = final_action.bank_msb {
+                    msgs.push(vec![0xB0 + (final_action.channel & 0x0F), 0, msb.min(127)]);
+                }
+                if let Some(lsb) = final_action.bank_lsb {
+                    msgs.push(vec![0xB0 + (final_action.channel & 0x0F), 32, lsb.min(127)]);
+                }
+                msgs.push(vec![
                     0xC0 + (final_action.channel & 0x0F),
-                    final_action.value.unwrap_or(0).clamp(0, 127),
-                ]),
-            };
+                    final_action.value.unwrap_or(0).min(127) as u8,
+                ]);
+                msgs
+            }
+        };
 
-            if let Some(msg_bytes) = midi_msg {
-                if let Err(e) = handler.send_midi_message(&msg_bytes) {
-                    error!("Failed to send merged MIDI message for {}: {:?}", topic, e);
-                } else {
-                    debug!("Sent merged MIDI message for {}: {:?}", topic, msg_bytes);
+        result.actions += 1;
+        if !delay.is_zero() {
+            // Humanized actions go out a little later, off the processing loop.
+            result.messages += midi_msgs.len();
+            result.bytes += midi_msgs.iter().map(Vec::len).sum::<usize>();
+            let topic_clone = topic.to_string();
+            run_later(handler, ctx, delay, move |handler| {
+                for msg_bytes in midi_msgs {
+                    if let Err(e) = handler.send_midi_message(&msg_bytes) {
+                        error!("Failed to send humanized MIDI message for {}: {:?}", topic_clone, e);
+                    }
                 }
+            });
+            continue;
+        }
+        for msg_bytes in midi_msgs {
+            if let Err(e) = handler.send_midi_message(&msg_bytes) {
+                error!("Failed to send merged MIDI message for {}: {:?}", topic, e);
+                result.errors.push(e.to_string());
+            } else {
+                debug!("Sent merged MIDI message for {}: {:?}", topic, msg_bytes);
+                result.messages += 1;
+                result.bytes += msg_bytes.len();
             }
         }
     }
+    (result, false)
 }
 
-// Multicast discovery listener
+// Multicast discovery listener, for an IPv4 or IPv6 group
 pub async fn run_multicast_discovery_listener(
-    main_server_bind_address: String,
+    group: SocketAddr,
+    main_server_addr: SocketAddr,
+    ip_filter: Option<Arc<IpFilter>>,
+    envelope: Option<Arc<Envelope>>,
+    failover: Arc<Failover>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    info!("Starting multicast discovery listener on {}", MULTICAST_ADDRESS);
+    info!("Starting multicast discovery listener on {}", group);
 
-    let listen_addr_str = MULTICAST_ADDRESS.split(':').collect::<Vec<&str>>()[1];
-    let listen_port: u16 = listen_addr_str.parse()?;
-    let listen_ip = "0.0.0.0";
+    let socket = match group.ip() {
+        IpAddr::V4(multicast_group_addr) => {
+            let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), group.port())).await?;
+            let interface_to_join_on = Ipv4Addr::new(0,0,0,0);
+            socket.join_multicast_v4(multicast_group_addr, interface_to_join_on)?;
+            info!("Joined multicast group {} on interface {}", multicast_group_addr, interface_to_join_on);
+            socket
+        }
+        IpAddr::V6(multicast_group_addr) => {
+            // IPv6 only, so it can share the port with the IPv4 listener.
+            let socket = network::bind_udp(SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), group.port()), true, false)?;
+            // Interface 0 lets the OS pick the default interface.
+            socket.join_multicast_v6(&multicast_group_addr, 0)?;
+            info!("Joined multicast group {} on the default interface", multicast_group_addr);
+            socket
+        }
+    };
+    answer_discovery_pings(ServerSocket::new(socket, envelope), main_server_addr, ip_filter, failover, "multicast").await
+}
 
-    let socket = UdpSocket::bind(format!("{}:{}", listen_ip, listen_port)).await?;
-    
-    let multicast_group_addr: Ipv4Addr = MULTICAST_ADDRESS.split(':').collect::<Vec<&str>>()[0].parse()?;
-    let interface_to_join_on = Ipv4Addr::new(0,0,0,0);
-    socket.join_multicast_v4(multicast_group_addr, interface_to_join_on)?;
-    info!("Joined multicast group {} on interface {}", multicast_group_addr, interface_to_join_on);
+// Broadcast discovery listener, for networks that block multicast. Clients send the same
+// ping to 255.255.255.255 or their subnet's broadcast address.
+pub async fn run_broadcast_discovery_listener(
+    port: u16,
+    main_server_addr: SocketAddr,
+    ip_filter: Option<Arc<IpFilter>>,
+    envelope: Option<Arc<Envelope>>,
+    failover: Arc<Failover>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    info!("Starting broadcast discovery listener on port {}", port);
+    let socket = UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)).await?;
+    socket.set_broadcast(true)?;
+    answer_discovery_pings(ServerSocket::new(socket, envelope), main_server_addr, ip_filter, failover, "broadcast").await
+}
 
+// Answers DISCOVER_SUBPUB_SERVER pings with the main server's address. A failover
+// standby stays quiet while its primary is alive.
+async fn answer_discovery_pings(
+    socket: ServerSocket,
+    main_server_addr: SocketAddr,
+    ip_filter: Option<Arc<IpFilter>>,
+    failover: Arc<Failover>,
+    kind: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
     let mut buf = [0; 1024];
     loop {
-        let (len, src_addr) = socket.recv_from(&mut buf).await?;
-        let message = std::str::from_utf8(&buf[..len])?.trim();
+        // A bad datagram or a failed send only costs that one ping, never the listener.
+        let (len, src_addr) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                warn!("Failed to receive on the {} discovery socket: {}", kind, e);
+                continue;
+            }
+        };
+        if let Some(ip_filter) = &ip_filter
+            && !ip_filter.permits(src_addr.ip())
+        {
+            debug!("Ignored discovery ping from filtered address {}", src_addr);
+            continue;
+        }
+        let message = match std::str::from_utf8(&buf[..len]) {
+            Ok(message) => message.trim(),
+            Err(e) => {
+                debug!("Ignored non-UTF8 {} discovery datagram from {}: {}", kind, src_addr, e);
+                continue;
+            }
+        };
 
         if message == DISCOVERY_MESSAGE {
-            info!("Received discovery ping from {}", src_addr);
-            let response = format!("{} {}", DISCOVERY_RESPONSE_PREFIX, main_server_bind_address);
-            socket.send_to(response.as_bytes(), src_addr).await?;
-            info!("Sent discovery response to {}: {}", src_addr, response);
+            if !failover.is_active() {
+                debug!("Ignored {} discovery ping from {}: this server is the failover standby.", kind, src_addr);
+                continue;
+            }
+            info!("Received {} discovery ping from {}", kind, src_addr);
+            let response = format!("{} {}", DISCOVERY_RESPONSE_PREFIX, network::advertised_address(main_server_addr, src_addr));
+            match socket.send_to(response.as_bytes(), src_addr).await {
+                Ok(_) => info!("Sent discovery response to {}: {}", src_addr, response),
+                Err(e) => error!("Failed to send discovery response to {}: {}", src_addr, e),
+            }
         } else {
-            warn!("Received unknown multicast message from {}: {}", src_addr, message);
+            warn!("Received unknown {} message from {}: {}", kind, src_addr, message);
+        }
+    }
+}
+
+// Forwards $SYS events to clients subscribed to the matching topic.
+async fn forward_sys_events(
+    socket: Arc<ServerSocket>,
+    subscribers: Subscribers,
+    mut sys_rx: broadcast::Receiver<SysEvent>,
+) {
+    loop {
+        match sys_rx.recv().await {
+            Ok(event) => {
+                let subs_to_notify: Vec<SocketAddr> = match subscribers.get(&event.topic) {
+                    Some(channel_set_ref) => channel_set_ref.value().iter().cloned().collect(),
+                    None => continue,
+                };
+                for (subscriber_addr, e) in socket.send_to_many(event.payload.as_bytes(), &subs_to_notify).await {
+                    error!("Failed to send {} event to {}: {}", event.topic, subscriber_addr, e);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("$SYS forwarder lagged behind, skipped {} events.", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+// Sets the global transpose from `+3`, `-12`, `0` or `reset`.
+fn handle_transpose_command(ctx: &ServerContext, payload: &str) {
+    if let Some(semitones) = parse_transpose(payload) {
+        ctx.midi.execute(move |handler| handler.set_transpose(semitones));
+    }
+}
+
+// `+3`, `-12` or `reset`
+fn parse_transpose(payload: &str) -> Option<i8> {
+    let value = payload.trim();
+    let semitones = if value.eq_ignore_ascii_case("reset") {
+        Some(0)
+    } else {
+        value.trim_start_matches('+').parse::<i8>().ok().filter(|s| (-48..=48).contains(s))
+    };
+    if semitones.is_none() {
+        warn!("Invalid transpose '{}'. Expected semitones between -48 and +48.", value);
+    }
+    semitones
+}
+
+// Sends the configured initialization sequence through the same path as a client PUB.
+async fn run_startup_publishes(publishes: &[StartupPublish], ctx: &ServerContext) {
+    if publishes.is_empty() {
+        return;
+    }
+    info!("Sending {} startup publish(es)...", publishes.len());
+    for publish in publishes {
+        if publish.topic.starts_with(SYS_TOPIC_PREFIX) {
+            warn!("Startup publish to reserved topic '{}' ignored.", publish.topic);
+            continue;
+        }
+        if ctx.sequencer.handle_publish(&publish.topic, &publish.payload) {
+            debug!("Handled startup sequencer command on '{}'", publish.topic);
+            continue;
+        }
+        if ctx.lfos.handle_publish(&publish.topic, &publish.payload) {
+            debug!("Handled startup LFO command on '{}'", publish.topic);
+            continue;
+        }
+        if publish.topic == CONTROL_TRANSPOSE_TOPIC {
+            handle_transpose_command(ctx, &publish.payload);
+            continue;
+        }
+        process_midi_actions(&publish.topic, &publish.payload, true, ctx, None);
+    }
+}
+
+// Resolves the local network address and binds the main socket.
+// On boot the network may not be up yet, so both steps are retried with backoff.
+// With IPv6 the socket takes the wildcard address, so it doesn't depend on one interface.
+// `reuse_port` lets the other receive loops bind the same address afterwards.
+async fn bind_main_socket(
+    retry: &StartupRetryConfig,
+    network_config: &NetworkConfig,
+    reuse_port: bool,
+    sys_events: &SysEvents,
+) -> Result<UdpSocket> {
+    let port: u16 = BIND_ADDRESS.split(':').next_back().and_then(|port| port.parse().ok()).unwrap_or(7878);
+    let max_attempts = retry.max_attempts.max(1);
+    let mut attempt = 1;
+
+    loop {
+        sys_events.emit(SYS_SERVER_STATUS, format!("binding (attempt {}/{})", attempt, max_attempts));
+        let bind_result = if network_config.ip_mode != IpMode::Ipv4 {
+            let wildcard = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port);
+            info!("Attempting to bind main server to: {} ({:?})", wildcard, network_config.ip_mode);
+            network::bind_udp(wildcard, network_config.ip_mode == IpMode::Ipv6, reuse_port)
+                .with_context(|| format!("Failed to bind main server to {}", wildcard))
+        } else {
+            match local_ip_address::local_ip() {
+                Ok(local_ip) => {
+                    let actual_bind_address = SocketAddr::new(local_ip, port);
+                    info!("Attempting to bind main server to: {}", actual_bind_address);
+                    network::bind_udp(actual_bind_address, false, reuse_port)
+                        .with_context(|| format!("Failed to bind main server to {}", actual_bind_address))
+                }
+                Err(e) if attempt >= max_attempts => {
+                    warn!("Could not get local IP address: {}. Defaulting to 127.0.0.1", e);
+                    let fallback_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+                    network::bind_udp(fallback_address, false, reuse_port)
+                        .with_context(|| format!("Failed to bind main server to {}", fallback_address))
+                }
+                Err(e) => Err(anyhow!("Could not get local IP address: {}", e)),
+            }
+        };
+
+        match bind_result {
+            Ok(socket) => return Ok(socket),
+            Err(e) if attempt < max_attempts => {
+                let delay = retry.delay_after_attempt(attempt);
+                warn!("Bind attempt {}/{} failed: {:?}. Retrying in {}ms.", attempt, max_attempts, e, delay.as_millis());
+                sys_events.emit(
+                    SYS_SERVER_STATUS,
+                    format!("bind failed, retrying in {}ms (attempt {}/{})", delay.as_millis(), attempt, max_attempts),
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                sys_events.emit(SYS_SERVER_STATUS, "bind failed");
+                return Err(e);
+            }
         }
     }
 }
@@ -241,56 +1496,240 @@ pub async fn run_multicast_discovery_listener(
 pub async fn run_server_application(
     runtime_handle: Handle,
     shutdown_rx: Receiver<()>,
-    midi_handler_arc: Arc<Mutex<MidiHandler>>, // Added midi_handler_arc
+    midi: MidiHandle,
+    services: AppServices,
 ) -> Result<()> {
+    let AppServices { config, sys_events, stats, zones, safe_mode, show_mode, event_store, hotkey_publishes } = services;
     info!("=================================================");
     info!("🚀 Starting SubPub UDP Server v0.1.0");
     info!("=================================================");
 
-    let local_ip = local_ip_address::local_ip().unwrap_or_else(|e| {
-        warn!("Could not get local IP address: {}. Defaulting to 127.0.0.1", e);
-        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
-    });
-    
-    let port_str = BIND_ADDRESS.split(':').last().unwrap_or("7878");
-    let actual_bind_address = format!("{}:{}", local_ip, port_str);
-
-    info!("Attempting to bind main server to: {}", actual_bind_address);
-
-    let socket = Arc::new(UdpSocket::bind(&actual_bind_address).await?);
-    let actual_addr = socket.local_addr()?;
+    failover::check_config(&config)?;
+    federation::check_config(&config)?;
+    let envelope = Envelope::from_config(&config.encryption)?;
+    let mut receive_loops = config.datagrams.effective_receive_loops();
+    if receive_loops > 1 && !network::REUSE_PORT_SUPPORTED {
+        warn!("[datagrams] receive_loops needs SO_REUSEPORT, which this platform doesn't have. Using one loop.");
+        receive_loops = 1;
+    }
+    let main_socket = bind_main_socket(&config.startup_retry, &config.network, receive_loops > 1, &sys_events).await?;
+    let actual_addr = main_socket.local_addr()?;
+    // The other loops' sockets share the address the first one got; the kernel spreads
+    // incoming datagrams over them by source address, so one client stays on one loop.
+    let mut sockets = vec![Arc::new(ServerSocket::new(main_socket, envelope.clone()))];
+    for _ in 1..receive_loops {
+        let socket = network::bind_udp(actual_addr, config.network.ip_mode == IpMode::Ipv6, true)
+            .with_context(|| format!("Failed to bind another receive socket to {}", actual_addr))?;
+        sockets.push(Arc::new(ServerSocket::new(socket, envelope.clone())));
+    }
+    let socket = sockets[0].clone();
     info!("✅ Main server successfully bound and listening on: {}", actual_addr);
+    if receive_loops > 1 {
+        info!("Receiving with {} parallel loops (SO_REUSEPORT).", receive_loops);
+    }
+    sys_events.emit(SYS_SERVER_STATUS, format!("listening on {}", actual_addr));
     info!("Awaiting incoming UDP messages...");
     info!("-------------------------------------------------");
 
-    let discovery_main_server_addr = actual_addr.to_string();
-    runtime_handle.spawn(async move {
-        if let Err(e) = run_multicast_discovery_listener(discovery_main_server_addr).await {
-            error!("Multicast discovery listener failed: {}", e);
-        }
-    });
+    let ip_filter = IpFilter::from_config(&config.ip_filter)?;
+    // Also starts mDNS, unless this server is a failover standby
+    let failover = Failover::new(&config.failover, &config.mdns, actual_addr);
+    // A standby that had taken over before a server restart closes the MIDI output again.
+    let standby = !failover.is_active();
+    midi.execute(move |handler| handler.set_output_suspended(standby));
+    let discovery_groups = network::discovery_groups(&config.discovery, config.network.ip_mode)?;
+    if discovery_groups.is_empty() {
+        info!("Multicast discovery is disabled.");
+    }
+    for group in discovery_groups {
+        let discovery_ip_filter = ip_filter.clone();
+        let discovery_envelope = envelope.clone();
+        let discovery_failover = failover.clone();
+        runtime_handle.spawn(async move {
+            if let Err(e) = run_multicast_discovery_listener(group, actual_addr, discovery_ip_filter, discovery_envelope, discovery_failover).await {
+                error!("Multicast discovery listener on {} failed: {}", group, e);
+            }
+        });
+    }
+    if let Some(port) = network::broadcast_discovery_port(&config.discovery, config.network.ip_mode)? {
+        let discovery_ip_filter = ip_filter.clone();
+        let discovery_envelope = envelope.clone();
+        let discovery_failover = failover.clone();
+        runtime_handle.spawn(async move {
+            if let Err(e) = run_broadcast_discovery_listener(port, actual_addr, discovery_ip_filter, discovery_envelope, discovery_failover).await {
+                error!("Broadcast discovery listener on port {} failed: {}", port, e);
+            }
+        });
+    }
 
     let subscribers: Subscribers = Arc::new(DashMap::new());
 
-    let server_loop_socket = socket.clone();
-    let server_loop_subscribers = subscribers.clone();
-    let server_loop_midi_handler = midi_handler_arc.clone(); // Clone for the server loop
-    let server_loop_runtime_handle = runtime_handle.clone(); // Clone for the server loop (for NoteOnOff)
-    
-    let server_task = runtime_handle.spawn(async move {
-        if let Err(e) = run_server_processing_loop(
-            server_loop_socket, 
-            server_loop_subscribers, 
-            server_loop_midi_handler,
-            server_loop_runtime_handle,
-        ).await {
-            error!("Server loop exited with error: {}", e);
-        }
-    });
+    let auth = if config.auth.enabled {
+        Some(auth::backend_from_config(&config.auth)?)
+    } else {
+        None
+    };
 
-    shutdown_rx.recv().context("Failed to receive shutdown signal")?;
-    info!("Shutdown signal received. Attempting to gracefully shut down server...");
-    server_task.abort();
+    let verifier = signing::verifier_from_config(&config.signing)?;
+    stats.attach_subscribers(Some(subscribers.clone()));
+
+    let sys_forward_task = runtime_handle.spawn(forward_sys_events(
+        socket.clone(),
+        subscribers.clone(),
+        sys_events.subscribe(),
+    ));
+
+    let sequences = midi.query(|handler| handler.get_sequences()).await?;
+    let sequencer = Arc::new(Sequencer::start(
+        sequences,
+        midi.clone(),
+        zones.clone(),
+        &runtime_handle,
+    ));
+    let lfo_configs = midi.query(|handler| handler.get_lfos()).await?;
+    let lfos = Arc::new(Lfos::start(
+        lfo_configs,
+        midi.clone(),
+        zones.clone(),
+        &runtime_handle,
+    ));
+
+    let loop_stats = stats.register_receive_loops(receive_loops);
+    let (session_replay, replay_rx, render_rx) = SessionReplay::new(&config.session_replay, show_mode.clone());
+    let (federation, federation_rx) = Federation::new(&config.federation);
+    let (websocket_bridge, websocket_bridge_rx) = WebSocketBridge::new(&config.websocket_bridge);
+    let ctx = ServerContext {
+        socket: socket.clone(),
+        subscribers: subscribers.clone(),
+        clients: Arc::new(ClientRegistry::default()),
+        delivery_limiter: Arc::new(DeliveryLimiter::default()),
+        compressor: Arc::new(PayloadCompressor::new(&config.compression)),
+        delayed_publishes: Arc::new(DelayedPublishes::new(&config.delayed_publish)),
+        pipe_bridge: PipeBridge::start(&config.pipe_bridge),
+        message_ids: MessageIds::load(&config.message_ids),
+        history: Arc::new(ChannelHistory::new(config.history.depth)),
+        channel_expiry: Arc::new(ChannelExpiry::new(&config.channel_expiry)),
+        transforms: Arc::new(PayloadTransforms::new(&config.transforms)),
+        federation,
+        websocket_bridge,
+        sacn: SacnOutput::start(&config.sacn),
+        event_store,
+        session_replay,
+        midi: midi.clone(),
+        runtime_handle: runtime_handle.clone(),
+        sequencer,
+        lfos,
+        auth,
+        failed_logins: FailedLogins::new(&config.auth),
+        verifier,
+        acl: Acl::from_config(&config.acl),
+        ip_filter,
+        rate_limiter: ClientRateLimiter::from_config(&config.rate_limit),
+        sequences: SequenceTracker::new(&config.sequence_numbers),
+        stats,
+        zones,
+        receive_loop: loop_stats[0].clone(),
+    };
+
+    subscription_store::restore(&config.persist_subscriptions, &ctx);
+    run_startup_publishes(&config.startup.publish, &ctx).await;
+
+    let http_api_task = http_api::spawn_if_enabled(
+        &runtime_handle,
+        &config.http_api,
+        HttpApiContext {
+            server: ctx.clone(),
+            safe_mode: safe_mode.clone(),
+            show_mode: show_mode.clone(),
+            webhooks: Arc::new(config.http_api.webhooks.clone()),
+            admin_token: config.http_api.admin_token.as_str().into(),
+        },
+    );
+
+    // Keepalives and subscriber liveness
+    let mut background_tasks = vec![sys_forward_task];
+    background_tasks.push(runtime_handle.spawn(session_replay::run_replayer(ctx.clone(), replay_rx)));
+    background_tasks.push(runtime_handle.spawn(session_replay::run_renderer(ctx.clone(), render_rx)));
+    background_tasks.push(runtime_handle.spawn(hotkeys::run_publisher(ctx.clone(), hotkey_publishes.subscribe())));
+    if let Some(every) = config.keepalive.effective_interval() {
+        background_tasks.push(runtime_handle.spawn(keepalive::run_keepalive_sender(ctx.clone(), every)));
+    }
+    if let Some(ttl) = config.keepalive.subscriber_ttl() {
+        background_tasks.push(runtime_handle.spawn(keepalive::run_subscriber_expiry(ctx.clone(), ttl)));
+    }
+    if ctx.channel_expiry.enabled() {
+        background_tasks.push(runtime_handle.spawn(channel_expiry::run_channel_expiry(ctx.clone())));
+    }
+    background_tasks.extend(http_api_task);
+    background_tasks.extend(pipe_bridge::spawn_input(&config.pipe_bridge, ctx.clone()));
+    background_tasks.extend(midi_input::spawn(&config.midi_input, ctx.clone()));
+    background_tasks.extend(serial_input::spawn(&config.serial_input, ctx.clone()));
+    background_tasks.extend(midi_mirror::spawn(&config.midi_mirror, ctx.clone()));
+    background_tasks.extend(federation::spawn(&config.federation, ctx.clone(), actual_addr, federation_rx));
+    background_tasks.extend(websocket_bridge::spawn(&config.websocket_bridge, ctx.clone(), websocket_bridge_rx));
+    background_tasks.extend(sacn::spawn(&config.sacn, ctx.clone()));
+    background_tasks.extend(scheduler::spawn(&config.scheduler, ctx.clone()));
+    background_tasks.extend(failover::spawn(&config.failover, failover.clone(), ctx.clone(), sys_events.clone()));
+    background_tasks.push(runtime_handle.spawn(safe_mode::run_alert_repeater(safe_mode)));
+    if config.persist_subscriptions.enabled {
+        background_tasks.push(runtime_handle.spawn(subscription_store::run_snapshots(
+            config.persist_subscriptions.clone(),
+            ctx.clone(),
+        )));
+    }
+    let ctx_for_shutdown = ctx.clone();
+    let datagrams = config.datagrams.clone();
+
+    // One supervised loop per socket, sharing everything else in the context.
+    let (loop_failed_tx, loop_failed_rx) = crossbeam_channel::bounded::<String>(1);
+    let mut server_tasks = Vec::new();
+    for (index, (loop_socket, counters)) in sockets.into_iter().zip(loop_stats).enumerate() {
+        let name = if receive_loops > 1 { format!("processing loop {}", index) } else { "processing loop".to_string() };
+        server_tasks.push(runtime_handle.spawn(watchdog::supervise_processing_loop(
+            ServerContext { socket: loop_socket, receive_loop: counters, ..ctx.clone() },
+            name,
+            datagrams.clone(),
+            config.watchdog.clone(),
+            sys_events.clone(),
+            loop_failed_tx.clone(),
+        )));
+    }
+
+    // Runs until the tray stops the server, or the watchdog gives up on a loop.
+    let failure = crossbeam_channel::select! {
+        recv(shutdown_rx) -> signal => {
+            signal.context("Failed to receive shutdown signal")?;
+            info!("Shutdown signal received. Attempting to gracefully shut down server...");
+            None
+        }
+        recv(loop_failed_rx) -> reason => {
+            let reason = reason.unwrap_or_else(|_| "processing loop stopped".to_string());
+            error!("Shutting down the server: {}.", reason);
+            Some(reason)
+        }
+    };
+    for task in server_tasks.into_iter().chain(background_tasks) {
+        task.abort();
+    }
+    // The aborted processing loops can't schedule more; release what is still sounding.
+    match midi.query(|handler| handler.flush_pending_note_offs()).await {
+        Ok(0) => {}
+        Ok(flushed) => info!("Sent {} pending NoteOff(s) before shutting down.", flushed),
+        Err(e) => error!("Failed to send pending NoteOffs before shutting down: {:?}", e),
+    }
+    failover.stop_mdns();
+    if config.persist_subscriptions.enabled {
+        match subscription_store::save(&config.persist_subscriptions, &ctx_for_shutdown) {
+            Ok(()) => info!("Saved subscriptions to '{}'.", config.persist_subscriptions.file),
+            Err(e) => error!("Failed to save subscriptions on shutdown: {:?}", e),
+        }
+    }
+    ctx_for_shutdown.stats.attach_subscribers(None);
+    if let Some(reason) = failure {
+        sys_events.emit(SYS_SERVER_STATUS, "failed");
+        return Err(anyhow!("Server {}", reason));
+    }
+    sys_events.emit(SYS_SERVER_STATUS, "stopped");
     info!("Server gracefully shut down.");
 
     Ok(())
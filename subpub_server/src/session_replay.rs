@@ -0,0 +1,328 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::Local;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::config::SessionReplayConfig;
+use crate::midi_file;
+use crate::midi_handler::MidiHandler;
+use crate::paths;
+use crate::server::{handle_publish, render_publish, ServerContext};
+use crate::show_mode::ShowMode;
+
+// `PUB:_control/record:start [file]` / `PUB:_control/record:stop`
+pub const CONTROL_RECORD_TOPIC: &str = "_control/record";
+// `PUB:_control/replay:<file> [speed]` / `PUB:_control/replay:stop`
+pub const CONTROL_REPLAY_TOPIC: &str = "_control/replay";
+// `PUB:_control/render:<file> <mapping file>`
+pub const CONTROL_RENDER_TOPIC: &str = "_control/render";
+
+// One recorded publish, a line of NDJSON in the recording file.
+#[derive(Serialize, Deserialize)]
+struct RecordedMessage {
+    // Milliseconds since the recording started
+    t_ms: u64,
+    channel: String,
+    payload: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source: Option<SocketAddr>,
+}
+
+struct Recording {
+    path: PathBuf,
+    started: Instant,
+    writer: BufWriter<File>,
+    messages: usize,
+}
+
+pub enum ReplayCommand {
+    Start { path: PathBuf, speed: f64 },
+    Stop,
+}
+
+// A recording to run through `mapping_path` into a MIDI file
+pub struct RenderRequest {
+    path: PathBuf,
+    mapping_path: PathBuf,
+}
+
+// Records incoming publishes to a file and plays recordings back through the normal
+// publish path (MIDI, subscribers, bridge), to rehearse an installation without its
+// sensors. A recording can also be re-rendered offline through a different mapping
+// file into a .mid file. Driven by the control topics above, so it works over UDP, the
+// HTTP API and as a startup publish alike.
+pub struct SessionReplay {
+    directory: PathBuf,
+    recording: Mutex<Option<Recording>>,
+    replay_tx: UnboundedSender<ReplayCommand>,
+    render_tx: UnboundedSender<RenderRequest>,
+    show_mode: Arc<ShowMode>,
+}
+
+// The topics `SessionReplay` handles, which are never recorded.
+pub fn is_control_topic(topic: &str) -> bool {
+    matches!(topic, CONTROL_RECORD_TOPIC | CONTROL_REPLAY_TOPIC | CONTROL_RENDER_TOPIC)
+}
+
+impl SessionReplay {
+    // The receivers go to `run_replayer` and `run_renderer`.
+    pub fn new(
+        config: &SessionReplayConfig,
+        show_mode: Arc<ShowMode>,
+    ) -> (Arc<Self>, UnboundedReceiver<ReplayCommand>, UnboundedReceiver<RenderRequest>) {
+        let (replay_tx, replay_rx) = unbounded_channel();
+        let (render_tx, render_rx) = unbounded_channel();
+        let directory = paths::data_path(&config.directory);
+        let session = Self { directory, recording: Mutex::new(None), replay_tx, render_tx, show_mode };
+        (Arc::new(session), replay_rx, render_rx)
+    }
+
+    // Handles the record/replay control topics. Returns true if `topic` was one of them.
+    pub fn handle_publish(&self, topic: &str, payload: &str) -> bool {
+        let mut words = payload.split_whitespace();
+        let command = words.next().unwrap_or("");
+        // Replays and renders stand in for the live show, so Show Mode locks starting
+        // them. Stopping a replay is always allowed.
+        if matches!(topic, CONTROL_REPLAY_TOPIC | CONTROL_RENDER_TOPIC)
+            && !command.eq_ignore_ascii_case("stop")
+            && let Err(e) = self.show_mode.authorize(topic, None)
+        {
+            warn!("Ignoring '{}': {:#}", payload, e);
+            return true;
+        }
+        match topic {
+            CONTROL_RECORD_TOPIC => {
+                let result = match command.to_lowercase().as_str() {
+                    "start" => self.start_recording(words.next()),
+                    "stop" => {
+                        self.stop_recording();
+                        Ok(())
+                    }
+                    _ => Err(anyhow!("expected 'start [file]' or 'stop'")),
+                };
+                if let Err(e) = result {
+                    warn!("Invalid record command '{}': {:#}", payload, e);
+                }
+                true
+            }
+            CONTROL_REPLAY_TOPIC => {
+                let result = if command.eq_ignore_ascii_case("stop") {
+                    let _ = self.replay_tx.send(ReplayCommand::Stop);
+                    Ok(())
+                } else {
+                    self.start_replay(command, words.next())
+                };
+                if let Err(e) = result {
+                    warn!("Invalid replay command '{}': {:#}", payload, e);
+                }
+                true
+            }
+            CONTROL_RENDER_TOPIC => {
+                if let Err(e) = self.start_render(command, words.next()) {
+                    warn!("Invalid render command '{}': {:#}", payload, e);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Appends a publish to the running recording, if there is one.
+    pub fn record(&self, channel: &str, payload: &str, source: Option<SocketAddr>) {
+        if is_control_topic(channel) {
+            return;
+        }
+        let mut recording = self.recording.lock().unwrap();
+        let Some(active) = recording.as_mut() else {
+            return;
+        };
+        let message = RecordedMessage {
+            t_ms: active.started.elapsed().as_millis() as u64,
+            channel: channel.to_string(),
+            payload: payload.to_string(),
+            source,
+        };
+        let line = serde_json::to_string(&message).unwrap_or_default();
+        // Flushed per line so a crash keeps everything up to that point.
+        let written = writeln!(active.writer, "{}", line).and_then(|_| active.writer.flush());
+        match written {
+            Ok(()) => active.messages += 1,
+            Err(e) => {
+                error!("Failed to write to recording {:?}: {}. Recording stopped.", active.path, e);
+                *recording = None;
+            }
+        }
+    }
+
+    fn start_recording(&self, file: Option<&str>) -> Result<()> {
+        let name = match file {
+            Some(name) => name.to_string(),
+            None => format!("session_{}.ndjson", Local::now().format("%Y%m%d_%H%M%S")),
+        };
+        let path = self.resolve(&name)?;
+        fs::create_dir_all(&self.directory)
+            .with_context(|| format!("Failed to create recordings directory {:?}", self.directory))?;
+        let file = File::create(&path).with_context(|| format!("Failed to create recording {:?}", path))?;
+        self.stop_recording();
+        info!("Recording publishes to {:?}", path);
+        *self.recording.lock().unwrap() =
+            Some(Recording { path, started: Instant::now(), writer: BufWriter::new(file), messages: 0 });
+        Ok(())
+    }
+
+    fn stop_recording(&self) {
+        if let Some(recording) = self.recording.lock().unwrap().take() {
+            info!("Stopped recording {:?} ({} messages)", recording.path, recording.messages);
+        }
+    }
+
+    fn start_replay(&self, file: &str, speed: Option<&str>) -> Result<()> {
+        if file.is_empty() {
+            bail!("expected '<file> [speed]' or 'stop'");
+        }
+        let speed = match speed {
+            Some(speed) => speed
+                .parse::<f64>()
+                .ok()
+                .filter(|speed| *speed > 0.0 && speed.is_finite())
+                .context("speed must be a positive number, e.g. 2 for double speed")?,
+            None => 1.0,
+        };
+        let path = self.resolve(file)?;
+        if !path.is_file() {
+            bail!("no recording at {:?}", path);
+        }
+        let _ = self.replay_tx.send(ReplayCommand::Start { path, speed });
+        Ok(())
+    }
+
+    fn start_render(&self, file: &str, mapping_file: Option<&str>) -> Result<()> {
+        let Some(mapping_file) = mapping_file.filter(|_| !file.is_empty()) else {
+            bail!("expected '<file> <mapping file>'");
+        };
+        let path = self.resolve(file)?;
+        if !path.is_file() {
+            bail!("no recording at {:?}", path);
+        }
+        // Alternative mapping files sit next to the live one, so their includes resolve the same way.
+        let mapping_path = resolve_in(paths::config_dir(), mapping_file)?;
+        if !mapping_path.is_file() {
+            bail!("no mapping file at {:?}", mapping_path);
+        }
+        let _ = self.render_tx.send(RenderRequest { path, mapping_path });
+        Ok(())
+    }
+
+    fn resolve(&self, name: &str) -> Result<PathBuf> {
+        resolve_in(&self.directory, name)
+    }
+}
+
+// Recordings live in the configured directory; names from the network can't leave it.
+fn resolve_in(directory: &Path, name: &str) -> Result<PathBuf> {
+    let plain_name = Path::new(name).file_name().is_some_and(|file_name| file_name == name);
+    if !plain_name || name.starts_with('.') {
+        bail!("'{}' must be a plain file name inside {:?}", name, directory);
+    }
+    Ok(directory.join(name))
+}
+
+fn load_recording(path: &Path) -> Result<Vec<RecordedMessage>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read recording {:?}", path))?;
+    let mut messages = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<RecordedMessage>(line) {
+            Ok(message) => messages.push(message),
+            Err(e) => warn!("Skipping line {} of recording {:?}: {}", index + 1, path, e),
+        }
+    }
+    Ok(messages)
+}
+
+// Plays recordings as they are requested. A new replay or `stop` ends the current one.
+// Replayed messages go through `handle_publish` without a publisher address.
+pub async fn run_replayer(ctx: ServerContext, mut commands: UnboundedReceiver<ReplayCommand>) {
+    let mut next_command = None;
+    loop {
+        let command = match next_command.take() {
+            Some(command) => command,
+            None => match commands.recv().await {
+                Some(command) => command,
+                None => return,
+            },
+        };
+        let ReplayCommand::Start { path, speed } = command else {
+            continue;
+        };
+        let messages = match load_recording(&path) {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("Failed to replay: {:?}", e);
+                continue;
+            }
+        };
+        info!("Replaying {} messages from {:?} at {}x speed", messages.len(), path, speed);
+        let started = Instant::now();
+        let mut replayed = 0;
+        for message in &messages {
+            let due = started + Duration::from_secs_f64(message.t_ms as f64 / 1000.0 / speed);
+            tokio::select! {
+                _ = sleep_until(due) => {}
+                command = commands.recv() => {
+                    next_command = command;
+                    break;
+                }
+            }
+            handle_publish(&ctx, None, &message.channel, &message.payload, None, None).await;
+            replayed += 1;
+        }
+        if replayed == messages.len() {
+            info!("Finished replaying {:?}", path);
+        } else {
+            info!("Stopped replaying {:?} after {} of {} messages", path, replayed, messages.len());
+        }
+    }
+}
+
+// Runs re-renders one after another, off the runtime's worker threads.
+pub async fn run_renderer(ctx: ServerContext, mut requests: UnboundedReceiver<RenderRequest>) {
+    while let Some(RenderRequest { path, mapping_path }) = requests.recv().await {
+        let render_ctx = ctx.clone();
+        match tokio::task::spawn_blocking(move || render(&render_ctx, &path, &mapping_path)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => error!("{:#}", e),
+            Err(e) => error!("The re-render stopped: {}", e),
+        }
+    }
+}
+
+// Runs a recording through the mappings in `mapping_path` on a MIDI handler of its own
+// and writes what they send to `<recording>_<mapping file>.mid` next to the recording.
+// The recording's timestamps are the clock, so it takes as long as the mappings need to
+// run, and the live handler, the port, subscribers and the other outputs never see it.
+fn render(ctx: &ServerContext, path: &Path, mapping_path: &Path) -> Result<()> {
+    let failed = || format!("Failed to re-render {:?} through {:?}", path, mapping_path);
+    let messages = load_recording(path).with_context(failed)?;
+    let mut handler = MidiHandler::for_render(mapping_path).with_context(failed)?;
+    info!("Re-rendering {} messages from {:?} through {:?}", messages.len(), path, mapping_path);
+    for message in &messages {
+        handler.advance_render(message.t_ms);
+        render_publish(&mut handler, &message.channel, &message.payload, ctx);
+    }
+    let events = handler.finish_render();
+    let stem = |path: &Path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let output = path.with_file_name(format!("{}_{}.mid", stem(path), stem(mapping_path)));
+    midi_file::write(&output, &events).with_context(failed)?;
+    info!("Wrote the re-render of {:?} ({} MIDI messages) to {:?}", path, events.len(), output);
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::auth::secrets_match;
+use crate::config::ShowModeConfig;
+
+// HTTP callers supply the override passphrase in this header.
+pub const PASSPHRASE_HEADER: &str = "X-Show-Mode-Passphrase";
+
+// Show Mode freezes the configuration so nothing can be changed by accident mid-performance.
+// While active, config reloads and destructive commands are refused unless
+// the caller supplies the override passphrase from the server config.
+pub struct ShowMode {
+    active: AtomicBool,
+    override_passphrase: Option<String>,
+}
+
+impl ShowMode {
+    pub fn new(config: &ShowModeConfig) -> Arc<Self> {
+        if config.enabled {
+            info!("Show Mode is enabled at startup. Configuration changes are locked.");
+        }
+        Arc::new(Self {
+            active: AtomicBool::new(config.enabled),
+            override_passphrase: config.override_passphrase.clone().filter(|p| !p.is_empty()),
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub fn set_active(&self, active: bool) {
+        let was_active = self.active.swap(active, Ordering::SeqCst);
+        if was_active != active {
+            if active {
+                info!("🔒 Show Mode enabled. Configuration changes are locked.");
+            } else {
+                info!("🔓 Show Mode disabled. Configuration changes are allowed.");
+            }
+        }
+    }
+
+    // Checks whether `action` may run right now.
+    // Always Ok outside of Show Mode; during Show Mode only with the correct override passphrase.
+    pub fn authorize(&self, action: &str, passphrase: Option<&str>) -> Result<()> {
+        if !self.is_active() {
+            return Ok(());
+        }
+        match (&self.override_passphrase, passphrase) {
+            (Some(expected), Some(given)) if secrets_match(given, expected) => {
+                warn!("Show Mode override passphrase accepted for '{}'.", action);
+                Ok(())
+            }
+            (_, Some(_)) => {
+                warn!("Show Mode override passphrase rejected for '{}'.", action);
+                bail!("'{}' is locked by Show Mode (invalid override passphrase)", action)
+            }
+            _ => bail!("'{}' is locked by Show Mode", action),
+        }
+    }
+
+    // `authorize` for tray actions: during Show Mode the user is asked for the override
+    // passphrase in a native dialog, if one is configured.
+    pub fn authorize_interactively(&self, action: &str) -> Result<()> {
+        if !self.is_active() || self.override_passphrase.is_none() {
+            return self.authorize(action, None);
+        }
+        let passphrase = prompt_passphrase(action);
+        self.authorize(action, passphrase.as_deref())
+    }
+}
+
+// Asks for the override passphrase with the platform's own dialog tool.
+// None when the dialog is cancelled or can't be shown.
+fn prompt_passphrase(action: &str) -> Option<String> {
+    let message = format!("'{}' is locked by Show Mode. Enter the override passphrase:", action);
+    let output = passphrase_dialog(&message)
+        .output()
+        .map_err(|e| warn!("Failed to ask for the Show Mode passphrase: {}", e))
+        .ok()?;
+    let passphrase = String::from_utf8_lossy(&output.stdout).trim_end_matches(['\r', '\n']).to_string();
+    (output.status.success() && !passphrase.is_empty()).then_some(passphrase)
+}
+
+#[cfg(target_os = "macos")]
+fn passphrase_dialog(message: &str) -> Command {
+    let script = format!(
+        "text returned of (display dialog \"{}\" default answer \"\" with hidden answer with title \"SubPub Show Mode\")",
+        message.replace('\\', "\\\\").replace('"', "\\\"")
+    );
+    let mut command = Command::new("osascript");
+    command.args(["-e", &script]);
+    command
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn passphrase_dialog(message: &str) -> Command {
+    let mut command = Command::new("zenity");
+    command.args(["--entry", "--hide-text", "--title", "SubPub Show Mode", "--text", message]);
+    command
+}
+
+#[cfg(windows)]
+fn passphrase_dialog(message: &str) -> Command {
+    let script = format!(
+        "Add-Type -AssemblyName Microsoft.VisualBasic; [Microsoft.VisualBasic.Interaction]::InputBox('{}', 'SubPub Show Mode')",
+        message.replace('\'', "''")
+    );
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-Command", &script]);
+    command
+}
@@ -0,0 +1,82 @@
+use anyhow::{anyhow, bail, Context, Result};
+use hmac::{Hmac, Mac};
+use log::info;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::SigningConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Envelope of a signed datagram: `SIG:<unix_ts>:<hex hmac>:<message>`
+const SIGNED_PREFIX: &str = "SIG";
+
+// Checks HMAC-SHA256 signatures made with the pre-shared key. The signature covers
+// `<unix_ts>:<message>`, and the timestamp must be within `max_skew_secs` of the server
+// clock, so a captured datagram only works for that long. Within that window each
+// signature is accepted once, so a captured datagram can't be sent again either.
+pub struct MessageVerifier {
+    key: Vec<u8>,
+    max_skew_secs: u64,
+    seen: Mutex<SeenSignatures>,
+}
+
+// Signatures accepted within the skew window, with their timestamps
+#[derive(Default)]
+struct SeenSignatures {
+    by_signature: HashMap<Vec<u8>, u64>,
+    // When expired entries were last dropped, so that happens at most once a second
+    pruned_at: u64,
+}
+
+pub fn verifier_from_config(config: &SigningConfig) -> Result<Option<Arc<MessageVerifier>>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    if config.key.is_empty() {
+        bail!("[signing] is enabled but `key` is empty");
+    }
+    info!("Message signing enabled. Unsigned datagrams are dropped.");
+    Ok(Some(Arc::new(MessageVerifier {
+        key: config.key.as_bytes().to_vec(),
+        max_skew_secs: config.max_skew_secs,
+        seen: Mutex::new(SeenSignatures::default()),
+    })))
+}
+
+impl MessageVerifier {
+    // Returns the message inside a correctly signed datagram.
+    pub fn verify<'a>(&self, datagram: &'a str) -> Result<&'a str> {
+        let mut parts = datagram.splitn(4, ':');
+        let (Some(SIGNED_PREFIX), Some(ts), Some(signature), Some(message)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("not signed");
+        };
+        let signature = hex::decode(signature).context("signature is not hex")?;
+        let mut mac = HmacSha256::new_from_slice(&self.key).map_err(|e| anyhow!("invalid key: {}", e))?;
+        mac.update(ts.as_bytes());
+        mac.update(b":");
+        mac.update(message.as_bytes());
+        // Constant-time comparison
+        mac.verify_slice(&signature).map_err(|_| anyhow!("bad signature"))?;
+
+        let ts: u64 = ts.parse().context("timestamp is not a number")?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        if now.abs_diff(ts) > self.max_skew_secs {
+            bail!("timestamp is {}s off the server clock", now.abs_diff(ts));
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        if seen.pruned_at != now {
+            seen.by_signature.retain(|_, seen_ts| now.abs_diff(*seen_ts) <= self.max_skew_secs);
+            seen.pruned_at = now;
+        }
+        if seen.by_signature.insert(signature, ts).is_some() {
+            bail!("replayed signature");
+        }
+        Ok(message)
+    }
+}
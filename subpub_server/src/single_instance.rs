@@ -0,0 +1,92 @@
+use crossbeam_channel::{unbounded, Receiver};
+use log::{info, warn};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Loopback port held by the running instance. Binding it is the lock; connecting to it
+// forwards a request to the instance that holds it.
+const INSTANCE_ADDRESS: &str = "127.0.0.1:47878";
+const SHOW_STATUS_REQUEST: &str = "SHOW_STATUS";
+// Followed by a space and the subpub:// link a later launch was started with
+const OPEN_URL_REQUEST: &str = "OPEN_URL";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+// What a later launch asks the running instance to do.
+pub enum Request {
+    // Answered by the tray with a notification
+    ShowStatus,
+    // A subpub:// link to publish, see url_scheme.rs
+    OpenUrl(String),
+}
+
+pub enum Instance {
+    // This is the only instance. The receiver yields the requests from later launches.
+    Primary(Receiver<Request>),
+    // Another instance is running and has been asked to show its status, or to open
+    // the link this launch was started with.
+    AlreadyRunning,
+}
+
+// Makes sure only one tray app runs at a time. A second launch would add a second tray
+// icon and then fail to bind the server port, so it hands over to the first one instead.
+pub fn acquire(launch_url: Option<&str>) -> Instance {
+    match TcpListener::bind(INSTANCE_ADDRESS) {
+        Ok(listener) => {
+            let (request_tx, request_rx) = unbounded();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(mut stream) = stream else { continue };
+                    let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+                    let mut request = String::new();
+                    if BufReader::new(&stream).read_line(&mut request).is_err() {
+                        continue;
+                    }
+                    let request = match request.trim().split_once(' ') {
+                        None if request.trim() == SHOW_STATUS_REQUEST => {
+                            info!("Another instance was launched; showing status instead.");
+                            Request::ShowStatus
+                        }
+                        Some((OPEN_URL_REQUEST, url)) => Request::OpenUrl(url.to_string()),
+                        _ => continue,
+                    };
+                    let _ = request_tx.send(request);
+                    let _ = stream.write_all(b"OK\n");
+                }
+            });
+            Instance::Primary(request_rx)
+        }
+        Err(e) if e.kind() == ErrorKind::AddrInUse => match send_request(launch_url) {
+            Ok(()) => Instance::AlreadyRunning,
+            Err(e) => {
+                // Something else owns the port. Better to risk a second instance than
+                // to refuse to start at all.
+                warn!("Port {} is taken but no SubPub Server answered ({}). Starting anyway.", INSTANCE_ADDRESS, e);
+                Instance::Primary(unbounded().1)
+            }
+        },
+        Err(e) => {
+            warn!("Failed to set up the single-instance lock on {}: {}. Starting anyway.", INSTANCE_ADDRESS, e);
+            Instance::Primary(unbounded().1)
+        }
+    }
+}
+
+fn send_request(launch_url: Option<&str>) -> std::io::Result<()> {
+    let addr = INSTANCE_ADDRESS.parse().expect("valid instance address");
+    let mut stream = TcpStream::connect_timeout(&addr, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = match launch_url {
+        Some(url) => format!("{} {}\n", OPEN_URL_REQUEST, url.trim()),
+        None => format!("{}\n", SHOW_STATUS_REQUEST),
+    };
+    stream.write_all(request.as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)?;
+    if reply.trim() == "OK" {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(ErrorKind::InvalidData, format!("unexpected reply {:?}", reply.trim())))
+    }
+}
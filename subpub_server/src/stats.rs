@@ -0,0 +1,290 @@
+use dashmap::DashMap;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::client_stats::ClientStats;
+use crate::recent_events::RecentEvents;
+use crate::server::Subscribers;
+
+// Counters for a single MIDI output port.
+#[derive(Debug, Clone, Default)]
+pub struct MidiOutputStats {
+    pub messages_sent: u64,
+    pub last_activity: Option<Instant>,
+}
+
+// Trigger counters for a single mapping entry, keyed by its sub_topic.
+#[derive(Debug, Clone, Default)]
+pub struct MappingTriggerStats {
+    pub count: u64,
+    pub last_triggered: Option<SystemTime>,
+    // Triggers whose JSON payload was malformed, see `on_payload_error`
+    pub malformed_payloads: u64,
+    // Publishes rejected by the mapping's `payload_schema`
+    pub invalid_payloads: u64,
+}
+
+// Fanout timings for one channel: how long sending a publish to its subscribers took.
+#[derive(Debug, Clone, Default)]
+pub struct FanoutStats {
+    pub publishes: u64,
+    pub datagrams: u64,
+    pub total: Duration,
+    pub max: Duration,
+    pub last: Duration,
+}
+
+// Counters for one receive loop (see `[datagrams] receive_loops`). Each loop holds its
+// own, so the hot path doesn't share a cache line or lock with the other loops.
+#[derive(Debug, Default)]
+pub struct ReceiveLoopStats {
+    datagrams_received: AtomicU64,
+    messages_processed: AtomicU64,
+}
+
+impl ReceiveLoopStats {
+    pub fn record_datagram(&self) {
+        self.datagrams_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_message_processed(&self) {
+        self.messages_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn datagrams_received(&self) -> u64 {
+        self.datagrams_received.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_processed(&self) -> u64 {
+        self.messages_processed.load(Ordering::Relaxed)
+    }
+}
+
+// Central stats collector shared by the MIDI handler, the server and the tray.
+pub struct Stats {
+    midi_outputs: DashMap<String, MidiOutputStats>,
+    mapping_triggers: DashMap<String, MappingTriggerStats>,
+    recent_events: RecentEvents,
+    clients: ClientStats,
+    // Client messages that made it to the action dispatch
+    messages_processed: AtomicU64,
+    started: Instant,
+    // Subscriptions of the running server, None while it is stopped
+    subscribers: Mutex<Option<Subscribers>>,
+    // One entry per receive loop of the running (or last) server run
+    receive_loops: Mutex<Vec<Arc<ReceiveLoopStats>>>,
+    fanout: DashMap<String, FanoutStats>,
+    // Queue to the MIDI thread, see midi_actor.rs
+    midi_queue_depth: AtomicU64,
+    midi_queue_dropped: AtomicU64,
+    midi_queue_coalesced: AtomicU64,
+    // Datagrams sent compressed, and what that saved, see compression.rs
+    compressed_datagrams: AtomicU64,
+    compression_saved_bytes: AtomicU64,
+}
+
+impl Stats {
+    pub fn new(recent_events_capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            midi_outputs: DashMap::new(),
+            mapping_triggers: DashMap::new(),
+            recent_events: RecentEvents::new(recent_events_capacity),
+            clients: ClientStats::default(),
+            messages_processed: AtomicU64::new(0),
+            started: Instant::now(),
+            subscribers: Mutex::new(None),
+            receive_loops: Mutex::new(Vec::new()),
+            fanout: DashMap::new(),
+            midi_queue_depth: AtomicU64::new(0),
+            midi_queue_dropped: AtomicU64::new(0),
+            midi_queue_coalesced: AtomicU64::new(0),
+            compressed_datagrams: AtomicU64::new(0),
+            compression_saved_bytes: AtomicU64::new(0),
+        })
+    }
+
+    // Ring buffer of the last pub/sub and MIDI events
+    pub fn recent_events(&self) -> &RecentEvents {
+        &self.recent_events
+    }
+
+    // Counters per client address
+    pub fn clients(&self) -> &ClientStats {
+        &self.clients
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn record_message_processed(&self) {
+        self.messages_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn messages_processed(&self) -> u64 {
+        self.messages_processed.load(Ordering::Relaxed)
+    }
+
+    // Counters for `count` receive loops, called when the server starts. Loops that
+    // existed in an earlier run keep their counts, like the other counters.
+    pub fn register_receive_loops(&self, count: usize) -> Vec<Arc<ReceiveLoopStats>> {
+        let mut loops = self.receive_loops.lock().unwrap();
+        loops.truncate(count);
+        while loops.len() < count {
+            loops.push(Arc::new(ReceiveLoopStats::default()));
+        }
+        loops.clone()
+    }
+
+    // (datagrams received, messages processed) per receive loop, in loop order.
+    pub fn receive_loops_snapshot(&self) -> Vec<(u64, u64)> {
+        self.receive_loops
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|counters| (counters.datagrams_received(), counters.messages_processed()))
+            .collect()
+    }
+
+    // A publish on `channel` went out to `subscribers` subscribers in `elapsed`. The
+    // channel name is only copied the first time, this runs for every publish.
+    pub fn record_fanout(&self, channel: &str, subscribers: usize, elapsed: Duration) {
+        let update = |entry: &mut FanoutStats| {
+            entry.publishes += 1;
+            entry.datagrams += subscribers as u64;
+            entry.total += elapsed;
+            entry.max = entry.max.max(elapsed);
+            entry.last = elapsed;
+        };
+        match self.fanout.get_mut(channel) {
+            Some(mut entry) => update(&mut entry),
+            None => update(&mut self.fanout.entry(channel.to_string()).or_default()),
+        }
+    }
+
+    // `datagrams` compressed copies went out, each `saved_bytes` shorter than the payload.
+    pub fn record_compressed(&self, datagrams: usize, saved_bytes: usize) {
+        self.compressed_datagrams.fetch_add(datagrams as u64, Ordering::Relaxed);
+        self.compression_saved_bytes.fetch_add((datagrams * saved_bytes) as u64, Ordering::Relaxed);
+    }
+
+    // (compressed datagrams sent, bytes saved)
+    pub fn compression_counts(&self) -> (u64, u64) {
+        (self.compressed_datagrams.load(Ordering::Relaxed), self.compression_saved_bytes.load(Ordering::Relaxed))
+    }
+
+    // The channel expired (see channel_expiry.rs), its timings go with it.
+    pub fn forget_channel(&self, channel: &str) {
+        self.fanout.remove(channel);
+    }
+
+    // Snapshot of the fanout timings, sorted by channel.
+    pub fn fanout_snapshot(&self) -> Vec<(String, FanoutStats)> {
+        let mut snapshot: Vec<(String, FanoutStats)> =
+            self.fanout.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot
+    }
+
+    // Called by the server when it starts and stops, so the tray can count subscriptions.
+    pub fn attach_subscribers(&self, subscribers: Option<Subscribers>) {
+        *self.subscribers.lock().unwrap() = subscribers;
+    }
+
+    // (channels with at least one subscriber, distinct subscribed clients), zero while stopped
+    pub fn subscription_counts(&self) -> (usize, usize) {
+        let Some(subscribers) = self.subscribers.lock().unwrap().clone() else {
+            return (0, 0);
+        };
+        let mut channels = 0;
+        let mut clients = HashSet::new();
+        for entry in subscribers.iter() {
+            if !entry.value().is_empty() {
+                channels += 1;
+                clients.extend(entry.value().iter().copied());
+            }
+        }
+        (channels, clients.len())
+    }
+
+    pub fn set_midi_queue_depth(&self, depth: usize) {
+        self.midi_queue_depth.store(depth as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_midi_queue_dropped(&self) {
+        self.midi_queue_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_midi_queue_coalesced(&self) {
+        self.midi_queue_coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // (queued actions, dropped so far, coalesced so far)
+    pub fn midi_queue_counts(&self) -> (u64, u64, u64) {
+        (
+            self.midi_queue_depth.load(Ordering::Relaxed),
+            self.midi_queue_dropped.load(Ordering::Relaxed),
+            self.midi_queue_coalesced.load(Ordering::Relaxed),
+        )
+    }
+
+    // MIDI messages sent on all outputs together
+    pub fn midi_messages_sent(&self) -> u64 {
+        self.midi_outputs.iter().map(|entry| entry.value().messages_sent).sum()
+    }
+
+    // Makes an output show up (with zero counters) before it has sent anything.
+    pub fn register_midi_output(&self, output: &str) {
+        self.midi_outputs.entry(output.to_string()).or_default();
+    }
+
+    pub fn record_midi_sent(&self, output: &str) {
+        let mut entry = self.midi_outputs.entry(output.to_string()).or_default();
+        entry.messages_sent += 1;
+        entry.last_activity = Some(Instant::now());
+    }
+
+    // Snapshot of all MIDI output counters, sorted by output name.
+    pub fn midi_outputs_snapshot(&self) -> Vec<(String, MidiOutputStats)> {
+        let mut snapshot: Vec<(String, MidiOutputStats)> = self
+            .midi_outputs
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot
+    }
+
+    // Makes a mapping show up with a zero count before it has fired.
+    // Counters survive mapping reloads so a whole show can be verified afterwards.
+    pub fn register_mapping(&self, sub_topic: &str) {
+        self.mapping_triggers.entry(sub_topic.to_string()).or_default();
+    }
+
+    pub fn record_mapping_trigger(&self, sub_topic: &str) {
+        let mut entry = self.mapping_triggers.entry(sub_topic.to_string()).or_default();
+        entry.count += 1;
+        entry.last_triggered = Some(SystemTime::now());
+    }
+
+    pub fn record_malformed_payload(&self, sub_topic: &str) {
+        self.mapping_triggers.entry(sub_topic.to_string()).or_default().malformed_payloads += 1;
+    }
+
+    pub fn record_invalid_payload(&self, sub_topic: &str) {
+        self.mapping_triggers.entry(sub_topic.to_string()).or_default().invalid_payloads += 1;
+    }
+
+    // Snapshot of all mapping trigger counters, sorted by topic.
+    pub fn mapping_triggers_snapshot(&self) -> Vec<(String, MappingTriggerStats)> {
+        let mut snapshot: Vec<(String, MappingTriggerStats)> = self
+            .mapping_triggers
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::config::PersistSubscriptionsConfig;
+use crate::paths;
+use crate::server::ServerContext;
+
+#[derive(Serialize, Deserialize)]
+struct SavedSubscription {
+    channel: String,
+    client: SocketAddr,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_hz: Option<f64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    compress: bool,
+}
+
+// Contents of the subscriptions file.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    saved_at_unix: u64,
+    subscriptions: Vec<SavedSubscription>,
+}
+
+fn snapshot_of(ctx: &ServerContext) -> Vec<SavedSubscription> {
+    let mut subscriptions: Vec<SavedSubscription> = ctx
+        .subscribers
+        .iter()
+        .flat_map(|entry| {
+            let channel = entry.key().clone();
+            entry
+                .value()
+                .iter()
+                .map(|addr| SavedSubscription {
+                    channel: channel.clone(),
+                    client: *addr,
+                    max_hz: ctx.delivery_limiter.max_hz(&channel, *addr),
+                    compress: ctx.compressor.requested(&channel, *addr),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    // Stable order, so an unchanged map produces an unchanged file.
+    subscriptions.sort_by(|a, b| (&a.channel, a.client).cmp(&(&b.channel, b.client)));
+    subscriptions
+}
+
+// Puts the subscriptions from the last run back. Restored clients count as just seen,
+// so the keepalive TTL gives them a full period to show up again.
+pub fn restore(config: &PersistSubscriptionsConfig, ctx: &ServerContext) {
+    if !config.enabled {
+        return;
+    }
+    let path = paths::data_path(&config.file);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(_) => {
+            info!("No saved subscriptions at {:?}.", path);
+            return;
+        }
+    };
+    let snapshot: Snapshot = match serde_json::from_str(&text) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            warn!("Ignoring unreadable subscriptions file {:?}: {}", path, e);
+            return;
+        }
+    };
+    for saved in &snapshot.subscriptions {
+        ctx.subscribers.entry(saved.channel.clone()).or_default().value_mut().insert(saved.client);
+        ctx.delivery_limiter.set_limit(&saved.channel, saved.client, saved.max_hz);
+        ctx.compressor.set_requested(&saved.channel, saved.client, saved.compress);
+        ctx.clients.touch(saved.client);
+    }
+    info!(
+        "Restored {} subscription(s) from {:?} (saved at unix {}).",
+        snapshot.subscriptions.len(),
+        path,
+        snapshot.saved_at_unix
+    );
+}
+
+// Writes the current subscriptions, replacing the file in one step so a crash mid-write
+// never leaves a truncated file behind.
+pub fn save(config: &PersistSubscriptionsConfig, ctx: &ServerContext) -> Result<()> {
+    let snapshot = Snapshot {
+        saved_at_unix: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        subscriptions: snapshot_of(ctx),
+    };
+    let text = serde_json::to_string_pretty(&snapshot)?;
+    let path = paths::data_path(&config.file);
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, text).with_context(|| format!("Failed to write {:?}", tmp_path))?;
+    fs::rename(&tmp_path, &path).with_context(|| format!("Failed to replace {:?}", path))?;
+    Ok(())
+}
+
+// Saves the subscriptions every `snapshot_interval_ms`, but only when they changed.
+pub async fn run_snapshots(config: PersistSubscriptionsConfig, ctx: ServerContext) {
+    info!("Saving subscriptions to {:?} every {}ms", paths::data_path(&config.file), config.snapshot_interval_ms);
+    let mut ticker = interval(Duration::from_millis(config.snapshot_interval_ms.max(100)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_saved = None;
+    loop {
+        ticker.tick().await;
+        let current = serde_json::to_string(&snapshot_of(&ctx)).ok();
+        if current == last_saved {
+            continue;
+        }
+        match save(&config, &ctx) {
+            Ok(()) => last_saved = current,
+            Err(e) => error!("Failed to save subscriptions: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use log::debug;
+use tokio::sync::broadcast;
+
+// Prefix for all server-generated status topics, e.g. `$SYS/midi/status`.
+pub const SYS_TOPIC_PREFIX: &str = "$SYS/";
+pub const SYS_MIDI_STATUS: &str = "$SYS/midi/status";
+pub const SYS_SERVER_STATUS: &str = "$SYS/server/status";
+pub const SYS_ALERT: &str = "$SYS/alert";
+pub const SYS_MAPPINGS_STATUS: &str = "$SYS/mappings/status";
+pub const SYS_FAILOVER_HEARTBEAT: &str = "$SYS/failover/heartbeat";
+pub const SYS_FAILOVER_STATUS: &str = "$SYS/failover/status";
+
+const SYS_EVENT_CAPACITY: usize = 64;
+
+// A status event produced by the server itself rather than by a client.
+#[derive(Debug, Clone)]
+pub struct SysEvent {
+    pub topic: String,
+    pub payload: String,
+}
+
+// Cheap clonable broadcaster for $SYS events.
+// Consumers (tray tooltip, server forwarding to subscribers) each get their own receiver.
+#[derive(Clone)]
+pub struct SysEvents {
+    tx: broadcast::Sender<SysEvent>,
+}
+
+impl SysEvents {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(SYS_EVENT_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn emit(&self, topic: &str, payload: impl Into<String>) {
+        let event = SysEvent { topic: topic.to_string(), payload: payload.into() };
+        debug!("$SYS event {}: {}", event.topic, event.payload);
+        // An error only means nobody is listening right now, which is fine.
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SysEvent> {
+        self.tx.subscribe()
+    }
+}
+
+impl Default for SysEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
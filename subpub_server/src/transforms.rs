@@ -0,0 +1,34 @@
+use log::warn;
+use std::borrow::Cow;
+
+use crate::config::TransformConfig;
+use crate::normalizer;
+use crate::server::topic_matches;
+
+// Per-channel normalizer chains run on payloads before they go out to subscribers, so
+// publishers with different formats reach downstream clients in one schema. Mappings,
+// history and the event log still see the payload as it was published.
+pub struct PayloadTransforms {
+    transforms: Vec<TransformConfig>,
+}
+
+impl PayloadTransforms {
+    pub fn new(transforms: &[TransformConfig]) -> Self {
+        Self { transforms: transforms.to_vec() }
+    }
+
+    // The payload subscribers of `channel` get. The first matching transform applies; if it
+    // fails the payload is forwarded as published.
+    pub fn apply<'a>(&self, channel: &str, payload: &'a str) -> Cow<'a, str> {
+        let Some(transform) = self.transforms.iter().find(|t| topic_matches(&t.channel, channel)) else {
+            return Cow::Borrowed(payload);
+        };
+        match normalizer::apply_chain(&transform.chain, payload) {
+            Ok(transformed) => Cow::Owned(transformed),
+            Err(e) => {
+                warn!(topic = channel; "Failed to transform payload on '{}': {:?}. Forwarding it as published.", channel, e);
+                Cow::Borrowed(payload)
+            }
+        }
+    }
+}
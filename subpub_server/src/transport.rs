@@ -0,0 +1,138 @@
+use anyhow::{anyhow, bail, Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use log::{debug, info};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+use crate::config::EncryptionConfig;
+use crate::fanout;
+use crate::network::canonical;
+
+const NONCE_LEN: usize = 24;
+
+// XChaCha20-Poly1305 envelope: `<24-byte random nonce><ciphertext + 16-byte tag>`.
+// Datagrams that don't decrypt with the shared key are dropped, so they can neither be
+// read nor forged without it.
+pub struct Envelope {
+    cipher: XChaCha20Poly1305,
+}
+
+impl Envelope {
+    pub fn from_config(config: &EncryptionConfig) -> Result<Option<Arc<Self>>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let key = hex::decode(config.key.trim()).context("[encryption] key must be hex")?;
+        if key.len() != 32 {
+            bail!("[encryption] key must be 32 bytes (64 hex characters), got {} bytes", key.len());
+        }
+        let cipher = XChaCha20Poly1305::new_from_slice(&key).map_err(|e| anyhow!("invalid key: {}", e))?;
+        info!("Encrypted transport enabled. Plain datagrams are dropped.");
+        Ok(Some(Arc::new(Self { cipher })))
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .map_err(|_| io::Error::other("encryption failed"))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    fn open(&self, datagram: &[u8]) -> Option<Vec<u8>> {
+        if datagram.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = datagram.split_at(NONCE_LEN);
+        self.cipher.decrypt(XNonce::from_slice(nonce), ciphertext).ok()
+    }
+}
+
+// A UDP socket that seals and opens datagrams when encryption is enabled, and is a plain
+// socket otherwise. Used for the main server socket and discovery. On an IPv6 socket,
+// IPv4 peers are reported and addressed as plain IPv4 (see `network::canonical`).
+pub struct ServerSocket {
+    socket: UdpSocket,
+    envelope: Option<Arc<Envelope>>,
+    ipv6: bool,
+}
+
+impl ServerSocket {
+    pub fn new(socket: UdpSocket, envelope: Option<Arc<Envelope>>) -> Self {
+        let ipv6 = socket.local_addr().is_ok_and(|addr| addr.is_ipv6());
+        Self { socket, envelope, ipv6 }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn socket_addr(&self, target: SocketAddr) -> SocketAddr {
+        match target {
+            SocketAddr::V4(v4) if self.ipv6 => SocketAddr::new(IpAddr::V6(v4.ip().to_ipv6_mapped()), v4.port()),
+            target => target,
+        }
+    }
+
+    pub async fn send_to(&self, data: &[u8], target: SocketAddr) -> io::Result<usize> {
+        let target = self.socket_addr(target);
+        match &self.envelope {
+            Some(envelope) => self.socket.send_to(&envelope.seal(data)?, target).await,
+            None => self.socket.send_to(data, target).await,
+        }
+    }
+
+    // Sends `data` to every target in one batch (see fanout.rs). Returns the targets that
+    // failed. With encryption each target still gets its own nonce.
+    pub async fn send_to_many(&self, data: &[u8], targets: &[SocketAddr]) -> Vec<(SocketAddr, io::Error)> {
+        let mut failures = Vec::new();
+        let sealed: Vec<(Vec<u8>, SocketAddr)> = match &self.envelope {
+            Some(envelope) => targets
+                .iter()
+                .filter_map(|target| match envelope.seal(data) {
+                    Ok(sealed) => Some((sealed, *target)),
+                    Err(e) => {
+                        failures.push((*target, e));
+                        None
+                    }
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let datagrams: Vec<(&[u8], SocketAddr)> = match &self.envelope {
+            Some(_) => sealed.iter().map(|(sealed, target)| (sealed.as_slice(), self.socket_addr(*target))).collect(),
+            None => targets.iter().map(|target| (data, self.socket_addr(*target))).collect(),
+        };
+        // Reported with the address the caller passed in, not the mapped one.
+        failures.extend(fanout::send_batch(&self.socket, &datagrams).await.into_iter().map(|(target, e)| (canonical(target), e)));
+        failures
+    }
+
+    // Waits for the next datagram that decrypts (any datagram without encryption) and
+    // copies its plaintext into `buf`.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let Some(envelope) = &self.envelope else {
+            let (len, addr) = self.socket.recv_from(buf).await?;
+            return Ok((len, canonical(addr)));
+        };
+        let mut sealed = vec![0; buf.len() + NONCE_LEN + 16];
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut sealed).await?;
+            let addr = canonical(addr);
+            match envelope.open(&sealed[..len]) {
+                Some(plaintext) => {
+                    let len = plaintext.len().min(buf.len());
+                    buf[..len].copy_from_slice(&plaintext[..len]);
+                    return Ok((len, addr));
+                }
+                None => debug!("Dropped datagram from {} that doesn't decrypt", addr),
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::config::UrlHandlerConfig;
+use crate::http_api::{percent_decode, query_param};
+use crate::server::topic_matches;
+use crate::sys_events::SYS_TOPIC_PREFIX;
+
+// subpub://pub/<channel>?payload=<payload> links publish on this machine, so macOS
+// Shortcuts, Stream Deck "Open URL" actions and QR codes can fire cues without a client.
+// Windows and Linux start the app with the link as its argument; a second launch hands
+// it to the running instance (see single_instance.rs). macOS delivers it to the running
+// app as an event, given the app bundle declares the scheme in its Info.plist.
+const URL_SCHEME: &str = "subpub";
+
+#[derive(Debug, Clone)]
+pub struct UrlPublish {
+    pub topic: String,
+    pub payload: String,
+}
+
+// The channel may contain slashes and %-escapes ("subpub://pub/cues/go%202?payload=1").
+// Without `payload` an empty payload is published.
+pub fn parse(url: &str, config: &UrlHandlerConfig) -> Result<UrlPublish> {
+    let rest = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case(URL_SCHEME) => rest,
+        _ => bail!("Not a {}:// link", URL_SCHEME),
+    };
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let channel = path.strip_prefix("pub/").context("Only subpub://pub/<channel> links are supported")?;
+    let topic = percent_decode(channel.trim_end_matches('/'));
+    if topic.is_empty() {
+        bail!("The link has no channel");
+    }
+    if topic.starts_with(SYS_TOPIC_PREFIX) {
+        bail!("'{}' is a reserved channel", topic);
+    }
+    if !config.channels.iter().any(|pattern| topic_matches(pattern, &topic)) {
+        bail!("'{}' is not one of the [url_handler] channels", topic);
+    }
+    let payload = query_param(query, "payload").unwrap_or_default();
+    Ok(UrlPublish { topic, payload })
+}
+
+// The subpub:// link the app was started with, if any.
+pub fn launch_url(args: &[String]) -> Option<String> {
+    let prefix = format!("{}://", URL_SCHEME);
+    args.iter().skip(1).find(|arg| arg.to_ascii_lowercase().starts_with(&prefix)).cloned()
+}
+
+// Makes this executable the handler for subpub:// links, for the current user.
+//   macOS    nothing to do at runtime: CFBundleURLTypes in the app bundle's Info.plist
+//   Linux    ~/.local/share/applications/subpub-url-handler.desktop, set as default with xdg-mime
+//   Windows  HKCU\Software\Classes\subpub
+#[cfg(target_os = "macos")]
+pub fn register() -> Result<()> {
+    info!("subpub:// links reach the app if its bundle declares the scheme in Info.plist (CFBundleURLTypes).");
+    Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+pub fn register() -> Result<()> {
+    use std::path::PathBuf;
+
+    const DESKTOP_FILE_NAME: &str = "subpub-url-handler.desktop";
+    let exe = std::env::current_exe().context("Failed to find the application executable")?;
+    let data_dir = match std::env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").context("HOME is not set")?).join(".local/share"),
+    };
+    let applications = data_dir.join("applications");
+    std::fs::create_dir_all(&applications).with_context(|| format!("Failed to create {:?}", applications))?;
+    let path = applications.join(DESKTOP_FILE_NAME);
+    let contents = format!(
+        "[Desktop Entry]\nType=Application\nName=SubPub Server\nExec=\"{}\" %u\nNoDisplay=true\nMimeType=x-scheme-handler/{};\n",
+        exe.display(),
+        URL_SCHEME
+    );
+    std::fs::write(&path, contents).with_context(|| format!("Failed to write {:?}", path))?;
+    let status = std::process::Command::new("xdg-mime")
+        .args(["default", DESKTOP_FILE_NAME, &format!("x-scheme-handler/{}", URL_SCHEME)])
+        .status()
+        .context("Failed to run xdg-mime")?;
+    if !status.success() {
+        bail!("xdg-mime could not make {} the handler for {}:// links", DESKTOP_FILE_NAME, URL_SCHEME);
+    }
+    info!("Registered the handler for {}:// links ({:?}).", URL_SCHEME, path);
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn register() -> Result<()> {
+    use crate::launch_at_login::reg;
+
+    let exe = std::env::current_exe().context("Failed to find the application executable")?;
+    let key = format!(r"HKCU\Software\Classes\{}", URL_SCHEME);
+    let command_key = format!(r"{}\shell\open\command", key);
+    let command = format!("\"{}\" \"%1\"", exe.display());
+    let added = reg(&["add", &key, "/ve", "/t", "REG_SZ", "/d", "URL:SubPub", "/f"])?
+        && reg(&["add", &key, "/v", "URL Protocol", "/t", "REG_SZ", "/d", "", "/f"])?
+        && reg(&["add", &command_key, "/ve", "/t", "REG_SZ", "/d", &command, "/f"])?;
+    if !added {
+        bail!("reg.exe could not add {}", key);
+    }
+    info!("Registered the handler for {}:// links ({}).", URL_SCHEME, key);
+    Ok(())
+}
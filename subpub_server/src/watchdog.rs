@@ -0,0 +1,66 @@
+use crossbeam_channel::Sender;
+use log::{error, info, warn};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::config::{DatagramConfig, WatchdogConfig};
+use crate::server::{run_server_processing_loop, ServerContext};
+use crate::sys_events::{SysEvents, SYS_SERVER_STATUS};
+
+// A loop that ran this long before failing counts as healthy again, so the restart
+// budget is for failures in a row, not over the whole show.
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+// Runs the processing loop and restarts it with backoff if it returns or panics, which
+// would otherwise leave the server deaf while the tray still says it's running.
+// When restarts are off or used up, the reason is sent on `failed_tx`, so
+// `run_server_application` shuts down and reports the error to the tray. With several
+// receive loops each has its own supervisor and budget; `name` tells them apart in logs.
+pub async fn supervise_processing_loop(
+    ctx: ServerContext,
+    name: String,
+    datagrams: DatagramConfig,
+    config: WatchdogConfig,
+    sys_events: SysEvents,
+    failed_tx: Sender<String>,
+) {
+    let mut restarts = 0;
+    loop {
+        let started = Instant::now();
+        // Spawned so a panic ends up here as a JoinError. Dropping the set (when this
+        // task is aborted on shutdown) aborts the loop with it.
+        let mut loop_task = JoinSet::new();
+        loop_task.spawn(run_server_processing_loop(ctx.clone(), datagrams.clone()));
+        let reason = match loop_task.join_next().await {
+            Some(Ok(Ok(()))) => format!("{} returned", name),
+            Some(Ok(Err(e))) => format!("{} failed: {}", name, e),
+            Some(Err(e)) => format!("{} panicked: {}", name, e),
+            None => format!("{} was not started", name),
+        };
+        error!("Server {}.", reason);
+
+        if started.elapsed() >= STABLE_RUN {
+            restarts = 0;
+        }
+        if !config.auto_restart || restarts >= config.max_restarts {
+            if config.auto_restart {
+                error!("Giving up after {} restart(s) in a row.", restarts);
+            }
+            // The first loop to give up shuts the server down; later ones aren't needed.
+            let _ = failed_tx.try_send(reason);
+            return;
+        }
+        restarts += 1;
+        let delay = config.delay_before_restart(restarts);
+        warn!("Restarting the {} in {}ms (restart {}/{}).", name, delay.as_millis(), restarts, config.max_restarts);
+        sys_events.emit(
+            SYS_SERVER_STATUS,
+            format!("loop failed, restarting in {}ms (restart {}/{})", delay.as_millis(), restarts, config.max_restarts),
+        );
+        sleep(delay).await;
+        info!("Restarted the {}.", name);
+        if let Ok(addr) = ctx.socket.local_addr() {
+            sys_events.emit(SYS_SERVER_STATUS, format!("listening on {}", addr));
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc as tokio_mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, Duration, Instant, MissedTickBehavior};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::config::WebSocketBridgeConfig;
+use crate::server::{handle_publish, topic_matches, ServerContext};
+use crate::sys_events::SYS_TOPIC_PREFIX;
+
+// Keeps NAT mappings and proxies from dropping an idle connection
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+// A connection that lasted this long resets the reconnect delay
+const STABLE_CONNECTION: Duration = Duration::from_secs(30);
+
+// One text frame in either direction: {"channel": "cues/go", "payload": "1"}
+// A non-string payload from the relay is published as its JSON text.
+#[derive(Serialize)]
+struct OutgoingFrame<'a> {
+    channel: &'a str,
+    payload: &'a str,
+}
+
+#[derive(Deserialize)]
+struct IncomingFrame {
+    channel: String,
+    payload: serde_json::Value,
+}
+
+// Connects out to a WebSocket relay and bridges topics both ways, so a cloud service can
+// feed an installation behind NAT without port forwarding. Publishes on `outgoing` topics
+// are sent to the relay; frames from the relay on `incoming` topics are published here.
+// A payload that just came in from the relay isn't sent straight back to it.
+pub struct WebSocketBridge {
+    outgoing_topics: Vec<String>,
+    outgoing: Option<tokio_mpsc::UnboundedSender<(String, String)>>,
+    connected: AtomicBool,
+    // Last payload imported per channel
+    imported: Mutex<HashMap<String, String>>,
+}
+
+impl WebSocketBridge {
+    // The receiver goes to `spawn`, along with the context holding the bridge.
+    pub fn new(config: &WebSocketBridgeConfig) -> (Arc<Self>, Option<tokio_mpsc::UnboundedReceiver<(String, String)>>) {
+        let (outgoing, rx) = if config.enabled && !config.url.is_empty() {
+            let (tx, rx) = tokio_mpsc::unbounded_channel();
+            (Some(tx), Some(rx))
+        } else {
+            (None, None)
+        };
+        let bridge = Self {
+            outgoing_topics: config.outgoing.clone(),
+            outgoing,
+            connected: AtomicBool::new(false),
+            imported: Mutex::new(HashMap::new()),
+        };
+        (Arc::new(bridge), rx)
+    }
+
+    // Called for every local publish; queues it for the relay if its topic is bridged.
+    pub fn forward(&self, channel: &str, payload: &str) {
+        let Some(outgoing) = &self.outgoing else {
+            return;
+        };
+        if !self.connected.load(Ordering::Relaxed) || !self.outgoing_topics.iter().any(|pattern| topic_matches(pattern, channel)) {
+            return;
+        }
+        {
+            let mut imported = self.imported.lock().unwrap();
+            if imported.get(channel).is_some_and(|last| last == payload) {
+                imported.remove(channel);
+                return; // The relay sent it
+            }
+        }
+        let _ = outgoing.send((channel.to_string(), payload.to_string()));
+    }
+}
+
+// Keeps the connection up for as long as the server runs, reconnecting with backoff.
+pub fn spawn(
+    config: &WebSocketBridgeConfig,
+    ctx: ServerContext,
+    outgoing: Option<tokio_mpsc::UnboundedReceiver<(String, String)>>,
+) -> Option<JoinHandle<()>> {
+    let mut outgoing = outgoing?;
+    let config = config.clone();
+    // The URL may carry a token in its query; keep it out of the log.
+    let url_for_log = config.url.split('?').next().unwrap_or_default().to_string();
+    let runtime_handle = ctx.runtime_handle.clone();
+    Some(runtime_handle.spawn(async move {
+        let min_delay = Duration::from_millis(config.reconnect_min_ms.max(1));
+        let max_delay = Duration::from_millis(config.reconnect_max_ms).max(min_delay);
+        let mut delay = min_delay;
+        loop {
+            let started = Instant::now();
+            if let Err(e) = run_link(&config, &ctx, &url_for_log, &mut outgoing).await {
+                warn!("WebSocket bridge to {}: {:#}. Reconnecting in {}ms.", url_for_log, e, delay.as_millis());
+            }
+            ctx.websocket_bridge.connected.store(false, Ordering::Relaxed);
+            ctx.websocket_bridge.imported.lock().unwrap().clear();
+            if started.elapsed() >= STABLE_CONNECTION {
+                delay = min_delay;
+            }
+            sleep(delay).await;
+            delay = (delay * 2).min(max_delay);
+            // Whatever was published while disconnected is stale by now.
+            while outgoing.try_recv().is_ok() {}
+        }
+    }))
+}
+
+// One connection to the relay, until it closes or fails.
+async fn run_link(
+    config: &WebSocketBridgeConfig,
+    ctx: &ServerContext,
+    url_for_log: &str,
+    outgoing: &mut tokio_mpsc::UnboundedReceiver<(String, String)>,
+) -> Result<()> {
+    let (socket, _response) = connect_async(config.url.as_str()).await.context("Failed to connect")?;
+    let (mut sink, mut stream) = socket.split();
+    ctx.websocket_bridge.connected.store(true, Ordering::Relaxed);
+    info!("WebSocket bridge connected to {}: sending {:?}, receiving {:?}", url_for_log, config.outgoing, config.incoming);
+
+    let mut ping = interval(PING_INTERVAL);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            Some((channel, payload)) = outgoing.recv() => {
+                let frame = serde_json::to_string(&OutgoingFrame { channel: &channel, payload: &payload })?;
+                sink.send(Message::Text(frame)).await.context("Failed to send")?;
+            }
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => import(config, ctx, &text).await,
+                Some(Ok(Message::Close(_))) | None => bail!("Closed by the relay"),
+                Some(Ok(_)) => {} // Pongs; pings are answered by the library
+                Some(Err(e)) => return Err(e).context("Connection failed"),
+            },
+            _ = ping.tick() => {
+                sink.send(Message::Ping(Vec::new())).await.context("Failed to send a ping")?;
+            }
+        }
+    }
+}
+
+// Publishes a frame from the relay, if its topic is one of the incoming ones.
+async fn import(config: &WebSocketBridgeConfig, ctx: &ServerContext, text: &str) {
+    let frame: IncomingFrame = match serde_json::from_str(text) {
+        Ok(frame) => frame,
+        Err(e) => {
+            warn!("Ignoring malformed WebSocket bridge frame {:?}: {}", text, e);
+            return;
+        }
+    };
+    if frame.channel.starts_with(SYS_TOPIC_PREFIX) || !config.incoming.iter().any(|pattern| topic_matches(pattern, &frame.channel)) {
+        debug!("WebSocket bridge: ignoring a frame on '{}', which isn't an incoming topic.", frame.channel);
+        return;
+    }
+    let payload = match frame.payload {
+        serde_json::Value::String(text) => text,
+        other => other.to_string(),
+    };
+    if config.outgoing.iter().any(|pattern| topic_matches(pattern, &frame.channel)) {
+        ctx.websocket_bridge.imported.lock().unwrap().insert(frame.channel.clone(), payload.clone());
+    }
+    debug!(topic = frame.channel.as_str(); "WebSocket bridge published to channel '{}': {}", frame.channel, payload);
+    handle_publish(ctx, None, &frame.channel, &payload, None, None).await;
+}
@@ -0,0 +1,50 @@
+use dashmap::DashMap;
+use log::info;
+use std::sync::Arc;
+
+// Runtime zone-enable matrix. Mappings and sequences tagged with a zone only
+// produce MIDI while that zone is enabled, so a room can be silenced without
+// touching the mapping file. Zones start out enabled.
+#[derive(Default)]
+pub struct Zones {
+    enabled: DashMap<String, bool>,
+}
+
+impl Zones {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    // Makes a zone known (enabled) without changing it if it already exists.
+    pub fn register(&self, zone: &str) {
+        self.enabled.entry(zone.to_string()).or_insert(true);
+    }
+
+    // Unknown zones count as enabled.
+    pub fn is_enabled(&self, zone: &str) -> bool {
+        self.enabled.get(zone).map(|e| *e.value()).unwrap_or(true)
+    }
+
+    // Untagged routes (no zone) are always allowed.
+    pub fn allows(&self, zone: Option<&str>) -> bool {
+        zone.is_none_or(|z| self.is_enabled(z))
+    }
+
+    pub fn set_enabled(&self, zone: &str, enabled: bool) {
+        let previous = self.enabled.insert(zone.to_string(), enabled);
+        if previous != Some(enabled) {
+            info!("Zone '{}' {}.", zone, if enabled { "enabled" } else { "silenced" });
+        }
+    }
+
+    // Snapshot of all known zones, sorted by name.
+    pub fn snapshot(&self) -> Vec<(String, bool)> {
+        let mut snapshot: Vec<(String, bool)> = self
+            .enabled
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot
+    }
+}